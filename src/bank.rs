@@ -0,0 +1,322 @@
+use crate::battery::{Battery, EnergyFlow, EnergyStorage};
+use crate::error::BatteryError;
+use log::{debug, warn};
+
+/// Tolerance below which remaining power is considered fully distributed, in MW.
+const TOLERANCE_MW: f64 = 1e-12;
+
+/// Several battery packs operated as one, e.g. packs with different capacities
+/// and rates on the same site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryBank {
+    batteries: Vec<Battery>,
+}
+
+impl BatteryBank {
+    /// Creates a bank from its member packs.
+    ///
+    /// # Parameters
+    /// - `batteries`: The packs in the bank.
+    pub fn new(batteries: Vec<Battery>) -> Self {
+        BatteryBank { batteries }
+    }
+
+    /// Returns the packs in the bank.
+    pub fn batteries(&self) -> &[Battery] {
+        &self.batteries
+    }
+
+    /// Returns the combined capacity of the packs in MWh.
+    pub fn capacity(&self) -> f64 {
+        self.batteries.iter().map(Battery::capacity).sum()
+    }
+
+    /// Returns the combined charge of the packs in MWh.
+    pub fn charge(&self) -> f64 {
+        self.batteries.iter().map(|battery| battery.charge).sum()
+    }
+
+    /// Returns the combined charging/discharging rate of the packs in MW, taking
+    /// the higher of the two rates of each pack.
+    pub fn max_rate(&self) -> f64 {
+        self.batteries.iter().map(Battery::max_rate).sum()
+    }
+
+    /// Returns the combined charging rate of the packs in MW.
+    pub fn max_charge_rate(&self) -> f64 {
+        self.batteries.iter().map(Battery::max_charge_rate).sum()
+    }
+
+    /// Returns the combined discharging rate of the packs in MW.
+    pub fn max_discharge_rate(&self) -> f64 {
+        self.batteries.iter().map(Battery::max_discharge_rate).sum()
+    }
+
+    /// Charges the packs with the specified amount of power for a given duration.
+    ///
+    /// The power is split in proportion to each pack's room to full, and no pack
+    /// gets more than its `max_charge_rate`; what a pack cannot take goes to the others.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The total charging power in MW.
+    /// - `duration_hours`: The duration for which to charge, in hours.
+    ///
+    /// # Returns
+    /// The combined `EnergyFlow` of the packs, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn charge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        self.charge_battery_up_to(amount_mw, duration_hours, 1.0)
+    }
+
+    /// Charges the packs like `charge_battery`, but no pack beyond a state of charge.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The total charging power in MW.
+    /// - `duration_hours`: The duration for which to charge, in hours.
+    /// - `max_soc`: The highest state of charge of each pack, as a fraction of its capacity.
+    ///
+    /// # Returns
+    /// The combined `EnergyFlow` of the packs, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn charge_battery_up_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        max_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        if amount_mw < 0.0 {
+            warn!("Attempted to charge with a negative power: {}", amount_mw);
+            return Err(BatteryError::NegativePower {
+                operation: "charge",
+                power_mw: amount_mw,
+            });
+        }
+
+        // Each pack's room to full, or to the top of the band if that is lower
+        let headroom: Vec<f64> = self
+            .batteries
+            .iter()
+            .map(|battery| {
+                battery
+                    .energy_to_full()
+                    .min((max_soc * battery.capacity() - battery.charge).max(0.0))
+            })
+            .collect();
+        let rates: Vec<f64> = self
+            .batteries
+            .iter()
+            .map(Battery::max_charge_rate)
+            .collect();
+        let shares = self.distribute(amount_mw, &headroom, &rates);
+
+        let mut total = EnergyFlow {
+            grid_wh: 0.0,
+            battery_wh: 0.0,
+        };
+        for (battery, share_mw) in self.batteries.iter_mut().zip(shares) {
+            if share_mw > 0.0 {
+                let flow = battery.charge_battery_up_to(share_mw, duration_hours, max_soc)?;
+                total.grid_wh += flow.grid_wh;
+                total.battery_wh += flow.battery_wh;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Discharges the packs by the specified amount of power for a given duration.
+    ///
+    /// The power is split in proportion to the energy each pack has available above
+    /// its minimum charge, and no pack delivers more than its `max_discharge_rate`.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The total discharging power in MW.
+    /// - `duration_hours`: The duration for which to discharge, in hours.
+    ///
+    /// # Returns
+    /// The combined `EnergyFlow` of the packs, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn discharge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        self.discharge_battery_down_to(amount_mw, duration_hours, 0.0)
+    }
+
+    /// Discharges the packs like `discharge_battery`, but no pack below a state of charge.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The total discharging power in MW.
+    /// - `duration_hours`: The duration for which to discharge, in hours.
+    /// - `min_soc`: The lowest state of charge of each pack, as a fraction of its capacity.
+    ///
+    /// # Returns
+    /// The combined `EnergyFlow` of the packs, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn discharge_battery_down_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        min_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        if amount_mw < 0.0 {
+            warn!(
+                "Attempted to discharge with a negative power: {}",
+                amount_mw
+            );
+            return Err(BatteryError::NegativePower {
+                operation: "discharge",
+                power_mw: amount_mw,
+            });
+        }
+
+        // Each pack's energy above its minimum charge, or above the band if that is higher
+        let available: Vec<f64> = self
+            .batteries
+            .iter()
+            .map(|battery| {
+                battery
+                    .energy_available()
+                    .min((battery.charge - min_soc * battery.capacity()).max(0.0))
+            })
+            .collect();
+        let rates: Vec<f64> = self
+            .batteries
+            .iter()
+            .map(Battery::max_discharge_rate)
+            .collect();
+        let shares = self.distribute(amount_mw, &available, &rates);
+
+        let mut total = EnergyFlow {
+            grid_wh: 0.0,
+            battery_wh: 0.0,
+        };
+        for (battery, share_mw) in self.batteries.iter_mut().zip(shares) {
+            if share_mw > 0.0 {
+                let flow = battery.discharge_battery_down_to(share_mw, duration_hours, min_soc)?;
+                total.grid_wh += flow.grid_wh;
+                total.battery_wh += flow.battery_wh;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Splits a power across the packs in proportion to `weights`, capping each
+    /// pack at its rate and sharing the excess among the others.
+    ///
+    /// # Arguments
+    ///
+    /// * `power_mw`: The total power to split.
+    /// * `weights`: One weight per pack, e.g. its room to full; packs with no weight get nothing.
+    /// * `rates`: The maximum power of each pack in the direction being split, in MW.
+    ///
+    /// # Returns
+    /// The power of each pack in MW.
+    fn distribute(&self, power_mw: f64, weights: &[f64], rates: &[f64]) -> Vec<f64> {
+        let mut shares = vec![0.0; self.batteries.len()];
+        let mut remaining = power_mw;
+        let mut open: Vec<usize> = (0..weights.len()).filter(|i| weights[*i] > 0.0).collect();
+
+        while remaining > TOLERANCE_MW && !open.is_empty() {
+            let total_weight: f64 = open.iter().map(|i| weights[*i]).sum();
+            let (capped, uncapped): (Vec<usize>, Vec<usize>) = open
+                .iter()
+                .partition(|i| remaining * weights[**i] / total_weight >= rates[**i]);
+
+            if capped.is_empty() {
+                for i in &uncapped {
+                    shares[*i] = remaining * weights[*i] / total_weight;
+                }
+                break;
+            }
+
+            // Packs at their max rate take no more; share the rest among the others
+            for i in &capped {
+                shares[*i] = rates[*i];
+                remaining -= shares[*i];
+            }
+            open = uncapped;
+        }
+
+        debug!("Distributed {} MW across the bank: {:?}", power_mw, shares);
+        shares
+    }
+}
+
+impl EnergyStorage for BatteryBank {
+    fn charge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        BatteryBank::charge_battery(self, amount_mw, duration_hours)
+    }
+
+    fn discharge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        BatteryBank::discharge_battery(self, amount_mw, duration_hours)
+    }
+
+    fn charge_battery_up_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        max_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        BatteryBank::charge_battery_up_to(self, amount_mw, duration_hours, max_soc)
+    }
+
+    fn discharge_battery_down_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        min_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        BatteryBank::discharge_battery_down_to(self, amount_mw, duration_hours, min_soc)
+    }
+
+    fn apply_self_discharge(&mut self, duration_hours: f64) {
+        for battery in &mut self.batteries {
+            battery.apply_self_discharge(duration_hours);
+        }
+    }
+
+    fn energy_available(&self) -> f64 {
+        self.batteries.iter().map(Battery::energy_available).sum()
+    }
+
+    fn available_discharge_power(&self, duration_hours: f64) -> f64 {
+        self.batteries
+            .iter()
+            .map(|battery| battery.available_discharge_power(duration_hours))
+            .sum()
+    }
+
+    /// Returns the bank's equivalent full cycles, the packs' cycles weighted by capacity.
+    fn equivalent_cycles(&self) -> f64 {
+        let capacity = self.capacity();
+        if capacity <= 0.0 {
+            return 0.0;
+        }
+        self.batteries
+            .iter()
+            .map(|battery| battery.cycles * battery.capacity())
+            .sum::<f64>()
+            / capacity
+    }
+}
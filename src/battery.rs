@@ -1,36 +1,402 @@
-use anyhow::{anyhow, Result};
-use log::{info, warn};
+use crate::config::Settings;
+use crate::error::BatteryError;
+use crate::units::{convert, to_mwh, EnergyUnit};
+use log::{debug, warn};
+
+/// Fraction of the capacity lost per equivalent full cycle.
+const CAPACITY_FADE_PER_CYCLE: f64 = 0.0002;
+
+/// State of health below which the capacity is assumed not to fade further.
+const MIN_STATE_OF_HEALTH: f64 = 0.8;
+
+/// Number of watt-hours in a megawatt-hour.
+pub const WH_PER_MWH: f64 = 1_000_000.0;
+
+/// Returns the grid energy a charge draws to store an amount of energy in the pack.
+///
+/// # Parameters
+/// - `stored_mwh`: The energy to store, in MWh.
+/// - `charge_efficiency`: The fraction of the grid energy that ends up stored.
+///
+/// # Returns
+/// The grid energy to draw, in MWh.
+pub fn grid_energy_for_stored(stored_mwh: f64, charge_efficiency: f64) -> f64 {
+    stored_mwh / charge_efficiency
+}
+
+/// Returns the energy a discharge delivers to the site when it draws an amount of
+/// energy from the pack.
+///
+/// # Parameters
+/// - `drawn_mwh`: The energy drawn from the pack, in MWh.
+/// - `discharge_efficiency`: The fraction of the drawn energy that is delivered.
+///
+/// # Returns
+/// The delivered energy, in MWh.
+pub fn delivered_for_drawn(drawn_mwh: f64, discharge_efficiency: f64) -> f64 {
+    drawn_mwh * discharge_efficiency
+}
+
+/// Energy moved by a single charge or discharge operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnergyFlow {
+    /// Energy exchanged with the grid side of the battery, in watt-hours: drawn
+    /// from the grid when charging, delivered to the site when discharging.
+    pub grid_wh: f64,
+    /// Energy added to or removed from the pack, in watt-hours.
+    pub battery_wh: f64,
+}
+
+impl EnergyFlow {
+    /// Creates an `EnergyFlow` from grid-side and battery-side energies in MWh.
+    fn from_mwh(grid_mwh: f64, battery_mwh: f64) -> Self {
+        EnergyFlow {
+            grid_wh: convert(grid_mwh, EnergyUnit::Wh),
+            battery_wh: convert(battery_mwh, EnergyUnit::Wh),
+        }
+    }
+}
+
+/// The outcome of a charge, reporting how much of the request the pack could not take;
+/// see `Battery::charge_battery_reporting`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargeOutcome {
+    /// The energy moved by the charge.
+    pub flow: EnergyFlow,
+    /// Energy the pack would have stored at the requested power, within the
+    /// charging rate and after efficiency losses, in MWh.
+    pub requested_mwh: f64,
+    /// Energy actually stored in the pack, in MWh.
+    pub stored_mwh: f64,
+    /// Energy that did not fit because the battery was full, in MWh.
+    pub curtailed_mwh: f64,
+}
+
+/// The outcome a charge or discharge would have, computed without applying it;
+/// see `Battery::peek_charge` and `Battery::peek_discharge`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeekOutcome {
+    /// The energy the operation would move.
+    pub flow: EnergyFlow,
+    /// Energy the pack would store or give up, in MWh.
+    pub moved_mwh: f64,
+    /// The charge the battery would be left with, in MWh.
+    pub charge_mwh: f64,
+}
+
+/// The mutable state of a battery, captured with `Battery::snapshot`.
+///
+/// The state of health follows from the cycles, so this is all that changes as
+/// the battery is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryState {
+    /// Charge in MWh.
+    pub charge: f64,
+    /// Equivalent full cycles.
+    pub cycles: f64,
+}
+
+/// The operations the planner needs from an energy store.
+///
+/// Implemented by a single `Battery` and by a `BatteryBank` of several packs, so
+/// `plan_battery_usage` can plan for either.
+pub trait EnergyStorage {
+    /// Charges with up to `amount_mw` for `duration_hours`; see `Battery::charge_battery`.
+    fn charge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError>;
+
+    /// Discharges with up to `amount_mw` for `duration_hours`; see `Battery::discharge_battery`.
+    fn discharge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError>;
+
+    /// Charges like `charge_battery`, but stops once the state of charge reaches `max_soc`.
+    fn charge_battery_up_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        max_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError>;
+
+    /// Discharges like `discharge_battery`, but stops once the state of charge reaches `min_soc`.
+    fn discharge_battery_down_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        min_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError>;
+
+    /// Applies passive self-discharge for an idle period of `duration_hours`.
+    fn apply_self_discharge(&mut self, duration_hours: f64);
+
+    /// Returns how much energy can still be drawn, in MWh.
+    fn energy_available(&self) -> f64;
+
+    /// Returns the highest power that can be delivered for `duration_hours`, in MW;
+    /// see `Battery::available_discharge_power`.
+    fn available_discharge_power(&self, duration_hours: f64) -> f64;
+
+    /// Returns the equivalent full cycles made so far.
+    fn equivalent_cycles(&self) -> f64;
+}
 
 /// A struct representing a battery with specific properties.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Battery {
-    capacity: f64,   // Max capacity in MWh
-    pub charge: f64, // Current charge in MWh
-    max_rate: f64,   // Max charging/discharging rate in MW
-    efficiency: f64, // Efficiency in charging/discharging
+    capacity: f64,                    // Max capacity in MWh
+    initial_charge: f64,              // Charge in MWh the battery was created with
+    pub charge: f64,                  // Current charge in MWh
+    max_charge_rate: f64,             // Max charging rate in MW
+    max_discharge_rate: f64,          // Max discharging rate in MW
+    charge_efficiency: f64,           // Efficiency in charging
+    discharge_efficiency: f64,        // Efficiency in discharging
+    min_charge: f64,                  // Minimum reserve charge in MWh, never discharged
+    self_discharge_rate_per_day: f64, // Fraction of the charge lost per idle day
+    pub cycles: f64,                  // Equivalent full cycles so far
 }
 
 impl Battery {
-    /// Creates a new `Battery` instance with predefined properties.
-    ///
-    /// # Returns
-    /// A `Battery` instance initialized with a capacity of 3.0 MWh,
-    /// a starting charge of 1.5 MWh, a max charging/discharging rate of 1.5 MW,
-    /// and an efficiency of 90%.
-    // pub fn new() -> Self {
-    //     Battery {
-    //         capacity: 3.0, // Ensure this is set to 3 MWh
-    //         charge: 1.5,   // Initial charge can be adjusted as needed
-    //         max_rate: 1.5,
-    //         efficiency: 0.90,
-    //     }
-    // }
-    pub fn new(capacity: f64, initial_charge: f64, max_rate: f64, efficiency: f64) -> Self {
-        Battery {
-            capacity,
-            charge: initial_charge,
-            max_rate,
-            efficiency,
+    /// Creates a new `Battery` with the given properties, starting at `initial_charge`.
+    ///
+    /// # Parameters
+    /// - `capacity`: The maximum capacity in MWh.
+    /// - `initial_charge`: The starting charge in MWh.
+    /// - `max_rate`: The maximum charging and discharging rate in MW.
+    /// - `charge_efficiency`: The fraction of the energy drawn that is stored when charging.
+    /// - `discharge_efficiency`: The fraction of the energy drawn that is delivered when
+    ///   discharging.
+    /// - `min_charge`: The reserve in MWh that is never discharged.
+    /// - `self_discharge_rate_per_day`: The fraction of the charge lost per idle day.
+    ///
+    /// # Returns
+    /// The `Battery`.
+    ///
+    /// The values are not validated; use `Battery::builder` to catch swapped or
+    /// out-of-range arguments.
+    pub fn new(
+        capacity: f64,
+        initial_charge: f64,
+        max_rate: f64,
+        charge_efficiency: f64,
+        discharge_efficiency: f64,
+        min_charge: f64,
+        self_discharge_rate_per_day: f64,
+    ) -> Self {
+        Battery::builder()
+            .capacity(capacity)
+            .initial_charge(initial_charge)
+            .max_rate(max_rate)
+            .charge_efficiency(charge_efficiency)
+            .discharge_efficiency(discharge_efficiency)
+            .min_charge(min_charge)
+            .self_discharge_rate_per_day(self_discharge_rate_per_day)
+            .assemble()
+    }
+
+    /// Starts building a `Battery` with named, validated properties.
+    pub fn builder() -> BatteryBuilder {
+        BatteryBuilder::default()
+    }
+
+    /// Creates the battery described by the configuration settings.
+    ///
+    /// This is the single place that maps `Settings` to battery properties, with the
+    /// directional rates and efficiencies falling back to the shared ones.
+    ///
+    /// # Parameters
+    /// - `settings`: The configuration settings.
+    ///
+    /// # Returns
+    /// The `Battery`, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::InvalidProperty` if the settings describe an invalid
+    /// battery; see `BatteryBuilder::build`.
+    pub fn from_settings(settings: &Settings) -> Result<Self, BatteryError> {
+        Battery::builder()
+            .capacity(settings.capacity)
+            .initial_charge(settings.initial_charge)
+            .max_rate(settings.max_rate)
+            .max_charge_rate(settings.max_charge_rate())
+            .max_discharge_rate(settings.max_discharge_rate())
+            .charge_efficiency(settings.charge_efficiency())
+            .discharge_efficiency(settings.discharge_efficiency())
+            .min_charge(settings.min_charge)
+            .self_discharge_rate_per_day(settings.self_discharge_rate_per_day)
+            .build()
+    }
+
+    /// Returns the maximum capacity of the battery in MWh.
+    pub fn capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    /// Sets separate charging and discharging rates, for inverters that charge and
+    /// discharge at different maximum powers. `new` uses its `max_rate` for both.
+    ///
+    /// # Parameters
+    /// - `max_charge_rate`: The maximum charging rate in MW.
+    /// - `max_discharge_rate`: The maximum discharging rate in MW.
+    pub fn with_rates(mut self, max_charge_rate: f64, max_discharge_rate: f64) -> Self {
+        self.max_charge_rate = max_charge_rate;
+        self.max_discharge_rate = max_discharge_rate;
+        self
+    }
+
+    /// Returns the higher of the charging and discharging rates of the battery in MW.
+    pub fn max_rate(&self) -> f64 {
+        self.max_charge_rate.max(self.max_discharge_rate)
+    }
+
+    /// Returns the maximum charging rate of the battery in MW.
+    pub fn max_charge_rate(&self) -> f64 {
+        self.max_charge_rate
+    }
+
+    /// Returns the maximum discharging rate of the battery in MW.
+    pub fn max_discharge_rate(&self) -> f64 {
+        self.max_discharge_rate
+    }
+
+    /// Returns the charging efficiency of the battery.
+    pub fn charge_efficiency(&self) -> f64 {
+        self.charge_efficiency
+    }
+
+    /// Returns the discharging efficiency of the battery.
+    pub fn discharge_efficiency(&self) -> f64 {
+        self.discharge_efficiency
+    }
+
+    /// Returns the minimum reserve charge of the battery in MWh.
+    pub fn min_charge(&self) -> f64 {
+        self.min_charge
+    }
+
+    /// Returns the fraction of the charge the battery loses per idle day.
+    pub fn self_discharge_rate_per_day(&self) -> f64 {
+        self.self_discharge_rate_per_day
+    }
+
+    /// Starts the battery from a different charge than it was created with, e.g. the
+    /// charge metered just before planning. `reset` returns to this charge afterwards.
+    ///
+    /// # Parameters
+    /// - `start_charge`: The charge to start from, in MWh.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::InvalidProperty` if `start_charge` is not between
+    /// `min_charge` and the capacity.
+    pub fn set_start_charge(&mut self, start_charge: f64) -> Result<(), BatteryError> {
+        if !(start_charge >= self.min_charge && start_charge <= self.capacity) {
+            return Err(BatteryError::InvalidProperty {
+                property: "start_charge",
+                reason: format!(
+                    "must be between the minimum charge ({} MWh) and the capacity ({} MWh), got {} MWh",
+                    self.min_charge, self.capacity, start_charge
+                ),
+            });
         }
+        self.initial_charge = start_charge;
+        self.charge = start_charge;
+        Ok(())
+    }
+
+    /// Returns the battery to the state it was created in: the initial charge and
+    /// no cycles, so it is as good as new.
+    pub fn reset(&mut self) {
+        self.charge = self.initial_charge;
+        self.cycles = 0.0;
+    }
+
+    /// Captures the battery's current charge and cycles.
+    pub fn snapshot(&self) -> BatteryState {
+        BatteryState {
+            charge: self.charge,
+            cycles: self.cycles,
+        }
+    }
+
+    /// Returns the battery to a state captured with `snapshot`.
+    ///
+    /// # Parameters
+    /// - `state`: The state to restore.
+    pub fn restore(&mut self, state: BatteryState) {
+        self.charge = state.charge;
+        self.cycles = state.cycles;
+    }
+
+    /// Returns the nominal round-trip efficiency: the fraction of the grid energy
+    /// drawn when charging that is delivered again when discharging.
+    pub fn round_trip_efficiency(&self) -> f64 {
+        self.charge_efficiency * self.discharge_efficiency
+    }
+
+    /// Returns how full the battery is as a fraction of its capacity.
+    ///
+    /// # Returns
+    /// The state of charge between 0.0 (empty) and 1.0 (full). A battery with
+    /// zero capacity reports 0.0.
+    pub fn state_of_charge(&self) -> f64 {
+        if self.capacity <= 0.0 {
+            return 0.0;
+        }
+        (self.charge / self.capacity).clamp(0.0, 1.0)
+    }
+
+    /// Returns the capacity retention of the battery as it ages.
+    ///
+    /// The capacity fades linearly by 0.02% per equivalent full cycle, down to a
+    /// floor of 80%.
+    ///
+    /// # Returns
+    /// The state of health between 0.8 and 1.0.
+    pub fn state_of_health(&self) -> f64 {
+        (1.0 - CAPACITY_FADE_PER_CYCLE * self.cycles).max(MIN_STATE_OF_HEALTH)
+    }
+
+    /// Returns the usable capacity of the battery in MWh after degradation.
+    pub fn effective_capacity(&self) -> f64 {
+        self.capacity * self.state_of_health()
+    }
+
+    /// Returns how much more energy the battery can store, in MWh.
+    ///
+    /// This respects the degraded capacity and is never negative.
+    pub fn energy_to_full(&self) -> f64 {
+        (self.effective_capacity() - self.charge).max(0.0)
+    }
+
+    /// Returns how much energy can still be drawn from the battery, in MWh.
+    ///
+    /// This respects the minimum charge floor and is never negative.
+    pub fn energy_available(&self) -> f64 {
+        (self.charge - self.min_charge).max(0.0)
+    }
+
+    /// Returns the highest power the battery can deliver for a whole period, in MW.
+    ///
+    /// This is the discharging rate, unless the energy above `min_charge` runs out
+    /// sooner: `min(max_discharge_rate, energy_available * discharge_efficiency / duration_hours)`.
+    ///
+    /// # Parameters
+    /// - `duration_hours`: The length of the period, in hours.
+    ///
+    /// # Returns
+    /// The sustainable power in MW, or 0.0 if `duration_hours` is not positive.
+    pub fn available_discharge_power(&self, duration_hours: f64) -> f64 {
+        if duration_hours <= 0.0 {
+            return 0.0;
+        }
+        self.max_discharge_rate.min(
+            delivered_for_drawn(self.energy_available(), self.discharge_efficiency)
+                / duration_hours,
+        )
     }
 
     /// Charges the battery with the specified amount of power for a given duration.
@@ -40,49 +406,225 @@ impl Battery {
     /// - `duration_hours`: The duration for which to charge the battery, in hours.
     ///
     /// # Returns
-    /// The amount of energy charged in megawatt-hours (MWh), wrapped in a `Result`.
+    /// The `EnergyFlow` of the operation, wrapped in a `Result`: the energy drawn
+    /// from the grid and the energy stored in the pack after efficiency losses.
     /// If the amount of power is negative, it returns an error.
     ///
     /// # Errors
-    /// Returns an error if `amount_mw` is negative.
-    pub fn charge_battery(&mut self, amount_mw: f64, duration_hours: f64) -> Result<f64> {
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn charge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        self.charge_battery_reporting(amount_mw, duration_hours)
+            .map(|outcome| outcome.flow)
+    }
+
+    /// Charges the battery like `charge_battery`, and reports how much of the
+    /// request was curtailed because the battery was full.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The amount of power in megawatts (MW) to charge the battery.
+    /// - `duration_hours`: The duration for which to charge the battery, in hours.
+    ///
+    /// # Returns
+    /// The `ChargeOutcome` of the operation, wrapped in a `Result`; its
+    /// `curtailed_mwh` is `requested_mwh - stored_mwh`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn charge_battery_reporting(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<ChargeOutcome, BatteryError> {
+        let outcome = self.charge_outcome(amount_mw, duration_hours)?;
+
+        // Stored energy never exceeds the room to full, so the charge stays within the
+        // degraded capacity; a charge already above it after fading is kept, not lost
+        self.charge += outcome.stored_mwh; // Add usable energy to the charge
+        self.add_cycles(outcome.stored_mwh);
+
+        debug!("New charge after charging: {} MW", self.charge);
+
+        Ok(outcome)
+    }
+
+    /// Computes what `charge_battery` would do, without charging the battery.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The amount of power in megawatts (MW) to charge the battery.
+    /// - `duration_hours`: The duration for which to charge the battery, in hours.
+    ///
+    /// # Returns
+    /// The `PeekOutcome` of the charge, wrapped in a `Result`: the energy that would
+    /// be drawn and stored, and the resulting charge.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn peek_charge(
+        &self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<PeekOutcome, BatteryError> {
+        let outcome = self.charge_outcome(amount_mw, duration_hours)?;
+        Ok(PeekOutcome {
+            flow: outcome.flow,
+            moved_mwh: outcome.stored_mwh,
+            charge_mwh: self.charge + outcome.stored_mwh,
+        })
+    }
+
+    /// Computes the `ChargeOutcome` of a charge, leaving the battery untouched;
+    /// shared by `charge_battery_reporting` and `peek_charge`.
+    fn charge_outcome(
+        &self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<ChargeOutcome, BatteryError> {
         if amount_mw < 0.0 {
             warn!("Attempted to charge with a negative power: {}", amount_mw);
-            return Err(anyhow!(
-                "Attempted to charge with a negative power: {}",
-                amount_mw
-            ));
+            return Err(BatteryError::NegativePower {
+                operation: "charge",
+                power_mw: amount_mw,
+            });
         }
 
-        // Ensure charging rate does not exceed max_rate
-        let effective_mw = amount_mw.min(self.max_rate); // Limit to max_rate
+        // Ensure charging rate does not exceed max_charge_rate
+        let effective_mw = amount_mw.min(self.max_charge_rate); // Limit to max_charge_rate
         let energy_to_battery = effective_mw * duration_hours; // Total energy input
-        let actual_energy = energy_to_battery * self.efficiency; // Effective energy due to efficiency
+        let actual_energy = energy_to_battery * self.charge_efficiency; // Effective energy due to efficiency
 
-        info!(
+        debug!(
             "Charging with: {} MW for {} hours. Total energy to battery: {}, Effective energy (after efficiency): {}",
             effective_mw, duration_hours, energy_to_battery, actual_energy
         );
 
-        // Calculate how much energy can be stored based on capacity
-        let available_capacity = self.capacity - self.charge; // Remaining capacity
+        // Calculate how much energy can be stored based on the degraded capacity
+        let available_capacity = self.energy_to_full(); // Remaining capacity
         let energy_stored = actual_energy.min(available_capacity); // Store only what can fit
 
-        info!(
+        debug!(
             "Available capacity: {}, Energy stored: {}",
             available_capacity, energy_stored
         );
 
-        self.charge += energy_stored; // Add usable energy to the charge
+        // Only the grid energy that ended up stored was actually drawn
+        let energy_drawn = grid_energy_for_stored(energy_stored, self.charge_efficiency);
+
+        Ok(ChargeOutcome {
+            flow: EnergyFlow::from_mwh(energy_drawn, energy_stored), // The actual energy added
+            requested_mwh: actual_energy,
+            stored_mwh: energy_stored,
+            curtailed_mwh: actual_energy - energy_stored,
+        })
+    }
 
-        // Ensure we do not exceed capacity
-        if self.charge > self.capacity {
-            self.charge = self.capacity;
+    /// Charges the battery toward a target state of charge within a given duration.
+    ///
+    /// The battery charges at the power needed to reach `target_soc * capacity`,
+    /// but at most `max_charge_rate`, so the target may not be reached if the duration is
+    /// too short. Efficiency losses and the degraded capacity are respected as in
+    /// `charge_battery`.
+    ///
+    /// # Parameters
+    /// - `target_soc`: The state of charge to reach, as a fraction of the capacity.
+    /// - `duration_hours`: The time available to reach the target, in hours.
+    ///
+    /// # Returns
+    /// The energy drawn from the grid in MWh, wrapped in a `Result`; 0.0 if
+    /// `duration_hours` is not positive.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::InvalidTargetSoc` if `target_soc` is not between 0 and 1,
+    /// or `BatteryError::TargetBelowCharge` if it is below the current state of charge.
+    pub fn charge_to_target(
+        &mut self,
+        target_soc: f64,
+        duration_hours: f64,
+    ) -> Result<f64, BatteryError> {
+        if !(0.0..=1.0).contains(&target_soc) {
+            return Err(BatteryError::InvalidTargetSoc { target_soc });
+        }
+        if target_soc < self.state_of_charge() {
+            return Err(BatteryError::TargetBelowCharge {
+                target_soc,
+                state_of_charge: self.state_of_charge(),
+            });
+        }
+        if duration_hours <= 0.0 {
+            return Ok(0.0);
         }
 
-        info!("New charge after charging: {} MW", self.charge);
+        // Grid power needed to store the missing energy in time, limited by max_charge_rate
+        let missing = (target_soc * self.capacity - self.charge).max(0.0);
+        let power_mw = grid_energy_for_stored(missing, self.charge_efficiency) / duration_hours;
+
+        debug!(
+            "Charging toward {}% state of charge: {} MWh missing",
+            target_soc * 100.0,
+            missing
+        );
+
+        let charged = self.charge_battery(power_mw, duration_hours)?;
+        Ok(to_mwh(charged.grid_wh, EnergyUnit::Wh))
+    }
+
+    /// Charges the battery with an amount of grid energy spread over a given duration.
+    ///
+    /// The energy is drawn at the constant power that delivers it in `duration_hours`,
+    /// so it is limited to `max_charge_rate * duration_hours`, and to what fits below
+    /// the capacity after efficiency losses, as in `charge_battery`.
+    ///
+    /// # Parameters
+    /// - `energy_mwh`: The energy to draw from the grid, in MWh.
+    /// - `duration_hours`: The duration over which to charge, in hours.
+    ///
+    /// # Returns
+    /// The energy actually drawn from the grid in MWh, wrapped in a `Result`; 0.0 if
+    /// `duration_hours` is not positive.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `energy_mwh` is negative.
+    pub fn charge_energy(
+        &mut self,
+        energy_mwh: f64,
+        duration_hours: f64,
+    ) -> Result<f64, BatteryError> {
+        if duration_hours <= 0.0 {
+            return Ok(0.0);
+        }
+        let charged = self.charge_battery(energy_mwh / duration_hours, duration_hours)?;
+        Ok(to_mwh(charged.grid_wh, EnergyUnit::Wh))
+    }
 
-        Ok(energy_stored) // Return the actual energy added
+    /// Discharges the battery to deliver an amount of energy over a given duration.
+    ///
+    /// The energy is delivered at the constant power that supplies it in `duration_hours`,
+    /// so it is limited to `max_discharge_rate * duration_hours`, and to the energy
+    /// available above `min_charge` after efficiency losses, as in `discharge_battery`.
+    ///
+    /// # Parameters
+    /// - `energy_mwh`: The energy to deliver to the site, in MWh.
+    /// - `duration_hours`: The duration over which to discharge, in hours.
+    ///
+    /// # Returns
+    /// The energy actually delivered in MWh, wrapped in a `Result`; 0.0 if
+    /// `duration_hours` is not positive.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `energy_mwh` is negative.
+    pub fn discharge_energy(
+        &mut self,
+        energy_mwh: f64,
+        duration_hours: f64,
+    ) -> Result<f64, BatteryError> {
+        if duration_hours <= 0.0 {
+            return Ok(0.0);
+        }
+        let discharged = self.discharge_battery(energy_mwh / duration_hours, duration_hours)?;
+        Ok(to_mwh(discharged.grid_wh, EnergyUnit::Wh))
     }
 
     /// Discharges the battery by the specified amount of power for a given duration.
@@ -92,40 +634,420 @@ impl Battery {
     /// - `duration_hours`: The duration for which to discharge the battery, in hours.
     ///
     /// # Returns
-    /// The amount of energy discharged in megawatt-hours (MWh), wrapped in a `Result`.
-    /// If the amount of power is negative, it returns an error.
+    /// The `EnergyFlow` of the operation, wrapped in a `Result`: the energy delivered
+    /// to the site after efficiency losses and the energy drawn from the pack. The
+    /// charge never drops below `min_charge`; if the request would cross that floor,
+    /// only the energy above it is delivered.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn discharge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        let outcome = self.peek_discharge(amount_mw, duration_hours)?;
+
+        self.charge = outcome.charge_mwh;
+        self.add_cycles(outcome.moved_mwh);
+        debug!("Remaining charge after discharging: {} MWh", self.charge);
+
+        Ok(outcome.flow)
+    }
+
+    /// Computes what `discharge_battery` would do, without discharging the battery.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The amount of power in megawatts (MW) to discharge from the battery.
+    /// - `duration_hours`: The duration for which to discharge the battery, in hours.
+    ///
+    /// # Returns
+    /// The `PeekOutcome` of the discharge, wrapped in a `Result`: the energy that would
+    /// be delivered and drawn from the pack, and the resulting charge.
     ///
     /// # Errors
-    /// Returns an error if `amount_mw` is negative.
-    pub fn discharge_battery(&mut self, amount_mw: f64, duration_hours: f64) -> Result<f64> {
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn peek_discharge(
+        &self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<PeekOutcome, BatteryError> {
         if amount_mw < 0.0 {
             warn!(
                 "Attempted to discharge with a negative power: {}",
                 amount_mw
             );
-            return Err(anyhow!(
-                "Attempted to discharge with a negative power: {}",
-                amount_mw
-            ));
+            return Err(BatteryError::NegativePower {
+                operation: "discharge",
+                power_mw: amount_mw,
+            });
         }
 
-        // Ensure discharging rate does not exceed max_rate
-        let effective_mw = amount_mw.min(self.max_rate); // Limit to max_rate
-        let energy_needed = effective_mw * duration_hours; // Total energy needed
-        let actual_energy_needed = energy_needed / self.efficiency; // Adjust for efficiency
+        // Ensure discharging rate does not exceed max_discharge_rate
+        let effective_mw = amount_mw.min(self.max_discharge_rate); // Limit to max_discharge_rate
+        let energy_needed = effective_mw * duration_hours; // Total energy to deliver
+        let actual_energy_needed = energy_needed / self.discharge_efficiency; // Adjust for efficiency
 
-        if self.charge < actual_energy_needed {
-            let discharged = self.charge; // Discharge only what's available
-            self.charge = 0.0; // Set charge to zero
-            info!("Discharged all available energy: {} MWh", discharged);
-            Ok(discharged) // Return how much was discharged
+        let available = self.energy_available(); // Energy above the floor
+
+        if available < actual_energy_needed {
+            let delivered = delivered_for_drawn(available, self.discharge_efficiency); // Deliver only what's available
+            debug!(
+                "Discharging all available energy: {} MWh, delivered: {} MWh",
+                available, delivered
+            );
+            Ok(PeekOutcome {
+                flow: EnergyFlow::from_mwh(delivered, available), // Deliver how much is available
+                moved_mwh: available,
+                charge_mwh: self.charge - available, // Stop at the minimum charge
+            })
         } else {
-            self.charge -= actual_energy_needed; // Reduce charge based on energy needed
-            info!(
-                "Discharged energy: {} MWh, Remaining charge: {} MWh",
-                actual_energy_needed, self.charge
+            debug!(
+                "Discharging energy: {} MWh, delivered: {} MWh",
+                actual_energy_needed, energy_needed
             );
-            Ok(actual_energy_needed) // Return the actual energy discharged
+            Ok(PeekOutcome {
+                flow: EnergyFlow::from_mwh(energy_needed, actual_energy_needed), // The energy delivered
+                moved_mwh: actual_energy_needed,
+                charge_mwh: (self.charge - actual_energy_needed).max(self.min_charge), // Never go below the floor
+            })
+        }
+    }
+
+    /// Charges the battery like `charge_battery`, but no further than a state of charge.
+    ///
+    /// The power is reduced so that the charge stops at `max_soc * capacity`, or at
+    /// the degraded capacity if that is lower. A battery already at or above
+    /// `max_soc` stores nothing.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The amount of power in megawatts (MW) to charge the battery.
+    /// - `duration_hours`: The duration for which to charge the battery, in hours.
+    /// - `max_soc`: The highest state of charge to reach, as a fraction of the capacity.
+    ///
+    /// # Returns
+    /// The `EnergyFlow` of the operation, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn charge_battery_up_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        max_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        if max_soc >= 1.0 {
+            return self.charge_battery(amount_mw, duration_hours);
+        }
+
+        // Grid power that stores exactly the room left below the band
+        let room = (max_soc * self.capacity).min(self.effective_capacity()) - self.charge;
+        let power_limit =
+            grid_energy_for_stored(room.max(0.0), self.charge_efficiency) / duration_hours;
+        self.charge_battery(amount_mw.min(power_limit), duration_hours)
+    }
+
+    /// Discharges the battery like `discharge_battery`, but no further than a state
+    /// of charge.
+    ///
+    /// The power is reduced so that the charge stops at `min_soc * capacity`, or at
+    /// `min_charge` if that is higher. A battery already at or below `min_soc`
+    /// delivers nothing.
+    ///
+    /// # Parameters
+    /// - `amount_mw`: The amount of power in megawatts (MW) to discharge from the battery.
+    /// - `duration_hours`: The duration for which to discharge the battery, in hours.
+    /// - `min_soc`: The lowest state of charge to reach, as a fraction of the capacity.
+    ///
+    /// # Returns
+    /// The `EnergyFlow` of the operation, wrapped in a `Result`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::NegativePower` if `amount_mw` is negative.
+    pub fn discharge_battery_down_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        min_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        if min_soc * self.capacity <= self.min_charge {
+            return self.discharge_battery(amount_mw, duration_hours);
+        }
+
+        // Power that delivers exactly the energy above the band
+        let available = self.charge - min_soc * self.capacity;
+        let power_limit =
+            delivered_for_drawn(available.max(0.0), self.discharge_efficiency) / duration_hours;
+        self.discharge_battery(amount_mw.min(power_limit), duration_hours)
+    }
+
+    /// Adds the equivalent full cycles for energy moved in or out of the pack.
+    ///
+    /// Each direction counts for half, so fully charging and then fully discharging
+    /// the battery adds one cycle.
+    fn add_cycles(&mut self, energy_mwh: f64) {
+        if self.capacity > 0.0 {
+            self.cycles += energy_mwh / self.capacity / 2.0;
+        }
+    }
+
+    /// Applies passive self-discharge to the battery for an idle period.
+    ///
+    /// The charge is reduced by `self_discharge_rate_per_day`, scaled to the length
+    /// of the period, but never below `min_charge`.
+    ///
+    /// # Parameters
+    /// - `duration_hours`: The length of the idle period, in hours.
+    pub fn apply_self_discharge(&mut self, duration_hours: f64) {
+        let loss = self.charge * self.self_discharge_rate_per_day * duration_hours / 24.0;
+        let floor = self.min_charge.min(self.charge); // Never raise a charge already below the floor
+        self.charge = (self.charge - loss).max(floor);
+
+        debug!(
+            "Self-discharge over {} hours: {} MWh lost, Remaining charge: {} MWh",
+            duration_hours, loss, self.charge
+        );
+    }
+}
+
+impl EnergyStorage for Battery {
+    fn charge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        Battery::charge_battery(self, amount_mw, duration_hours)
+    }
+
+    fn discharge_battery(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        Battery::discharge_battery(self, amount_mw, duration_hours)
+    }
+
+    fn charge_battery_up_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        max_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        Battery::charge_battery_up_to(self, amount_mw, duration_hours, max_soc)
+    }
+
+    fn discharge_battery_down_to(
+        &mut self,
+        amount_mw: f64,
+        duration_hours: f64,
+        min_soc: f64,
+    ) -> Result<EnergyFlow, BatteryError> {
+        Battery::discharge_battery_down_to(self, amount_mw, duration_hours, min_soc)
+    }
+
+    fn apply_self_discharge(&mut self, duration_hours: f64) {
+        Battery::apply_self_discharge(self, duration_hours)
+    }
+
+    fn energy_available(&self) -> f64 {
+        Battery::energy_available(self)
+    }
+
+    fn available_discharge_power(&self, duration_hours: f64) -> f64 {
+        Battery::available_discharge_power(self, duration_hours)
+    }
+
+    fn equivalent_cycles(&self) -> f64 {
+        self.cycles
+    }
+}
+
+/// Builds a `Battery` from named properties, checking that they are consistent.
+///
+/// `capacity`, `initial_charge`, `max_rate` and the efficiencies are required;
+/// `min_charge` and `self_discharge_rate_per_day` default to 0.
+#[derive(Debug, Clone, Default)]
+pub struct BatteryBuilder {
+    capacity: Option<f64>,
+    initial_charge: Option<f64>,
+    max_rate: Option<f64>,
+    max_charge_rate: Option<f64>,
+    max_discharge_rate: Option<f64>,
+    charge_efficiency: Option<f64>,
+    discharge_efficiency: Option<f64>,
+    min_charge: Option<f64>,
+    self_discharge_rate_per_day: Option<f64>,
+}
+
+impl BatteryBuilder {
+    /// Sets the maximum capacity in MWh.
+    pub fn capacity(mut self, capacity: f64) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the charge in MWh the battery starts with.
+    pub fn initial_charge(mut self, initial_charge: f64) -> Self {
+        self.initial_charge = Some(initial_charge);
+        self
+    }
+
+    /// Sets the maximum charging and discharging rate in MW.
+    pub fn max_rate(mut self, max_rate: f64) -> Self {
+        self.max_rate = Some(max_rate);
+        self
+    }
+
+    /// Sets the maximum charging rate in MW, overriding `max_rate` for charging.
+    pub fn max_charge_rate(mut self, max_charge_rate: f64) -> Self {
+        self.max_charge_rate = Some(max_charge_rate);
+        self
+    }
+
+    /// Sets the maximum discharging rate in MW, overriding `max_rate` for discharging.
+    pub fn max_discharge_rate(mut self, max_discharge_rate: f64) -> Self {
+        self.max_discharge_rate = Some(max_discharge_rate);
+        self
+    }
+
+    /// Sets the same efficiency for charging and discharging.
+    pub fn efficiency(self, efficiency: f64) -> Self {
+        self.charge_efficiency(efficiency)
+            .discharge_efficiency(efficiency)
+    }
+
+    /// Sets the fraction of the grid energy that ends up stored when charging.
+    pub fn charge_efficiency(mut self, charge_efficiency: f64) -> Self {
+        self.charge_efficiency = Some(charge_efficiency);
+        self
+    }
+
+    /// Sets the fraction of the stored energy that is delivered when discharging.
+    pub fn discharge_efficiency(mut self, discharge_efficiency: f64) -> Self {
+        self.discharge_efficiency = Some(discharge_efficiency);
+        self
+    }
+
+    /// Sets the reserve charge in MWh that is never discharged.
+    pub fn min_charge(mut self, min_charge: f64) -> Self {
+        self.min_charge = Some(min_charge);
+        self
+    }
+
+    /// Sets the fraction of the charge lost per idle day.
+    pub fn self_discharge_rate_per_day(mut self, self_discharge_rate_per_day: f64) -> Self {
+        self.self_discharge_rate_per_day = Some(self_discharge_rate_per_day);
+        self
+    }
+
+    /// Checks the properties and creates the `Battery`.
+    ///
+    /// # Returns
+    /// A `Result` containing the `Battery`.
+    ///
+    /// # Errors
+    /// Returns `BatteryError::InvalidProperty` naming the first property that is
+    /// missing or out of range:
+    /// - `capacity` must be positive;
+    /// - `initial_charge` and `min_charge` must be between 0 and `capacity`;
+    /// - the rates must be positive;
+    /// - the efficiencies must be greater than 0 and at most 1;
+    /// - `self_discharge_rate_per_day` must be between 0 and 1.
+    pub fn build(self) -> Result<Battery, BatteryError> {
+        let invalid = |property: &'static str, reason: String| BatteryError::InvalidProperty {
+            property,
+            reason,
+        };
+        let required = |property: &'static str, value: Option<f64>| {
+            value.ok_or_else(|| invalid(property, "is required".to_string()))
+        };
+
+        let capacity = required("capacity", self.capacity)?;
+        if !(capacity > 0.0 && capacity.is_finite()) {
+            return Err(invalid(
+                "capacity",
+                format!("must be positive, got {} MWh", capacity),
+            ));
+        }
+
+        for (property, value) in [
+            (
+                "initial_charge",
+                required("initial_charge", self.initial_charge)?,
+            ),
+            ("min_charge", self.min_charge.unwrap_or(0.0)),
+        ] {
+            if !(value >= 0.0 && value <= capacity) {
+                return Err(invalid(
+                    property,
+                    format!(
+                        "must be between 0 and the capacity ({} MWh), got {} MWh",
+                        capacity, value
+                    ),
+                ));
+            }
+        }
+
+        let max_rate = required("max_rate", self.max_rate)?;
+        for (property, value) in [
+            ("max_rate", Some(max_rate)),
+            ("max_charge_rate", self.max_charge_rate),
+            ("max_discharge_rate", self.max_discharge_rate),
+        ] {
+            if let Some(value) = value {
+                if !(value > 0.0 && value.is_finite()) {
+                    return Err(invalid(
+                        property,
+                        format!("must be positive, got {} MW", value),
+                    ));
+                }
+            }
+        }
+
+        for (property, value) in [
+            (
+                "charge_efficiency",
+                required("charge_efficiency", self.charge_efficiency)?,
+            ),
+            (
+                "discharge_efficiency",
+                required("discharge_efficiency", self.discharge_efficiency)?,
+            ),
+        ] {
+            if !(value > 0.0 && value <= 1.0) {
+                return Err(invalid(
+                    property,
+                    format!("must be greater than 0 and at most 1, got {}", value),
+                ));
+            }
+        }
+
+        let self_discharge = self.self_discharge_rate_per_day.unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&self_discharge) {
+            return Err(invalid(
+                "self_discharge_rate_per_day",
+                format!("must be between 0 and 1, got {}", self_discharge),
+            ));
+        }
+
+        Ok(self.assemble())
+    }
+
+    /// Creates the `Battery` without checking the properties; missing ones are 0.
+    fn assemble(self) -> Battery {
+        let initial_charge = self.initial_charge.unwrap_or(0.0);
+        let max_rate = self.max_rate.unwrap_or(0.0);
+        Battery {
+            capacity: self.capacity.unwrap_or(0.0),
+            initial_charge,
+            charge: initial_charge,
+            max_charge_rate: self.max_charge_rate.unwrap_or(max_rate),
+            max_discharge_rate: self.max_discharge_rate.unwrap_or(max_rate),
+            charge_efficiency: self.charge_efficiency.unwrap_or(0.0),
+            discharge_efficiency: self.discharge_efficiency.unwrap_or(0.0),
+            min_charge: self.min_charge.unwrap_or(0.0),
+            self_discharge_rate_per_day: self.self_discharge_rate_per_day.unwrap_or(0.0),
+            cycles: 0.0,
         }
     }
 }
@@ -0,0 +1,121 @@
+use crate::planning::Plan;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Represents the carbon intensity of grid electricity for a specific time interval.
+#[derive(Deserialize, Serialize)]
+pub struct CarbonIntensity {
+    /// Start time of the carbon intensity interval.
+    pub start: DateTime<Utc>,
+
+    /// End time of the carbon intensity interval.
+    pub end: DateTime<Utc>,
+
+    /// Emissions of grid electricity in grams of CO2 per kWh.
+    pub grams_co2_per_kwh: f64,
+}
+
+/// A series of grid carbon intensities.
+#[derive(Deserialize, Serialize)]
+pub struct CarbonIntensities {
+    /// A list of carbon intensities for specific time intervals.
+    pub intensities: Vec<CarbonIntensity>,
+}
+
+/// Loads a grid carbon intensity series from a JSON file.
+///
+/// # Parameters
+/// - `file_path`: The path to the JSON file containing the carbon intensities.
+///
+/// # Returns
+/// A `Result` containing `CarbonIntensities` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, the JSON data is invalid, or an
+/// interval fails validation.
+pub fn load_carbon_intensities(file_path: &str) -> Result<CarbonIntensities> {
+    let data = fs::read_to_string(file_path).context(format!(
+        "Unable to read carbon intensity file: {}",
+        file_path
+    ))?;
+
+    let carbon: CarbonIntensities =
+        serde_json::from_str(&data).context("JSON parsing error in carbon intensities")?;
+
+    for intensity in &carbon.intensities {
+        validate_carbon_intensity(intensity)?;
+    }
+
+    info!("Successfully loaded carbon intensities from {}", file_path);
+
+    Ok(carbon)
+}
+
+/// Looks up the carbon intensity covering the given interval.
+///
+/// # Arguments
+///
+/// * `intensities`: The carbon intensity series.
+/// * `start`: Start time of the interval.
+/// * `end`: End time of the interval.
+///
+/// # Returns
+/// The carbon intensity in gCO2/kWh, or `None` if no entry covers the interval.
+pub fn intensity_at(
+    intensities: &[CarbonIntensity],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<f64> {
+    intensities
+        .iter()
+        .find(|intensity| intensity.start <= start && end <= intensity.end)
+        .map(|intensity| intensity.grams_co2_per_kwh)
+}
+
+/// Calculates the CO2 emissions avoided by discharging the battery instead of
+/// importing from the grid.
+///
+/// Each interval's discharged energy is valued at the grid's carbon intensity for
+/// that interval, so discharging during high-carbon intervals avoids the most
+/// emissions. Intervals without a carbon intensity are skipped.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `intensities`: The carbon intensity series.
+///
+/// # Returns
+/// The avoided emissions in kilograms of CO2.
+pub fn co2_avoided_kg(plan: &[Plan], intensities: &[CarbonIntensity]) -> f64 {
+    plan.iter()
+        .filter_map(|interval| {
+            intensity_at(intensities, interval.start, interval.end).map(|grams_per_kwh| {
+                let discharged_kwh = interval.energy_from_battery_wh / 1_000.0;
+                discharged_kwh * grams_per_kwh / 1_000.0
+            })
+        })
+        .sum()
+}
+
+/// Validates a carbon intensity entry.
+///
+/// # Arguments
+///
+/// * `intensity`: A reference to the `CarbonIntensity` to validate.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the validation.
+fn validate_carbon_intensity(intensity: &CarbonIntensity) -> Result<()> {
+    if intensity.grams_co2_per_kwh < 0.0 {
+        return Err(anyhow!("Carbon intensity must be non-negative."));
+    }
+    if intensity.start >= intensity.end {
+        return Err(anyhow!(
+            "Carbon intensity start time must be before end time."
+        ));
+    }
+    Ok(())
+}
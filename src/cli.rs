@@ -0,0 +1,73 @@
+use crate::logging::LogFormat;
+use crate::planning::OutputFormat;
+use clap::Parser;
+
+/// Command-line arguments of the battery planner.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Plans battery charging and discharging from forecasts and prices"
+)]
+pub struct Cli {
+    /// Path to the configuration file.
+    #[arg(long, default_value = "config.toml")]
+    pub config: String,
+
+    /// Path to the consumption forecasts (JSON or CSV), or `-` for JSON on standard input.
+    #[arg(long, default_value = "forecasts.json")]
+    pub forecasts: String,
+
+    /// Path to the day-ahead prices, or `-` for standard input.
+    #[arg(long, default_value = "day-ahead.json")]
+    pub prices: String,
+
+    /// Path the plan is written to (JSON, CSV or NDJSON).
+    #[arg(long, default_value = "output_plan.json")]
+    pub output: String,
+
+    /// Format of the plan file; by default it follows the extension of `--output`.
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
+
+    /// Format of the log output.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Leaves the per-interval decisions out of the log.
+    #[arg(long)]
+    pub quiet: bool,
+
+    /// Adds the reason for each interval's decision to the saved plan.
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Charge in MWh to start planning from, instead of `initial_charge` in the configuration.
+    #[arg(long)]
+    pub start_charge: Option<f64>,
+
+    /// Path of a JSON file to also write the run's status, errors, warnings, plan,
+    /// economics and summary to.
+    #[arg(long)]
+    pub result_json: Option<String>,
+
+    /// Only check that the configuration, forecasts and prices are consistent, without planning.
+    #[arg(long)]
+    pub validate_only: bool,
+
+    /// Runs a mode other than planning the input files once.
+    #[cfg(feature = "server")]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The subcommands of the battery planner.
+#[cfg(feature = "server")]
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Serves plans over HTTP: `POST /plan` with `{config, forecasts, prices}`.
+    Serve {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        addr: String,
+    },
+}
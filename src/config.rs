@@ -1,27 +1,672 @@
-use anyhow::{Context, Result};
+use crate::inverter::InverterLimits;
+use crate::prices::{ElectricityPrice, DEFAULT_INTERVAL_MINUTES};
+use crate::units::EnergyUnit;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveTime;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub settings: Settings,
+    /// Maps field names used by external data providers to the names this crate expects.
+    #[serde(default)]
+    pub field_mapping: HashMap<String, String>,
+    /// Inverter constraints the plan must satisfy before it is saved.
+    pub inverter: Option<InverterLimits>,
 }
 
-#[derive(Debug, Deserialize)]
+/// The objective the planner uses to decide when to charge and discharge.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Charge when electricity is cheaper than average.
+    #[default]
+    Price,
+    /// Charge when grid electricity is cleaner than average and discharge when it is dirtier.
+    Carbon,
+}
+
+/// The consumption forecast the planner compares with the grid limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanningQuantile {
+    /// The mean forecast, `consumption_average_power_interval`.
+    #[default]
+    Mean,
+    /// The 90th percentile forecast, `consumption_p90`, where the forecast has one.
+    P90,
+}
+
+/// What the optimal planner minimizes over the horizon.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    /// The cost of the energy imported from the grid.
+    #[default]
+    MinCost,
+    /// The energy imported from the grid, whatever its price.
+    MaxSelfSufficiency,
+    /// The energy moved through the battery, to spare it wear.
+    MinCycles,
+}
+
+/// Battery chemistries whose preset fills in the settings they determine.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Chemistry {
+    /// Lithium iron phosphate.
+    #[serde(rename = "lifepo4", alias = "LiFePO4")]
+    LiFePo4,
+    /// Lithium nickel manganese cobalt oxide.
+    #[serde(rename = "nmc", alias = "NMC")]
+    Nmc,
+    /// Lead-acid, which wears quickly when discharged below half its capacity.
+    #[serde(rename = "lead_acid", alias = "LeadAcid")]
+    LeadAcid,
+}
+
+/// The settings a `Chemistry` preset fills in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChemistryPreset {
+    /// Efficiency applied to both charging and discharging.
+    pub efficiency: f64,
+    /// Lowest recommended state of charge.
+    pub min_soc: f64,
+    /// Highest recommended state of charge.
+    pub max_soc: f64,
+    /// Fraction of the charge lost per idle day.
+    pub self_discharge_rate_per_day: f64,
+}
+
+impl Chemistry {
+    /// Returns the typical efficiency, recommended state of charge band and
+    /// self-discharge rate of the chemistry.
+    pub fn preset(self) -> ChemistryPreset {
+        let (efficiency, min_soc, max_soc, self_discharge_rate_per_day) = match self {
+            Chemistry::LiFePo4 => (0.95, 0.1, 0.95, 0.001),
+            Chemistry::Nmc => (0.95, 0.1, 0.9, 0.0007),
+            Chemistry::LeadAcid => (0.85, 0.5, 1.0, 0.0017),
+        };
+        ChemistryPreset {
+            efficiency,
+            min_soc,
+            max_soc,
+            self_discharge_rate_per_day,
+        }
+    }
+}
+
+/// When the price planner charges the battery from the grid.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeStrategy {
+    /// Charge at or below the average import price, or below `charge_percentile` if set.
+    #[default]
+    BelowAverage,
+    /// Charge at or below this percentile of the import prices, e.g. 0.3.
+    BelowPercentile(f64),
+    /// Charge only during these clock windows (UTC), whatever the price, for fixed
+    /// tariffs. A window whose end is not after its start wraps past midnight.
+    FixedWindows(Vec<(NaiveTime, NaiveTime)>),
+}
+
+impl ChargeStrategy {
+    /// Returns whether the clock time falls in one of the `FixedWindows`, or `None`
+    /// for the price-based strategies.
+    pub fn in_window(&self, time: NaiveTime) -> Option<bool> {
+        match self {
+            ChargeStrategy::FixedWindows(windows) => Some(windows.iter().any(|&(start, end)| {
+                if start < end {
+                    start <= time && time < end
+                } else {
+                    start <= time || time < end
+                }
+            })),
+            ChargeStrategy::BelowAverage | ChargeStrategy::BelowPercentile(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Settings {
     pub capacity: f64,
     pub initial_charge: f64,
     pub max_rate: f64,
-    pub efficiency: f64,
+    /// Maximum charging rate in MW, unless the same as `max_rate`.
+    pub max_charge_rate: Option<f64>,
+    /// Maximum discharging rate in MW, unless the same as `max_rate`.
+    pub max_discharge_rate: Option<f64>,
+    /// Power used when the planner charges the battery, in MW.
+    #[serde(default = "default_charge_power_mw")]
+    pub charge_power_mw: f64,
+    /// Efficiency applied to both charging and discharging, unless overridden below.
+    pub efficiency: Option<f64>,
+    /// Fraction of the drawn energy stored when charging; defaults to `efficiency`.
+    pub charge_efficiency: Option<f64>,
+    /// Fraction of the drawn energy delivered when discharging; defaults to `efficiency`.
+    pub discharge_efficiency: Option<f64>,
+    /// Contractual limit of the grid import in W, the unit of the forecasts.
     pub grid_limit: f64,
+    /// Physical limit of the grid connection's import power, in the unit of `grid_limit`;
+    /// the planner charges from the grid only within the power left under it.
+    #[serde(default = "default_grid_import_limit")]
+    pub grid_import_limit: f64,
+    /// Physical limit of the grid connection's export power, in the unit of `grid_limit`;
+    /// surplus production above it is curtailed, and the battery exports only within it.
+    #[serde(default = "default_grid_export_limit")]
+    pub grid_export_limit: f64,
+    /// Consumption forecast used to decide when to shave peaks.
+    #[serde(default)]
+    pub planning_quantile: PlanningQuantile,
+    /// What the optimal planner minimizes; the grid limit is respected whatever it is.
+    #[serde(default)]
+    pub objective: Objective,
+    /// Soft limit the planner shaves consumption down to, below `grid_limit`.
+    pub peak_shave_target: Option<f64>,
+    /// Reserve charge in MWh the battery is never discharged below.
+    #[serde(default)]
+    pub min_charge: f64,
+    /// Lowest state of charge routine discharging goes down to; peak shaving may go below.
+    #[serde(default)]
+    pub min_soc: f64,
+    /// Highest state of charge routine charging goes up to.
+    #[serde(default = "default_max_soc")]
+    pub max_soc: f64,
+    /// Fraction of the stored energy lost per day while the battery idles.
+    #[serde(default)]
+    pub self_discharge_rate_per_day: f64,
+    /// Path to a JSON file with mandatory EV charging windows, added to the forecast load in W.
+    pub ev_schedule: Option<String>,
+    /// Path to intraday prices, in the day-ahead format, that override the day-ahead price
+    /// of the same interval.
+    pub intraday_prices: Option<String>,
+    /// Path to a JSON price profile pricing the intervals the day-ahead prices do not cover.
+    pub price_profile: Option<String>,
+    /// Ambient temperature in °C below which the battery is not discharged; no limit if unset.
+    pub min_discharge_temp_c: Option<f64>,
+    /// Price at or above which stored energy is exported to the grid; no export if unset.
+    pub sell_threshold_per_kwh: Option<f64>,
+    /// Dead band around the charge threshold, in EUR/kWh: charging needs a price at least
+    /// this far below it and exporting a price at least this far above it.
+    #[serde(default)]
+    pub charge_price_margin: f64,
+    /// Fraction of the market price paid for exported energy.
+    #[serde(default = "default_feed_in_factor")]
+    pub feed_in_factor: f64,
+    /// Fixed grid fee added to the market price of every imported kWh.
+    #[serde(default)]
+    pub grid_fee_per_kwh: f64,
+    /// Tax rate applied to the market price plus grid fee, e.g. 0.21 for 21 %.
+    #[serde(default)]
+    pub tax_rate: f64,
+    /// Currency all prices are converted to before averaging and planning.
+    #[serde(default = "default_reporting_currency")]
+    pub reporting_currency: String,
+    /// Static exchange rates into `reporting_currency`, keyed by source currency.
+    #[serde(default)]
+    pub exchange_rates: HashMap<String, f64>,
+    /// Maximum equivalent full cycles per calendar day before discretionary dispatch stops.
+    pub max_daily_cycles: Option<f64>,
+    /// Charge only in this cheapest fraction of intervals instead of below the average price.
+    pub charge_percentile: Option<f64>,
+    /// When to charge from the grid: below the average price, below a percentile, or
+    /// in fixed clock windows.
+    #[serde(default)]
+    pub charge_strategy: ChargeStrategy,
+    /// Charge only when a later interval can use the energy profitably.
+    #[serde(default)]
+    pub skip_unprofitable_charge: bool,
+    /// Record in each planned interval why the planner decided as it did; set with `--explain`.
+    #[serde(skip)]
+    pub explain: bool,
+    /// Record in each planned interval how much grid charging was curtailed by a full battery.
+    #[serde(default)]
+    pub record_curtailment: bool,
+    /// Write one plan file per day plus a summary instead of a single plan, next to
+    /// `--output` or into the directory it names.
+    #[serde(default)]
+    pub split_plan_by_day: bool,
+    /// Length of the planning intervals in minutes; must divide an hour evenly.
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: i64,
+    /// Grid in minutes the input timestamps are snapped to when loaded, for feeds whose
+    /// timestamps are off by a few seconds; not snapped if unset.
+    pub snap_timestamps_minutes: Option<i64>,
+    /// Unit of the energy fields in the saved plan.
+    #[serde(default)]
+    pub plan_energy_unit: EnergyUnit,
+    /// Decimals the saved plan's energies in Wh are rounded to, half to even; unrounded if unset.
+    pub output_decimals: Option<u32>,
+    /// Path to grid carbon intensities in gCO2/kWh per interval, for reporting avoided CO2
+    /// and carbon-driven dispatch.
+    pub carbon_intensity: Option<String>,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Chemistry whose preset filled in the settings this configuration leaves out.
+    pub chemistry: Option<Chemistry>,
+}
+
+/// Charge power used when the configuration does not set one, in MW.
+fn default_charge_power_mw() -> f64 {
+    1.5
+}
+
+/// Planning interval length used when the configuration does not set one, in minutes.
+fn default_interval_minutes() -> i64 {
+    DEFAULT_INTERVAL_MINUTES
+}
+
+/// Import limit used when the configuration does not set one: unlimited.
+fn default_grid_import_limit() -> f64 {
+    f64::INFINITY
+}
+
+/// Export limit used when the configuration does not set one: unlimited.
+fn default_grid_export_limit() -> f64 {
+    f64::INFINITY
+}
+
+/// Upper end of the operating band used when the configuration does not set one.
+fn default_max_soc() -> f64 {
+    1.0
+}
+
+/// Feed-in factor used when the configuration does not set one.
+fn default_feed_in_factor() -> f64 {
+    1.0
+}
+
+/// Reporting currency used when the configuration does not set one.
+fn default_reporting_currency() -> String {
+    "EUR".to_string()
+}
+
+impl Settings {
+    /// Returns the charging efficiency, falling back to the shared `efficiency`.
+    pub fn charge_efficiency(&self) -> f64 {
+        self.charge_efficiency.or(self.efficiency).unwrap_or(1.0)
+    }
+
+    /// Returns the discharging efficiency, falling back to the shared `efficiency`.
+    pub fn discharge_efficiency(&self) -> f64 {
+        self.discharge_efficiency.or(self.efficiency).unwrap_or(1.0)
+    }
+
+    /// Returns the maximum charging rate, falling back to the shared `max_rate`.
+    pub fn max_charge_rate(&self) -> f64 {
+        self.max_charge_rate.unwrap_or(self.max_rate)
+    }
+
+    /// Returns the maximum discharging rate, falling back to the shared `max_rate`.
+    pub fn max_discharge_rate(&self) -> f64 {
+        self.max_discharge_rate.unwrap_or(self.max_rate)
+    }
+
+    /// Returns the consumption the planner shaves peaks down to: the peak-shaving
+    /// target if set, but never more than the grid limit.
+    pub fn shave_limit(&self) -> f64 {
+        self.peak_shave_target
+            .map_or(self.grid_limit, |target| target.min(self.grid_limit))
+    }
+
+    /// Returns the power left for charging from the grid under `grid_import_limit`,
+    /// on top of the given net consumption, in W like both; never negative.
+    pub fn import_headroom(&self, net_consumption: f64) -> f64 {
+        (self.grid_import_limit - net_consumption).max(0.0)
+    }
+
+    /// Returns the power left for exporting to the grid under `grid_export_limit`,
+    /// given the net consumption, which absorbs part of the export when positive and
+    /// takes up the limit with surplus production when negative; in W, never negative.
+    pub fn export_headroom(&self, net_consumption: f64) -> f64 {
+        (self.grid_export_limit + net_consumption).max(0.0)
+    }
+
+    /// Returns the price actually paid per imported kWh in the given interval,
+    /// grid fee and tax included.
+    pub fn import_price(&self, price: &ElectricityPrice) -> f64 {
+        price.effective_price(self.grid_fee_per_kwh, self.tax_rate)
+    }
+
+    /// Returns the price received per exported kWh in the given interval, the market
+    /// price times `feed_in_factor`.
+    pub fn feed_in_price(&self, price: &ElectricityPrice) -> f64 {
+        price.market_price_per_kwh * self.feed_in_factor
+    }
 }
 
+impl Config {
+    /// Fills in the settings of the `chemistry` preset, if any, that the configuration
+    /// does not set itself; see `Chemistry::preset`.
+    ///
+    /// # Parameters
+    /// - `raw`: The configuration as parsed, before serde defaults, to tell which
+    ///   settings were set explicitly.
+    pub fn apply_chemistry(&mut self, raw: &Value) {
+        let Some(chemistry) = self.settings.chemistry else {
+            return;
+        };
+        let explicit = |setting: &str| {
+            raw.get("settings")
+                .and_then(|settings| settings.get(setting))
+                .is_some()
+        };
+
+        let preset = chemistry.preset();
+        let settings = &mut self.settings;
+        if !explicit("efficiency") {
+            settings.efficiency = Some(preset.efficiency);
+        }
+        if !explicit("min_soc") {
+            settings.min_soc = preset.min_soc;
+        }
+        if !explicit("max_soc") {
+            settings.max_soc = preset.max_soc;
+        }
+        if !explicit("self_discharge_rate_per_day") {
+            settings.self_discharge_rate_per_day = preset.self_discharge_rate_per_day;
+        }
+    }
+
+    /// Checks that the settings describe a physically meaningful battery.
+    ///
+    /// # Returns
+    /// A `Result` indicating success, or an error naming the first invalid field.
+    pub fn validate(&self) -> Result<()> {
+        let settings = &self.settings;
+
+        for (field, value) in [
+            ("efficiency", settings.efficiency),
+            ("charge_efficiency", settings.charge_efficiency),
+            ("discharge_efficiency", settings.discharge_efficiency),
+        ] {
+            if let Some(value) = value {
+                if !(value > 0.0 && value <= 1.0) {
+                    return Err(anyhow!(
+                        "`{}` must be greater than 0 and at most 1, got {}",
+                        field,
+                        value
+                    ));
+                }
+            }
+        }
+
+        if !(settings.capacity > 0.0 && settings.capacity.is_finite()) {
+            return Err(anyhow!(
+                "`capacity` must be positive, got {} MWh",
+                settings.capacity
+            ));
+        }
+
+        if !(settings.initial_charge >= 0.0 && settings.initial_charge <= settings.capacity) {
+            return Err(anyhow!(
+                "`initial_charge` must be between 0 and `capacity` ({} MWh), got {} MWh",
+                settings.capacity,
+                settings.initial_charge
+            ));
+        }
+
+        for (field, value) in [
+            ("max_rate", Some(settings.max_rate)),
+            ("max_charge_rate", settings.max_charge_rate),
+            ("max_discharge_rate", settings.max_discharge_rate),
+        ] {
+            if let Some(value) = value {
+                if !(value > 0.0 && value.is_finite()) {
+                    return Err(anyhow!("`{}` must be positive, got {} MW", field, value));
+                }
+            }
+        }
+
+        if !(0.0 <= settings.min_soc
+            && settings.min_soc < settings.max_soc
+            && settings.max_soc <= 1.0)
+        {
+            return Err(anyhow!(
+                "`min_soc` and `max_soc` must satisfy 0 <= min_soc < max_soc <= 1, got {} and {}",
+                settings.min_soc,
+                settings.max_soc
+            ));
+        }
+
+        if settings.grid_limit < 0.0 || settings.grid_limit.is_nan() {
+            return Err(anyhow!(
+                "`grid_limit` must be non-negative, got {}",
+                settings.grid_limit
+            ));
+        }
+
+        if settings.grid_import_limit < 0.0 || settings.grid_import_limit.is_nan() {
+            return Err(anyhow!(
+                "`grid_import_limit` must be non-negative, got {}",
+                settings.grid_import_limit
+            ));
+        }
+
+        if settings.grid_export_limit < 0.0 || settings.grid_export_limit.is_nan() {
+            return Err(anyhow!(
+                "`grid_export_limit` must be non-negative, got {}",
+                settings.grid_export_limit
+            ));
+        }
+
+        if let Some(target) = settings.peak_shave_target {
+            if target < 0.0 || !target.is_finite() {
+                return Err(anyhow!(
+                    "`peak_shave_target` must be a finite, non-negative power, got {}",
+                    target
+                ));
+            }
+        }
+
+        // Either a shared efficiency or both directional efficiencies must be given
+        if settings.efficiency.is_none()
+            && (settings.charge_efficiency.is_none() || settings.discharge_efficiency.is_none())
+        {
+            return Err(anyhow!(
+                "Configuration must set `efficiency` or both `charge_efficiency` and `discharge_efficiency`"
+            ));
+        }
+
+        if !(settings.charge_power_mw > 0.0
+            && settings.charge_power_mw <= settings.max_charge_rate())
+        {
+            return Err(anyhow!(
+                "`charge_power_mw` must be positive and not greater than the charging rate ({} MW), got {} MW",
+                settings.max_charge_rate(),
+                settings.charge_power_mw
+            ));
+        }
+
+        if let Some(snap_minutes) = settings.snap_timestamps_minutes {
+            if snap_minutes <= 0 || 60 % snap_minutes != 0 {
+                return Err(anyhow!(
+                    "`snap_timestamps_minutes` must divide 60 evenly, e.g. 1 or 15, got {}",
+                    snap_minutes
+                ));
+            }
+        }
+
+        if settings.interval_minutes <= 0 || 60 % settings.interval_minutes != 0 {
+            return Err(anyhow!(
+                "`interval_minutes` must divide 60 evenly, e.g. 5, 15 or 30, got {}",
+                settings.interval_minutes
+            ));
+        }
+
+        if let Some(percentile) = settings.charge_percentile {
+            if !(percentile > 0.0 && percentile <= 1.0) {
+                return Err(anyhow!(
+                    "`charge_percentile` must be greater than 0 and at most 1, got {}",
+                    percentile
+                ));
+            }
+        }
+
+        match &settings.charge_strategy {
+            ChargeStrategy::BelowAverage => {}
+            _ if settings.charge_percentile.is_some() => {
+                return Err(anyhow!(
+                    "`charge_percentile` only applies to the below_average `charge_strategy`"
+                ));
+            }
+            ChargeStrategy::BelowPercentile(percentile) => {
+                if !(*percentile > 0.0 && *percentile <= 1.0) {
+                    return Err(anyhow!(
+                        "The below_percentile `charge_strategy` must be greater than 0 and at most 1, got {}",
+                        percentile
+                    ));
+                }
+            }
+            ChargeStrategy::FixedWindows(windows) => {
+                if windows.is_empty() {
+                    return Err(anyhow!(
+                        "The fixed_windows `charge_strategy` needs at least one window"
+                    ));
+                }
+                if let Some((start, _)) = windows.iter().find(|(start, end)| start == end) {
+                    return Err(anyhow!(
+                        "Window of the fixed_windows `charge_strategy` starting at {} must end at a different time",
+                        start
+                    ));
+                }
+            }
+        }
+
+        if let Some(max_cycles) = settings.max_daily_cycles {
+            if !(max_cycles > 0.0 && max_cycles.is_finite()) {
+                return Err(anyhow!(
+                    "`max_daily_cycles` must be positive, got {}",
+                    max_cycles
+                ));
+            }
+        }
+
+        if !(settings.charge_price_margin >= 0.0 && settings.charge_price_margin.is_finite()) {
+            return Err(anyhow!(
+                "`charge_price_margin` must be non-negative, got {}",
+                settings.charge_price_margin
+            ));
+        }
+
+        if !(settings.feed_in_factor >= 0.0 && settings.feed_in_factor.is_finite()) {
+            return Err(anyhow!(
+                "`feed_in_factor` must be non-negative, got {}",
+                settings.feed_in_factor
+            ));
+        }
+
+        for (field, value) in [
+            ("grid_fee_per_kwh", settings.grid_fee_per_kwh),
+            ("tax_rate", settings.tax_rate),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(anyhow!("`{}` must be non-negative, got {}", field, value));
+            }
+        }
+
+        if settings.reporting_currency.trim().is_empty() {
+            return Err(anyhow!("`reporting_currency` must not be empty"));
+        }
+        for (currency, rate) in &settings.exchange_rates {
+            if !rate.is_finite() || *rate <= 0.0 {
+                return Err(anyhow!(
+                    "`exchange_rates.{}` must be a positive number, got {}",
+                    currency,
+                    rate
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads and validates the configuration from a TOML or YAML file.
+///
+/// The format is chosen by the file extension: `.toml`, or `.yaml`/`.yml`. A
+/// `chemistry` preset fills in the settings the file leaves out; see
+/// `Config::apply_chemistry`.
+///
+/// # Parameters
+/// - `file_path`: The path to the configuration file.
+///
+/// # Returns
+/// A `Result` containing the `Config` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the extension is not supported, the file cannot be read or
+/// parsed, or the settings fail validation.
 pub fn load_config(file_path: &str) -> Result<Config> {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
     let data = fs::read_to_string(file_path)
         .with_context(|| format!("Failed to read configuration file: {}", file_path))?;
 
-    let config: Config =
-        toml::de::from_str(&data).with_context(|| "Failed to parse configuration file")?;
+    let mut config: Config = parse_config_data(&data, extension.as_deref(), file_path)?;
+    if config.settings.chemistry.is_some() {
+        let raw: Value = parse_config_data(&data, extension.as_deref(), file_path)?;
+        config.apply_chemistry(&raw);
+    }
+
+    config.validate()?;
 
     Ok(config)
 }
+
+/// Parses configuration data in the format of its file extension.
+fn parse_config_data<T: DeserializeOwned>(
+    data: &str,
+    extension: Option<&str>,
+    file_path: &str,
+) -> Result<T> {
+    match extension {
+        Some("toml") => {
+            toml::de::from_str(data).with_context(|| "Failed to parse configuration file")
+        }
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(data).with_context(|| "Failed to parse configuration file")
+        }
+        _ => Err(anyhow!(
+            "Unsupported configuration file format: {} (expected .toml, .yaml or .yml)",
+            file_path
+        )),
+    }
+}
+
+/// Renames fields in parsed JSON data according to a field mapping.
+///
+/// Every object key found in `mapping` is replaced by its mapped name, at any depth,
+/// so provider-specific input files can be deserialized into the crate's types.
+///
+/// # Arguments
+///
+/// * `value`: The JSON value to rewrite in place.
+/// * `mapping`: A map from external field names to the expected field names.
+pub fn apply_field_mapping(value: &mut Value, mapping: &HashMap<String, String>) {
+    match value {
+        Value::Object(object) => {
+            for (external, expected) in mapping {
+                if let Some(field) = object.remove(external) {
+                    object.insert(expected.clone(), field);
+                }
+            }
+            for field in object.values_mut() {
+                apply_field_mapping(field, mapping);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                apply_field_mapping(item, mapping);
+            }
+        }
+        _ => {}
+    }
+}
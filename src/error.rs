@@ -0,0 +1,82 @@
+use thiserror::Error;
+
+/// Errors returned by battery operations.
+#[derive(Debug, Error)]
+pub enum BatteryError {
+    /// A charge or discharge was requested with a negative power.
+    #[error("Attempted to {operation} with a negative power: {power_mw}")]
+    NegativePower {
+        /// The requested operation, "charge" or "discharge".
+        operation: &'static str,
+        /// The requested power in MW.
+        power_mw: f64,
+    },
+
+    /// A target state of charge outside `[0, 1]` was requested.
+    #[error("Target state of charge must be between 0 and 1, got {target_soc}")]
+    InvalidTargetSoc {
+        /// The requested state of charge.
+        target_soc: f64,
+    },
+
+    /// A charge target below the current state of charge was requested.
+    #[error("Target state of charge {target_soc} is below the current state of charge {state_of_charge}")]
+    TargetBelowCharge {
+        /// The requested state of charge.
+        target_soc: f64,
+        /// The battery's state of charge.
+        state_of_charge: f64,
+    },
+
+    /// A battery was built with a missing or out-of-range property.
+    #[error("Invalid battery `{property}`: {reason}")]
+    InvalidProperty {
+        /// The name of the property, e.g. "capacity".
+        property: &'static str,
+        /// Why the value was rejected.
+        reason: String,
+    },
+}
+
+/// Errors returned when loading or converting forecasts and prices.
+#[derive(Debug, Error)]
+pub enum LoadError {
+    /// The input could not be read.
+    #[error("{context}")]
+    Io {
+        /// What was being read, e.g. the file path.
+        context: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The input is not valid JSON or does not have the expected fields.
+    #[error("{context}")]
+    Json {
+        /// What was being parsed.
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The input is not valid CSV or does not have the expected columns.
+    #[error("{context}")]
+    Csv {
+        /// What was being parsed, including the row if known.
+        context: String,
+        #[source]
+        source: csv::Error,
+    },
+
+    /// The input is valid JSON but lacks a required field or has one of the wrong type.
+    #[error("Schema error: {0}")]
+    Schema(String),
+
+    /// The input was parsed but its values are inconsistent or out of range.
+    #[error("{0}")]
+    Validation(String),
+
+    /// The input was parsed but has no intervals, e.g. `{"prices": []}`.
+    #[error("No {0} in the input")]
+    Empty(&'static str),
+}
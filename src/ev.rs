@@ -0,0 +1,144 @@
+use crate::forecast::Forecast;
+use crate::prices::{rank_by_price, ElectricityPrice};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// A window during which an electric vehicle must receive a given amount of energy.
+#[derive(Deserialize, Serialize)]
+pub struct EvWindow {
+    /// Earliest time the vehicle can start charging.
+    pub start: DateTime<Utc>,
+
+    /// Time by which the vehicle must be charged.
+    pub end: DateTime<Utc>,
+
+    /// Energy the vehicle needs within the window, in watt-hours.
+    pub energy_required_wh: f64,
+
+    /// Maximum power the charger can draw, in watts.
+    pub max_power_w: f64,
+}
+
+/// A schedule of mandatory EV charging windows.
+#[derive(Deserialize, Serialize)]
+pub struct EvSchedule {
+    /// A list of charging windows.
+    pub windows: Vec<EvWindow>,
+}
+
+/// Loads an EV charging schedule from a JSON file.
+///
+/// # Parameters
+/// - `file_path`: The path to the JSON file containing the schedule.
+///
+/// # Returns
+/// A `Result` containing the `EvSchedule` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, the JSON data is invalid,
+/// or a window fails validation.
+pub fn load_ev_schedule(file_path: &str) -> Result<EvSchedule> {
+    let data = fs::read_to_string(file_path)
+        .context(format!("Unable to read EV schedule file: {}", file_path))?;
+
+    let schedule: EvSchedule =
+        serde_json::from_str(&data).context("JSON parsing error in EV schedule")?;
+
+    for window in &schedule.windows {
+        validate_ev_window(window)?;
+    }
+
+    info!("Successfully loaded EV schedule from {}", file_path);
+
+    Ok(schedule)
+}
+
+/// Adds the EV charging load to the forecasted consumption.
+///
+/// The energy required by each window is placed in the cheapest intervals that fall
+/// entirely inside the window, limited by the charger's maximum power. Ties in price
+/// are broken by start time, so the earliest interval wins. The planner then serves the
+/// extra load from the grid, or from the battery if it pushes consumption over the grid
+/// limit.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts to add the EV load to, aligned with `prices`.
+/// * `prices`: The electricity prices for each forecast interval.
+/// * `schedule`: The EV schedule to apply.
+///
+/// # Returns
+/// A `Result` indicating success, or an error if a window's energy cannot be
+/// delivered within its intervals.
+pub fn apply_ev_schedule(
+    forecasts: &mut [Forecast],
+    prices: &[ElectricityPrice],
+    schedule: &EvSchedule,
+) -> Result<()> {
+    for window in &schedule.windows {
+        // Rank the intervals inside the window, cheapest and then earliest first
+        let candidates: Vec<usize> = rank_by_price(prices)
+            .into_iter()
+            .filter(|index| {
+                forecasts.get(*index).is_some_and(|forecast| {
+                    forecast.start >= window.start && forecast.end <= window.end
+                })
+            })
+            .collect();
+
+        let mut remaining_wh = window.energy_required_wh;
+        for index in candidates {
+            if remaining_wh <= 0.0 {
+                break;
+            }
+
+            let forecast = &mut forecasts[index];
+            let duration_hours = (forecast.end - forecast.start).num_seconds() as f64 / 3600.0;
+            let energy_wh = remaining_wh.min(window.max_power_w * duration_hours);
+
+            // Energy in Wh over hours is the charger's power in W, the unit of the forecast
+            forecast.consumption_average_power_interval += energy_wh / duration_hours;
+            remaining_wh -= energy_wh;
+
+            info!(
+                "Scheduled EV charging: {} Wh at {} (Price: {} EUR/kWh)",
+                energy_wh, forecast.start, prices[index].market_price_per_kwh
+            );
+        }
+
+        if remaining_wh > 0.0 {
+            return Err(anyhow!(
+                "EV window {} - {} is short of {} Wh",
+                window.start,
+                window.end,
+                remaining_wh
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates an EV charging window.
+///
+/// # Arguments
+///
+/// * `window`: A reference to the `EvWindow` to validate.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the validation.
+fn validate_ev_window(window: &EvWindow) -> Result<()> {
+    if window.energy_required_wh < 0.0 {
+        return Err(anyhow!("EV energy required must be non-negative."));
+    }
+    if window.max_power_w <= 0.0 {
+        return Err(anyhow!("EV charger power must be positive."));
+    }
+    if window.start >= window.end {
+        return Err(anyhow!("EV window start time must be before end time."));
+    }
+    Ok(())
+}
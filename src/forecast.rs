@@ -1,11 +1,20 @@
-use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Utc};
-use log::info;
+use crate::config::{apply_field_mapping, PlanningQuantile};
+use crate::error::LoadError;
+use crate::prices::SNAP_WARN_SECONDS;
+use crate::schema::{validate_schema, FORECAST_FIELDS};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::io::Read;
+use std::path::Path;
 
 /// Represents a single forecast for energy consumption.
-#[derive(Deserialize, Serialize)]
+///
+/// Powers are in W, like `grid_limit`; use `units::w_to_mw` to compare them with the
+/// battery's rates in MW.
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Forecast {
     /// Start time of the forecast period.
     pub start: DateTime<Utc>,
@@ -13,8 +22,87 @@ pub struct Forecast {
     /// End time of the forecast period.
     pub end: DateTime<Utc>,
 
-    /// Average power consumption during the forecast period in MW.
+    /// Average power consumption during the forecast period in W.
     pub consumption_average_power_interval: f64,
+
+    /// 90th percentile of the average power consumption during the period in W, if
+    /// the forecast has an uncertainty band.
+    #[serde(default)]
+    pub consumption_p90: Option<f64>,
+
+    /// Average on-site production (e.g. PV) during the forecast period in W, if any.
+    #[serde(default)]
+    pub production_average_power_interval: Option<f64>,
+
+    /// Forecasted ambient temperature during the period in degrees Celsius, if known.
+    #[serde(default)]
+    pub temperature_c: Option<f64>,
+}
+
+impl Forecast {
+    /// Returns the consumption left after subtracting on-site production, in W.
+    ///
+    /// The result is negative when production exceeds consumption and the site exports.
+    pub fn net_consumption(&self) -> f64 {
+        self.consumption_average_power_interval
+            - self.production_average_power_interval.unwrap_or(0.0)
+    }
+
+    /// Returns the consumption left after subtracting on-site production, taking the
+    /// consumption at the given quantile.
+    ///
+    /// Forecasts without a P90 consumption fall back to the mean.
+    pub fn net_consumption_at(&self, quantile: PlanningQuantile) -> f64 {
+        let consumption = match quantile {
+            PlanningQuantile::Mean => self.consumption_average_power_interval,
+            PlanningQuantile::P90 => self
+                .consumption_p90
+                .unwrap_or(self.consumption_average_power_interval),
+        };
+        consumption - self.production_average_power_interval.unwrap_or(0.0)
+    }
+}
+
+/// Rounds a timestamp to the nearest boundary of a grid of `interval_minutes`, counted
+/// from midnight UTC; timestamps halfway between two boundaries round up.
+///
+/// # Arguments
+///
+/// * `dt`: The timestamp to round.
+/// * `interval_minutes`: The length of the grid's steps in minutes.
+///
+/// # Returns
+/// The nearest boundary, or `dt` unchanged if `interval_minutes` is not positive.
+pub fn snap_to_grid(dt: DateTime<Utc>, interval_minutes: i64) -> DateTime<Utc> {
+    if interval_minutes <= 0 {
+        return dt;
+    }
+    dt.duration_round(Duration::minutes(interval_minutes))
+        .unwrap_or(dt)
+}
+
+/// Snaps the start and end of every forecast to the nearest boundary of a grid;
+/// see `snap_to_grid` and `prices::snap_prices`.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts to snap, in place.
+/// * `interval_minutes`: The length of the grid's steps in minutes.
+pub fn snap_forecasts(forecasts: &mut [Forecast], interval_minutes: i64) {
+    for forecast in forecasts {
+        for time in [&mut forecast.start, &mut forecast.end] {
+            let snapped = snap_to_grid(*time, interval_minutes);
+            if (snapped - *time).num_seconds().abs() > SNAP_WARN_SECONDS {
+                warn!(
+                    "Moved forecast timestamp {} by {} seconds to {}",
+                    time,
+                    (snapped - *time).num_seconds(),
+                    snapped
+                );
+            }
+            *time = snapped;
+        }
+    }
 }
 
 /// A collection of forecasts.
@@ -24,27 +112,113 @@ pub struct Forecasts {
     pub forecasts: Vec<Forecast>,
 }
 
-/// Loads forecasts from a JSON file.
+/// Loads forecasts from a JSON or CSV file.
+///
+/// Files with a `.csv` extension are read with `load_forecasts_csv`, all others as JSON.
 ///
 /// # Parameters
-/// - `file_path`: The path to the JSON file containing the forecasts.
+/// - `file_path`: The path to the file containing the forecasts.
+/// - `strict`: Whether the forecasts must form a contiguous timeline. If set, the
+///   forecasts are sorted by start time and every gap or overlap is an error.
 ///
 /// # Returns
 /// A `Result` containing `Forecasts` on success or an error on failure.
 ///
 /// # Errors
-/// Returns an error if the file cannot be read or if the JSON data is invalid.
-pub fn load_forecasts(file_path: &str) -> Result<Forecasts> {
-    // Attempt to read the forecasts file
-    let data = fs::read_to_string(file_path)
-        .context(format!("Unable to read forecasts file: {}", file_path))?;
+/// Returns an error if the file cannot be read, if the JSON data is invalid, or,
+/// in strict mode, if the intervals leave a gap or overlap.
+pub fn load_forecasts(file_path: &str, strict: bool) -> Result<Forecasts, LoadError> {
+    let mut forecasts = load_forecasts_with_mapping(file_path, &HashMap::new())?;
+
+    if strict {
+        validate_timeline(&mut forecasts.forecasts)?;
+    }
 
-    // Log the successful reading of the file
+    Ok(forecasts)
+}
+
+/// Loads forecasts from a JSON or CSV file whose field names differ from the expected ones.
+///
+/// Files with a `.csv` extension are read as CSV, with the mapping applied to the
+/// header; all others are read as JSON.
+///
+/// # Parameters
+/// - `file_path`: The path to the file containing the forecasts.
+/// - `mapping`: A map from the file's field names to the expected field names.
+///
+/// # Returns
+/// A `Result` containing `Forecasts` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or if the data is invalid.
+pub fn load_forecasts_with_mapping(
+    file_path: &str,
+    mapping: &HashMap<String, String>,
+) -> Result<Forecasts, LoadError> {
+    if is_csv(file_path) {
+        return read_forecasts_csv(file_path, mapping);
+    }
+
+    // Attempt to open the forecasts file
+    let file = fs::File::open(file_path).map_err(|source| LoadError::Io {
+        context: format!("Unable to read forecasts file: {}", file_path),
+        source,
+    })?;
+
+    // Log the successful opening of the file
     info!("Successfully read forecasts from file: {}", file_path);
 
-    // Attempt to parse the JSON data
-    let forecasts: Forecasts =
-        serde_json::from_str(&data).context("JSON parsing error in forecasts")?;
+    load_forecasts_reader_with_mapping(file, mapping)
+}
+
+/// Loads forecasts in JSON format from a reader, e.g. standard input.
+///
+/// # Parameters
+/// - `reader`: The source of the JSON data.
+///
+/// # Returns
+/// A `Result` containing `Forecasts` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the data cannot be read, the JSON data is invalid, or a
+/// forecast fails validation.
+pub fn load_forecasts_reader<R: Read>(reader: R) -> Result<Forecasts, LoadError> {
+    load_forecasts_reader_with_mapping(reader, &HashMap::new())
+}
+
+/// Loads forecasts in JSON format from a reader, renaming fields according to `mapping`.
+///
+/// # Parameters
+/// - `reader`: The source of the JSON data.
+/// - `mapping`: A map from the data's field names to the expected field names.
+///
+/// # Returns
+/// A `Result` containing `Forecasts` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the data cannot be read, the JSON data is invalid or lacks a
+/// required field (see `validate_schema`), or a forecast fails validation.
+pub fn load_forecasts_reader_with_mapping<R: Read>(
+    mut reader: R,
+    mapping: &HashMap<String, String>,
+) -> Result<Forecasts, LoadError> {
+    let mut data = String::new();
+    reader
+        .read_to_string(&mut data)
+        .map_err(|source| LoadError::Io {
+            context: "Unable to read forecasts".to_string(),
+            source,
+        })?;
+
+    // Attempt to parse the JSON data, renaming provider-specific fields first
+    let json_error = |source| LoadError::Json {
+        context: "JSON parsing error in forecasts".to_string(),
+        source,
+    };
+    let mut value: serde_json::Value = serde_json::from_str(&data).map_err(json_error)?;
+    apply_field_mapping(&mut value, mapping);
+    validate_schema(&value, "forecasts", FORECAST_FIELDS)?;
+    let forecasts: Forecasts = serde_json::from_value(value).map_err(json_error)?;
 
     // Validate the forecasts data
     for forecast in &forecasts.forecasts {
@@ -57,6 +231,298 @@ pub fn load_forecasts(file_path: &str) -> Result<Forecasts> {
     Ok(forecasts) // Return the parsed forecasts wrapped in Ok
 }
 
+/// Loads forecasts from a CSV file.
+///
+/// The file must have a header with the columns `start`, `end` and
+/// `consumption_average_power_interval`, with RFC 3339 timestamps. The
+/// `consumption_p90` and `temperature_c` columns are optional.
+///
+/// # Parameters
+/// - `file_path`: The path to the CSV file containing the forecasts.
+///
+/// # Returns
+/// A `Result` containing `Forecasts` on success or an error on failure.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, a column is missing, or a row is
+/// malformed or fails validation.
+pub fn load_forecasts_csv(file_path: &str) -> Result<Forecasts, LoadError> {
+    read_forecasts_csv(file_path, &HashMap::new())
+}
+
+/// Reads forecasts from a CSV file, renaming header columns according to `mapping`.
+fn read_forecasts_csv(
+    file_path: &str,
+    mapping: &HashMap<String, String>,
+) -> Result<Forecasts, LoadError> {
+    let mut reader = csv::Reader::from_path(file_path).map_err(|source| LoadError::Csv {
+        context: format!("Unable to read forecasts file: {}", file_path),
+        source,
+    })?;
+
+    info!("Successfully read forecasts from file: {}", file_path);
+
+    // Rename provider-specific columns before deserializing
+    let headers: csv::StringRecord = reader
+        .headers()
+        .map_err(|source| LoadError::Csv {
+            context: "CSV parsing error in forecasts header".to_string(),
+            source,
+        })?
+        .iter()
+        .map(|column| mapping.get(column).map_or(column, String::as_str))
+        .collect();
+    reader.set_headers(headers);
+
+    let mut forecasts = Vec::new();
+    for (index, row) in reader.deserialize().enumerate() {
+        // Row 1 is the header
+        let forecast: Forecast = row.map_err(|source| LoadError::Csv {
+            context: format!("CSV parsing error in forecasts at row {}", index + 2),
+            source,
+        })?;
+        validate_forecast(&forecast)?;
+        forecasts.push(forecast);
+    }
+
+    info!("Successfully parsed forecasts data.");
+
+    Ok(Forecasts { forecasts })
+}
+
+/// Resamples forecasts to intervals of `target_minutes`.
+///
+/// Intervals longer than the target are split into equal sub-intervals carrying the
+/// same average power. Consecutive intervals shorter than the target are averaged
+/// up into a single interval, weighted by their duration. Intervals that already
+/// have the target length are kept as they are.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts to resample, in chronological order.
+/// * `target_minutes`: The length of the resampled intervals in minutes.
+///
+/// # Returns
+/// A `Result` containing the resampled forecasts.
+///
+/// # Errors
+/// Returns an error if `target_minutes` is not positive, if it does not evenly
+/// divide (or is not evenly divided by) an interval's length, or if finer intervals
+/// cannot be combined into a contiguous target interval.
+pub fn resample_forecasts(
+    forecasts: Vec<Forecast>,
+    target_minutes: i64,
+) -> Result<Vec<Forecast>, LoadError> {
+    if target_minutes <= 0 {
+        return Err(LoadError::Validation(
+            "Target interval length must be positive.".to_string(),
+        ));
+    }
+    let target = Duration::minutes(target_minutes);
+
+    let mut resampled = Vec::new();
+    let mut pending: Vec<Forecast> = Vec::new(); // Finer intervals not yet averaged up
+
+    for forecast in forecasts {
+        let duration = forecast.end - forecast.start;
+
+        if duration > target {
+            if !pending.is_empty() || duration.num_seconds() % target.num_seconds() != 0 {
+                return Err(LoadError::Validation(format!(
+                    "Cannot resample the forecast at {} ({} minutes) to {} minutes",
+                    forecast.start,
+                    duration.num_minutes(),
+                    target_minutes
+                )));
+            }
+
+            let parts = duration.num_seconds() / target.num_seconds();
+            for i in 0..parts as i32 {
+                let start = forecast.start + target * i;
+                resampled.push(Forecast {
+                    start,
+                    end: start + target,
+                    consumption_average_power_interval: forecast.consumption_average_power_interval,
+                    consumption_p90: forecast.consumption_p90,
+                    production_average_power_interval: forecast.production_average_power_interval,
+                    temperature_c: forecast.temperature_c,
+                });
+            }
+        } else {
+            if target.num_seconds() % duration.num_seconds() != 0
+                || pending
+                    .last()
+                    .is_some_and(|previous| previous.end != forecast.start)
+            {
+                return Err(LoadError::Validation(format!(
+                    "Cannot resample the forecast at {} ({} minutes) to {} minutes",
+                    forecast.start,
+                    duration.num_minutes(),
+                    target_minutes
+                )));
+            }
+
+            pending.push(forecast);
+            let covered = pending[pending.len() - 1].end - pending[0].start;
+            if covered == target {
+                resampled.push(average_forecasts(&pending));
+                pending.clear();
+            } else if covered > target {
+                return Err(LoadError::Validation(format!(
+                    "Forecasts starting at {} do not add up to {} minutes",
+                    pending[0].start, target_minutes
+                )));
+            }
+        }
+    }
+
+    if let Some(first) = pending.first() {
+        return Err(LoadError::Validation(format!(
+            "Forecasts starting at {} do not add up to {} minutes",
+            first.start, target_minutes
+        )));
+    }
+
+    Ok(resampled)
+}
+
+/// Fills missing intervals in an otherwise regular series of forecasts.
+///
+/// Every gap between two neighbouring forecasts must be a whole number of
+/// `interval_minutes` intervals. Each missing interval gets a synthetic forecast
+/// whose values are interpolated linearly between the neighbours; production and
+/// temperature are only interpolated if both neighbours have them. Gaps before the
+/// first or after the last forecast cannot be detected and are not filled.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts to fill, in chronological order.
+/// * `interval_minutes`: The length of the intervals of the series in minutes.
+///
+/// # Returns
+/// A `Result` containing the forecasts with the gaps filled.
+///
+/// # Errors
+/// Returns an error if `interval_minutes` is not positive, or forecasts overlap or
+/// are separated by a gap that is not a whole number of intervals.
+pub fn fill_gaps(
+    forecasts: Vec<Forecast>,
+    interval_minutes: i64,
+) -> Result<Vec<Forecast>, LoadError> {
+    if interval_minutes <= 0 {
+        return Err(LoadError::Validation(
+            "Interval length must be positive.".to_string(),
+        ));
+    }
+    let interval = Duration::minutes(interval_minutes);
+
+    let mut filled: Vec<Forecast> = Vec::with_capacity(forecasts.len());
+    for forecast in forecasts {
+        if let Some(previous) = filled.last() {
+            let gap = forecast.start - previous.end;
+            if gap < Duration::zero() || gap.num_seconds() % interval.num_seconds() != 0 {
+                return Err(LoadError::Validation(format!(
+                    "Cannot fill the gap between the forecasts ending at {} and starting at {} with {}-minute intervals",
+                    previous.end, forecast.start, interval_minutes
+                )));
+            }
+
+            let missing = gap.num_seconds() / interval.num_seconds();
+            if missing > 0 {
+                info!(
+                    "Filling {} missing forecast interval(s) from {}",
+                    missing, previous.end
+                );
+            }
+            let previous = previous.clone();
+            for step in 1..=missing {
+                let fraction = step as f64 / (missing + 1) as f64;
+                let interpolate = |from: f64, to: f64| from + (to - from) * fraction;
+                let start = previous.end + interval * (step - 1) as i32;
+                filled.push(Forecast {
+                    start,
+                    end: start + interval,
+                    consumption_average_power_interval: interpolate(
+                        previous.consumption_average_power_interval,
+                        forecast.consumption_average_power_interval,
+                    ),
+                    consumption_p90: previous
+                        .consumption_p90
+                        .zip(forecast.consumption_p90)
+                        .map(|(from, to)| interpolate(from, to)),
+                    production_average_power_interval: previous
+                        .production_average_power_interval
+                        .zip(forecast.production_average_power_interval)
+                        .map(|(from, to)| interpolate(from, to)),
+                    temperature_c: previous
+                        .temperature_c
+                        .zip(forecast.temperature_c)
+                        .map(|(from, to)| interpolate(from, to)),
+                });
+            }
+        }
+        filled.push(forecast);
+    }
+
+    Ok(filled)
+}
+
+/// Combines contiguous forecasts into one, averaging their values by duration.
+fn average_forecasts(forecasts: &[Forecast]) -> Forecast {
+    let start = forecasts[0].start;
+    let end = forecasts[forecasts.len() - 1].end;
+    let total_seconds = (end - start).num_seconds() as f64;
+    let weighted_average = |value: &dyn Fn(&Forecast) -> f64| {
+        forecasts
+            .iter()
+            .map(|forecast| value(forecast) * (forecast.end - forecast.start).num_seconds() as f64)
+            .sum::<f64>()
+            / total_seconds
+    };
+
+    let production = forecasts
+        .iter()
+        .any(|forecast| forecast.production_average_power_interval.is_some())
+        .then(|| {
+            weighted_average(&|forecast| forecast.production_average_power_interval.unwrap_or(0.0))
+        });
+    // Intervals without a P90 contribute their mean consumption
+    let consumption_p90 = forecasts
+        .iter()
+        .any(|forecast| forecast.consumption_p90.is_some())
+        .then(|| {
+            weighted_average(&|forecast| {
+                forecast
+                    .consumption_p90
+                    .unwrap_or(forecast.consumption_average_power_interval)
+            })
+        });
+    let temperatures: Vec<f64> = forecasts
+        .iter()
+        .filter_map(|forecast| forecast.temperature_c)
+        .collect();
+    let temperature = (!temperatures.is_empty())
+        .then(|| temperatures.iter().sum::<f64>() / temperatures.len() as f64);
+
+    Forecast {
+        start,
+        end,
+        consumption_average_power_interval: weighted_average(&|forecast| {
+            forecast.consumption_average_power_interval
+        }),
+        consumption_p90,
+        production_average_power_interval: production,
+        temperature_c: temperature,
+    }
+}
+
+/// Returns whether the file has a `.csv` extension.
+fn is_csv(file_path: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
 /// Validates a forecast for energy consumption.
 ///
 /// # Arguments
@@ -65,14 +531,61 @@ pub fn load_forecasts(file_path: &str) -> Result<Forecasts> {
 ///
 /// # Returns
 /// A `Result` indicating success or failure of the validation.
-fn validate_forecast(forecast: &Forecast) -> Result<()> {
+fn validate_forecast(forecast: &Forecast) -> Result<(), LoadError> {
     if forecast.consumption_average_power_interval < 0.0 {
-        return Err(anyhow!(
-            "Consumption average power interval must be non-negative."
+        return Err(LoadError::Validation(
+            "Consumption average power interval must be non-negative.".to_string(),
+        ));
+    }
+    if forecast
+        .production_average_power_interval
+        .is_some_and(|production| production < 0.0)
+    {
+        return Err(LoadError::Validation(
+            "Production average power interval must be non-negative.".to_string(),
+        ));
+    }
+    if forecast
+        .consumption_p90
+        .is_some_and(|p90| p90 < 0.0 || p90.is_nan())
+    {
+        return Err(LoadError::Validation(
+            "Consumption P90 must be a non-negative number.".to_string(),
         ));
     }
     if forecast.start >= forecast.end {
-        return Err(anyhow!("Forecast start time must be before end time."));
+        return Err(LoadError::Validation(
+            "Forecast start time must be before end time.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that forecasts form a contiguous, non-overlapping timeline.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts to check, sorted by start time in place.
+///
+/// # Returns
+/// A `Result` indicating success, or an error naming the first gap or overlap.
+fn validate_timeline(forecasts: &mut [Forecast]) -> Result<(), LoadError> {
+    forecasts.sort_by_key(|forecast| forecast.start);
+
+    for pair in forecasts.windows(2) {
+        let (current, next) = (&pair[0], &pair[1]);
+        if current.end < next.start {
+            return Err(LoadError::Validation(format!(
+                "Gap in forecasts: interval ending at {} is followed by one starting at {}",
+                current.end, next.start
+            )));
+        }
+        if current.end > next.start {
+            return Err(LoadError::Validation(format!(
+                "Overlap in forecasts: interval ending at {} overlaps one starting at {}",
+                current.end, next.start
+            )));
+        }
     }
     Ok(())
 }
@@ -0,0 +1,139 @@
+use crate::planning::Plan;
+use crate::units::{to_mwh, EnergyUnit};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Deserialize;
+
+/// Tolerance used when comparing powers against the limits, in MW.
+const TOLERANCE_MW: f64 = 1e-9;
+
+/// Physical constraints of the inverter that executes a plan.
+#[derive(Debug, Deserialize)]
+pub struct InverterLimits {
+    /// Maximum charging/discharging power in MW.
+    pub max_rate_mw: f64,
+    /// Maximum change in power between two consecutive intervals in MW.
+    pub max_ramp_mw: f64,
+    /// Minimum number of consecutive intervals the inverter must stay on or off.
+    pub min_dwell_intervals: usize,
+}
+
+/// The constraint broken by a plan interval.
+#[derive(Debug, PartialEq)]
+pub enum ViolationKind {
+    /// The interval's power exceeds the inverter's maximum rate.
+    MaxRate,
+    /// The power changes faster than the inverter's ramp limit.
+    Ramp,
+    /// The inverter switches on or off before the minimum dwell time has passed.
+    Dwell,
+}
+
+/// A plan interval that breaks an inverter constraint.
+#[derive(Debug)]
+pub struct Violation {
+    /// Start time of the offending interval.
+    pub start: DateTime<Utc>,
+    /// The constraint that is broken.
+    pub kind: ViolationKind,
+    /// A description of the violation.
+    pub message: String,
+}
+
+/// Validates a plan against the inverter's constraints before it is sent to hardware.
+///
+/// The power of each interval is the net charging power (positive when charging,
+/// negative when discharging). The inverter is "on" while charging or discharging.
+/// The first and last on/off periods are not checked for dwell time, since they
+/// may extend beyond the plan's horizon.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan to validate.
+/// * `limits`: The inverter's constraints.
+///
+/// # Returns
+/// A vector of all violations found, empty if the plan is feasible.
+pub fn validate_against_inverter(plan: &[Plan], limits: &InverterLimits) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    let powers: Vec<f64> = plan
+        .iter()
+        .map(|interval| {
+            let duration_hours = (interval.end - interval.start).num_seconds() as f64 / 3600.0;
+            to_mwh(
+                interval.energy_to_battery_wh + interval.energy_from_surplus_wh
+                    - interval.energy_from_battery_wh
+                    - interval.energy_to_grid_wh,
+                EnergyUnit::Wh,
+            ) / duration_hours
+        })
+        .collect();
+
+    for (index, (interval, power)) in plan.iter().zip(powers.iter()).enumerate() {
+        if power.abs() > limits.max_rate_mw + TOLERANCE_MW {
+            violations.push(Violation {
+                start: interval.start,
+                kind: ViolationKind::MaxRate,
+                message: format!(
+                    "power {} MW exceeds the max rate of {} MW",
+                    power.abs(),
+                    limits.max_rate_mw
+                ),
+            });
+        }
+
+        if index > 0 {
+            let ramp = (power - powers[index - 1]).abs();
+            if ramp > limits.max_ramp_mw + TOLERANCE_MW {
+                violations.push(Violation {
+                    start: interval.start,
+                    kind: ViolationKind::Ramp,
+                    message: format!(
+                        "power changes by {} MW, more than the ramp limit of {} MW",
+                        ramp, limits.max_ramp_mw
+                    ),
+                });
+            }
+        }
+    }
+
+    // Split the plan into runs of on/off intervals and check the inner runs' length
+    let mut runs: Vec<(usize, usize)> = Vec::new(); // (first index, length)
+    for (index, power) in powers.iter().enumerate() {
+        let on = power.abs() > TOLERANCE_MW;
+        match runs.last_mut() {
+            Some((first, length)) if (powers[*first].abs() > TOLERANCE_MW) == on => *length += 1,
+            _ => runs.push((index, 1)),
+        }
+    }
+    if runs.len() > 2 {
+        for (first, length) in &runs[1..runs.len() - 1] {
+            if *length < limits.min_dwell_intervals {
+                violations.push(Violation {
+                    start: plan[*first].start,
+                    kind: ViolationKind::Dwell,
+                    message: format!(
+                        "inverter stays {} for {} intervals, less than the minimum of {}",
+                        if powers[*first].abs() > TOLERANCE_MW {
+                            "on"
+                        } else {
+                            "off"
+                        },
+                        length,
+                        limits.min_dwell_intervals
+                    ),
+                });
+            }
+        }
+    }
+
+    for violation in &violations {
+        warn!(
+            "Inverter constraint violated at {}: {}",
+            violation.start, violation.message
+        );
+    }
+
+    violations
+}
@@ -0,0 +1,27 @@
+//! Battery management library.
+//!
+//! Plans the charging and discharging of a battery from energy consumption
+//! forecasts and day-ahead electricity prices.
+
+pub mod bank;
+pub mod battery;
+pub mod carbon;
+pub mod cli;
+pub mod config;
+pub mod error;
+pub mod ev;
+pub mod forecast;
+pub mod inverter;
+pub mod logging;
+pub mod pipeline;
+pub mod planning;
+pub mod prices;
+pub mod reconcile;
+pub mod scenario;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+mod tests;
+pub mod timeline;
+pub mod units;
+pub mod validation;
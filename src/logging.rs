@@ -0,0 +1,76 @@
+use crate::planning::DECISION_LOG_TARGET;
+use env_logger::fmt::Formatter;
+use env_logger::{Builder, Env};
+use log::kv::{Error, Key, Value, VisitSource};
+use log::{LevelFilter, Record};
+use serde_json::Map;
+use std::io::{self, Write};
+
+/// The format log records are written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the record's key-values as fields.
+    Json,
+}
+
+/// Creates the logger configuration used by the command line.
+///
+/// The level defaults to `info` and can be overridden with `RUST_LOG`.
+///
+/// # Arguments
+///
+/// * `format`: The format of the log records.
+/// * `quiet`: Whether to leave out the per-interval decisions logged under
+///   `DECISION_LOG_TARGET`.
+///
+/// # Returns
+/// The `Builder`, to be initialized by the caller.
+pub fn logger_builder(format: LogFormat, quiet: bool) -> Builder {
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    if quiet {
+        builder.filter_module(DECISION_LOG_TARGET, LevelFilter::Off);
+    }
+    if format == LogFormat::Json {
+        builder.format(write_json);
+    }
+    builder
+}
+
+/// Writes a record as a single-line JSON object with its timestamp, level, target
+/// and message, followed by its key-values.
+fn write_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_string(), buf.timestamp().to_string().into());
+    fields.insert("level".to_string(), record.level().as_str().into());
+    fields.insert("target".to_string(), record.target().into());
+    fields.insert("message".to_string(), record.args().to_string().into());
+
+    let mut visitor = JsonFields(&mut fields);
+    record
+        .key_values()
+        .visit(&mut visitor)
+        .map_err(io::Error::other)?;
+
+    writeln!(buf, "{}", serde_json::Value::Object(fields))
+}
+
+/// Collects the key-values of a record as JSON fields, keeping numbers and
+/// booleans and writing anything else as a string.
+struct JsonFields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = if let Some(flag) = value.to_bool() {
+            flag.into()
+        } else if let Some(number) = value.to_f64() {
+            number.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
@@ -1,17 +1,20 @@
-use std::env;
-use anyhow::{Context, Result}; // Import Result from anyhow
-use battery::Battery;
-use forecast::load_forecasts;
-use log::info;
-use planning::plan_battery_usage;
-use prices::load_day_ahead_prices; // Import log macros
-
-mod battery;
-mod config;
-mod forecast;
-mod planning;
-mod prices;
-mod tests;
+use anyhow::{anyhow, Context, Result}; // Import Result from anyhow
+use battery::battery::Battery;
+use battery::cli::Cli;
+use battery::config;
+use battery::forecast::{load_forecasts_reader_with_mapping, load_forecasts_with_mapping};
+use battery::logging::logger_builder;
+use battery::pipeline::{self, PlanOutcome, ResultEnvelope};
+use battery::planning::{self, battery_utilization, OutputFormat};
+use battery::prices::{
+    load_day_ahead_prices_reader_with_mapping, load_day_ahead_prices_with_mapping,
+};
+use battery::units::{w_to_mw, EnergyUnit};
+use battery::validation::validate_inputs;
+use clap::Parser;
+use log::info; // Import log macros
+use std::io;
+use std::path::Path;
 
 /// The main entry point for the battery management application.
 ///
@@ -21,49 +24,215 @@ mod tests;
 /// # Returns
 /// A `Result` which is `Ok(())` if everything runs successfully, or an error if any step fails.
 fn main() -> Result<()> {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
+    // Parse the input and output paths from the command line
+    let cli = Cli::parse();
+
+    // Initialize the logger, at the info level unless RUST_LOG says otherwise
+    logger_builder(cli.log_format, cli.quiet).init();
+
+    // Serve plans over HTTP instead of planning the input files once
+    #[cfg(feature = "server")]
+    if let Some(battery::cli::Command::Serve { addr }) = &cli.command {
+        return battery::server::serve(addr);
     }
-    // Initialize the logger
-    env_logger::init();
 
+    // Report the run as one JSON document as well, if requested
+    let result = run(&cli);
+    if let Some(result_path) = &cli.result_json {
+        match &result {
+            Ok((envelope, unit)) => envelope.save(result_path, *unit)?,
+            Err(error) => ResultEnvelope::failed(error).save(result_path, EnergyUnit::Wh)?,
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Plans the input files named on the command line and saves the plan.
+///
+/// # Arguments
+///
+/// * `cli`: The command-line arguments.
+///
+/// # Returns
+/// A `Result` containing the envelope of the run and the unit of its plan's energies,
+/// or an error if any step fails.
+fn run(cli: &Cli) -> Result<(ResultEnvelope, EnergyUnit)> {
     // Load configuration from config.toml
-    let config = config::load_config("config.toml").context("Failed to load config")?;
+    let mut config = config::load_config(&cli.config).context("Failed to load config")?;
+    config.settings.explain = cli.explain;
     info!("Loaded configuration: {:?}", config);
 
-    // Load forecasts from forecasts.json
-    let forecasts_data = load_forecasts("forecasts.json").context("Failed to load forecasts")?;
+    // Standard input can only feed one of the inputs
+    if cli.forecasts == "-" && cli.prices == "-" {
+        return Err(anyhow!(
+            "Forecasts and prices cannot both be read from standard input"
+        ));
+    }
+
+    // Load forecasts from forecasts.json, or from standard input for `-`
+    let mut forecasts_data = if cli.forecasts == "-" {
+        load_forecasts_reader_with_mapping(io::stdin().lock(), &config.field_mapping)
+    } else {
+        load_forecasts_with_mapping(&cli.forecasts, &config.field_mapping)
+    }
+    .context("Failed to load forecasts")?;
     info!("Loaded forecasts data successfully.");
 
     // Initialize the battery with the values from the config
-    let battery = Battery::new(
-        config.settings.capacity,
-        config.settings.initial_charge,
-        config.settings.max_rate,
-        config.settings.efficiency,
-    );
+    let mut battery =
+        Battery::from_settings(&config.settings).context("Invalid battery configuration")?;
+    if let Some(start_charge) = cli.start_charge {
+        battery
+            .set_start_charge(start_charge)
+            .context("Invalid --start-charge")?;
+        info!("Planning from a start charge of {} MWh", start_charge);
+    }
 
-    // Load day-ahead prices from day-ahead.json and calculate the average price
-    let (prices_data, average_price) =
-        load_day_ahead_prices("day-ahead.json").context("Failed to load day-ahead prices")?;
+    // Load day-ahead prices from day-ahead.json, or standard input for `-`, and calculate the average price
+    let (mut prices_data, average_price) = if cli.prices == "-" {
+        load_day_ahead_prices_reader_with_mapping(
+            io::stdin().lock(),
+            &config.field_mapping,
+            Some(&config.settings.reporting_currency),
+            &config.settings.exchange_rates,
+            config.settings.interval_minutes,
+            config.settings.snap_timestamps_minutes,
+        )
+    } else {
+        load_day_ahead_prices_with_mapping(
+            &cli.prices,
+            &config.field_mapping,
+            Some(&config.settings.reporting_currency),
+            &config.settings.exchange_rates,
+            config.settings.interval_minutes,
+            config.settings.snap_timestamps_minutes,
+        )
+    }
+    .context("Failed to load day-ahead prices")?;
     info!(
         "Loaded day-ahead prices successfully. Average price: {}",
         average_price
     );
 
-    // Generate the charge/discharge plan using the average price
-    let plan = plan_battery_usage(
-        forecasts_data.forecasts,
-        prices_data.prices,
-        battery,
-        config.settings.grid_limit, // Pass grid_limit here
-        average_price,              // Pass the average price calculated
-    )
-    .context("Failed to plan battery usage")?;
-
-    // Save the plan to an output file
-    planning::save_plan(plan, "output_plan.json").context("Failed to save the plan")?;
-
-    println!("Battery planning complete! Check output_plan.json for details.");
-    Ok(()) // Return Ok if everything goes well
+    // Bring the inputs to the planning resolution and complete the prices
+    pipeline::prepare_inputs(&config, &mut forecasts_data, &mut prices_data)?;
+
+    // Stop after checking the inputs if only a dry run was requested
+    if cli.validate_only {
+        validate_inputs(&config, &forecasts_data, &prices_data)
+            .context("Input validation failed")?;
+        println!("Inputs are valid: configuration, forecasts and prices are consistent.");
+        return Ok((
+            ResultEnvelope::succeeded(None, None, Vec::new()),
+            config.settings.plan_energy_unit,
+        ));
+    }
+
+    // Plan the battery usage and check the plan before saving it
+    let PlanOutcome {
+        plan,
+        summary,
+        co2_avoided_kg: co2_avoided,
+        warnings,
+        ..
+    } = pipeline::plan_inputs(&config, &mut battery, &mut forecasts_data, &prices_data)?;
+    // The final charge is where the next run's plan would start
+    info!(
+        "Battery charge at the end of the plan: {} MWh ({:.1}% state of charge)",
+        battery.charge,
+        battery.state_of_charge() * 100.0
+    );
+
+    let utilization = battery_utilization(
+        &plan,
+        config.settings.capacity,
+        config.settings.max_charge_rate(),
+        config.settings.max_discharge_rate(),
+    );
+
+    // Round the energies for a reproducible output, if configured
+    let mut plan = plan;
+    if let Some(decimals) = config.settings.output_decimals {
+        planning::round_plan(&mut plan, decimals);
+    }
+
+    // Keep the plan for the result envelope before saving it
+    let saved_plan = cli.result_json.as_ref().map(|_| plan.clone());
+
+    // Save the plan to one file per day or to a single output file
+    if config.settings.split_plan_by_day {
+        // The daily files go into the directory `--output` names, or next to the file it names
+        let output = Path::new(&cli.output);
+        let directory = if output.is_dir() {
+            output
+        } else {
+            output
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        };
+        let days = planning::save_plan_per_day(
+            plan,
+            &prices_data.prices,
+            &config.settings,
+            &directory.to_string_lossy(),
+            config.settings.plan_energy_unit,
+        )
+        .context("Failed to save the daily plans")?;
+        println!(
+            "Battery planning complete! Check the {} plan_YYYY-MM-DD.json files and plan_summary.json in {} for details.",
+            days.len(),
+            directory.display()
+        );
+    } else {
+        let format = cli
+            .output_format
+            .unwrap_or_else(|| OutputFormat::from_path(&cli.output));
+        planning::save_plan_as(plan, &cli.output, config.settings.plan_energy_unit, format)
+            .context("Failed to save the plan")?;
+        println!(
+            "Battery planning complete! Check {} for details.",
+            cli.output
+        );
+    }
+    if let (Some(start), Some(end)) = (summary.covered_start, summary.covered_end) {
+        println!("Planned window: {} to {}", start, end);
+    }
+    println!("Battery utilization: {:.1}%", utilization * 100.0);
+    println!(
+        "Energy charged: {:.1} kWh, discharged: {:.1} kWh in {} active intervals",
+        summary.energy_charged_wh / 1_000.0,
+        summary.energy_discharged_wh / 1_000.0,
+        summary.active_intervals
+    );
+    if let Some(realized) = summary.realized_round_trip_efficiency {
+        println!(
+            "Round-trip efficiency: {:.1}% realized, {:.1}% nominal",
+            realized * 100.0,
+            battery.round_trip_efficiency() * 100.0
+        );
+    }
+    println!(
+        "Grid cost without battery: {:.2} EUR, with battery: {:.2} EUR, net savings: {:.2} EUR",
+        summary.cost_without_battery_eur, summary.cost_with_battery_eur, summary.net_savings_eur
+    );
+    if summary.intervals_over_grid_limit > 0 {
+        let largest_shortfall = summary
+            .grid_limit_violations
+            .iter()
+            .map(|violation| violation.shortfall_w)
+            .fold(0.0, f64::max);
+        println!(
+            "Intervals still over the grid limit: {} (largest shortfall: {:.3} MW)",
+            summary.intervals_over_grid_limit,
+            w_to_mw(largest_shortfall)
+        );
+    }
+    if let Some(co2_avoided) = co2_avoided {
+        println!("CO2 avoided: {:.1} kg", co2_avoided);
+    }
+    Ok((
+        ResultEnvelope::succeeded(saved_plan, Some(summary), warnings),
+        config.settings.plan_energy_unit,
+    ))
 }
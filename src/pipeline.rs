@@ -0,0 +1,300 @@
+use crate::battery::Battery;
+use crate::carbon::{co2_avoided_kg, load_carbon_intensities};
+use crate::config::{ChargeStrategy, Config};
+use crate::ev::{apply_ev_schedule, load_ev_schedule};
+use crate::forecast::{resample_forecasts, snap_forecasts, Forecasts};
+use crate::inverter::validate_against_inverter;
+use crate::planning::{
+    charge_threshold, express_energy_in, plan_battery_usage, plan_totals, summarize_plan, Plan,
+    PlanSummary, PlanTotals,
+};
+use crate::prices::{
+    load_day_ahead_prices_with_mapping, load_price_profile, merge_price_profile, overlay_prices,
+    DayAheadPrices,
+};
+use crate::units::EnergyUnit;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+
+/// The plan of a pipeline run, with what was derived along the way.
+pub struct PlanOutcome {
+    /// The battery usage plan, unrounded.
+    pub plan: Vec<Plan>,
+    /// The summary of the plan against the forecasts and prices it was made for.
+    pub summary: PlanSummary,
+    /// The import price at or below which the battery charges.
+    pub charge_threshold: f64,
+    /// CO2 avoided by the plan in kg, if carbon intensities are configured.
+    pub co2_avoided_kg: Option<f64>,
+    /// Non-fatal problems with the plan, e.g. peaks the battery cannot fully shave.
+    pub warnings: Vec<String>,
+}
+
+/// The outcome of a whole CLI run as one machine-readable document, written with
+/// `--result-json`.
+#[derive(Serialize)]
+pub struct ResultEnvelope {
+    /// `"ok"` if the run succeeded, `"error"` if it failed.
+    pub status: &'static str,
+    /// The error chain of a failed run, outermost first.
+    pub errors: Vec<String>,
+    /// Non-fatal problems with the plan, which are otherwise only logged.
+    pub warnings: Vec<String>,
+    /// The saved plan; `None` if the run failed or only validated the inputs.
+    pub plan: Option<Vec<Plan>>,
+    /// Monetary totals of the plan.
+    pub economics: Option<PlanTotals>,
+    /// Summary of the plan against the forecasts and prices.
+    pub summary: Option<PlanSummary>,
+}
+
+impl ResultEnvelope {
+    /// The envelope of a successful run, with the plan if one was made.
+    pub fn succeeded(
+        plan: Option<Vec<Plan>>,
+        summary: Option<PlanSummary>,
+        warnings: Vec<String>,
+    ) -> Self {
+        ResultEnvelope {
+            status: "ok",
+            errors: Vec::new(),
+            warnings,
+            economics: plan.as_deref().map(plan_totals),
+            plan,
+            summary,
+        }
+    }
+
+    /// The envelope of a failed run, with every message of the error chain.
+    pub fn failed(error: &anyhow::Error) -> Self {
+        ResultEnvelope {
+            status: "error",
+            errors: error.chain().map(|cause| cause.to_string()).collect(),
+            warnings: Vec::new(),
+            plan: None,
+            economics: None,
+            summary: None,
+        }
+    }
+
+    /// Writes the envelope as pretty JSON, with the plan's energies in `unit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path`: The path of the JSON file.
+    /// * `unit`: The unit of the energy fields.
+    ///
+    /// # Returns
+    /// A `Result` that is an error if the file cannot be written.
+    pub fn save(&self, file_path: &str, unit: EnergyUnit) -> Result<()> {
+        let mut value = serde_json::to_value(self).context("Error serializing the result")?;
+        express_energy_in(&mut value, unit);
+        let pretty_output =
+            serde_json::to_string_pretty(&value).context("Error generating pretty JSON")?;
+        fs::write(file_path, pretty_output)
+            .with_context(|| format!("Unable to write result to file: {}", file_path))?;
+        info!("Saved result envelope to {}", file_path);
+        Ok(())
+    }
+}
+
+/// Brings loaded forecasts and prices to the planning resolution and completes the prices.
+///
+/// The forecasts are snapped and resampled to `interval_minutes`, intraday prices
+/// override the day-ahead price of their interval, and the price profile prices the
+/// rest of the forecast horizon, if configured.
+///
+/// # Arguments
+///
+/// * `config`: The validated configuration.
+/// * `forecasts`: The loaded forecasts, updated in place.
+/// * `prices`: The loaded day-ahead prices, updated in place.
+///
+/// # Returns
+/// A `Result` that is an error if resampling fails or a configured price file
+/// cannot be loaded or combined with the day-ahead prices.
+pub fn prepare_inputs(
+    config: &Config,
+    forecasts: &mut Forecasts,
+    prices: &mut DayAheadPrices,
+) -> Result<()> {
+    let settings = &config.settings;
+    if let Some(snap_minutes) = settings.snap_timestamps_minutes {
+        snap_forecasts(&mut forecasts.forecasts, snap_minutes);
+    }
+
+    // Bring the forecasts to the planning resolution; intervals already at it are kept
+    forecasts.forecasts = resample_forecasts(
+        std::mem::take(&mut forecasts.forecasts),
+        settings.interval_minutes,
+    )
+    .context("Failed to resample forecasts")?;
+
+    // Prefer intraday prices where available, if configured
+    if let Some(intraday_path) = &settings.intraday_prices {
+        let (intraday_data, _) = load_day_ahead_prices_with_mapping(
+            intraday_path,
+            &config.field_mapping,
+            Some(&settings.reporting_currency),
+            &settings.exchange_rates,
+            settings.interval_minutes,
+            settings.snap_timestamps_minutes,
+        )
+        .context("Failed to load intraday prices")?;
+        prices.prices = overlay_prices(std::mem::take(&mut prices.prices), intraday_data.prices)
+            .context("Failed to overlay intraday prices")?;
+    }
+
+    // Price the rest of the forecast horizon from the fallback profile, if configured
+    if let (Some(profile_path), Some(first), Some(last)) = (
+        &settings.price_profile,
+        forecasts.forecasts.first(),
+        forecasts.forecasts.last(),
+    ) {
+        let profile = load_price_profile(profile_path).context("Failed to load price profile")?;
+        prices.prices = merge_price_profile(
+            std::mem::take(&mut prices.prices),
+            &profile,
+            first.start,
+            last.end,
+            settings.interval_minutes,
+            Some(&settings.reporting_currency),
+            &settings.exchange_rates,
+        )
+        .context("Failed to merge the price profile")?;
+    }
+
+    Ok(())
+}
+
+/// Plans the battery usage for prepared inputs and checks the plan before it ships.
+///
+/// The EV schedule is added to the forecasted load and the carbon intensities are
+/// loaded, if configured, before planning. A plan the inverter cannot execute is
+/// refused.
+///
+/// # Arguments
+///
+/// * `config`: The validated configuration.
+/// * `battery`: The battery to plan for; it is left in its state after the last interval.
+/// * `forecasts`: The prepared forecasts; the EV load is added to them.
+/// * `prices`: The prepared prices.
+///
+/// # Returns
+/// A `Result` containing the `PlanOutcome`, or an error if a configured file cannot
+/// be loaded, planning fails, or the plan violates the inverter limits.
+pub fn plan_inputs(
+    config: &Config,
+    battery: &mut Battery,
+    forecasts: &mut Forecasts,
+    prices: &DayAheadPrices,
+) -> Result<PlanOutcome> {
+    let settings = &config.settings;
+
+    // Charge in the fixed windows, below the configured price percentile, or below the
+    // average price by default, with the grid fee and tax included
+    let charge_threshold = charge_threshold(&prices.prices, settings);
+    match (&settings.charge_strategy, settings.charge_percentile) {
+        (ChargeStrategy::FixedWindows(windows), _) => info!(
+            "Charging in {} fixed window(s), exporting from an import price of {}",
+            windows.len(),
+            charge_threshold
+        ),
+        (&ChargeStrategy::BelowPercentile(percentile), _) | (_, Some(percentile)) => info!(
+            "Charging at or below the {} percentile import price: {}",
+            percentile, charge_threshold
+        ),
+        _ => info!(
+            "Charging at or below the average import price: {}",
+            charge_threshold
+        ),
+    }
+
+    // Add the mandatory EV charging load, if an EV schedule is configured
+    if let Some(ev_schedule_path) = &settings.ev_schedule {
+        let ev_schedule =
+            load_ev_schedule(ev_schedule_path).context("Failed to load EV schedule")?;
+        apply_ev_schedule(&mut forecasts.forecasts, &prices.prices, &ev_schedule)
+            .context("Failed to apply EV schedule")?;
+        info!("Applied EV schedule from {}", ev_schedule_path);
+    }
+
+    // Load the grid carbon intensities, if configured
+    let carbon = match &settings.carbon_intensity {
+        Some(carbon_path) => Some(
+            load_carbon_intensities(carbon_path).context("Failed to load carbon intensities")?,
+        ),
+        None => None,
+    };
+
+    let plan = plan_battery_usage(
+        forecasts.forecasts.clone(),
+        prices.prices.clone(),
+        battery,
+        settings,
+        charge_threshold,
+        carbon.as_ref().map(|carbon| carbon.intensities.as_slice()),
+    )
+    .context("Failed to plan battery usage")?;
+
+    // Refuse to ship a plan the inverter cannot execute
+    if let Some(limits) = &config.inverter {
+        let violations = validate_against_inverter(&plan, limits);
+        if !violations.is_empty() {
+            let details: Vec<String> = violations
+                .iter()
+                .map(|violation| {
+                    format!(
+                        "{} ({:?}): {}",
+                        violation.start, violation.kind, violation.message
+                    )
+                })
+                .collect();
+            for detail in &details {
+                warn!("Inverter constraint violated at {}", detail);
+            }
+            return Err(anyhow!(
+                "Plan violates {} inverter constraint(s): {}",
+                violations.len(),
+                details.join("; ")
+            ));
+        }
+    }
+
+    let summary = summarize_plan(&plan, &forecasts.forecasts, &prices.prices, settings);
+    let co2_avoided_kg = carbon
+        .as_ref()
+        .map(|carbon| co2_avoided_kg(&plan, &carbon.intensities));
+    let warnings = plan_warnings(&plan, &summary);
+
+    Ok(PlanOutcome {
+        plan,
+        summary,
+        charge_threshold,
+        co2_avoided_kg,
+        warnings,
+    })
+}
+
+/// Describes the non-fatal problems of a plan: peaks the battery delivers less for
+/// than requested, and intervals still importing above the grid limit.
+fn plan_warnings(plan: &[Plan], summary: &PlanSummary) -> Vec<String> {
+    let short_peaks = plan.iter().filter_map(|interval| {
+        let requested_wh = interval.requested_discharge_wh?;
+        (interval.energy_from_battery_wh < requested_wh - 1e-6).then(|| {
+            format!(
+                "Peak at {} needs {} Wh from the battery, but it delivers {} Wh",
+                interval.start, requested_wh, interval.energy_from_battery_wh
+            )
+        })
+    });
+    let violations = summary.grid_limit_violations.iter().map(|violation| {
+        format!(
+            "Grid limit exceeded at {}: import is {} W above the limit",
+            violation.start, violation.shortfall_w
+        )
+    });
+    short_peaks.chain(violations).collect()
+}
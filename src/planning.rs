@@ -1,134 +1,2167 @@
-use crate::battery::Battery;
+use crate::battery::{Battery, EnergyFlow, EnergyStorage};
+use crate::carbon::{intensity_at, CarbonIntensity};
+use crate::config::{ChargeStrategy, Objective, Settings, Strategy};
 use crate::forecast::Forecast;
-use crate::prices::ElectricityPrice;
-use anyhow::{Context, Result};
-use chrono::{DateTime, Utc}; // Import DateTime<Utc>
-use log::{debug, info}; // Import log macros
-use serde::Serialize;
+use crate::prices::{price_percentile, ElectricityPrice};
+use crate::units::{convert, from_wh, round_wh, to_mwh, w_to_mw, EnergyUnit};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc}; // Import DateTime<Utc>
+use log::{debug, info, warn}; // Import log macros
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// Cost per kWh imported above the grid limit, used by the optimal planner to
+/// treat the limit as a constraint while still finding a plan when it cannot be met.
+const GRID_LIMIT_PENALTY_EUR_PER_KWH: f64 = 1_000_000.0;
+
+/// Number of steps the stored energy is divided into by the arbitrage planner.
+const ARBITRAGE_SOC_LEVELS: usize = 60;
+
+/// Difference in energy below which `diff_plans` treats two intervals as equal, in Wh.
+const DIFF_TOLERANCE_WH: f64 = 1e-3;
+
+/// Log target of the per-interval decisions, one record per planned interval with
+/// the fields `interval`, `action`, `energy_wh` and `price_per_kwh`.
+pub const DECISION_LOG_TARGET: &str = "battery::decisions";
+
+/// The file format a plan is saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A pretty-printed JSON document with the intervals and the plan's totals.
+    Json,
+    /// One row per interval; see `save_plan_csv`.
+    Csv,
+    /// Newline-delimited JSON, one compact interval per line; see `save_plan_ndjson`.
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// Picks the format from the file extension: `.csv`, `.ndjson` (or `.jsonl`),
+    /// and JSON for anything else.
+    pub fn from_path(file_path: &str) -> Self {
+        match Path::new(file_path)
+            .extension()
+            .map(|extension| extension.to_ascii_lowercase())
+        {
+            Some(extension) if extension == "csv" => OutputFormat::Csv,
+            Some(extension) if extension == "ndjson" || extension == "jsonl" => {
+                OutputFormat::Ndjson
+            }
+            _ => OutputFormat::Json,
+        }
+    }
+}
 
 /// Represents a planned battery usage interval.
-#[derive(Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Plan {
     /// Start time of the battery operation.
     pub start: DateTime<Utc>,
     /// End time of the battery operation.
     pub end: DateTime<Utc>,
-    /// Energy discharged from the battery in watt-hours.
+    /// Energy discharged from the battery and delivered to the site, in watt-hours.
     pub energy_from_battery_wh: f64,
-    /// Energy charged into the battery in watt-hours.
+    /// Energy the battery was asked to deliver to shave a peak, in watt-hours; more
+    /// than `energy_from_battery_wh` when it could only cover part of the excess.
+    /// Only recorded in intervals whose consumption exceeds the shaving limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_discharge_wh: Option<f64>,
+    /// Energy drawn from the grid to charge the battery, in watt-hours.
     pub energy_to_battery_wh: f64,
+    /// Energy discharged from the battery and exported to the grid, in watt-hours.
+    pub energy_to_grid_wh: f64,
+    /// Surplus on-site production stored in the battery instead of being exported
+    /// (self-consumption), in watt-hours.
+    pub energy_from_surplus_wh: f64,
+    /// Cost of the energy drawn from the grid to charge the battery, in EUR.
+    pub grid_cost_eur: f64,
+    /// Value of the grid imports avoided by discharging the battery, plus the
+    /// feed-in revenue of exported energy, in EUR.
+    pub battery_revenue_eur: f64,
+    /// Grid energy a charge could not draw because the battery was full, in
+    /// watt-hours; only recorded with `record_curtailment`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_curtailed_wh: Option<f64>,
+    /// Surplus production that could neither be stored nor exported under
+    /// `grid_export_limit`, in watt-hours; only recorded when production is curtailed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy_pv_curtailed_wh: Option<f64>,
+    /// Why the planner charged, discharged or idled in the interval; only recorded
+    /// in explain mode, and only by the price and carbon planners.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Plan {
+    /// Creates the plan for a single interval, valuing its energy at the interval's price.
+    ///
+    /// # Arguments
+    ///
+    /// * `forecast`: The forecast of the interval.
+    /// * `price_per_kwh`: The price paid per imported kWh in the interval.
+    /// * `energy_from_battery_wh`: Energy delivered by the battery, in watt-hours.
+    /// * `energy_to_battery_wh`: Energy drawn from the grid to charge, in watt-hours.
+    fn for_interval(
+        forecast: &Forecast,
+        price_per_kwh: f64,
+        energy_from_battery_wh: f64,
+        energy_to_battery_wh: f64,
+    ) -> Self {
+        Plan {
+            start: forecast.start,
+            end: forecast.end,
+            energy_from_battery_wh,
+            requested_discharge_wh: None,
+            energy_to_battery_wh,
+            energy_to_grid_wh: 0.0,
+            energy_from_surplus_wh: 0.0,
+            grid_cost_eur: energy_to_battery_wh / 1_000.0 * price_per_kwh,
+            battery_revenue_eur: energy_from_battery_wh / 1_000.0 * price_per_kwh,
+            energy_curtailed_wh: None,
+            energy_pv_curtailed_wh: None,
+            reason: None,
+        }
+    }
+}
+
+/// Monetary totals of a plan.
+#[derive(Debug, Serialize)]
+pub struct PlanTotals {
+    /// Total cost of charging the battery from the grid, in EUR.
+    pub total_cost_eur: f64,
+    /// Value of the avoided grid imports minus the cost of charging, in EUR.
+    pub total_savings_eur: f64,
+}
+
+/// Aggregates of a plan, comparing the grid cost with and without the battery.
+#[derive(Debug, Serialize)]
+pub struct PlanSummary {
+    /// Total energy drawn from the grid or surplus production to charge the battery,
+    /// in watt-hours.
+    pub energy_charged_wh: f64,
+    /// Total energy discharged to the site or the grid, in watt-hours.
+    pub energy_discharged_wh: f64,
+    /// Energy discharged divided by energy charged from the grid or surplus
+    /// production, or `None` if nothing was charged.
+    pub realized_round_trip_efficiency: Option<f64>,
+    /// Number of intervals in which the battery charges or discharges.
+    pub active_intervals: usize,
+    /// Cost of importing the forecasted consumption without a battery, in EUR.
+    pub cost_without_battery_eur: f64,
+    /// Cost of the grid imports with the planned battery usage, in EUR.
+    pub cost_with_battery_eur: f64,
+    /// Difference between the cost without and with the battery, in EUR.
+    pub net_savings_eur: f64,
+    /// Number of intervals whose grid import still exceeds the grid limit.
+    pub intervals_over_grid_limit: usize,
+    /// The intervals whose grid import still exceeds the grid limit, and by how much.
+    pub grid_limit_violations: Vec<GridLimitViolation>,
+    /// Start of the window covered by both the forecasts and the prices, if they overlap.
+    pub covered_start: Option<DateTime<Utc>>,
+    /// End of the window covered by both the forecasts and the prices, if they overlap.
+    pub covered_end: Option<DateTime<Utc>>,
+}
+
+/// The intervals dropped because only one of the forecasts and prices covers them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HorizonTrim {
+    /// Start of the window covered by both series.
+    pub start: DateTime<Utc>,
+    /// End of the window covered by both series.
+    pub end: DateTime<Utc>,
+    /// Number of forecast intervals outside the window.
+    pub forecasts_dropped: usize,
+    /// Number of price intervals outside the window.
+    pub prices_dropped: usize,
+}
+
+/// An interval whose grid import still exceeds the grid limit with the planned
+/// battery usage, e.g. because the battery ran empty during a peak.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GridLimitViolation {
+    /// Start time of the interval.
+    pub start: DateTime<Utc>,
+    /// End time of the interval.
+    pub end: DateTime<Utc>,
+    /// Average import above the grid limit, in W like `grid_limit`.
+    pub shortfall_w: f64,
+}
+
+/// An interval in which two plans charge or discharge different amounts, as
+/// reported by `diff_plans`. Each value is `None` if that plan lacks the interval.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanDiff {
+    /// Start time of the interval.
+    pub start: DateTime<Utc>,
+    /// End time of the interval.
+    pub end: DateTime<Utc>,
+    /// Energy charged from the grid or surplus production in the first plan, in watt-hours.
+    pub charge_a_wh: Option<f64>,
+    /// Energy charged from the grid or surplus production in the second plan, in watt-hours.
+    pub charge_b_wh: Option<f64>,
+    /// Charged energy of the second plan minus the first, in watt-hours.
+    pub charge_delta_wh: f64,
+    /// Energy discharged to the site or the grid in the first plan, in watt-hours.
+    pub discharge_a_wh: Option<f64>,
+    /// Energy discharged to the site or the grid in the second plan, in watt-hours.
+    pub discharge_b_wh: Option<f64>,
+    /// Discharged energy of the second plan minus the first, in watt-hours.
+    pub discharge_delta_wh: f64,
+    /// Net cost (charging cost minus revenue) of the second plan minus the first, in EUR.
+    pub cost_delta_eur: f64,
+}
+
+/// Totals of a plan over a reporting period, a day or a month.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanAggregate {
+    /// Number of planned intervals starting in the period.
+    pub intervals: usize,
+    /// Total length of those intervals in hours, less than the period's length at the
+    /// edges of the plan horizon.
+    pub hours: f64,
+    /// Energy charged from the grid or surplus production, in watt-hours.
+    pub energy_charged_wh: f64,
+    /// Energy discharged to the site or the grid, in watt-hours.
+    pub energy_discharged_wh: f64,
+    /// Cost of charging the battery from the grid, in EUR.
+    pub cost_eur: f64,
+    /// Value of the avoided grid imports minus the cost of charging, in EUR.
+    pub savings_eur: f64,
+    /// Equivalent full cycles the battery makes in the period.
+    pub cycles: f64,
+    /// Average market price of the period's intervals per kWh, or `None` if none of
+    /// them has a price.
+    pub average_price_per_kwh: Option<f64>,
+}
+
+/// Totals of a plan for a single calendar day (UTC).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DailyAggregate {
+    /// The calendar day (UTC) of the intervals.
+    pub date: NaiveDate,
+    /// The totals of the day.
+    #[serde(flatten)]
+    pub totals: PlanAggregate,
+}
+
+/// Totals of a plan for a single calendar month (UTC).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonthlyAggregate {
+    /// The year of the intervals.
+    pub year: i32,
+    /// The month of the intervals, from 1 to 12.
+    pub month: u32,
+    /// The totals of the month.
+    #[serde(flatten)]
+    pub totals: PlanAggregate,
 }
 
 /// Plans the battery usage based on forecasts and electricity prices.
 ///
 /// This function checks the forecasts for energy consumption and the prices for
-/// charging the battery. If the consumption exceeds the grid limit, it discharges
-/// the battery; if the price is low, it charges the battery.
+/// charging the battery. If the consumption exceeds the grid limit (or the lower
+/// `peak_shave_target`, if set), it discharges the battery down to it; if the price is
+/// low, it charges the battery. Discharging is blocked when the forecasted temperature
+/// is below `min_discharge_temp_c`, and with `skip_unprofitable_charge` set, charging
+/// only happens if a later interval can make use of the energy. Intervals with a
+/// negative price always charge. With the `fixed_windows` charge strategy, the battery
+/// instead charges from the grid in every interval starting inside a window and in no
+/// other, whatever the price. When production exceeds consumption, the battery stores
+/// the surplus first, whatever the price, since it would otherwise be exported. With
+/// `sell_threshold_per_kwh` set, the battery exports to the grid at its discharging
+/// rate when the price reaches the threshold, at the feed-in price.
+///
+/// `charge_price_margin` leaves a dead band around the charge threshold, so the
+/// decision does not flip on small price changes: charging needs an import price of
+/// at most `charge_threshold - charge_price_margin`, and exporting one of at least
+/// `charge_threshold + charge_price_margin`. Inside the band the battery idles.
+///
+/// Charging, exporting and carbon-driven dispatch keep the state of charge between
+/// `min_soc` and `max_soc`; only peak shaving may discharge below `min_soc`, down to
+/// `min_charge`.
+///
+/// With `max_daily_cycles` set, charging (including from surplus), exporting and
+/// carbon-driven dispatch stop for the rest of a calendar day (UTC) once the battery
+/// has done that many equivalent full cycles that day. Peaks above the shaving limit
+/// are still shaved.
+///
+/// With the carbon strategy, intervals below the grid limit are dispatched by
+/// carbon intensity instead of price: the battery charges when the grid is cleaner
+/// than average and discharges when it is dirtier.
+///
+/// The battery loses charge to self-discharge in every interval where it idles.
+///
+/// Each forecast is paired with the price covering the same interval, matched on
+/// its start and end time, so the inputs may start at different times. When one
+/// series covers a longer horizon than the other, only the window covered by both
+/// is planned and the dropped intervals are logged as a warning.
 ///
 /// # Arguments
 ///
 /// * `forecasts`: A vector of forecasted energy consumption data.
 /// * `prices`: A vector of day-ahead electricity prices.
-/// * `battery`: A mutable reference to the battery being used for charging/discharging.
-/// * `grid_limit`: The maximum allowable energy consumption from the grid.
+/// * `battery`: The battery, or a `BatteryBank` of several packs, used for charging/discharging.
+///   It is left in its state after the last interval, so the next day's plan can start from it.
+/// * `settings`: The configuration settings, including the grid limit and charge power.
+/// * `charge_threshold`: The import price (grid fee and tax included) at or below which
+///   the battery charges, e.g. the average import price or a percentile of it.
+/// * `carbon`: The grid carbon intensities, required by the carbon strategy.
 ///
 /// # Returns
 /// A `Result` containing a vector of `Plan` structs if successful, or an error if any step fails.
-
-pub fn plan_battery_usage(
+///
+/// # Errors
+/// Returns an error if there are no forecasts or no prices, if the forecasts and
+/// prices do not overlap, or listing every forecast interval inside the common
+/// window that has no matching price.
+pub fn plan_battery_usage<B: EnergyStorage>(
     forecasts: Vec<Forecast>,
     prices: Vec<ElectricityPrice>,
-    mut battery: Battery,
-    grid_limit: f64,    // Fixed grid limit of 7.8 MW
-    average_price: f64, // Average day-ahead price
+    battery: &mut B,
+    settings: &Settings,
+    charge_threshold: f64, // Average or percentile import price
+    carbon: Option<&[CarbonIntensity]>,
 ) -> Result<Vec<Plan>, anyhow::Error> {
+    if forecasts.is_empty() || prices.is_empty() {
+        return Err(anyhow!(
+            "No intervals to plan: got {} forecast(s) and {} price(s)",
+            forecasts.len(),
+            prices.len()
+        ));
+    }
+
     let mut plan = Vec::new();
+    let (forecasts, prices, trim) = trim_to_common_horizon(forecasts, prices)?;
+    if let Some(trim) = trim {
+        warn!(
+            "Forecasts and prices cover different horizons, planning {} to {} only: dropped {} forecast interval(s) and {} price interval(s)",
+            trim.start, trim.end, trim.forecasts_dropped, trim.prices_dropped
+        );
+    }
+    let prices = align_prices(&forecasts, &prices)?;
 
-    for (forecast, price) in forecasts.iter().zip(prices.iter()) {
-        let duration_hours = 15.0 / 60.0; // Duration in hours
+    // The carbon strategy compares each interval against the average intensity
+    let carbon = match (settings.strategy, carbon) {
+        (Strategy::Carbon, Some(intensities)) => {
+            let known: Vec<f64> = forecasts
+                .iter()
+                .filter_map(|forecast| intensity_at(intensities, forecast.start, forecast.end))
+                .collect();
+            let average_intensity = known.iter().sum::<f64>() / known.len().max(1) as f64;
+            Some((intensities, average_intensity))
+        }
+        (Strategy::Carbon, None) => {
+            return Err(anyhow!("The carbon strategy requires carbon intensities"));
+        }
+        (Strategy::Price, _) => None,
+    };
 
-        debug!(
-            "{} - {}",
-            forecast.consumption_average_power_interval, grid_limit
-        );
+    let mut planner = IntervalPlanner::new(settings, charge_threshold, carbon, battery);
+    for (index, (forecast, price)) in forecasts.iter().zip(prices.iter()).enumerate() {
+        plan.push(planner.plan(forecast, price, battery, || {
+            has_profitable_later_use(index, &forecasts, &prices, settings)
+        })?);
+    }
+
+    Ok(plan) // Return the plan wrapped in Ok
+}
+
+/// Plans the battery usage one interval at a time, without collecting the inputs.
+///
+/// This is the price planner of `plan_battery_usage` for inputs too long to hold in
+/// memory, e.g. a year of 15-minute intervals: each plan is yielded as soon as its
+/// forecast and price are read, and the battery carries over between intervals.
+/// Both inputs must be in chronological order. Prices before a forecast are
+/// skipped, and the next price must cover the forecast's interval exactly.
+///
+/// The charge threshold is passed in, as it cannot be taken from prices that have
+/// not been read yet. Looking ahead is not possible either, so the carbon strategy
+/// and `skip_unprofitable_charge` are not supported.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasted consumption intervals, in chronological order.
+/// * `prices`: The electricity prices, in chronological order.
+/// * `battery`: The battery, or a `BatteryBank` of several packs, in its initial state.
+/// * `settings`: The configuration settings.
+/// * `charge_threshold`: The import price at or below which the battery charges.
+///
+/// # Returns
+/// An iterator over the `Plan` of every interval. After the first error, it ends.
+///
+/// # Errors
+/// Yields an error if the carbon strategy or `skip_unprofitable_charge` is
+/// configured, a forecast has no matching price, or the battery rejects an operation.
+pub fn plan_battery_usage_iter<'a, B: EnergyStorage + 'a>(
+    forecasts: impl Iterator<Item = Forecast> + 'a,
+    prices: impl Iterator<Item = ElectricityPrice> + 'a,
+    mut battery: B,
+    settings: &'a Settings,
+    charge_threshold: f64,
+) -> impl Iterator<Item = Result<Plan>> + 'a {
+    let mut forecasts = forecasts;
+    let mut prices = prices.peekable();
+    let mut planner = IntervalPlanner::new(settings, charge_threshold, None, &battery);
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let result = (|| {
+            if settings.strategy == Strategy::Carbon {
+                return Err(anyhow!(
+                    "The streaming planner does not support the carbon strategy"
+                ));
+            }
+            if settings.skip_unprofitable_charge {
+                return Err(anyhow!(
+                    "The streaming planner cannot look ahead for `skip_unprofitable_charge`"
+                ));
+            }
+            let Some(forecast) = forecasts.next() else {
+                return Ok(None);
+            };
+
+            // Skip prices of intervals before the forecast
+            while prices
+                .next_if(|price| price.start < forecast.start)
+                .is_some()
+            {}
+            let price = prices
+                .next_if(|price| price.start == forecast.start && price.end == forecast.end)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No price for forecast interval: {} - {}",
+                        forecast.start,
+                        forecast.end
+                    )
+                })?;
+
+            planner
+                .plan(&forecast, &price, &mut battery, || false)
+                .map(Some)
+        })();
+
+        match result {
+            Ok(plan) => plan.map(Ok),
+            Err(error) => {
+                failed = true;
+                Some(Err(error))
+            }
+        }
+    })
+}
+
+/// The per-interval decisions of the price and carbon planners, and the daily
+/// cycle budget they share.
+struct IntervalPlanner<'a> {
+    settings: &'a Settings,
+    // Average or percentile import price
+    charge_threshold: f64,
+    // Carbon intensities and their average, with the carbon strategy
+    carbon: Option<(&'a [CarbonIntensity], f64)>,
+    // Calendar day of the previous interval
+    current_day: Option<NaiveDate>,
+    // Equivalent full cycles at the start of the current calendar day
+    day_start_cycles: f64,
+}
+
+impl<'a> IntervalPlanner<'a> {
+    fn new<B: EnergyStorage>(
+        settings: &'a Settings,
+        charge_threshold: f64,
+        carbon: Option<(&'a [CarbonIntensity], f64)>,
+        battery: &B,
+    ) -> Self {
+        IntervalPlanner {
+            settings,
+            charge_threshold,
+            carbon,
+            current_day: None,
+            day_start_cycles: battery.equivalent_cycles(),
+        }
+    }
+
+    /// Decides the battery usage of one interval, applies it to the battery and logs
+    /// the decision under `DECISION_LOG_TARGET`.
+    ///
+    /// See `plan_battery_usage` for the rules.
+    ///
+    /// # Arguments
+    ///
+    /// * `forecast`: The forecast of the interval.
+    /// * `price`: The price of the same interval.
+    /// * `battery`: The battery, left in the state after the interval.
+    /// * `has_later_use`: Whether energy charged now can be used profitably later;
+    ///   only called with `skip_unprofitable_charge`.
+    ///
+    /// # Returns
+    /// A `Result` containing the `Plan` of the interval.
+    fn plan<B: EnergyStorage>(
+        &mut self,
+        forecast: &Forecast,
+        price: &ElectricityPrice,
+        battery: &mut B,
+        has_later_use: impl FnOnce() -> bool,
+    ) -> Result<Plan> {
+        let mut interval = self.decide(forecast, price, battery, has_later_use)?;
+        if self.settings.record_curtailment {
+            interval.energy_curtailed_wh.get_or_insert(0.0);
+        }
+        log_decision(&interval, self.settings.import_price(price));
+        Ok(interval)
+    }
+
+    /// Returns the reason for a decision in explain mode, or `None` without
+    /// formatting it otherwise.
+    fn reason(&self, describe: impl FnOnce() -> String) -> Option<String> {
+        self.settings.explain.then(describe)
+    }
+
+    /// Decides the battery usage of one interval and applies it to the battery;
+    /// see `plan`.
+    fn decide<B: EnergyStorage>(
+        &mut self,
+        forecast: &Forecast,
+        price: &ElectricityPrice,
+        battery: &mut B,
+        has_later_use: impl FnOnce() -> bool,
+    ) -> Result<Plan> {
+        let settings = self.settings;
+        // Discharge down to the peak-shaving target, which never exceeds the grid limit
+        let shave_limit = settings.shave_limit();
+        let shave_limit_name = if shave_limit < settings.grid_limit {
+            "peak_shave_target"
+        } else {
+            "grid_limit"
+        };
+        let duration_hours = (forecast.end - forecast.start).num_seconds() as f64 / 3600.0; // Duration in hours
+
+        // Imports are paid at the market price plus grid fee and tax
+        let import_price = settings.import_price(price);
+
+        let day = forecast.start.date_naive();
+        if self.current_day != Some(day) {
+            self.current_day = Some(day);
+            self.day_start_cycles = battery.equivalent_cycles();
+        }
+        // Once the daily cycle budget is used up, only peaks are still shaved
+        let cycles_exhausted = settings.max_daily_cycles.is_some_and(|max_cycles| {
+            battery.equivalent_cycles() - self.day_start_cycles >= max_cycles
+        });
+        if cycles_exhausted {
+            debug!("Daily cycle limit reached at {}", forecast.start);
+        }
+
+        // With fixed charge windows, the clock rather than the price decides charging
+        let start_time = forecast.start.time();
+        let charge_window = settings.charge_strategy.in_window(start_time);
+
+        // Risk-averse operators shave against a high quantile of the consumption
+        let planned_consumption = forecast.net_consumption_at(settings.planning_quantile);
+        debug!("{} - {}", planned_consumption, shave_limit);
 
-        // Check if the consumption exceeds the grid limit
-        if forecast.consumption_average_power_interval > grid_limit {
-            info!(
-                "Consumption of {} exeeds the grid limit {}",
-                forecast.consumption_average_power_interval, grid_limit
+        // Check if the consumption net of on-site production exceeds the shaving limit
+        if planned_consumption > shave_limit {
+            debug!(
+                "Consumption of {} exeeds the limit {}",
+                planned_consumption, shave_limit
             );
+            // The excess is in W, like the forecast; the battery's rates are in MW
+            let excess = planned_consumption - shave_limit;
+            // The site needs the whole excess, even when the battery can only cover part of it
+            let requested_discharge_wh = excess * duration_hours;
+
+            // Lithium cells must not be discharged hard when it is too cold
+            if let (Some(min_temp), Some(temp)) =
+                (settings.min_discharge_temp_c, forecast.temperature_c)
+            {
+                if temp < min_temp {
+                    warn!(
+                        "Discharge blocked at {}: temperature {} °C is below {} °C",
+                        forecast.start, temp, min_temp
+                    );
+                    battery.apply_self_discharge(duration_hours);
+
+                    let mut interval = Plan::for_interval(forecast, import_price, 0.0, 0.0);
+                    interval.requested_discharge_wh = Some(requested_discharge_wh);
+                    interval.reason = self.reason(|| {
+                        format!(
+                            "consumption {} > {} {}, but temperature {} < min_discharge_temp_c {}, idle",
+                            planned_consumption, shave_limit_name, shave_limit, temp, min_temp
+                        )
+                    });
+                    return Ok(interval);
+                }
+            }
 
-            let excess = forecast.consumption_average_power_interval - grid_limit;
             debug!("EXCESS: {}", excess);
+            // Only commit the power the battery can sustain for the whole interval
+            let power = w_to_mw(excess).min(battery.available_discharge_power(duration_hours));
             // Calculate energy to discharge to meet the grid limit
-            let discharged_energy = battery
-                .discharge_battery(excess, duration_hours)
+            let discharged = battery
+                .discharge_battery(power, duration_hours)
                 .context("Failed to calculage discharged energy")?; // Handle discharge errors
 
-            info!(
+            debug!(
                 "Discharging battery: {} Wh at {}",
-                (discharged_energy * 1_000_000.0).floor() / 10.0,
-                forecast.start
+                discharged.grid_wh, forecast.start
             );
 
-            plan.push(Plan {
-                start: forecast.start,
-                end: forecast.end,
-                energy_from_battery_wh: (discharged_energy * 1_000_000.0).floor() / 10.0, // Energy used from the battery
-                energy_to_battery_wh: 0.0, // No energy charged
+            let mut interval = Plan::for_interval(forecast, import_price, discharged.grid_wh, 0.0);
+            interval.requested_discharge_wh = Some(requested_discharge_wh);
+            if discharged.grid_wh < requested_discharge_wh {
+                debug!(
+                    "Battery covers {} of the {} Wh excess at {}",
+                    discharged.grid_wh, requested_discharge_wh, forecast.start
+                );
+            }
+            interval.reason = self.reason(|| {
+                format!(
+                    "consumption {} > {} {}, discharged {} MW",
+                    planned_consumption, shave_limit_name, shave_limit, power
+                )
             });
-        } else {
-            // If consumption is below the grid limit, check if we can charge the battery
-            if price.market_price_per_kwh <= average_price {
-                // Using average price directly
-
-                let charge_amount = battery
-                    .charge_battery(1.5, duration_hours)
-                    .context("Failed to charge battery")?; // Handle charge errors
-
-                info!(
-                    "Charging battery: {} Wh at {} (Price: {} EUR/kWh)",
-                    (charge_amount * 1_000_000.0).floor() / 10.0,
-                    forecast.start,
-                    price.market_price_per_kwh
+            Ok(interval)
+        } else if !cycles_exhausted && forecast.net_consumption() < 0.0 {
+            // Store surplus production rather than exporting it, whatever the price
+            let surplus = -forecast.net_consumption();
+            let charged = battery
+                .charge_battery_up_to(w_to_mw(surplus), duration_hours, settings.max_soc)
+                .context("Failed to charge battery from surplus")?;
+
+            debug!(
+                "Charging battery from surplus: {} Wh at {}",
+                charged.grid_wh, forecast.start
+            );
+
+            // The rest is exported, and what the grid connection cannot take is curtailed;
+            // surplus, stored power and export limit are all in W
+            let stored_w = charged.grid_wh / duration_hours;
+            let curtailed_w = (surplus - stored_w - settings.grid_export_limit).max(0.0);
+
+            let mut interval = Plan::for_interval(forecast, import_price, 0.0, 0.0);
+            interval.energy_from_surplus_wh = charged.grid_wh;
+            if curtailed_w > 0.0 {
+                let curtailed = curtailed_w * duration_hours;
+                warn!(
+                    "Curtailing {} Wh of surplus production at {}: the export limit is {}",
+                    curtailed, forecast.start, settings.grid_export_limit
                 );
+                interval.energy_pv_curtailed_wh = Some(curtailed);
+            }
+            interval.reason = self.reason(|| {
+                format!(
+                    "production exceeds consumption by {}, stored {} Wh of surplus",
+                    surplus, charged.grid_wh
+                )
+            });
+            Ok(interval)
+        } else if let Some((intensities, average_intensity)) = self.carbon {
+            // Dispatch on how clean the grid is, regardless of price
+            let intensity = intensity_at(intensities, forecast.start, forecast.end);
+            let mut curtailed = 0.0;
+            let reason;
+            let (energy_from_battery_wh, energy_to_battery_wh) = match intensity {
+                Some(intensity) if !cycles_exhausted && intensity > average_intensity => {
+                    let discharged = battery
+                        .discharge_battery_down_to(
+                            settings.max_discharge_rate(),
+                            duration_hours,
+                            settings.min_soc,
+                        )
+                        .context("Failed to discharge battery")?;
+                    debug!(
+                        "Discharging battery: {} Wh at {} (Carbon: {} gCO2/kWh)",
+                        discharged.grid_wh, forecast.start, intensity
+                    );
+                    reason = self.reason(|| {
+                        format!(
+                            "carbon {} > avg {}, discharged {} MW",
+                            intensity,
+                            average_intensity,
+                            settings.max_discharge_rate()
+                        )
+                    });
+                    (discharged.grid_wh, 0.0)
+                }
+                Some(intensity) if !cycles_exhausted => {
+                    let charge_power = settings.charge_power_mw.min(w_to_mw(
+                        settings.import_headroom(forecast.net_consumption()),
+                    ));
+                    let charged = battery
+                        .charge_battery_up_to(charge_power, duration_hours, settings.max_soc)
+                        .context("Failed to charge battery")?;
+                    debug!(
+                        "Charging battery: {} Wh at {} (Carbon: {} gCO2/kWh)",
+                        charged.grid_wh, forecast.start, intensity
+                    );
+                    curtailed = curtailed_wh(forecast, charge_power, &charged);
+                    reason = self.reason(|| {
+                        format!(
+                            "carbon {} <= avg {}, charged {} MW",
+                            intensity, average_intensity, charge_power
+                        )
+                    });
+                    (0.0, charged.grid_wh)
+                }
+                _ => {
+                    // No carbon data or no cycles left today, stay idle
+                    battery.apply_self_discharge(duration_hours);
+                    reason = self.reason(|| {
+                        if cycles_exhausted {
+                            "daily cycle limit reached, idle".to_string()
+                        } else {
+                            "no carbon intensity, idle".to_string()
+                        }
+                    });
+                    (0.0, 0.0)
+                }
+            };
 
-                plan.push(Plan {
-                    start: forecast.start,
-                    end: forecast.end,
-                    energy_from_battery_wh: 0.0, // No energy used from the battery
-                    energy_to_battery_wh: (charge_amount * 1_000_000.0).floor() / 10.0, // Energy charged to the battery
-                });
+            let mut interval = Plan::for_interval(
+                forecast,
+                import_price,
+                energy_from_battery_wh,
+                energy_to_battery_wh,
+            );
+            if settings.record_curtailment {
+                interval.energy_curtailed_wh = Some(curtailed);
+            }
+            interval.reason = reason;
+            Ok(interval)
+        } else if !cycles_exhausted
+            && charge_window.unwrap_or_else(|| {
+                import_price < 0.0
+                    || (import_price <= self.charge_threshold - settings.charge_price_margin
+                        && (!settings.skip_unprofitable_charge || has_later_use()))
+            })
+        {
+            // If consumption is below the grid limit, check if we can charge the battery
+            // Optionally skip charging when the energy can never be used profitably,
+            // but always charge when the price is negative and consuming pays
+            // Throttle the charge power so the import stays within the connection's limit
+            let charge_power = settings.charge_power_mw.min(w_to_mw(
+                settings.import_headroom(forecast.net_consumption()),
+            ));
+            let charged = battery
+                .charge_battery_up_to(charge_power, duration_hours, settings.max_soc)
+                .context("Failed to charge battery")?; // Handle charge errors
+
+            debug!(
+                "Charging battery: {} Wh at {} (Price: {} EUR/kWh)",
+                charged.grid_wh, forecast.start, import_price
+            );
+
+            let mut interval = Plan::for_interval(forecast, import_price, 0.0, charged.grid_wh);
+            if settings.record_curtailment {
+                interval.energy_curtailed_wh = Some(curtailed_wh(forecast, charge_power, &charged));
+            }
+            interval.reason = self.reason(|| {
+                if charge_window.is_some() {
+                    format!(
+                        "time {} in a charge window, charged {} MW",
+                        start_time, charge_power
+                    )
+                } else if import_price < 0.0 {
+                    format!("price {} < 0, charged {} MW", import_price, charge_power)
+                } else {
+                    format!(
+                        "price {} <= threshold {}, charged {} MW",
+                        import_price, self.charge_threshold, charge_power
+                    )
+                }
+            });
+            Ok(interval)
+        } else if !cycles_exhausted
+            && settings
+                .sell_threshold_per_kwh
+                .is_some_and(|threshold| price.market_price_per_kwh >= threshold)
+            && import_price >= self.charge_threshold + settings.charge_price_margin
+            && battery.energy_available() > 0.0
+        {
+            // Sell stored energy back to the grid while the price is high, within the export limit
+            let export_power = settings.max_discharge_rate().min(w_to_mw(
+                settings.export_headroom(forecast.net_consumption()),
+            ));
+            let exported = battery
+                .discharge_battery_down_to(export_power, duration_hours, settings.min_soc)
+                .context("Failed to discharge battery")?;
+            let feed_in_price = settings.feed_in_price(price);
+
+            debug!(
+                "Exporting to grid: {} Wh at {} (Feed-in price: {} EUR/kWh)",
+                exported.grid_wh, forecast.start, feed_in_price
+            );
+
+            let mut interval = Plan::for_interval(forecast, import_price, 0.0, 0.0);
+            interval.energy_to_grid_wh = exported.grid_wh;
+            interval.battery_revenue_eur = exported.grid_wh / 1_000.0 * feed_in_price;
+            interval.reason = self.reason(|| {
+                format!(
+                    "price {} >= sell_threshold {}, exported {} Wh",
+                    price.market_price_per_kwh,
+                    settings.sell_threshold_per_kwh.unwrap_or_default(),
+                    exported.grid_wh
+                )
+            });
+            Ok(interval)
+        } else {
+            // No action needed if price is not favorable for charging
+            battery.apply_self_discharge(duration_hours);
+            let mut interval = Plan::for_interval(forecast, import_price, 0.0, 0.0);
+            interval.reason = self.reason(|| {
+                let threshold = self.charge_threshold;
+                if cycles_exhausted {
+                    "daily cycle limit reached, idle".to_string()
+                } else if charge_window.is_some() {
+                    format!("time {} outside the charge windows, idle", start_time)
+                } else if import_price <= threshold - settings.charge_price_margin {
+                    format!(
+                        "price {} <= threshold {}, but no later use, idle",
+                        import_price, threshold
+                    )
+                } else if import_price <= threshold + settings.charge_price_margin {
+                    format!(
+                        "price {} within charge_price_margin of threshold {}, idle",
+                        import_price, threshold
+                    )
+                } else {
+                    format!("price {} > threshold {}, idle", import_price, threshold)
+                }
+            });
+            Ok(interval)
+        }
+    }
+}
+
+/// Returns the grid energy a charge at `power_mw` could not draw because the
+/// battery was full, logging it if any.
+///
+/// # Arguments
+///
+/// * `forecast`: The forecast of the charged interval.
+/// * `power_mw`: The charging power requested from the battery.
+/// * `charged`: The energy the charge actually moved.
+///
+/// # Returns
+/// The curtailed grid energy in watt-hours, never negative.
+fn curtailed_wh(forecast: &Forecast, power_mw: f64, charged: &EnergyFlow) -> f64 {
+    let duration_hours = (forecast.end - forecast.start).num_seconds() as f64 / 3600.0;
+    let requested_wh = convert(power_mw * duration_hours, EnergyUnit::Wh);
+    let curtailed = (requested_wh - charged.grid_wh).max(0.0);
+    if curtailed > DIFF_TOLERANCE_WH {
+        debug!(
+            "Charging curtailed by {} Wh at {} due to full battery",
+            curtailed, forecast.start
+        );
+    }
+    curtailed
+}
+
+/// Logs the decision of a planned interval as a structured record under
+/// `DECISION_LOG_TARGET`.
+///
+/// # Arguments
+///
+/// * `interval`: The planned interval.
+/// * `price_per_kwh`: The import price of the interval.
+fn log_decision(interval: &Plan, price_per_kwh: f64) {
+    let (action, energy_wh) = if interval.energy_to_battery_wh > 0.0 {
+        ("charge", interval.energy_to_battery_wh)
+    } else if interval.energy_from_surplus_wh > 0.0 {
+        ("charge_surplus", interval.energy_from_surplus_wh)
+    } else if interval.energy_from_battery_wh > 0.0 {
+        ("discharge", interval.energy_from_battery_wh)
+    } else if interval.energy_to_grid_wh > 0.0 {
+        ("export", interval.energy_to_grid_wh)
+    } else {
+        ("idle", 0.0)
+    };
+
+    let start = interval.start.to_rfc3339();
+    info!(
+        target: DECISION_LOG_TARGET,
+        interval = start.as_str(),
+        action = action,
+        energy_wh = energy_wh,
+        price_per_kwh = price_per_kwh;
+        "{} {} Wh at {} (Price: {} EUR/kWh)",
+        action,
+        energy_wh,
+        start,
+        price_per_kwh
+    );
+}
+
+/// Plans the battery usage with a receding horizon.
+///
+/// For each interval, the price planner runs over the next `horizon_hours` only,
+/// with the charge threshold taken from the prices in that window. The decision
+/// for the first interval is committed, the battery is advanced to the state after
+/// it, and the window slides forward by one interval. This mirrors an operator
+/// re-planning as forecasts update, and ignores prices beyond the horizon. The
+/// daily cycle budget is counted from the start of each window.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasted consumption intervals, in chronological order.
+/// * `prices`: The electricity prices covering the forecasts.
+/// * `battery`: The battery being planned for, in its initial state.
+/// * `settings`: The configuration settings.
+/// * `horizon_hours`: The length of the planning window, in hours.
+///
+/// # Returns
+/// A `Result` containing the committed `Plan` of every interval.
+///
+/// # Errors
+/// Returns an error if `horizon_hours` is not positive, a forecast interval has no
+/// matching price, or the carbon strategy is configured, which needs intensities.
+pub fn plan_rolling(
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+    mut battery: Battery,
+    settings: &Settings,
+    horizon_hours: f64,
+) -> Result<Vec<Plan>> {
+    if horizon_hours <= 0.0 {
+        return Err(anyhow!("The planning horizon must be positive"));
+    }
+    let horizon = Duration::seconds((horizon_hours * 3600.0).round() as i64);
+
+    let mut committed = Vec::with_capacity(forecasts.len());
+    for (index, forecast) in forecasts.iter().enumerate() {
+        let window_end = forecast.start + horizon;
+        let window: Vec<Forecast> = forecasts[index..]
+            .iter()
+            .take_while(|next| next.start < window_end)
+            .cloned()
+            .collect();
+        let window_prices: Vec<ElectricityPrice> = prices
+            .iter()
+            .filter(|price| price.start >= forecast.start && price.start < window_end)
+            .cloned()
+            .collect();
+
+        let threshold = charge_threshold(&window_prices, settings);
+        let step = plan_battery_usage(
+            window,
+            window_prices,
+            &mut battery.clone(),
+            settings,
+            threshold,
+            None,
+        )?;
+
+        // Commit the first interval and carry the battery over to the next window
+        let first = step
+            .into_iter()
+            .next()
+            .context("The planning window is empty")?;
+        simulate_plan(&mut battery, std::slice::from_ref(&first))
+            .context("Failed to advance the battery to the next window")?;
+        debug!(
+            "Committed interval at {} with a threshold of {}, charge now {} MWh",
+            first.start, threshold, battery.charge
+        );
+        committed.push(first);
+    }
+
+    info!(
+        "Planned {} intervals with a {} hour rolling horizon",
+        committed.len(),
+        horizon_hours
+    );
+
+    Ok(committed)
+}
+
+/// Calculates the import price at or below which the planner charges.
+///
+/// This is the percentile of the prices set by the `below_percentile` charge strategy
+/// or `charge_percentile`, or their average by default, with the grid fee and tax
+/// included. With fixed charge windows, the average still sets where exporting starts.
+///
+/// # Arguments
+///
+/// * `prices`: The electricity prices of the planning horizon.
+/// * `settings`: The configuration settings.
+///
+/// # Returns
+/// The charge threshold per kWh, or `NaN` if `prices` is empty.
+pub fn charge_threshold(prices: &[ElectricityPrice], settings: &Settings) -> f64 {
+    let import_prices: Vec<ElectricityPrice> = prices
+        .iter()
+        .map(|price| ElectricityPrice {
+            market_price_per_kwh: settings.import_price(price),
+            ..price.clone()
+        })
+        .collect();
+
+    let percentile = match settings.charge_strategy {
+        ChargeStrategy::BelowPercentile(percentile) => Some(percentile),
+        ChargeStrategy::BelowAverage => settings.charge_percentile,
+        ChargeStrategy::FixedWindows(_) => None,
+    };
+    match percentile {
+        Some(percentile) => price_percentile(&import_prices, percentile),
+        None => {
+            import_prices
+                .iter()
+                .map(|price| price.market_price_per_kwh)
+                .sum::<f64>()
+                / import_prices.len() as f64
+        }
+    }
+}
+
+/// Plans the battery usage that best meets the configured `objective` over the horizon.
+///
+/// The stored energy between `min_charge` and the battery's effective capacity is
+/// discretized into `soc_levels` equal steps, and dynamic programming finds the
+/// sequence of levels with the lowest total of the objective: the grid cost
+/// (`Objective::MinCost`, the default), the energy imported from the grid
+/// (`Objective::MaxSelfSufficiency`) or the energy moved through the battery
+/// (`Objective::MinCycles`). Each step is limited to the charging or discharging rate
+/// on the grid side, discharging never exceeds the site's net consumption (no export),
+/// and imports above the grid limit are avoided wherever the battery can cover them.
+/// The initial charge is rounded to the nearest level, and self-discharge is not
+/// modelled.
+///
+/// # Arguments
+///
+/// * `forecasts`: A vector of forecasted energy consumption data.
+/// * `prices`: A vector of day-ahead electricity prices.
+/// * `battery`: The battery being planned for, in its initial state.
+/// * `settings`: The configuration settings.
+/// * `soc_levels`: The number of steps the stored energy is divided into.
+///
+/// # Returns
+/// A `Result` containing a vector of `Plan` structs if successful, or an error if any step fails.
+///
+/// # Errors
+/// Returns an error if `soc_levels` is zero or a forecast interval has no matching price.
+pub fn plan_battery_usage_optimal(
+    forecasts: Vec<Forecast>,
+    prices: Vec<ElectricityPrice>,
+    battery: Battery,
+    settings: &Settings,
+    soc_levels: usize,
+) -> Result<Vec<Plan>> {
+    search_optimal_plan(forecasts, prices, battery, settings, soc_levels, None)?
+        .context("The optimal search stopped without a time budget")
+}
+
+/// A plan made within a time budget, and whether it had to fall back to the greedy planner.
+pub struct BudgetedPlan {
+    /// The optimal plan, or the greedy one if the search ran out of time.
+    pub plan: Vec<Plan>,
+    /// Whether the optimal search exceeded the budget and the plan is the greedy one.
+    pub fell_back_to_greedy: bool,
+}
+
+/// Plans like `plan_battery_usage_optimal`, but gives up on the optimal search once
+/// `time_budget` has elapsed and returns the plan of `plan_battery_usage` instead, for
+/// callers that must have a plan by a deadline.
+///
+/// The greedy fallback charges below the import price of `charge_threshold` and does
+/// not use carbon intensities. The budget is checked between intervals, so the search
+/// may overrun it by the time of one interval.
+///
+/// # Arguments
+///
+/// * `forecasts`: A vector of forecasted energy consumption data.
+/// * `prices`: A vector of day-ahead electricity prices.
+/// * `battery`: The battery being planned for, in its initial state.
+/// * `settings`: The configuration settings.
+/// * `soc_levels`: The number of steps the stored energy is divided into.
+/// * `time_budget`: How long the optimal search may take.
+///
+/// # Returns
+/// A `Result` containing the `BudgetedPlan`, flagged if it is the greedy fallback.
+///
+/// # Errors
+/// Returns an error if `soc_levels` is zero, a forecast interval has no matching
+/// price, or the greedy fallback fails.
+pub fn plan_battery_usage_within(
+    forecasts: Vec<Forecast>,
+    prices: Vec<ElectricityPrice>,
+    battery: Battery,
+    settings: &Settings,
+    soc_levels: usize,
+    time_budget: std::time::Duration,
+) -> Result<BudgetedPlan> {
+    let deadline = Instant::now() + time_budget;
+    if let Some(plan) = search_optimal_plan(
+        forecasts.clone(),
+        prices.clone(),
+        battery.clone(),
+        settings,
+        soc_levels,
+        Some(deadline),
+    )? {
+        return Ok(BudgetedPlan {
+            plan,
+            fell_back_to_greedy: false,
+        });
+    }
+
+    warn!(
+        "Optimal search exceeded its time budget of {:?}, falling back to the greedy plan",
+        time_budget
+    );
+    let threshold = charge_threshold(&prices, settings);
+    let plan = plan_battery_usage(
+        forecasts,
+        prices,
+        &mut battery.clone(),
+        settings,
+        threshold,
+        None,
+    )
+    .context("Failed to plan the greedy fallback")?;
+    Ok(BudgetedPlan {
+        plan,
+        fell_back_to_greedy: true,
+    })
+}
+
+/// Runs the dynamic programming of `plan_battery_usage_optimal`, giving up with `None`
+/// once `deadline` has passed.
+fn search_optimal_plan(
+    forecasts: Vec<Forecast>,
+    prices: Vec<ElectricityPrice>,
+    battery: Battery,
+    settings: &Settings,
+    soc_levels: usize,
+    deadline: Option<Instant>,
+) -> Result<Option<Vec<Plan>>> {
+    if soc_levels == 0 {
+        return Err(anyhow!("The optimal planner needs at least one SoC level"));
+    }
+    let prices = align_prices(&forecasts, &prices)?;
+
+    let min_charge = settings.min_charge.min(battery.effective_capacity());
+    let step = (battery.effective_capacity() - min_charge) / soc_levels as f64; // MWh per level
+    let level_energy = |level: usize| min_charge + level as f64 * step;
+    let initial_level = if step > 0.0 {
+        (((battery.charge - min_charge) / step).round().max(0.0) as usize).min(soc_levels)
+    } else {
+        0
+    };
+
+    let charge_efficiency = settings.charge_efficiency();
+    let discharge_efficiency = settings.discharge_efficiency();
+
+    // Grid-side energies (Wh) of moving between two levels, if the move is feasible
+    let transition = |forecast: &Forecast, from: usize, to: usize| -> Option<(f64, f64)> {
+        let duration_hours = (forecast.end - forecast.start).num_seconds() as f64 / 3600.0;
+        let max_charge_wh = convert(settings.max_charge_rate() * duration_hours, EnergyUnit::Wh);
+        let max_discharge_wh = convert(
+            settings.max_discharge_rate() * duration_hours,
+            EnergyUnit::Wh,
+        );
+        let stored_change_wh = convert(level_energy(to) - level_energy(from), EnergyUnit::Wh);
+
+        let (from_battery_wh, to_battery_wh) = if stored_change_wh >= 0.0 {
+            (0.0, stored_change_wh / charge_efficiency)
+        } else {
+            (-stored_change_wh * discharge_efficiency, 0.0)
+        };
+
+        let load_wh = forecast.net_consumption().max(0.0) * duration_hours;
+        let tolerance_wh = 1e-6;
+        if to_battery_wh > max_charge_wh + tolerance_wh
+            || from_battery_wh > max_discharge_wh + tolerance_wh
+            || from_battery_wh > load_wh + tolerance_wh
+        {
+            return None;
+        }
+        Some((from_battery_wh, to_battery_wh))
+    };
+
+    // Objective of an interval, with a penalty for importing above the grid limit
+    let import_cost = |forecast: &Forecast, price: f64, from_wh: f64, to_wh: f64| -> f64 {
+        let duration_hours = (forecast.end - forecast.start).num_seconds() as f64 / 3600.0;
+        let import_wh = forecast.net_consumption() * duration_hours + to_wh - from_wh;
+        let over_limit_wh = (import_wh - settings.grid_limit * duration_hours).max(0.0);
+        let objective = match settings.objective {
+            Objective::MinCost => import_wh / 1_000.0 * price,
+            Objective::MaxSelfSufficiency => import_wh.max(0.0) / 1_000.0,
+            Objective::MinCycles => (from_wh + to_wh) / 1_000.0,
+        };
+        objective + over_limit_wh / 1_000.0 * GRID_LIMIT_PENALTY_EUR_PER_KWH
+    };
+
+    // Forward pass: cheapest cost to reach each level, and the level it came from
+    let mut cost = vec![f64::INFINITY; soc_levels + 1];
+    cost[initial_level] = 0.0;
+    let mut previous: Vec<Vec<usize>> = Vec::with_capacity(forecasts.len());
+
+    for (forecast, price) in forecasts.iter().zip(prices.iter()) {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        let mut next_cost = vec![f64::INFINITY; soc_levels + 1];
+        let mut next_previous = vec![0; soc_levels + 1];
+
+        for from in (0..=soc_levels).filter(|level| cost[*level].is_finite()) {
+            for to in 0..=soc_levels {
+                if let Some((from_wh, to_wh)) = transition(forecast, from, to) {
+                    let total = cost[from]
+                        + import_cost(forecast, settings.import_price(price), from_wh, to_wh);
+                    if total < next_cost[to] {
+                        next_cost[to] = total;
+                        next_previous[to] = from;
+                    }
+                }
+            }
+        }
+
+        cost = next_cost;
+        previous.push(next_previous);
+    }
+
+    // Backward pass: follow the cheapest final level back to the start
+    let mut levels = vec![0; forecasts.len() + 1];
+    levels[forecasts.len()] = (0..=soc_levels)
+        .min_by(|a, b| cost[*a].total_cmp(&cost[*b]))
+        .unwrap_or(initial_level);
+    for index in (0..forecasts.len()).rev() {
+        levels[index] = previous[index][levels[index + 1]];
+    }
+
+    let mut plan = Vec::with_capacity(forecasts.len());
+    for (index, (forecast, price)) in forecasts.iter().zip(prices.iter()).enumerate() {
+        // Staying on the same level is always feasible, so the path is too
+        let (from_wh, to_wh) = transition(forecast, levels[index], levels[index + 1])
+            .context("The optimal path contains an infeasible transition")?;
+        debug!(
+            "Optimal plan at {}: {} Wh from battery, {} Wh to battery",
+            forecast.start, from_wh, to_wh
+        );
+        plan.push(Plan::for_interval(
+            forecast,
+            settings.import_price(price),
+            from_wh,
+            to_wh,
+        ));
+    }
+
+    info!(
+        "Planned {} intervals optimally for {:?} over {} SoC levels",
+        plan.len(),
+        settings.objective,
+        soc_levels
+    );
+
+    Ok(Some(plan))
+}
+
+/// Plans the battery as a pure trading asset, ignoring the site's consumption.
+///
+/// Energy is bought at the import price (grid fee and tax included) and sold to the
+/// grid at the feed-in price, so the battery charges in the cheapest intervals and
+/// discharges in the most expensive ones whenever the spread covers the losses. As in
+/// `plan_battery_usage_optimal`, the stored energy between `min_charge` and the
+/// battery's effective capacity is discretized into `ARBITRAGE_SOC_LEVELS` steps and
+/// dynamic programming finds the most profitable sequence of levels. Each step is
+/// limited to the charging or discharging rate on the grid side, the equivalent full
+/// cycles of every calendar day (UTC) stay within `max_daily_cycles` if set, and the
+/// plan ends with at least the initial charge, so the profit comes from trading rather
+/// than from selling what was already stored. Self-discharge is not modelled.
+///
+/// # Arguments
+///
+/// * `prices`: The electricity prices to trade on.
+/// * `battery`: The battery being planned for, in its initial state.
+/// * `settings`: The configuration settings, for the efficiencies, feed-in factor,
+///   grid fee, tax and daily cycle budget.
+///
+/// # Returns
+/// A `Result` containing one `Plan` per price interval, in chronological order, with
+/// the exported energy in `energy_to_grid_wh`.
+///
+/// # Errors
+/// Returns an error if a price interval does not have a positive duration.
+pub fn plan_arbitrage(
+    mut prices: Vec<ElectricityPrice>,
+    battery: Battery,
+    settings: &Settings,
+) -> Result<Vec<Plan>> {
+    prices.sort_by_key(|price| price.start);
+    if let Some(price) = prices.iter().find(|price| price.end <= price.start) {
+        return Err(anyhow!(
+            "Price interval at {} does not have a positive duration",
+            price.start
+        ));
+    }
+
+    let levels = ARBITRAGE_SOC_LEVELS;
+    let min_charge = settings.min_charge.min(battery.effective_capacity());
+    let step = (battery.effective_capacity() - min_charge) / levels as f64; // MWh per level
+    let initial_level = if step > 0.0 {
+        (((battery.charge - min_charge) / step).round().max(0.0) as usize).min(levels)
+    } else {
+        0
+    };
+
+    // Each level moved in or out of the pack is half a cycle of `step / capacity`
+    let max_moves = match settings.max_daily_cycles {
+        Some(max_cycles) if step > 0.0 => {
+            (max_cycles * 2.0 * battery.capacity() / step + 1e-9).floor() as usize
+        }
+        _ => usize::MAX,
+    };
+    // Level moves tracked per day; without a cycle budget there is nothing to track
+    let move_states = if max_moves == usize::MAX {
+        1
+    } else {
+        max_moves + 1
+    };
+    let state = |level: usize, moves: usize| level * move_states + moves;
+
+    let charge_efficiency = settings.charge_efficiency();
+    let discharge_efficiency = settings.discharge_efficiency();
+
+    // Grid-side energies (Wh) of moving between two levels, if the move is feasible
+    let transition = |price: &ElectricityPrice, from: usize, to: usize| -> Option<(f64, f64)> {
+        let duration_hours = (price.end - price.start).num_seconds() as f64 / 3600.0;
+        let max_charge_wh = convert(battery.max_charge_rate() * duration_hours, EnergyUnit::Wh);
+        let max_discharge_wh = convert(
+            battery.max_discharge_rate() * duration_hours,
+            EnergyUnit::Wh,
+        );
+        let stored_change_wh = convert((to as f64 - from as f64) * step, EnergyUnit::Wh);
+
+        let (to_grid_wh, to_battery_wh) = if stored_change_wh >= 0.0 {
+            (0.0, stored_change_wh / charge_efficiency)
+        } else {
+            (-stored_change_wh * discharge_efficiency, 0.0)
+        };
+
+        let tolerance_wh = 1e-6;
+        if to_battery_wh > max_charge_wh + tolerance_wh
+            || to_grid_wh > max_discharge_wh + tolerance_wh
+        {
+            return None;
+        }
+        Some((to_grid_wh, to_battery_wh))
+    };
+
+    // Forward pass: cheapest net cost to reach each state, and the state it came from
+    let mut cost = vec![f64::INFINITY; (levels + 1) * move_states];
+    cost[state(initial_level, 0)] = 0.0;
+    let mut previous: Vec<Vec<usize>> = Vec::with_capacity(prices.len());
+    let mut current_day = prices.first().map(|price| price.start.date_naive());
+
+    for price in &prices {
+        // The cycle budget starts over on every calendar day
+        let day = price.start.date_naive();
+        let new_day = current_day != Some(day);
+        current_day = Some(day);
+
+        let buy_price = settings.import_price(price);
+        let sell_price = settings.feed_in_price(price);
+        // Levels within reach at the rates; farther moves are never feasible
+        let duration_hours = (price.end - price.start).num_seconds() as f64 / 3600.0;
+        let max_stored_change = (battery.max_charge_rate() * charge_efficiency)
+            .max(battery.max_discharge_rate() / discharge_efficiency)
+            * duration_hours;
+        let reach = if step > 0.0 {
+            (max_stored_change / step).ceil() as usize
+        } else {
+            0
+        };
+        let mut next_cost = vec![f64::INFINITY; cost.len()];
+        let mut next_previous = vec![0; cost.len()];
+
+        for from in (0..cost.len()).filter(|index| cost[*index].is_finite()) {
+            let from_level = from / move_states;
+            let moves = if new_day { 0 } else { from % move_states };
+
+            for to_level in from_level.saturating_sub(reach)..=(from_level + reach).min(levels) {
+                let Some((to_grid_wh, to_battery_wh)) = transition(price, from_level, to_level)
+                else {
+                    continue;
+                };
+                let next_moves = if move_states == 1 {
+                    0
+                } else {
+                    moves + from_level.abs_diff(to_level)
+                };
+                if next_moves >= move_states {
+                    continue;
+                }
+
+                let to = state(to_level, next_moves);
+                let total = cost[from] + to_battery_wh / 1_000.0 * buy_price
+                    - to_grid_wh / 1_000.0 * sell_price;
+                if total < next_cost[to] {
+                    next_cost[to] = total;
+                    next_previous[to] = from;
+                }
+            }
+        }
+
+        cost = next_cost;
+        previous.push(next_previous);
+    }
+
+    // Backward pass: follow the cheapest final state that keeps the initial charge
+    let mut states = vec![0; prices.len() + 1];
+    states[prices.len()] = (0..cost.len())
+        .filter(|index| index / move_states >= initial_level)
+        .min_by(|a, b| cost[*a].total_cmp(&cost[*b]))
+        .unwrap_or(state(initial_level, 0));
+    for index in (0..prices.len()).rev() {
+        states[index] = previous[index][states[index + 1]];
+    }
+
+    let mut plan = Vec::with_capacity(prices.len());
+    for (index, price) in prices.iter().enumerate() {
+        let (to_grid_wh, to_battery_wh) = transition(
+            price,
+            states[index] / move_states,
+            states[index + 1] / move_states,
+        )
+        .context("The arbitrage path contains an infeasible transition")?;
+
+        let sell_price = settings.feed_in_price(price);
+        plan.push(Plan {
+            start: price.start,
+            end: price.end,
+            energy_from_battery_wh: 0.0,
+            requested_discharge_wh: None,
+            energy_to_battery_wh: to_battery_wh,
+            energy_to_grid_wh: to_grid_wh,
+            energy_from_surplus_wh: 0.0,
+            grid_cost_eur: to_battery_wh / 1_000.0 * settings.import_price(price),
+            battery_revenue_eur: to_grid_wh / 1_000.0 * sell_price,
+            energy_curtailed_wh: None,
+            energy_pv_curtailed_wh: None,
+            reason: None,
+        });
+    }
+
+    info!(
+        "Planned arbitrage over {} intervals: {} Wh bought, {} Wh sold",
+        plan.len(),
+        plan.iter()
+            .map(|interval| interval.energy_to_battery_wh)
+            .sum::<f64>(),
+        plan.iter()
+            .map(|interval| interval.energy_to_grid_wh)
+            .sum::<f64>()
+    );
+
+    Ok(plan)
+}
+
+/// Checks that no interval of a plan both charges and discharges the battery, which a
+/// single inverter cannot do, so such a plan points to a planner bug.
+///
+/// Charging includes the surplus production stored, and discharging the energy
+/// exported to the grid.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan to check.
+///
+/// # Returns
+/// A `Result` that is `Ok` if every interval only charges, only discharges or is idle.
+///
+/// # Errors
+/// Returns an error naming the first interval with both flows.
+pub fn check_simultaneous_flows(plan: &[Plan]) -> Result<()> {
+    for interval in plan {
+        let charge_wh = interval.energy_to_battery_wh + interval.energy_from_surplus_wh;
+        let discharge_wh = interval.energy_from_battery_wh + interval.energy_to_grid_wh;
+        if charge_wh > 0.0 && discharge_wh > 0.0 {
+            return Err(anyhow!(
+                "Interval at {} both charges {} Wh and discharges {} Wh",
+                interval.start,
+                charge_wh,
+                discharge_wh
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Replays a plan against a battery to check that it can actually be executed.
+///
+/// Each interval's charging and discharging is applied in order, and idle intervals
+/// apply self-discharge, as the planner does. The replay fails as soon as an interval
+/// needs more than the charging or discharging rate, or more energy than fits between
+/// the battery's charge and its capacity or `min_charge`.
+///
+/// # Arguments
+///
+/// * `battery`: The battery in its initial state; it is left in the final state.
+/// * `plan`: The battery usage plan to replay.
+///
+/// # Returns
+/// A `Result` containing a copy of the battery's final state.
+///
+/// # Errors
+/// Returns an error naming the first interval that breaks a constraint, or that both
+/// charges and discharges (see `check_simultaneous_flows`).
+pub fn simulate_plan(battery: &mut Battery, plan: &[Plan]) -> Result<Battery> {
+    check_simultaneous_flows(plan)?;
+
+    // Tolerance for rounding in the planned energies, in Wh
+    let tolerance_wh = 1e-6;
+
+    for interval in plan {
+        let duration_hours = (interval.end - interval.start).num_seconds() as f64 / 3600.0;
+        let max_charge_wh = convert(battery.max_charge_rate() * duration_hours, EnergyUnit::Wh);
+        let max_discharge_wh = convert(
+            battery.max_discharge_rate() * duration_hours,
+            EnergyUnit::Wh,
+        );
+        let charge_wh = interval.energy_to_battery_wh + interval.energy_from_surplus_wh;
+        let discharge_wh = interval.energy_from_battery_wh + interval.energy_to_grid_wh;
+
+        if charge_wh > max_charge_wh + tolerance_wh {
+            return Err(anyhow!(
+                "Interval at {} exceeds the max charge rate of {} MW",
+                interval.start,
+                battery.max_charge_rate()
+            ));
+        }
+        if discharge_wh > max_discharge_wh + tolerance_wh {
+            return Err(anyhow!(
+                "Interval at {} exceeds the max discharge rate of {} MW",
+                interval.start,
+                battery.max_discharge_rate()
+            ));
+        }
+
+        if charge_wh > 0.0 {
+            let charged_wh = convert(
+                battery.charge_energy(to_mwh(charge_wh, EnergyUnit::Wh), duration_hours)?,
+                EnergyUnit::Wh,
+            );
+            if charged_wh < charge_wh - tolerance_wh {
+                return Err(anyhow!(
+                    "Interval at {} charges {} Wh, but only {} Wh fit below the capacity",
+                    interval.start,
+                    charge_wh,
+                    charged_wh
+                ));
+            }
+        }
+
+        if discharge_wh > 0.0 {
+            let discharged_wh = convert(
+                battery.discharge_energy(to_mwh(discharge_wh, EnergyUnit::Wh), duration_hours)?,
+                EnergyUnit::Wh,
+            );
+            if discharged_wh < discharge_wh - tolerance_wh {
+                return Err(anyhow!(
+                    "Interval at {} discharges {} Wh, but only {} Wh are available above the minimum charge",
+                    interval.start,
+                    discharge_wh,
+                    discharged_wh
+                ));
+            }
+        }
+
+        if charge_wh <= 0.0 && discharge_wh <= 0.0 {
+            battery.apply_self_discharge(duration_hours);
+        }
+    }
+
+    Ok(battery.clone())
+}
+
+/// Rounds the energies of a plan to a number of decimals in Wh, half to even, so
+/// that the saved plan is reproducible across runs and platforms.
+///
+/// The costs and revenues are left as they are.
+///
+/// # Arguments
+///
+/// * `plan`: The plan to round.
+/// * `decimals`: The number of decimals to keep, in Wh.
+pub fn round_plan(plan: &mut [Plan], decimals: u32) {
+    let round = |energy_wh: f64| round_wh(to_mwh(energy_wh, EnergyUnit::Wh), decimals);
+    for interval in plan {
+        interval.energy_from_battery_wh = round(interval.energy_from_battery_wh);
+        interval.requested_discharge_wh = interval.requested_discharge_wh.map(round);
+        interval.energy_to_battery_wh = round(interval.energy_to_battery_wh);
+        interval.energy_to_grid_wh = round(interval.energy_to_grid_wh);
+        interval.energy_from_surplus_wh = round(interval.energy_from_surplus_wh);
+        interval.energy_curtailed_wh = interval.energy_curtailed_wh.map(round);
+        interval.energy_pv_curtailed_wh = interval.energy_pv_curtailed_wh.map(round);
+    }
+}
+
+/// Compares two plans interval by interval, e.g. to see where two strategies differ.
+///
+/// The plans are aligned on interval start time. An interval is reported if its
+/// charged or discharged energy differs by more than a milliwatt-hour, or if only
+/// one of the plans has it.
+///
+/// # Arguments
+///
+/// * `a`: The first plan.
+/// * `b`: The second plan.
+///
+/// # Returns
+/// The differing intervals in chronological order; empty if the plans match.
+pub fn diff_plans(a: &[Plan], b: &[Plan]) -> Vec<PlanDiff> {
+    let mut intervals: BTreeMap<DateTime<Utc>, (Option<&Plan>, Option<&Plan>)> = BTreeMap::new();
+    for interval in a {
+        intervals.entry(interval.start).or_default().0 = Some(interval);
+    }
+    for interval in b {
+        intervals.entry(interval.start).or_default().1 = Some(interval);
+    }
+
+    let charged = |interval: &Plan| interval.energy_to_battery_wh + interval.energy_from_surplus_wh;
+    let discharged = |interval: &Plan| interval.energy_from_battery_wh + interval.energy_to_grid_wh;
+    let net_cost = |interval: &Plan| interval.grid_cost_eur - interval.battery_revenue_eur;
+
+    let mut diffs = Vec::new();
+    for (start, (interval_a, interval_b)) in intervals {
+        let charge_a_wh = interval_a.map(charged);
+        let charge_b_wh = interval_b.map(charged);
+        let discharge_a_wh = interval_a.map(discharged);
+        let discharge_b_wh = interval_b.map(discharged);
+        let charge_delta_wh = charge_b_wh.unwrap_or(0.0) - charge_a_wh.unwrap_or(0.0);
+        let discharge_delta_wh = discharge_b_wh.unwrap_or(0.0) - discharge_a_wh.unwrap_or(0.0);
+
+        let in_both = interval_a.is_some() && interval_b.is_some();
+        if in_both
+            && charge_delta_wh.abs() <= DIFF_TOLERANCE_WH
+            && discharge_delta_wh.abs() <= DIFF_TOLERANCE_WH
+        {
+            continue;
+        }
+
+        diffs.push(PlanDiff {
+            start,
+            end: interval_a
+                .or(interval_b)
+                .map_or(start, |interval| interval.end),
+            charge_a_wh,
+            charge_b_wh,
+            charge_delta_wh,
+            discharge_a_wh,
+            discharge_b_wh,
+            discharge_delta_wh,
+            cost_delta_eur: interval_b.map_or(0.0, net_cost) - interval_a.map_or(0.0, net_cost),
+        });
+    }
+
+    debug!("Plans differ in {} intervals", diffs.len());
+    diffs
+}
+
+/// Returns how much more the second plan costs than the first over the whole
+/// horizon: the difference in charging cost minus revenue, in EUR.
+///
+/// # Arguments
+///
+/// * `a`: The first plan.
+/// * `b`: The second plan.
+pub fn plan_cost_delta_eur(a: &[Plan], b: &[Plan]) -> f64 {
+    plan_totals(a).total_savings_eur - plan_totals(b).total_savings_eur
+}
+
+/// Adds up the cost and savings of a plan.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+///
+/// # Returns
+/// The total cost of charging and the net savings, i.e. the avoided import cost
+/// minus the cost of charging.
+pub fn plan_totals(plan: &[Plan]) -> PlanTotals {
+    let total_cost_eur: f64 = plan.iter().map(|interval| interval.grid_cost_eur).sum();
+    let total_revenue_eur: f64 = plan
+        .iter()
+        .map(|interval| interval.battery_revenue_eur)
+        .sum();
+
+    PlanTotals {
+        total_cost_eur,
+        total_savings_eur: total_revenue_eur - total_cost_eur,
+    }
+}
+
+/// Summarizes what the plan does and what it saves compared to running without a battery.
+///
+/// The plan, forecasts and prices are matched on interval start time; plan intervals
+/// without a forecast or price are left out of the cost comparison and the grid
+/// limit check. Every interval still over the grid limit is logged as a warning.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `forecasts`: The forecasted consumption the plan was made for.
+/// * `prices`: The electricity prices the plan was made for.
+/// * `settings`: The configuration settings, for the grid limit, grid fee and tax.
+///
+/// # Returns
+/// The `PlanSummary` of the plan.
+pub fn summarize_plan(
+    plan: &[Plan],
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+) -> PlanSummary {
+    let covered = common_horizon(forecasts, prices);
+    let forecasts: HashMap<DateTime<Utc>, &Forecast> = forecasts
+        .iter()
+        .map(|forecast| (forecast.start, forecast))
+        .collect();
+    let prices: HashMap<DateTime<Utc>, &ElectricityPrice> =
+        prices.iter().map(|price| (price.start, price)).collect();
+
+    let mut summary = PlanSummary {
+        energy_charged_wh: 0.0,
+        energy_discharged_wh: 0.0,
+        realized_round_trip_efficiency: None,
+        active_intervals: 0,
+        cost_without_battery_eur: 0.0,
+        cost_with_battery_eur: 0.0,
+        net_savings_eur: 0.0,
+        intervals_over_grid_limit: 0,
+        grid_limit_violations: Vec::new(),
+        covered_start: covered.map(|(start, _)| start),
+        covered_end: covered.map(|(_, end)| end),
+    };
+
+    for interval in plan {
+        let discharged_wh = interval.energy_from_battery_wh + interval.energy_to_grid_wh;
+        let charged_wh = interval.energy_to_battery_wh + interval.energy_from_surplus_wh;
+        summary.energy_charged_wh += charged_wh;
+        summary.energy_discharged_wh += discharged_wh;
+        if charged_wh > 0.0 || discharged_wh > 0.0 {
+            summary.active_intervals += 1;
+        }
+
+        if let (Some(forecast), Some(price)) =
+            (forecasts.get(&interval.start), prices.get(&interval.start))
+        {
+            let duration_hours = (interval.end - interval.start).num_seconds() as f64 / 3600.0;
+            let consumption_wh = forecast.net_consumption() * duration_hours;
+            // Imports are paid at the import price, surplus production is exported at
+            // the feed-in price
+            let baseline_price = if consumption_wh >= 0.0 {
+                settings.import_price(price)
             } else {
-                // No action needed if price is not favorable for charging
-                plan.push(Plan {
-                    start: forecast.start,
-                    end: forecast.end,
-                    energy_from_battery_wh: 0.0,
-                    energy_to_battery_wh: 0.0,
+                settings.feed_in_price(price)
+            };
+            let baseline_eur = consumption_wh / 1_000.0 * baseline_price;
+            // Stored surplus is no longer exported, so it loses the credit the baseline gives it
+            let surplus_eur =
+                interval.energy_from_surplus_wh / 1_000.0 * settings.feed_in_price(price);
+            summary.cost_without_battery_eur += baseline_eur;
+            // Exports are credited at the feed-in price through the battery revenue
+            summary.cost_with_battery_eur +=
+                baseline_eur + surplus_eur + interval.grid_cost_eur - interval.battery_revenue_eur;
+
+            // Exports flow out through the same connection, so they offset the import
+            let import_wh =
+                consumption_wh + interval.energy_to_battery_wh + interval.energy_from_surplus_wh
+                    - interval.energy_from_battery_wh
+                    - interval.energy_to_grid_wh;
+            let shortfall_w = import_wh / duration_hours - settings.grid_limit;
+            if shortfall_w > 0.0 {
+                warn!(
+                    "Grid limit exceeded at {}: import is {} W above the limit of {} W",
+                    interval.start, shortfall_w, settings.grid_limit
+                );
+                summary.intervals_over_grid_limit += 1;
+                summary.grid_limit_violations.push(GridLimitViolation {
+                    start: interval.start,
+                    end: interval.end,
+                    shortfall_w,
                 });
             }
         }
     }
 
-    Ok(plan) // Return the plan wrapped in Ok
+    summary.net_savings_eur = summary.cost_without_battery_eur - summary.cost_with_battery_eur;
+    if summary.energy_charged_wh > 0.0 {
+        summary.realized_round_trip_efficiency =
+            Some(summary.energy_discharged_wh / summary.energy_charged_wh);
+    }
+    summary
+}
+
+/// Returns the window covered by both the forecasts and the prices.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasted consumption intervals, in any order.
+/// * `prices`: The electricity prices, in any order.
+///
+/// # Returns
+/// The start and end of the overlap, or `None` if either series is empty or they
+/// do not overlap.
+pub fn common_horizon(
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = forecasts
+        .iter()
+        .map(|forecast| forecast.start)
+        .min()?
+        .max(prices.iter().map(|price| price.start).min()?);
+    let end = forecasts
+        .iter()
+        .map(|forecast| forecast.end)
+        .max()?
+        .min(prices.iter().map(|price| price.end).max()?);
+
+    (start < end).then_some((start, end))
+}
+
+/// Drops the forecasts and prices outside the window covered by both.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasted consumption intervals.
+/// * `prices`: The electricity prices.
+///
+/// # Returns
+/// A `Result` containing the forecasts and prices inside the common window, and a
+/// `HorizonTrim` describing the window if any interval was dropped. Empty inputs
+/// are returned unchanged.
+///
+/// # Errors
+/// Returns an error if both series are non-empty but do not overlap.
+pub fn trim_to_common_horizon(
+    forecasts: Vec<Forecast>,
+    prices: Vec<ElectricityPrice>,
+) -> Result<(Vec<Forecast>, Vec<ElectricityPrice>, Option<HorizonTrim>)> {
+    if forecasts.is_empty() || prices.is_empty() {
+        return Ok((forecasts, prices, None));
+    }
+    let (start, end) = common_horizon(&forecasts, &prices).ok_or_else(|| {
+        anyhow!("The forecasts and prices do not overlap, so there is nothing to plan")
+    })?;
+
+    let forecast_count = forecasts.len();
+    let price_count = prices.len();
+    let forecasts: Vec<Forecast> = forecasts
+        .into_iter()
+        .filter(|forecast| forecast.start >= start && forecast.end <= end)
+        .collect();
+    let prices: Vec<ElectricityPrice> = prices
+        .into_iter()
+        .filter(|price| price.start >= start && price.end <= end)
+        .collect();
+
+    let trim = HorizonTrim {
+        start,
+        end,
+        forecasts_dropped: forecast_count - forecasts.len(),
+        prices_dropped: price_count - prices.len(),
+    };
+    let trimmed = trim.forecasts_dropped > 0 || trim.prices_dropped > 0;
+    Ok((forecasts, prices, trimmed.then_some(trim)))
+}
+
+/// Pairs each forecast with the price of the same interval.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasted consumption intervals.
+/// * `prices`: The electricity prices, in any order.
+///
+/// # Returns
+/// A `Result` containing one price per forecast, in forecast order.
+///
+/// # Errors
+/// Returns an error listing every forecast interval without a price for the same
+/// start and end time.
+pub(crate) fn align_prices<'a>(
+    forecasts: &[Forecast],
+    prices: &'a [ElectricityPrice],
+) -> Result<Vec<&'a ElectricityPrice>> {
+    let by_start: HashMap<DateTime<Utc>, &ElectricityPrice> =
+        prices.iter().map(|price| (price.start, price)).collect();
+
+    let mut aligned = Vec::with_capacity(forecasts.len());
+    let mut missing = Vec::new();
+    for forecast in forecasts {
+        match by_start.get(&forecast.start) {
+            Some(price) if price.end == forecast.end => aligned.push(*price),
+            _ => missing.push(format!("{} - {}", forecast.start, forecast.end)),
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "No price for {} forecast interval(s): {}",
+            missing.len(),
+            missing.join(", ")
+        ));
+    }
+
+    Ok(aligned)
+}
+
+/// Checks whether energy charged in the given interval can be used profitably later.
+///
+/// Energy has a later use if a following interval has consumption above the
+/// shaving limit (a peak to shave), or a price above the break-even price, i.e. the
+/// current price divided by the round-trip efficiency.
+///
+/// # Arguments
+///
+/// * `index`: The index of the interval considered for charging.
+/// * `forecasts`: The forecasted consumption for every interval.
+/// * `prices`: The electricity prices, aligned with `forecasts`.
+/// * `settings`: The configuration settings.
+///
+/// # Returns
+/// `true` if a later interval can use the charged energy profitably.
+fn has_profitable_later_use(
+    index: usize,
+    forecasts: &[Forecast],
+    prices: &[&ElectricityPrice],
+    settings: &Settings,
+) -> bool {
+    let break_even_price = settings.import_price(prices[index])
+        / (settings.charge_efficiency() * settings.discharge_efficiency());
+
+    forecasts
+        .iter()
+        .zip(prices.iter())
+        .skip(index + 1)
+        .any(|(forecast, price)| {
+            forecast.net_consumption_at(settings.planning_quantile) > settings.shave_limit()
+                || settings.import_price(price) > break_even_price
+        })
+}
+
+/// Calculates how hard the battery was worked over the plan horizon.
+///
+/// Utilization is `total_throughput / (capacity * max_possible_cycles)`, where the
+/// throughput is the energy charged (from the grid or surplus production) plus
+/// discharged (to the site or the grid) and `max_possible_cycles` is the number of
+/// capacity-sized transfers the battery could make running at its faster rate for
+/// every interval of the horizon, since it only moves energy one way at a time.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `capacity`: The battery capacity in MWh.
+/// * `max_charge_rate`: The maximum charging rate in MW.
+/// * `max_discharge_rate`: The maximum discharging rate in MW.
+///
+/// # Returns
+/// The utilization as a fraction between 0.0 and 1.0, or 0.0 for an empty plan.
+pub fn battery_utilization(
+    plan: &[Plan],
+    capacity: f64,
+    max_charge_rate: f64,
+    max_discharge_rate: f64,
+) -> f64 {
+    let total_throughput: f64 = plan
+        .iter()
+        .map(|interval| {
+            interval.energy_from_battery_wh
+                + interval.energy_to_battery_wh
+                + interval.energy_from_surplus_wh
+                + interval.energy_to_grid_wh
+        })
+        .sum();
+
+    let horizon_hours: f64 = plan
+        .iter()
+        .map(|interval| (interval.end - interval.start).num_seconds() as f64 / 3600.0)
+        .sum();
+    let max_possible_cycles = max_charge_rate.max(max_discharge_rate) * horizon_hours / capacity;
+
+    let max_throughput = convert(capacity * max_possible_cycles, EnergyUnit::Wh);
+    if max_throughput <= 0.0 {
+        return 0.0;
+    }
+
+    total_throughput / max_throughput
+}
+
+/// Rolls a plan up by calendar day, for reporting.
+///
+/// Intervals are grouped by the UTC date of their `start`, so the first and last
+/// day of a horizon that does not start or end at midnight are partial; their
+/// `hours` tell how much of the day was planned.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `prices`: The electricity prices of the planned intervals, for the average price.
+/// * `settings`: The battery settings, for the cycles.
+///
+/// # Returns
+/// One `DailyAggregate` per day with planned intervals, in chronological order.
+pub fn aggregate_by_day(
+    plan: &[Plan],
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+) -> Vec<DailyAggregate> {
+    aggregate_by(plan, prices, settings, |start| start.date_naive())
+        .into_iter()
+        .map(|(date, totals)| DailyAggregate { date, totals })
+        .collect()
+}
+
+/// Rolls a plan up by calendar month, for reporting; see `aggregate_by_day`.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `prices`: The electricity prices of the planned intervals, for the average price.
+/// * `settings`: The battery settings, for the cycles.
+///
+/// # Returns
+/// One `MonthlyAggregate` per month with planned intervals, in chronological order.
+pub fn aggregate_by_month(
+    plan: &[Plan],
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+) -> Vec<MonthlyAggregate> {
+    aggregate_by(plan, prices, settings, |start| {
+        (start.year(), start.month())
+    })
+    .into_iter()
+    .map(|((year, month), totals)| MonthlyAggregate {
+        year,
+        month,
+        totals,
+    })
+    .collect()
+}
+
+/// Groups the intervals of a plan by `period` of their start and adds up each group.
+///
+/// The energies are totaled on the grid side, as planned, while the cycles count the
+/// energy moved in or out of the pack, like `Battery::equivalent_cycles`.
+fn aggregate_by<K: Ord>(
+    plan: &[Plan],
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+    period: impl Fn(&DateTime<Utc>) -> K,
+) -> BTreeMap<K, PlanAggregate> {
+    let prices_by_start: HashMap<DateTime<Utc>, f64> = prices
+        .iter()
+        .map(|price| (price.start, price.market_price_per_kwh))
+        .collect();
+
+    let mut periods: BTreeMap<K, (PlanAggregate, Vec<f64>)> = BTreeMap::new();
+    for interval in plan {
+        let (totals, period_prices) = periods.entry(period(&interval.start)).or_insert_with(|| {
+            (
+                PlanAggregate {
+                    intervals: 0,
+                    hours: 0.0,
+                    energy_charged_wh: 0.0,
+                    energy_discharged_wh: 0.0,
+                    cost_eur: 0.0,
+                    savings_eur: 0.0,
+                    cycles: 0.0,
+                    average_price_per_kwh: None,
+                },
+                Vec::new(),
+            )
+        });
+
+        let charged_wh = interval.energy_to_battery_wh + interval.energy_from_surplus_wh;
+        let discharged_wh = interval.energy_from_battery_wh + interval.energy_to_grid_wh;
+        totals.intervals += 1;
+        totals.hours += (interval.end - interval.start).num_seconds() as f64 / 3600.0;
+        totals.energy_charged_wh += charged_wh;
+        totals.energy_discharged_wh += discharged_wh;
+        totals.cost_eur += interval.grid_cost_eur;
+        totals.savings_eur += interval.battery_revenue_eur - interval.grid_cost_eur;
+        let stored_wh = charged_wh * settings.charge_efficiency();
+        let drawn_wh = discharged_wh / settings.discharge_efficiency();
+        totals.cycles += to_mwh(stored_wh + drawn_wh, EnergyUnit::Wh) / settings.capacity / 2.0;
+        if let Some(price) = prices_by_start.get(&interval.start) {
+            period_prices.push(*price);
+        }
+    }
+
+    periods
+        .into_iter()
+        .map(|(key, (mut totals, period_prices))| {
+            if !period_prices.is_empty() {
+                totals.average_price_per_kwh =
+                    Some(period_prices.iter().sum::<f64>() / period_prices.len() as f64);
+            }
+            (key, totals)
+        })
+        .collect()
 }
 
 /// Saves the generated battery usage plan to a specified file.
 ///
+/// The format follows the file extension (see `OutputFormat::from_path`), so files
+/// with a `.csv` extension are written with `save_plan_csv` and `.ndjson` files
+/// with `save_plan_ndjson`.
+///
 /// # Arguments
 ///
 /// * `plan`: A vector of `Plan` structs representing the battery usage plan.
 /// * `file_path`: The path to the file where the plan will be saved.
+/// * `unit`: The unit of the energy fields; their `_wh` suffix is renamed to match.
 ///
 /// # Returns
 /// A `Result` indicating success or failure of the save operation.
-pub fn save_plan(plan: Vec<Plan>, file_path: &str) -> Result<(), anyhow::Error> {
-    let planning = serde_json::json!( {
-        "planning": plan
+pub fn save_plan(plan: Vec<Plan>, file_path: &str, unit: EnergyUnit) -> Result<(), anyhow::Error> {
+    save_plan_as(plan, file_path, unit, OutputFormat::from_path(file_path))
+}
+
+/// Saves the generated battery usage plan to a file in the given format.
+///
+/// JSON output contains, next to the intervals, the plan's `total_cost_eur` and
+/// `total_savings_eur` (see `plan_totals`).
+///
+/// # Arguments
+///
+/// * `plan`: A vector of `Plan` structs representing the battery usage plan.
+/// * `file_path`: The path to the file where the plan will be saved.
+/// * `unit`: The unit of the energy fields; NDJSON output is always in Wh.
+/// * `format`: The file format, regardless of the extension.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the save operation. A plan with an
+/// interval that both charges and discharges is not saved; see `check_simultaneous_flows`.
+pub fn save_plan_as(
+    plan: Vec<Plan>,
+    file_path: &str,
+    unit: EnergyUnit,
+    format: OutputFormat,
+) -> Result<()> {
+    check_simultaneous_flows(&plan).context("Refusing to save an infeasible plan")?;
+
+    match format {
+        OutputFormat::Csv => return save_plan_csv(&plan, file_path, unit),
+        OutputFormat::Ndjson => return save_plan_ndjson(&plan, file_path),
+        OutputFormat::Json => {}
+    }
+
+    let totals = plan_totals(&plan);
+    let mut planning = serde_json::json!( {
+        "planning": plan,
+        "total_cost_eur": totals.total_cost_eur,
+        "total_savings_eur": totals.total_savings_eur
     });
+    express_energy_in(&mut planning, unit);
 
     let pretty_output =
         serde_json::to_string_pretty(&planning).context("Error generating pretty JSON")?; // Handle JSON generation errors
@@ -139,3 +2172,160 @@ pub fn save_plan(plan: Vec<Plan>, file_path: &str) -> Result<(), anyhow::Error>
     info!("Saved planning to {}", file_path); // Log saving success
     Ok(()) // Indicate success
 }
+
+/// Saves the battery usage plan as newline-delimited JSON, for consumers that
+/// ingest one record at a time.
+///
+/// Each line holds one `Plan` as a compact JSON object with its energies in Wh;
+/// the plan's totals are not written.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `file_path`: The path to the file where the plan will be saved.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the save operation.
+pub fn save_plan_ndjson(plan: &[Plan], file_path: &str) -> Result<()> {
+    let mut output = String::new();
+    for interval in plan {
+        output.push_str(&serde_json::to_string(interval).context("Error generating JSON")?);
+        output.push('\n');
+    }
+
+    fs::write(file_path, output).context(format!("Unable to write plan to file: {}", file_path))?;
+
+    info!("Saved planning to {}", file_path);
+    Ok(())
+}
+
+/// Saves the battery usage plan to a CSV file.
+///
+/// The file has the header `start,end,energy_from_battery_wh,energy_to_battery_wh,`
+/// `energy_to_grid_wh,energy_from_surplus_wh` and one row per interval, with RFC 3339
+/// timestamps. For units other than Wh the energy columns are named after the unit,
+/// e.g. `energy_from_battery_kwh`.
+///
+/// # Arguments
+///
+/// * `plan`: The battery usage plan.
+/// * `file_path`: The path to the file where the plan will be saved.
+/// * `unit`: The unit of the energy columns.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the save operation.
+pub fn save_plan_csv(plan: &[Plan], file_path: &str, unit: EnergyUnit) -> Result<()> {
+    let mut writer = csv::Writer::from_path(file_path)
+        .context(format!("Unable to write plan to file: {}", file_path))?;
+
+    writer.write_record([
+        "start".to_string(),
+        "end".to_string(),
+        format!("energy_from_battery_{}", unit.suffix()),
+        format!("energy_to_battery_{}", unit.suffix()),
+        format!("energy_to_grid_{}", unit.suffix()),
+        format!("energy_from_surplus_{}", unit.suffix()),
+    ])?;
+    for interval in plan {
+        writer.write_record([
+            interval.start.to_rfc3339(),
+            interval.end.to_rfc3339(),
+            from_wh(interval.energy_from_battery_wh, unit).to_string(),
+            from_wh(interval.energy_to_battery_wh, unit).to_string(),
+            from_wh(interval.energy_to_grid_wh, unit).to_string(),
+            from_wh(interval.energy_from_surplus_wh, unit).to_string(),
+        ])?;
+    }
+    writer
+        .flush()
+        .context(format!("Unable to write plan to file: {}", file_path))?;
+
+    info!("Saved planning to {}", file_path);
+    Ok(())
+}
+
+/// Saves the plan as one file per calendar day, plus a combined summary.
+///
+/// Intervals are grouped by the UTC date of their `start`. Each day is written to
+/// `plan_YYYY-MM-DD.json` in the same format as `save_plan`, and the per-day totals
+/// of `aggregate_by_day` are written to `plan_summary.json`.
+///
+/// # Arguments
+///
+/// * `plan`: A vector of `Plan` structs representing the battery usage plan.
+/// * `prices`: The electricity prices of the planned intervals, for the average price.
+/// * `settings`: The battery settings, for the cycles.
+/// * `directory`: The directory where the files will be saved.
+/// * `unit`: The unit of the energy fields in the files.
+///
+/// # Returns
+/// A `Result` containing the per-day totals, or an error if any file cannot be written.
+pub fn save_plan_per_day(
+    plan: Vec<Plan>,
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+    directory: &str,
+    unit: EnergyUnit,
+) -> Result<Vec<DailyAggregate>> {
+    let summaries = aggregate_by_day(&plan, prices, settings);
+
+    let mut days: BTreeMap<NaiveDate, Vec<Plan>> = BTreeMap::new();
+    for interval in plan {
+        days.entry(interval.start.date_naive())
+            .or_default()
+            .push(interval);
+    }
+
+    for (date, day_plan) in days {
+        let file_path = Path::new(directory).join(format!("plan_{}.json", date));
+        save_plan(day_plan, &file_path.to_string_lossy(), unit)?;
+    }
+
+    let mut summary = serde_json::json!({ "days": summaries });
+    express_energy_in(&mut summary, unit);
+    let pretty_output =
+        serde_json::to_string_pretty(&summary).context("Error generating pretty JSON")?;
+
+    let summary_path = Path::new(directory).join("plan_summary.json");
+    fs::write(&summary_path, pretty_output).context(format!(
+        "Unable to write plan summary to file: {}",
+        summary_path.display()
+    ))?;
+
+    info!("Saved plan summary to {}", summary_path.display());
+    Ok(summaries)
+}
+
+/// Expresses the `_wh` fields of serialized plan data in `unit`, renaming them to
+/// end in the unit's suffix instead. Watt-hours are left untouched.
+pub(crate) fn express_energy_in(value: &mut Value, unit: EnergyUnit) {
+    if unit == EnergyUnit::Wh {
+        return;
+    }
+
+    match value {
+        Value::Object(object) => {
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut field)| match key.strip_suffix("_wh") {
+                    Some(name) => {
+                        if let Some(energy_wh) = field.as_f64() {
+                            field = Value::from(from_wh(energy_wh, unit));
+                        }
+                        (format!("{}_{}", name, unit.suffix()), field)
+                    }
+                    None => {
+                        express_energy_in(&mut field, unit);
+                        (key, field)
+                    }
+                })
+                .collect();
+        }
+        Value::Array(items) => {
+            for item in items {
+                express_energy_in(item, unit);
+            }
+        }
+        _ => {}
+    }
+}
@@ -1,11 +1,35 @@
-use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Duration, Utc};
-use log::info;
+use crate::config::apply_field_mapping;
+use crate::error::LoadError;
+use crate::forecast::snap_to_grid;
+use crate::schema::{validate_schema, PRICE_FIELDS};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc, Weekday};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::io::Read;
+
+/// Length of the intervals prices are converted to unless configured otherwise, in minutes.
+pub const DEFAULT_INTERVAL_MINUTES: i64 = 15;
+
+/// Snapping a timestamp by more than this many seconds is logged as a warning.
+pub const SNAP_WARN_SECONDS: i64 = 5;
+
+/// Where the price of an interval comes from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    /// The day-ahead auction.
+    #[default]
+    DayAhead,
+    /// The intraday market, which takes precedence over the day-ahead price.
+    Intraday,
+    /// The fallback price profile.
+    Fallback,
+}
 
 /// Represents the price of electricity for a specific time interval.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ElectricityPrice {
     /// Start time of the electricity price interval.
     pub start: DateTime<Utc>,
@@ -18,6 +42,31 @@ pub struct ElectricityPrice {
 
     /// Price of electricity per kWh.
     pub market_price_per_kwh: f64,
+
+    /// Whether the price comes from the fallback price profile rather than the
+    /// day-ahead prices.
+    #[serde(default)]
+    pub is_fallback: bool,
+
+    /// Where the price comes from.
+    #[serde(default)]
+    pub source: PriceSource,
+}
+
+impl ElectricityPrice {
+    /// Returns the price a consumer pays per kWh: the market price plus the grid
+    /// fee, with tax on top.
+    ///
+    /// # Arguments
+    ///
+    /// * `grid_fee`: Fixed grid fee per kWh, in the price's currency.
+    /// * `tax_rate`: Tax rate as a fraction, e.g. 0.21 for 21 %.
+    ///
+    /// # Returns
+    /// The effective price per kWh.
+    pub fn effective_price(&self, grid_fee: f64, tax_rate: f64) -> f64 {
+        (self.market_price_per_kwh + grid_fee) * (1.0 + tax_rate)
+    }
 }
 
 /// Represents the day-ahead prices of electricity, containing multiple price intervals.
@@ -35,75 +84,739 @@ pub struct DayAheadPrices {
 ///
 /// # Returns
 /// A `Result` containing a `DayAheadPrices` struct if successful, and the average price, or an error if loading or parsing fails.
-pub fn load_day_ahead_prices(file_path: &str) -> Result<(DayAheadPrices, f64)> {
-    // Attempt to read the day-ahead prices file
-    let data = fs::read_to_string(file_path).context(format!(
-        "Unable to read day-ahead prices file: {}",
-        file_path
-    ))?;
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, contains no prices, a price
+/// is invalid, or the prices are in more than one currency.
+pub fn load_day_ahead_prices(file_path: &str) -> Result<(DayAheadPrices, f64), LoadError> {
+    load_day_ahead_prices_with_mapping(
+        file_path,
+        &HashMap::new(),
+        None,
+        &HashMap::new(),
+        DEFAULT_INTERVAL_MINUTES,
+        None,
+    )
+}
 
-    // Attempt to parse the JSON data into DayAheadPrices
-    let prices: DayAheadPrices =
-        serde_json::from_str(&data).context("JSON parsing error in day-ahead prices")?;
+/// Loads day-ahead electricity prices from a JSON file whose field names differ from the expected ones,
+/// and converts them to intervals of `interval_minutes`.
+///
+/// # Arguments
+///
+/// * `file_path`: The path to the JSON file containing day-ahead prices.
+/// * `mapping`: A map from the file's field names to the expected field names.
+/// * `reporting_currency`: The currency to convert all prices to, or `None` to keep the file's own currency.
+/// * `exchange_rates`: Rates into `reporting_currency`, keyed by source currency.
+/// * `interval_minutes`: The length of the planning intervals in minutes.
+/// * `snap_minutes`: The grid to snap the timestamps to before converting them, or `None`
+///   to keep them as they are; see `snap_prices`.
+///
+/// # Returns
+/// A `Result` containing a `DayAheadPrices` struct if successful, and the average price, or an error if loading or parsing fails.
+pub fn load_day_ahead_prices_with_mapping(
+    file_path: &str,
+    mapping: &HashMap<String, String>,
+    reporting_currency: Option<&str>,
+    exchange_rates: &HashMap<String, f64>,
+    interval_minutes: i64,
+    snap_minutes: Option<i64>,
+) -> Result<(DayAheadPrices, f64), LoadError> {
+    // Attempt to open the day-ahead prices file
+    let file = fs::File::open(file_path).map_err(|source| LoadError::Io {
+        context: format!("Unable to read day-ahead prices file: {}", file_path),
+        source,
+    })?;
+
+    let loaded = load_day_ahead_prices_reader_with_mapping(
+        file,
+        mapping,
+        reporting_currency,
+        exchange_rates,
+        interval_minutes,
+        snap_minutes,
+    )?;
+
+    info!("Loaded day-ahead prices from {}", file_path);
+
+    Ok(loaded)
+}
+
+/// Loads day-ahead electricity prices in JSON format from a reader, e.g. standard input,
+/// and converts them to 15-minute intervals.
+///
+/// # Arguments
+///
+/// * `reader`: The source of the JSON data.
+///
+/// # Returns
+/// A `Result` containing a `DayAheadPrices` struct if successful, and the average price, or an error if reading or parsing fails.
+pub fn load_day_ahead_prices_reader<R: Read>(
+    reader: R,
+) -> Result<(DayAheadPrices, f64), LoadError> {
+    load_day_ahead_prices_reader_with_mapping(
+        reader,
+        &HashMap::new(),
+        None,
+        &HashMap::new(),
+        DEFAULT_INTERVAL_MINUTES,
+        None,
+    )
+}
+
+/// Loads day-ahead electricity prices in JSON format from a reader, renaming fields
+/// according to `mapping`, and converts them to intervals of `interval_minutes`.
+///
+/// # Arguments
+///
+/// * `reader`: The source of the JSON data.
+/// * `mapping`: A map from the data's field names to the expected field names.
+/// * `reporting_currency`: The currency to convert all prices to, or `None` to keep the data's own currency.
+/// * `exchange_rates`: Rates into `reporting_currency`, keyed by source currency.
+/// * `interval_minutes`: The length of the planning intervals in minutes.
+/// * `snap_minutes`: The grid to snap the timestamps to before converting them, or `None`
+///   to keep them as they are; see `snap_prices`.
+///
+/// # Returns
+/// A `Result` containing a `DayAheadPrices` struct if successful, and the average price, or an error if reading or parsing fails.
+pub fn load_day_ahead_prices_reader_with_mapping<R: Read>(
+    mut reader: R,
+    mapping: &HashMap<String, String>,
+    reporting_currency: Option<&str>,
+    exchange_rates: &HashMap<String, f64>,
+    interval_minutes: i64,
+    snap_minutes: Option<i64>,
+) -> Result<(DayAheadPrices, f64), LoadError> {
+    let mut data = String::new();
+    reader
+        .read_to_string(&mut data)
+        .map_err(|source| LoadError::Io {
+            context: "Unable to read day-ahead prices".to_string(),
+            source,
+        })?;
+
+    // Attempt to parse the JSON data into DayAheadPrices, renaming provider-specific fields first
+    let json_error = |source| LoadError::Json {
+        context: "JSON parsing error in day-ahead prices".to_string(),
+        source,
+    };
+    let mut value: serde_json::Value = serde_json::from_str(&data).map_err(json_error)?;
+    apply_field_mapping(&mut value, mapping);
+    validate_schema(&value, "prices", PRICE_FIELDS)?;
+    let mut prices: DayAheadPrices = serde_json::from_value(value).map_err(json_error)?;
+    if prices.prices.is_empty() {
+        // There is no average price of no prices
+        return Err(LoadError::Empty("day-ahead prices"));
+    }
+    if let Some(snap_minutes) = snap_minutes {
+        snap_prices(&mut prices.prices, snap_minutes);
+    }
 
     // Validate the prices data
     for price in &prices.prices {
         validate_price(price)?; // Ensure prices are valid
     }
 
-    // Convert hourly prices into 15-minute intervals
-    let fifteen_minute_prices = convert_to_fifteen_minute_intervals(prices.prices);
+    // Bring all prices into one currency, otherwise the average is meaningless
+    let prices = convert_currency(prices.prices, reporting_currency, exchange_rates)?;
+
+    // Convert the prices into planning intervals
+    let interval_prices = convert_to_intervals(prices, interval_minutes)?;
 
     // Calculate the average price
-    let average_price = fifteen_minute_prices
+    let average_price = interval_prices
         .iter()
         .map(|price| price.market_price_per_kwh)
         .sum::<f64>()
-        / fifteen_minute_prices.len() as f64;
+        / interval_prices.len() as f64;
 
-    info!("Successfully converted hourly prices into 15-minute intervals and loaded day-ahead prices from {}", file_path);
+    info!(
+        "Successfully converted prices into {}-minute intervals",
+        interval_minutes
+    );
 
     Ok((
         DayAheadPrices {
-            prices: fifteen_minute_prices,
+            prices: interval_prices,
         },
         average_price,
     )) // Wrap the result in Ok
 }
 
-/// Converts hourly electricity prices into 15-minute intervals.
-/// Each hourly interval is split into four 15-minute intervals with the same price.
+/// Loads day-ahead prices and, if given, intraday prices from JSON files, preferring
+/// the intraday price wherever both have one; see `load_prices_merged_with_mapping`.
+///
+/// # Arguments
+///
+/// * `day_ahead_path`: The path to the JSON file containing day-ahead prices.
+/// * `intraday_path`: The path to a JSON file containing intraday prices, in the same format.
+///
+/// # Returns
+/// A `Result` containing the merged prices in 15-minute intervals and their average price.
+///
+/// # Errors
+/// Returns an error if either file cannot be loaded.
+pub fn load_prices_merged(
+    day_ahead_path: &str,
+    intraday_path: Option<&str>,
+) -> Result<(DayAheadPrices, f64), LoadError> {
+    load_prices_merged_with_mapping(
+        day_ahead_path,
+        intraday_path,
+        &HashMap::new(),
+        None,
+        &HashMap::new(),
+        DEFAULT_INTERVAL_MINUTES,
+        None,
+    )
+}
+
+/// Loads day-ahead prices and, if given, intraday prices from JSON files, converts
+/// both to intervals of `interval_minutes`, and overlays the intraday prices onto the
+/// day-ahead intervals with the same start.
+///
+/// # Arguments
+///
+/// * `day_ahead_path`: The path to the JSON file containing day-ahead prices.
+/// * `intraday_path`: The path to a JSON file containing intraday prices, in the same format.
+/// * `mapping`: A map from the files' field names to the expected field names.
+/// * `reporting_currency`: The currency to convert all prices to, or `None` to keep the files' own currency.
+/// * `exchange_rates`: Rates into `reporting_currency`, keyed by source currency.
+/// * `interval_minutes`: The length of the planning intervals in minutes.
+/// * `snap_minutes`: The grid to snap the timestamps to, or `None` to keep them as they are.
+///
+/// # Returns
+/// A `Result` containing the merged prices, each tagged with its `source`, and their
+/// average price.
+///
+/// # Errors
+/// Returns an error if either file cannot be loaded, or the two are in different
+/// currencies and no reporting currency is given.
+pub fn load_prices_merged_with_mapping(
+    day_ahead_path: &str,
+    intraday_path: Option<&str>,
+    mapping: &HashMap<String, String>,
+    reporting_currency: Option<&str>,
+    exchange_rates: &HashMap<String, f64>,
+    interval_minutes: i64,
+    snap_minutes: Option<i64>,
+) -> Result<(DayAheadPrices, f64), LoadError> {
+    let load = |path: &str| {
+        load_day_ahead_prices_with_mapping(
+            path,
+            mapping,
+            reporting_currency,
+            exchange_rates,
+            interval_minutes,
+            snap_minutes,
+        )
+    };
+    let (day_ahead, average_price) = load(day_ahead_path)?;
+    let Some(intraday_path) = intraday_path else {
+        return Ok((day_ahead, average_price));
+    };
+    let (intraday, _) = load(intraday_path)?;
+
+    let prices = overlay_prices(day_ahead.prices, intraday.prices)?;
+    let average_price = prices
+        .iter()
+        .map(|price| price.market_price_per_kwh)
+        .sum::<f64>()
+        / prices.len() as f64;
+    Ok((DayAheadPrices { prices }, average_price))
+}
+
+/// Snaps the start and end of every price to the nearest boundary of a grid, e.g.
+/// `00:00:03Z` to `00:00:00Z`, so prices from feeds with jittery timestamps align
+/// with the forecasts; see `snap_to_grid`.
+///
+/// Adjustments of more than `SNAP_WARN_SECONDS` are logged as a warning, as they are
+/// more likely a misconfigured grid than jitter.
+///
+/// # Arguments
+///
+/// * `prices`: The prices to snap, in place.
+/// * `interval_minutes`: The length of the grid's steps in minutes.
+pub fn snap_prices(prices: &mut [ElectricityPrice], interval_minutes: i64) {
+    for price in prices {
+        for time in [&mut price.start, &mut price.end] {
+            let snapped = snap_to_grid(*time, interval_minutes);
+            if (snapped - *time).num_seconds().abs() > SNAP_WARN_SECONDS {
+                warn!(
+                    "Moved price timestamp {} by {} seconds to {}",
+                    time,
+                    (snapped - *time).num_seconds(),
+                    snapped
+                );
+            }
+            *time = snapped;
+        }
+    }
+}
+
+/// Overlays intraday prices onto day-ahead prices of the same resolution.
+///
+/// A day-ahead interval takes the price of the intraday interval with the same start,
+/// and is tagged `PriceSource::Intraday`. Intraday intervals without a matching
+/// day-ahead interval are ignored, so the horizon stays that of the day-ahead prices.
+///
+/// # Arguments
+///
+/// * `day_ahead`: The day-ahead prices.
+/// * `intraday`: The intraday prices.
+///
+/// # Returns
+/// A `Result` containing the day-ahead prices with the intraday prices overlaid.
+///
+/// # Errors
+/// Returns an error if a matching pair of prices is in different currencies.
+pub fn overlay_prices(
+    day_ahead: Vec<ElectricityPrice>,
+    intraday: Vec<ElectricityPrice>,
+) -> Result<Vec<ElectricityPrice>, LoadError> {
+    let mut intraday: HashMap<DateTime<Utc>, ElectricityPrice> = intraday
+        .into_iter()
+        .map(|price| (price.start, price))
+        .collect();
+
+    let merged = day_ahead
+        .into_iter()
+        .map(|price| match intraday.remove(&price.start) {
+            Some(intraday_price) if intraday_price.end == price.end => {
+                if intraday_price.market_price_currency != price.market_price_currency {
+                    return Err(LoadError::Validation(format!(
+                        "Intraday price at {} is in {}, but the day-ahead price is in {}",
+                        price.start,
+                        intraday_price.market_price_currency,
+                        price.market_price_currency
+                    )));
+                }
+                Ok(ElectricityPrice {
+                    source: PriceSource::Intraday,
+                    ..intraday_price
+                })
+            }
+            _ => Ok(price),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let overlaid = merged
+        .iter()
+        .filter(|price| price.source == PriceSource::Intraday)
+        .count();
+    info!(
+        "Overlaid {} intraday price interval(s); ignored {} outside the day-ahead horizon",
+        overlaid,
+        intraday.len()
+    );
+    Ok(merged)
+}
+
+/// Converts electricity prices into a single currency.
+///
+/// Prices already in `reporting_currency` are kept as they are; others are multiplied
+/// by their rate in `exchange_rates`. Without a reporting currency, the prices are
+/// only checked to share one currency.
+///
+/// # Arguments
+///
+/// * `prices`: The electricity prices to convert.
+/// * `reporting_currency`: The currency to convert to, or `None` to keep the prices' own currency.
+/// * `exchange_rates`: Rates into `reporting_currency`, keyed by source currency, e.g. `USD = 0.92`.
+///
+/// # Returns
+/// A `Result` containing the prices, all in the same currency.
+///
+/// # Errors
+/// Returns an error if a price's currency has no exchange rate, or if the prices mix
+/// currencies and no reporting currency is given.
+pub fn convert_currency(
+    prices: Vec<ElectricityPrice>,
+    reporting_currency: Option<&str>,
+    exchange_rates: &HashMap<String, f64>,
+) -> Result<Vec<ElectricityPrice>, LoadError> {
+    let Some(reporting_currency) = reporting_currency else {
+        let mut currencies: Vec<&str> = prices
+            .iter()
+            .map(|price| price.market_price_currency.as_str())
+            .collect();
+        currencies.sort_unstable();
+        currencies.dedup();
+        if currencies.len() > 1 {
+            return Err(LoadError::Validation(format!(
+                "Day-ahead prices mix currencies ({}); set a reporting currency to convert them",
+                currencies.join(", ")
+            )));
+        }
+        return Ok(prices);
+    };
+
+    prices
+        .into_iter()
+        .map(|mut price| {
+            if price.market_price_currency != reporting_currency {
+                let rate = exchange_rates
+                    .get(&price.market_price_currency)
+                    .ok_or_else(|| {
+                        LoadError::Validation(format!(
+                            "No exchange rate from {} to {} for the price at {}",
+                            price.market_price_currency, reporting_currency, price.start
+                        ))
+                    })?;
+                price.market_price_per_kwh *= rate;
+                price.market_price_currency = reporting_currency.to_string();
+            }
+            Ok(price)
+        })
+        .collect()
+}
+
+/// Converts electricity prices into intervals of `target_minutes`.
+/// Each price is split into as many intervals as fit in its own duration, all with the same price.
+/// The duration is the elapsed time between `start` and `end`, not the wall-clock hours, so a
+/// price spanning a daylight-saving change yields the intervals that actually elapse.
 ///
 /// # Arguments
 ///
-/// * `hourly_prices`: A vector of `ElectricityPrice` structs representing hourly prices.
+/// * `prices`: A vector of `ElectricityPrice` structs, e.g. hourly prices.
+/// * `target_minutes`: The length of the resulting intervals in minutes.
 ///
 /// # Returns
-/// A vector of `ElectricityPrice` structs with 15-minute intervals.
-fn convert_to_fifteen_minute_intervals(
-    hourly_prices: Vec<ElectricityPrice>,
-) -> Vec<ElectricityPrice> {
-    let mut fifteen_minute_prices = Vec::new();
-
-    for price in hourly_prices {
-        let start_time = price.start;
-        let price_per_kwh = price.market_price_per_kwh;
-        let currency = price.market_price_currency.clone();
-
-        for i in 0..4 {
-            let interval_start = start_time + Duration::minutes(i * 15);
-            let interval_end = interval_start + Duration::minutes(15);
-
-            fifteen_minute_prices.push(ElectricityPrice {
+/// A `Result` containing a vector of `ElectricityPrice` structs with `target_minutes` intervals.
+///
+/// # Errors
+/// Returns an error if `target_minutes` is not positive or a price's duration is not
+/// an integer multiple of it.
+pub fn convert_to_intervals(
+    prices: Vec<ElectricityPrice>,
+    target_minutes: i64,
+) -> Result<Vec<ElectricityPrice>, LoadError> {
+    if target_minutes <= 0 {
+        return Err(LoadError::Validation(
+            "Target interval length must be positive.".to_string(),
+        ));
+    }
+    let target = Duration::minutes(target_minutes);
+
+    let mut converted_prices = Vec::new();
+
+    for price in prices {
+        let duration = price.end - price.start;
+        if duration.num_seconds() % target.num_seconds() != 0 {
+            return Err(LoadError::Validation(format!(
+                "Price interval at {} lasts {} minutes, which is not a multiple of {} minutes",
+                price.start,
+                duration.num_minutes(),
+                target_minutes
+            )));
+        }
+
+        let intervals = duration.num_seconds() / target.num_seconds();
+        for i in 0..intervals as i32 {
+            let interval_start = price.start + target * i;
+            let interval_end = interval_start + target;
+
+            converted_prices.push(ElectricityPrice {
+                start: interval_start,
+                end: interval_end,
+                market_price_currency: price.market_price_currency.clone(),
+                market_price_per_kwh: price.market_price_per_kwh,
+                is_fallback: price.is_fallback,
+                source: price.source,
+            });
+        }
+    }
+
+    Ok(converted_prices)
+}
+
+/// A default price curve for the intervals the day-ahead prices do not cover, e.g.
+/// a flat tariff for weekends when no day-ahead auction publishes prices.
+#[derive(Deserialize, Serialize)]
+pub struct PriceProfile {
+    /// Currency of the profile's prices (e.g., "EUR").
+    pub currency: String,
+
+    /// Price per kWh for each hour of a weekday, from 00:00 to 23:00 UTC.
+    pub weekday: Vec<f64>,
+
+    /// Price per kWh for each hour of a Saturday or Sunday, from 00:00 to 23:00 UTC.
+    pub weekend: Vec<f64>,
+
+    /// Dates priced with the weekend curve although they fall on a weekday.
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl PriceProfile {
+    /// Returns the profile's price per kWh for the hour containing `time`.
+    ///
+    /// Weekends and holidays use the `weekend` curve, other days the `weekday` curve.
+    pub fn price_at(&self, time: DateTime<Utc>) -> f64 {
+        let date = time.date_naive();
+        let curve = if matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+            || self.holidays.contains(&date)
+        {
+            &self.weekend
+        } else {
+            &self.weekday
+        };
+        curve[time.hour() as usize]
+    }
+}
+
+/// Loads a fallback price profile from a JSON file.
+///
+/// # Arguments
+///
+/// * `file_path`: The path to the JSON file containing the profile.
+///
+/// # Returns
+/// A `Result` containing the `PriceProfile`.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, or a curve does not have
+/// 24 finite hourly prices.
+pub fn load_price_profile(file_path: &str) -> Result<PriceProfile, LoadError> {
+    let data = fs::read_to_string(file_path).map_err(|source| LoadError::Io {
+        context: format!("Unable to read price profile file: {}", file_path),
+        source,
+    })?;
+    let profile: PriceProfile = serde_json::from_str(&data).map_err(|source| LoadError::Json {
+        context: "JSON parsing error in price profile".to_string(),
+        source,
+    })?;
+
+    for (name, curve) in [("weekday", &profile.weekday), ("weekend", &profile.weekend)] {
+        if curve.len() != 24 || curve.iter().any(|price| !price.is_finite()) {
+            return Err(LoadError::Validation(format!(
+                "The {} curve of the price profile must have 24 hourly prices that are numbers",
+                name
+            )));
+        }
+    }
+
+    info!("Loaded price profile from {}", file_path);
+    Ok(profile)
+}
+
+/// Merges a fallback price profile into the day-ahead prices, pricing every interval
+/// from `start` to `end` the day-ahead prices do not cover.
+///
+/// The fallback prices are tagged with `is_fallback` and converted like the day-ahead
+/// prices; day-ahead prices always take precedence.
+///
+/// # Arguments
+///
+/// * `prices`: The day-ahead prices, at the planning resolution.
+/// * `profile`: The fallback price profile.
+/// * `start`: The start of the period the prices must cover.
+/// * `end`: The end of the period the prices must cover.
+/// * `interval_minutes`: The length of the planning intervals in minutes.
+/// * `reporting_currency`: The currency to convert the fallback prices to, or `None` to keep the profile's currency.
+/// * `exchange_rates`: Rates into `reporting_currency`, keyed by source currency.
+///
+/// # Returns
+/// A `Result` containing the merged prices, in chronological order.
+///
+/// # Errors
+/// Returns an error if `interval_minutes` is not positive, or the profile's currency
+/// cannot be converted to the day-ahead prices' currency.
+pub fn merge_price_profile(
+    prices: Vec<ElectricityPrice>,
+    profile: &PriceProfile,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    interval_minutes: i64,
+    reporting_currency: Option<&str>,
+    exchange_rates: &HashMap<String, f64>,
+) -> Result<Vec<ElectricityPrice>, LoadError> {
+    if interval_minutes <= 0 {
+        return Err(LoadError::Validation(
+            "Interval length must be positive.".to_string(),
+        ));
+    }
+    let interval = Duration::minutes(interval_minutes);
+
+    let mut fallback = Vec::new();
+    let mut interval_start = start;
+    while interval_start < end {
+        let interval_end = interval_start + interval;
+        let covered = prices
+            .iter()
+            .any(|price| price.start < interval_end && price.end > interval_start);
+        if !covered {
+            fallback.push(ElectricityPrice {
                 start: interval_start,
                 end: interval_end,
-                market_price_currency: currency.clone(),
-                market_price_per_kwh: price_per_kwh,
+                market_price_currency: profile.currency.clone(),
+                market_price_per_kwh: profile.price_at(interval_start),
+                is_fallback: true,
+                source: PriceSource::Fallback,
             });
         }
+        interval_start = interval_end;
+    }
+
+    if fallback.is_empty() {
+        return Ok(prices);
+    }
+    info!(
+        "Priced {} interval(s) from {} from the fallback price profile",
+        fallback.len(),
+        fallback[0].start
+    );
+
+    let mut merged = prices;
+    merged.extend(fallback);
+    let mut merged = convert_currency(merged, reporting_currency, exchange_rates)?;
+    merged.sort_by_key(|price| price.start);
+    Ok(merged)
+}
+
+/// Fills missing intervals in a series of prices so that it covers `start` to `end`.
+///
+/// A missing interval carries the last known price forward; missing intervals
+/// before the first price take the first price instead, the nearest one known.
+/// Gaps must be a whole number of `interval_minutes` intervals.
+///
+/// # Arguments
+///
+/// * `prices`: The prices to fill, in chronological order.
+/// * `start`: The start of the period the prices must cover.
+/// * `end`: The end of the period the prices must cover.
+/// * `interval_minutes`: The length of the intervals of the series in minutes.
+///
+/// # Returns
+/// A `Result` containing the prices with the gaps filled.
+///
+/// # Errors
+/// Returns an error if `interval_minutes` is not positive, there are no prices to
+/// carry, or prices overlap or leave a gap that is not a whole number of intervals.
+pub fn fill_price_gaps(
+    prices: Vec<ElectricityPrice>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    interval_minutes: i64,
+) -> Result<Vec<ElectricityPrice>, LoadError> {
+    if interval_minutes <= 0 {
+        return Err(LoadError::Validation(
+            "Interval length must be positive.".to_string(),
+        ));
+    }
+    let interval = Duration::minutes(interval_minutes);
+    let first = prices
+        .first()
+        .cloned()
+        .ok_or_else(|| LoadError::Validation("No prices to fill gaps from.".to_string()))?;
+
+    // Fills `from` to `to` with copies of `template`
+    let fill = |filled: &mut Vec<ElectricityPrice>,
+                template: &ElectricityPrice,
+                from: DateTime<Utc>,
+                to: DateTime<Utc>|
+     -> Result<(), LoadError> {
+        let gap = to - from;
+        if gap.num_seconds() % interval.num_seconds() != 0 {
+            return Err(LoadError::Validation(format!(
+                "Cannot fill the gap from {} to {} with {}-minute intervals",
+                from, to, interval_minutes
+            )));
+        }
+        let missing = gap.num_seconds() / interval.num_seconds();
+        if missing > 0 {
+            info!(
+                "Filling {} missing price interval(s) from {}",
+                missing, from
+            );
+        }
+        for step in 0..missing as i32 {
+            let interval_start = from + interval * step;
+            filled.push(ElectricityPrice {
+                start: interval_start,
+                end: interval_start + interval,
+                ..template.clone()
+            });
+        }
+        Ok(())
+    };
+
+    let mut filled = Vec::with_capacity(prices.len());
+    let mut covered = start;
+    let mut last = first;
+    for price in prices {
+        if price.start < covered {
+            if price.end > covered {
+                return Err(LoadError::Validation(format!(
+                    "Price interval at {} overlaps the prices covered up to {}",
+                    price.start, covered
+                )));
+            }
+            continue; // Before the period
+        }
+        if price.start >= end {
+            break;
+        }
+        fill(&mut filled, &last, covered, price.start)?;
+        covered = price.end;
+        last = price.clone();
+        filled.push(price);
+    }
+    if covered < end {
+        fill(&mut filled, &last, covered, end)?;
+    }
+
+    Ok(filled)
+}
+
+/// Ranks prices from cheapest to most expensive.
+///
+/// Equal prices are common with flat tariffs, so ties are broken explicitly by
+/// start time: the earliest interval wins. The ranking is therefore the same on
+/// every run, whatever the order of `prices`.
+///
+/// # Arguments
+///
+/// * `prices`: The electricity prices to rank.
+///
+/// # Returns
+/// The indices into `prices`, cheapest first.
+pub fn rank_by_price(prices: &[ElectricityPrice]) -> Vec<usize> {
+    let mut ranking: Vec<usize> = (0..prices.len()).collect();
+    ranking.sort_by(|a, b| {
+        prices[*a]
+            .market_price_per_kwh
+            .total_cmp(&prices[*b].market_price_per_kwh)
+            .then_with(|| prices[*a].start.cmp(&prices[*b].start))
+    });
+    ranking
+}
+
+/// Calculates a percentile of the given prices.
+///
+/// Uses the nearest-rank method, so charging at or below the result selects the
+/// cheapest `percentile` share of the intervals (more if prices tie). Only the
+/// price at the rank is returned, so the order of tied intervals does not matter.
+///
+/// # Arguments
+///
+/// * `prices`: The electricity prices.
+/// * `percentile`: The percentile as a fraction between 0 and 1, e.g. 0.3.
+///
+/// # Returns
+/// The price at the given percentile, or `NaN` if `prices` is empty.
+pub fn price_percentile(prices: &[ElectricityPrice], percentile: f64) -> f64 {
+    let mut values: Vec<f64> = prices
+        .iter()
+        .map(|price| price.market_price_per_kwh)
+        .collect();
+    if values.is_empty() {
+        return f64::NAN;
     }
+    values.sort_by(f64::total_cmp);
 
-    fifteen_minute_prices
+    let rank = (percentile * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
 }
 
 /// Validates an electricity price entry.
@@ -114,12 +827,17 @@ fn convert_to_fifteen_minute_intervals(
 ///
 /// # Returns
 /// A `Result` indicating success or failure of the validation.
-fn validate_price(price: &ElectricityPrice) -> Result<()> {
-    if price.market_price_per_kwh < 0.0 {
-        return Err(anyhow!("Market price per kWh must be non-negative."));
+fn validate_price(price: &ElectricityPrice) -> Result<(), LoadError> {
+    // Negative prices are real (the consumer gets paid), but must still be numbers
+    if !price.market_price_per_kwh.is_finite() {
+        return Err(LoadError::Validation(
+            "Market price per kWh must be a finite number.".to_string(),
+        ));
     }
     if price.start >= price.end {
-        return Err(anyhow!("Price start time must be before end time."));
+        return Err(LoadError::Validation(
+            "Price start time must be before end time.".to_string(),
+        ));
     }
     Ok(())
 }
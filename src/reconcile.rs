@@ -0,0 +1,170 @@
+use crate::config::Settings;
+use crate::planning::Plan;
+use crate::prices::ElectricityPrice;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// The battery energy metered in one interval after the plan was executed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MeteredInterval {
+    /// Start time of the interval.
+    pub start: DateTime<Utc>,
+    /// End time of the interval.
+    pub end: DateTime<Utc>,
+    /// Energy discharged from the battery, in watt-hours.
+    pub energy_from_battery_wh: f64,
+    /// Energy charged into the battery, in watt-hours.
+    pub energy_to_battery_wh: f64,
+}
+
+/// The divergence between the planned and the metered battery energy of one interval.
+///
+/// Differences are metered minus planned, so a positive difference means the
+/// battery moved more energy than planned.
+#[derive(Debug, Serialize)]
+pub struct IntervalDivergence {
+    /// Start time of the interval.
+    pub start: DateTime<Utc>,
+    /// End time of the interval.
+    pub end: DateTime<Utc>,
+    /// Planned discharge, to the site and the grid, in watt-hours.
+    pub planned_from_battery_wh: f64,
+    /// Metered discharge, in watt-hours.
+    pub actual_from_battery_wh: f64,
+    /// Planned charge, from the grid and surplus production, in watt-hours.
+    pub planned_to_battery_wh: f64,
+    /// Metered charge, in watt-hours.
+    pub actual_to_battery_wh: f64,
+    /// Metered minus planned discharge, in watt-hours.
+    pub from_battery_difference_wh: f64,
+    /// Metered minus planned charge, in watt-hours.
+    pub to_battery_difference_wh: f64,
+    /// Extra cost of the divergence at the interval's import price, in EUR; negative
+    /// when the metered operation was cheaper than planned.
+    pub cost_impact_eur: f64,
+}
+
+/// The divergence between a plan and the metered operation of the battery.
+#[derive(Debug, Serialize)]
+pub struct ReconcileReport {
+    /// The intervals found in both the plan and the metered data, in plan order.
+    pub intervals: Vec<IntervalDivergence>,
+    /// Total metered minus planned discharge, in watt-hours.
+    pub total_from_battery_difference_wh: f64,
+    /// Total metered minus planned charge, in watt-hours.
+    pub total_to_battery_difference_wh: f64,
+    /// Total extra cost of the divergence, in EUR.
+    pub total_cost_impact_eur: f64,
+    /// Starts of the planned intervals without metered data.
+    pub unmetered: Vec<DateTime<Utc>>,
+    /// Starts of the metered intervals that were not planned.
+    pub unplanned: Vec<DateTime<Utc>>,
+}
+
+/// Compares a plan with the battery energy metered while it was executed.
+///
+/// Intervals are aligned on their start time. The planned discharge includes the
+/// energy exported to the grid, and the planned charge the surplus production
+/// stored, since the meter sees both. The cost impact values the net extra energy
+/// drawn into the battery at the interval's import price, grid fee and tax included:
+/// charging more than planned costs more, discharging more than planned saves imports.
+///
+/// # Arguments
+///
+/// * `plan`: The executed plan.
+/// * `actual`: The metered battery energy, in any order.
+/// * `prices`: The electricity prices of the planned intervals.
+/// * `settings`: The settings the plan was made with, for the grid fee and tax rate.
+///
+/// # Returns
+/// A `ReconcileReport` with the divergence of every interval found in both the plan
+/// and the metered data, their totals, and the intervals found in only one of them.
+pub fn reconcile(
+    plan: &[Plan],
+    actual: &[MeteredInterval],
+    prices: &[ElectricityPrice],
+    settings: &Settings,
+) -> ReconcileReport {
+    let metered: HashMap<DateTime<Utc>, &MeteredInterval> = actual
+        .iter()
+        .map(|interval| (interval.start, interval))
+        .collect();
+    let import_prices: HashMap<DateTime<Utc>, f64> = prices
+        .iter()
+        .map(|price| (price.start, settings.import_price(price)))
+        .collect();
+
+    let mut intervals = Vec::new();
+    let mut unmetered = Vec::new();
+    for planned in plan {
+        let Some(metered) = metered.get(&planned.start) else {
+            unmetered.push(planned.start);
+            continue;
+        };
+
+        let planned_from_battery_wh = planned.energy_from_battery_wh + planned.energy_to_grid_wh;
+        let planned_to_battery_wh = planned.energy_to_battery_wh + planned.energy_from_surplus_wh;
+        let from_battery_difference_wh = metered.energy_from_battery_wh - planned_from_battery_wh;
+        let to_battery_difference_wh = metered.energy_to_battery_wh - planned_to_battery_wh;
+
+        let import_price = import_prices
+            .get(&planned.start)
+            .copied()
+            .unwrap_or_else(|| {
+                warn!(
+                    "No price for the interval at {}, valuing its divergence at 0",
+                    planned.start
+                );
+                0.0
+            });
+        let cost_impact_eur =
+            (to_battery_difference_wh - from_battery_difference_wh) / 1_000.0 * import_price;
+
+        intervals.push(IntervalDivergence {
+            start: planned.start,
+            end: planned.end,
+            planned_from_battery_wh,
+            actual_from_battery_wh: metered.energy_from_battery_wh,
+            planned_to_battery_wh,
+            actual_to_battery_wh: metered.energy_to_battery_wh,
+            from_battery_difference_wh,
+            to_battery_difference_wh,
+            cost_impact_eur,
+        });
+    }
+
+    let planned_starts: HashSet<DateTime<Utc>> = plan.iter().map(|planned| planned.start).collect();
+    let unplanned: Vec<DateTime<Utc>> = actual
+        .iter()
+        .filter(|interval| !planned_starts.contains(&interval.start))
+        .map(|interval| interval.start)
+        .collect();
+
+    let report = ReconcileReport {
+        total_from_battery_difference_wh: intervals
+            .iter()
+            .map(|interval| interval.from_battery_difference_wh)
+            .sum(),
+        total_to_battery_difference_wh: intervals
+            .iter()
+            .map(|interval| interval.to_battery_difference_wh)
+            .sum(),
+        total_cost_impact_eur: intervals
+            .iter()
+            .map(|interval| interval.cost_impact_eur)
+            .sum(),
+        intervals,
+        unmetered,
+        unplanned,
+    };
+    info!(
+        "Reconciled {} interval(s): {} Wh more discharged and {} Wh more charged than planned, costing {} EUR",
+        report.intervals.len(),
+        report.total_from_battery_difference_wh,
+        report.total_to_battery_difference_wh,
+        report.total_cost_impact_eur
+    );
+    report
+}
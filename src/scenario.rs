@@ -0,0 +1,143 @@
+use crate::battery::{Battery, EnergyStorage};
+use crate::config::Config;
+use crate::forecast::Forecast;
+use crate::inverter::validate_against_inverter;
+use crate::planning::{charge_threshold, plan_battery_usage, plan_totals, summarize_plan};
+use crate::prices::ElectricityPrice;
+use anyhow::{Context, Result};
+use log::info;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The settings one scenario of a sweep changes; the fields left `None` keep the
+/// base configuration's value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConfigOverride {
+    /// Name of the scenario, repeated in its `ScenarioResult`.
+    pub name: String,
+    /// Replaces `grid_limit`.
+    pub grid_limit: Option<f64>,
+    /// Replaces `charge_power_mw`.
+    pub charge_power_mw: Option<f64>,
+    /// The import price at or below which the battery charges, instead of the one
+    /// derived from the prices and `charge_price_percentile`.
+    pub charge_threshold: Option<f64>,
+    /// Replaces `sell_threshold_per_kwh`.
+    pub sell_threshold_per_kwh: Option<f64>,
+}
+
+/// What the plan of one scenario achieves.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScenarioResult {
+    /// Name of the scenario, from its `ConfigOverride`.
+    pub name: String,
+    /// Total cost of charging the battery from the grid, in EUR.
+    pub total_cost_eur: f64,
+    /// Value of the avoided grid imports minus the cost of charging, in EUR.
+    pub total_savings_eur: f64,
+    /// Equivalent full cycles the battery makes over the plan.
+    pub cycles: f64,
+    /// Number of intervals whose grid import still exceeds the grid limit.
+    pub grid_limit_violations: usize,
+    /// Number of inverter constraints the plan breaks, if the configuration has any.
+    pub inverter_violations: usize,
+}
+
+/// Plans the same forecasts and prices for every variation of a base configuration,
+/// in parallel.
+///
+/// Each scenario plans with its own battery, built fresh from its settings, so the
+/// scenarios do not depend on each other or on the order they run in. Scenarios
+/// are planned without carbon intensities, so the carbon strategy is not supported.
+///
+/// # Arguments
+///
+/// * `base`: The configuration every scenario starts from.
+/// * `variations`: The settings each scenario changes.
+/// * `forecasts`: The forecasts, at the planning resolution.
+/// * `prices`: The electricity prices, at the planning resolution.
+///
+/// # Returns
+/// A `Result` containing one `ScenarioResult` per variation, in the order of `variations`.
+///
+/// # Errors
+/// Returns the error of the first scenario, in order, whose battery cannot be built
+/// or whose planning fails.
+pub fn evaluate_scenarios(
+    base: &Config,
+    variations: Vec<ConfigOverride>,
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+) -> Result<Vec<ScenarioResult>> {
+    let results = variations
+        .par_iter()
+        .map(|variation| evaluate_scenario(base, variation, forecasts, prices))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!("Evaluated {} scenario(s)", results.len());
+    Ok(results)
+}
+
+/// Plans the forecasts and prices for a single variation of a base configuration;
+/// see `evaluate_scenarios`.
+///
+/// # Arguments
+///
+/// * `base`: The configuration the scenario starts from.
+/// * `variation`: The settings the scenario changes.
+/// * `forecasts`: The forecasts, at the planning resolution.
+/// * `prices`: The electricity prices, at the planning resolution.
+///
+/// # Returns
+/// A `Result` containing the `ScenarioResult` of the scenario.
+///
+/// # Errors
+/// Returns an error if the scenario's battery cannot be built or its planning fails.
+pub fn evaluate_scenario(
+    base: &Config,
+    variation: &ConfigOverride,
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+) -> Result<ScenarioResult> {
+    let mut settings = base.settings.clone();
+    if let Some(grid_limit) = variation.grid_limit {
+        settings.grid_limit = grid_limit;
+    }
+    if let Some(charge_power_mw) = variation.charge_power_mw {
+        settings.charge_power_mw = charge_power_mw;
+    }
+    if let Some(sell_threshold) = variation.sell_threshold_per_kwh {
+        settings.sell_threshold_per_kwh = Some(sell_threshold);
+    }
+    let threshold = variation
+        .charge_threshold
+        .unwrap_or_else(|| charge_threshold(prices, &settings));
+
+    let mut battery = Battery::from_settings(&settings)
+        .with_context(|| format!("Invalid battery in scenario {}", variation.name))?;
+    let plan = plan_battery_usage(
+        forecasts.to_vec(),
+        prices.to_vec(),
+        &mut battery,
+        &settings,
+        threshold,
+        None,
+    )
+    .with_context(|| format!("Failed to plan scenario {}", variation.name))?;
+
+    let totals = plan_totals(&plan);
+    let summary = summarize_plan(&plan, forecasts, prices, &settings);
+    let inverter_violations = base
+        .inverter
+        .as_ref()
+        .map_or(0, |limits| validate_against_inverter(&plan, limits).len());
+
+    Ok(ScenarioResult {
+        name: variation.name.clone(),
+        total_cost_eur: totals.total_cost_eur,
+        total_savings_eur: totals.total_savings_eur,
+        cycles: battery.equivalent_cycles(),
+        grid_limit_violations: summary.intervals_over_grid_limit,
+        inverter_violations,
+    })
+}
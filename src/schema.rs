@@ -0,0 +1,152 @@
+use crate::error::LoadError;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// The JSON type a field must have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldKind {
+    /// An RFC 3339 timestamp string.
+    Timestamp,
+    /// A number.
+    Number,
+    /// A string.
+    String,
+}
+
+/// A field every entry of an input array may or must have.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldSpec {
+    /// The field name, after any field mapping has been applied.
+    pub name: &'static str,
+    /// The type the field must have.
+    pub kind: FieldKind,
+    /// Whether the field must be present; optional fields may also be `null`.
+    pub required: bool,
+}
+
+/// The fields of a forecast entry.
+pub const FORECAST_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "start",
+        kind: FieldKind::Timestamp,
+        required: true,
+    },
+    FieldSpec {
+        name: "end",
+        kind: FieldKind::Timestamp,
+        required: true,
+    },
+    FieldSpec {
+        name: "consumption_average_power_interval",
+        kind: FieldKind::Number,
+        required: true,
+    },
+    FieldSpec {
+        name: "consumption_p90",
+        kind: FieldKind::Number,
+        required: false,
+    },
+    FieldSpec {
+        name: "production_average_power_interval",
+        kind: FieldKind::Number,
+        required: false,
+    },
+    FieldSpec {
+        name: "temperature_c",
+        kind: FieldKind::Number,
+        required: false,
+    },
+];
+
+/// The fields of a day-ahead price entry.
+pub const PRICE_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "start",
+        kind: FieldKind::Timestamp,
+        required: true,
+    },
+    FieldSpec {
+        name: "end",
+        kind: FieldKind::Timestamp,
+        required: true,
+    },
+    FieldSpec {
+        name: "market_price_currency",
+        kind: FieldKind::String,
+        required: true,
+    },
+    FieldSpec {
+        name: "market_price_per_kwh",
+        kind: FieldKind::Number,
+        required: true,
+    },
+];
+
+/// Checks the structure of an input document before it is deserialized.
+///
+/// The document must be an object holding an array under `array`, and every entry
+/// of the array must be an object with the given fields. Unknown fields are
+/// ignored, as during deserialization.
+///
+/// # Arguments
+///
+/// * `value`: The parsed JSON document.
+/// * `array`: The name of the top-level array, e.g. `forecasts`.
+/// * `fields`: The fields of each entry.
+///
+/// # Returns
+/// A `Result` indicating success or failure of the validation.
+///
+/// # Errors
+/// Returns `LoadError::Schema` naming the first problem and where it is, e.g.
+/// "missing field `start` in forecasts[3]".
+pub fn validate_schema(value: &Value, array: &str, fields: &[FieldSpec]) -> Result<(), LoadError> {
+    let entries = value
+        .get(array)
+        .ok_or_else(|| LoadError::Schema(format!("missing top-level field `{}`", array)))?
+        .as_array()
+        .ok_or_else(|| LoadError::Schema(format!("`{}` must be an array", array)))?;
+
+    for (index, entry) in entries.iter().enumerate() {
+        let object = entry
+            .as_object()
+            .ok_or_else(|| LoadError::Schema(format!("{}[{}] must be an object", array, index)))?;
+
+        for field in fields {
+            let field_value = match object.get(field.name) {
+                None | Some(Value::Null) if !field.required => continue,
+                None => {
+                    return Err(LoadError::Schema(format!(
+                        "missing field `{}` in {}[{}]",
+                        field.name, array, index
+                    )));
+                }
+                Some(field_value) => field_value,
+            };
+
+            let valid = match field.kind {
+                FieldKind::Timestamp => field_value
+                    .as_str()
+                    .is_some_and(|text| text.parse::<DateTime<Utc>>().is_ok()),
+                FieldKind::Number => field_value.is_number(),
+                FieldKind::String => field_value.is_string(),
+            };
+            if !valid {
+                return Err(LoadError::Schema(format!(
+                    "field `{}` in {}[{}] must be {}, got {}",
+                    field.name,
+                    array,
+                    index,
+                    match field.kind {
+                        FieldKind::Timestamp => "an RFC 3339 timestamp",
+                        FieldKind::Number => "a number",
+                        FieldKind::String => "a string",
+                    },
+                    field_value
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use crate::battery::Battery;
+use crate::config::Config;
+use crate::forecast::load_forecasts_reader_with_mapping;
+use crate::pipeline::{plan_inputs, prepare_inputs, PlanOutcome};
+use crate::planning::{express_energy_in, plan_totals, round_plan};
+use crate::prices::load_day_ahead_prices_reader_with_mapping;
+use anyhow::{anyhow, Context, Result};
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Json, Router};
+use log::{info, warn};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// The body of a `POST /plan` request.
+#[derive(Deserialize)]
+pub struct PlanRequest {
+    /// The configuration, with the same sections and fields as `config.toml`.
+    #[serde(deserialize_with = "deserialize_config")]
+    pub config: Config,
+    /// The forecasts document, as in `forecasts.json`.
+    pub forecasts: Value,
+    /// The day-ahead prices document, as in `day-ahead.json`.
+    pub prices: Value,
+}
+
+/// Deserializes a request's configuration, applying its `chemistry` preset as
+/// `load_config` does for configuration files.
+fn deserialize_config<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Config, D::Error> {
+    let raw = Value::deserialize(deserializer)?;
+    let mut config: Config =
+        serde_json::from_value(raw.clone()).map_err(serde::de::Error::custom)?;
+    config.apply_chemistry(&raw);
+    Ok(config)
+}
+
+/// Plans the battery usage for a request, the same way the CLI plans its input files.
+///
+/// # Arguments
+///
+/// * `request`: The configuration, forecasts and prices to plan for.
+///
+/// # Returns
+/// A `Result` containing the plan as the CLI writes it to its JSON output, with
+/// the `PlanSummary` added under `summary`.
+///
+/// # Errors
+/// Returns an error if the configuration is invalid or names files to read, the
+/// inputs cannot be loaded, planning fails, or the plan violates the inverter limits.
+pub fn plan_request(request: PlanRequest) -> Result<Value> {
+    let PlanRequest {
+        config,
+        forecasts,
+        prices,
+    } = request;
+    config.validate()?;
+    let settings = &config.settings;
+
+    // Settings that name files would be read from the server's file system, so a
+    // request could probe or read files it has no business with
+    for (field, path) in [
+        ("intraday_prices", &settings.intraday_prices),
+        ("price_profile", &settings.price_profile),
+        ("ev_schedule", &settings.ev_schedule),
+        ("carbon_intensity", &settings.carbon_intensity),
+    ] {
+        if path.is_some() {
+            return Err(anyhow!(
+                "`{}` names a file and cannot be set in a plan request",
+                field
+            ));
+        }
+    }
+
+    let mut forecasts_data =
+        load_forecasts_reader_with_mapping(forecasts.to_string().as_bytes(), &config.field_mapping)
+            .context("Failed to load forecasts")?;
+    let (mut prices_data, _) = load_day_ahead_prices_reader_with_mapping(
+        prices.to_string().as_bytes(),
+        &config.field_mapping,
+        Some(&settings.reporting_currency),
+        &settings.exchange_rates,
+        settings.interval_minutes,
+        settings.snap_timestamps_minutes,
+    )
+    .context("Failed to load day-ahead prices")?;
+    prepare_inputs(&config, &mut forecasts_data, &mut prices_data)?;
+
+    let mut battery = Battery::from_settings(settings).context("Invalid battery configuration")?;
+    let PlanOutcome { plan, summary, .. } =
+        plan_inputs(&config, &mut battery, &mut forecasts_data, &prices_data)?;
+
+    let mut plan = plan;
+    if let Some(decimals) = settings.output_decimals {
+        round_plan(&mut plan, decimals);
+    }
+    let totals = plan_totals(&plan);
+    let mut response = serde_json::json!({
+        "planning": plan,
+        "total_cost_eur": totals.total_cost_eur,
+        "total_savings_eur": totals.total_savings_eur,
+        "summary": summary
+    });
+    express_energy_in(&mut response, settings.plan_energy_unit);
+
+    Ok(response)
+}
+
+/// Handles `POST /plan`, answering invalid inputs with `422 Unprocessable Entity`
+/// and the error message.
+async fn plan_handler(
+    Json(request): Json<PlanRequest>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    // Planning is CPU-bound, so keep it off the async workers
+    let result = tokio::task::spawn_blocking(move || plan_request(request))
+        .await
+        .map_err(|error| (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))?;
+
+    result.map(Json).map_err(|error| {
+        warn!("Rejected plan request: {:#}", error);
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", error))
+    })
+}
+
+/// Returns the router of the plan service, with the single route `POST /plan`.
+pub fn router() -> Router {
+    Router::new().route("/plan", post(plan_handler))
+}
+
+/// Serves plans over HTTP until the process is stopped.
+///
+/// # Arguments
+///
+/// * `addr`: The address to listen on, e.g. `127.0.0.1:3000`.
+///
+/// # Returns
+/// A `Result` that is only returned early, if the server cannot start or fails.
+pub fn serve(addr: &str) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new().context("Failed to start the async runtime")?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Unable to listen on {}", addr))?;
+        info!("Serving plans on http://{}/plan", addr);
+        axum::serve(listener, router())
+            .await
+            .context("The plan server failed")
+    })
+}
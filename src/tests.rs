@@ -1,26 +1,100 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
 
-    use crate::battery::Battery;
-    use crate::config;
-    use crate::forecast::load_forecasts;
-    use crate::prices::load_day_ahead_prices;
+    use crate::bank::BatteryBank;
+    use crate::battery::{
+        delivered_for_drawn, grid_energy_for_stored, Battery, BatteryState, WH_PER_MWH,
+    };
+    use crate::carbon::{co2_avoided_kg, CarbonIntensity};
+    use crate::cli::Cli;
+    use crate::config::{
+        self, ChargeStrategy, Chemistry, Objective, PlanningQuantile, Settings, Strategy,
+    };
+    use crate::error::{BatteryError, LoadError};
+    use crate::ev::{apply_ev_schedule, EvSchedule, EvWindow};
+    use crate::forecast::{
+        fill_gaps, load_forecasts, load_forecasts_csv, load_forecasts_reader,
+        load_forecasts_with_mapping, resample_forecasts, snap_forecasts, snap_to_grid, Forecast,
+        Forecasts,
+    };
+    use crate::inverter::{validate_against_inverter, InverterLimits, ViolationKind};
+    use crate::logging::{logger_builder, LogFormat};
+    use crate::planning::{
+        aggregate_by_day, aggregate_by_month, battery_utilization, charge_threshold,
+        check_simultaneous_flows, diff_plans, plan_arbitrage, plan_battery_usage,
+        plan_battery_usage_iter, plan_battery_usage_optimal, plan_battery_usage_within,
+        plan_cost_delta_eur, plan_rolling, plan_totals, round_plan, save_plan, save_plan_csv,
+        save_plan_ndjson, save_plan_per_day, simulate_plan, summarize_plan, trim_to_common_horizon,
+        OutputFormat, Plan,
+    };
+    use crate::prices::{
+        convert_to_intervals, fill_price_gaps, load_day_ahead_prices, load_day_ahead_prices_reader,
+        load_day_ahead_prices_reader_with_mapping, load_day_ahead_prices_with_mapping,
+        load_price_profile, load_prices_merged, merge_price_profile, price_percentile,
+        rank_by_price, ElectricityPrice, PriceSource, DEFAULT_INTERVAL_MINUTES,
+    };
+    use crate::reconcile::{reconcile, MeteredInterval};
+    use crate::scenario::{evaluate_scenario, evaluate_scenarios, ConfigOverride};
+    use crate::timeline::{build_timeline, plan_from_timeline};
+    use crate::units::{convert, from_wh, round_half_even, round_wh, to_mwh, EnergyUnit};
+    use crate::validation::validate_inputs;
+    use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime, Utc};
+    use clap::Parser;
+    use std::collections::{BTreeMap, HashMap};
     use std::fs;
-    use tempfile::NamedTempFile;
+    use tempfile::{tempdir, NamedTempFile};
 
     /// Initializes a Battery instance using values from the configuration file.
     ///
     /// # Returns
     /// A Battery instance initialized with the configuration settings.
     fn initialize_battery() -> Battery {
-        let config = config::load_config("config.toml").expect("Failed to load configuration");
+        Battery::from_settings(&load_settings()).expect("Invalid battery configuration")
+    }
 
-        Battery::new(
-            config.settings.capacity,
-            config.settings.initial_charge,
-            config.settings.max_rate,
-            config.settings.efficiency,
-        )
+    /// Loads the settings from the configuration file.
+    fn load_settings() -> Settings {
+        config::load_config("config.toml")
+            .expect("Failed to load configuration")
+            .settings
+    }
+
+    /// Start of the first interval used by the synthetic test series.
+    fn series_start() -> DateTime<Utc> {
+        "2022-12-12T23:00:00Z".parse().unwrap()
+    }
+
+    /// Builds consecutive 15-minute forecasts with the given consumption values.
+    fn make_forecasts(consumptions: &[f64]) -> Vec<Forecast> {
+        consumptions
+            .iter()
+            .enumerate()
+            .map(|(i, consumption)| Forecast {
+                start: series_start() + Duration::minutes(i as i64 * 15),
+                end: series_start() + Duration::minutes((i as i64 + 1) * 15),
+                consumption_average_power_interval: *consumption,
+                consumption_p90: None,
+                production_average_power_interval: None,
+                temperature_c: None,
+            })
+            .collect()
+    }
+
+    /// Builds consecutive 15-minute prices with the given values in EUR/kWh.
+    fn make_prices(values: &[f64]) -> Vec<ElectricityPrice> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| ElectricityPrice {
+                start: series_start() + Duration::minutes(i as i64 * 15),
+                end: series_start() + Duration::minutes((i as i64 + 1) * 15),
+                market_price_currency: "EUR".to_string(),
+                market_price_per_kwh: *value,
+                is_fallback: false,
+                source: PriceSource::DayAhead,
+            })
+            .collect()
     }
 
     #[test]
@@ -32,7 +106,8 @@ mod tests {
         assert_eq!(battery.charge, 2.4); // Expect 2.4 MWh with 90% efficiency
 
         // Verify that the energy charged matches the expected energy considering efficiency
-        assert_eq!(energy_charged, 0.9); // Expect 0.9 MWh to be stored
+        assert_eq!(energy_charged.battery_wh, 900_000.0); // Expect 0.9 MWh to be stored
+        assert_eq!(energy_charged.grid_wh, 1_000_000.0); // Drawn from the grid
     }
 
     #[test]
@@ -40,14 +115,14 @@ mod tests {
         let mut battery = initialize_battery(); // Use the new function to initialize the battery
         let energy_charged = battery.charge_battery(1.0, 1.0).unwrap(); // Charge with 1 MW for 1 hour
         println!(
-            "After charging: Battery charge is {} MWh, energy charged: {} MWh",
-            battery.charge, energy_charged
+            "After charging: Battery charge is {} MWh, energy charged: {} Wh",
+            battery.charge, energy_charged.battery_wh
         );
 
         let energy_discharged = battery.discharge_battery(1.0, 1.0).unwrap(); // 1 MW for 1 hour
         println!(
-            "After discharging: Battery charge is {} MWh, energy discharged: {} MWh",
-            battery.charge, energy_discharged
+            "After discharging: Battery charge is {} MWh, energy discharged: {} Wh",
+            battery.charge, energy_discharged.battery_wh
         );
 
         assert!(
@@ -56,9 +131,14 @@ mod tests {
             battery.charge
         );
         assert!(
-            (energy_discharged - 1.111).abs() < 0.001,
-            "Expected discharged: 1.111 MWh, Actual discharged: {}",
-            energy_discharged
+            (energy_discharged.grid_wh - 1_000_000.0).abs() < 1.0,
+            "Expected delivered: 1000000 Wh, Actual delivered: {}",
+            energy_discharged.grid_wh
+        );
+        assert!(
+            (energy_discharged.battery_wh - 1_111_111.1).abs() < 1.0,
+            "Expected drawn: 1111111.1 Wh, Actual drawn: {}",
+            energy_discharged.battery_wh
         );
     }
 
@@ -67,8 +147,8 @@ mod tests {
         let mut battery = initialize_battery(); // Use the new function to initialize the battery
         let energy_charged = battery.charge_battery(5.0, 1.0).unwrap(); // 5 MW for 1 hour
         println!(
-            "After charging: Battery charge is {} MWh, energy charged: {} MWh",
-            battery.charge, energy_charged
+            "After charging: Battery charge is {} MWh, energy charged: {} Wh",
+            battery.charge, energy_charged.battery_wh
         );
 
         assert!(
@@ -78,9 +158,14 @@ mod tests {
         );
 
         assert!(
-            (energy_charged - 1.35).abs() < 0.01,
-            "Expected charged energy to be 1.35 MWh due to capacity limit, Actual: {}",
-            energy_charged
+            (energy_charged.battery_wh - 1_350_000.0).abs() < 1.0,
+            "Expected stored energy to be 1350000 Wh due to the 1.5 MW charge rate, Actual: {}",
+            energy_charged.battery_wh
+        );
+        assert!(
+            (energy_charged.grid_wh - 1_500_000.0).abs() < 1.0,
+            "Expected drawn energy to be 1500000 Wh, Actual: {}",
+            energy_charged.grid_wh
         );
     }
 
@@ -88,16 +173,59 @@ mod tests {
     fn test_discharge_exceed_capacity() {
         let mut battery = initialize_battery(); // Use the new function to initialize the battery
         let energy_discharged = battery.discharge_battery(3.0, 1.0).unwrap(); // Attempt to discharge more than available
+        assert_eq!(battery.max_discharge_rate(), 1.5);
         assert!(
             battery.charge.abs() < 0.01,
             "Expected charge: 0.0 MWh, Actual charge: {}",
             battery.charge
         );
         assert!(
-            (energy_discharged - 1.5).abs() < 0.01,
-            "Expected discharged energy: 1.5 MWh, Actual discharged energy: {}",
-            energy_discharged
+            (energy_discharged.grid_wh - 1_350_000.0).abs() < 1.0,
+            "Expected delivered energy: 1350000 Wh, Actual delivered energy: {}",
+            energy_discharged.grid_wh
+        );
+        assert!((energy_discharged.battery_wh - 1_500_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_separate_charge_and_discharge_rates() {
+        let mut battery = Battery::new(10.0, 5.0, 1.5, 1.0, 1.0, 0.0, 0.0).with_rates(2.0, 1.0);
+        assert_eq!(battery.max_charge_rate(), 2.0);
+        assert_eq!(battery.max_discharge_rate(), 1.0);
+        assert_eq!(battery.max_rate(), 2.0);
+
+        // The same 3 MW request is clamped to a different power in each direction
+        let charged = battery.charge_battery(3.0, 1.0).unwrap();
+        assert!((charged.grid_wh - 2_000_000.0).abs() < 1e-6);
+        let discharged = battery.discharge_battery(3.0, 1.0).unwrap();
+        assert!((discharged.grid_wh - 1_000_000.0).abs() < 1e-6);
+        assert!((battery.charge - 6.0).abs() < 1e-9);
+
+        // A single max_rate still applies to both directions
+        let battery = initialize_battery();
+        assert_eq!(battery.max_charge_rate(), 1.5);
+        assert_eq!(battery.max_discharge_rate(), 1.5);
+        let mut settings = load_settings();
+        assert_eq!(settings.max_charge_rate(), 1.5);
+        assert_eq!(settings.max_discharge_rate(), 1.5);
+        settings.max_discharge_rate = Some(1.0);
+        assert_eq!(settings.max_charge_rate(), 1.5);
+        assert_eq!(settings.max_discharge_rate(), 1.0);
+    }
+
+    #[test]
+    fn test_discharge_nearly_empty_accounts_for_efficiency() {
+        let mut battery = initialize_battery();
+        battery.charge = 0.1; // Nearly empty
+
+        let delivered = battery.discharge_battery(1.5, 0.25).unwrap(); // Wants 0.375 MWh
+        assert!(
+            (delivered.grid_wh - 90_000.0).abs() < 1e-6,
+            "Expected delivered: 90000 Wh (0.1 MWh at 90% efficiency), Actual delivered: {}",
+            delivered.grid_wh
         );
+        assert!((delivered.battery_wh - 100_000.0).abs() < 1e-6);
+        assert_eq!(battery.charge, 0.0);
     }
 
     #[test]
@@ -115,9 +243,9 @@ mod tests {
             battery.charge
         );
         assert!(
-            (discharged_energy - 1.111).abs() < 0.001,
-            "Expected discharged: 1.111, Actual discharged: {}",
-            discharged_energy
+            (discharged_energy.grid_wh - 1_000_000.0).abs() < 1.0,
+            "Expected delivered: 1000000 Wh, Actual delivered: {}",
+            discharged_energy.grid_wh
         );
     }
 
@@ -139,15 +267,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_asymmetric_efficiency() {
+        let mut battery = Battery::new(3.0, 1.5, 1.5, 0.95, 0.90, 0.0, 0.0);
+
+        let stored = battery.charge_battery(1.0, 1.0).unwrap(); // 1 MW for 1 hour
+        assert!(
+            (stored.battery_wh - 950_000.0).abs() < 1e-6,
+            "Expected 0.95 MWh stored"
+        );
+        assert!((stored.grid_wh - 1_000_000.0).abs() < 1e-6);
+        assert!((battery.charge - 2.45).abs() < 1e-9);
+
+        let delivered = battery.discharge_battery(1.0, 1.0).unwrap(); // 1 MW for 1 hour
+        assert!(
+            (delivered.grid_wh - 1_000_000.0).abs() < 1e-6,
+            "Expected 1.0 MWh delivered"
+        );
+        // Delivering 1.0 MWh at 90% draws 1.111 MWh from the pack
+        assert!(
+            (battery.charge - (2.45 - 1.0 / 0.9)).abs() < 1e-9,
+            "Expected charge: 1.339 MWh, Actual charge: {}",
+            battery.charge
+        );
+    }
+
+    #[test]
+    fn test_config_efficiency_compatibility() {
+        let settings = load_settings(); // config.toml only sets `efficiency`
+        assert_eq!(settings.charge_efficiency(), 0.9);
+        assert_eq!(settings.discharge_efficiency(), 0.9);
+
+        let temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let split = r#"
+        [settings]
+        capacity = 3.0
+        initial_charge = 1.5
+        max_rate = 1.5
+        charge_efficiency = 0.95
+        discharge_efficiency = 0.90
+        grid_limit = 7800000.0
+        "#;
+        let _ = fs::write(temp_file.path(), split);
+        let settings = config::load_config(temp_file.path().to_str().unwrap())
+            .unwrap()
+            .settings;
+        assert_eq!(settings.charge_efficiency(), 0.95);
+        assert_eq!(settings.discharge_efficiency(), 0.90);
+
+        let missing = split.replace("charge_efficiency = 0.95", "");
+        let _ = fs::write(temp_file.path(), missing);
+        assert!(config::load_config(temp_file.path().to_str().unwrap()).is_err());
+    }
+
     #[test]
     fn test_charge_negative_energy() {
         let mut battery = initialize_battery(); // Use the new function to initialize the battery
         battery.charge = 1.5; // Set initial charge to 1.5 MWh
         let result = battery.charge_battery(-1.0, 1.0); // Negative charge attempt
 
-        // Check that the operation returns an Err
+        // Check that the operation returns the matching error variant
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(BatteryError::NegativePower {
+                    operation: "charge",
+                    power_mw,
+                }) if power_mw == -1.0
+            ),
             "Expected an error when charging with negative power."
         );
 
@@ -160,9 +347,15 @@ mod tests {
         let mut battery = initialize_battery(); // Use the new function to initialize the battery
         let result = battery.discharge_battery(-1.0, 1.0); // Negative discharge attempt
 
-        // Check that the operation returns an Err
+        // Check that the operation returns the matching error variant
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(BatteryError::NegativePower {
+                    operation: "discharge",
+                    ..
+                })
+            ),
             "Expected an error when discharging with negative power."
         );
 
@@ -181,17 +374,150 @@ mod tests {
         ); // Should reach capacity
 
         assert!(
-            (energy_charged - 1.5).abs() < 0.01,
-            "Expected charged energy: 1.5 MWh, Actual charged energy: {}",
-            energy_charged
+            (energy_charged.battery_wh - 1_500_000.0).abs() < 1.0,
+            "Expected charged energy: 1500000 Wh, Actual charged energy: {}",
+            energy_charged.battery_wh
+        );
+    }
+
+    #[test]
+    fn test_state_of_charge() {
+        let mut battery = Battery::new(3.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+        assert_eq!(battery.capacity(), 3.0);
+        assert_eq!(battery.state_of_charge(), 0.0); // Empty pack
+
+        battery.charge = 3.0;
+        assert_eq!(battery.state_of_charge(), 1.0); // Full pack
+
+        battery.charge = 1.5;
+        assert_eq!(battery.state_of_charge(), 0.5); // Half-full pack
+    }
+
+    #[test]
+    fn test_state_of_charge_zero_capacity() {
+        let battery = Battery::new(0.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+        assert_eq!(battery.state_of_charge(), 0.0);
+    }
+
+    #[test]
+    fn test_discharge_respects_min_charge() {
+        let mut battery = Battery::new(3.0, 1.5, 1.5, 0.9, 0.9, 0.3, 0.0);
+
+        // Repeatedly discharge at full rate; the floor must hold every time
+        let mut delivered_total = 0.0;
+        for _ in 0..10 {
+            delivered_total += battery.discharge_battery(1.5, 0.25).unwrap().grid_wh;
+            assert!(
+                battery.charge >= 0.3 - 1e-12,
+                "Battery discharged below its floor: {} MWh",
+                battery.charge
+            );
+        }
+
+        assert!((battery.charge - 0.3).abs() < 1e-12);
+        // Only the 1.2 MWh above the floor was released, at 90% efficiency
+        assert!(
+            (delivered_total - 1_080_000.0).abs() < 1e-6,
+            "Expected delivered: 1080000 Wh, Actual delivered: {}",
+            delivered_total
+        );
+
+        // Nothing left above the floor
+        assert_eq!(battery.discharge_battery(1.5, 0.25).unwrap().grid_wh, 0.0);
+    }
+
+    #[test]
+    fn test_self_discharge() {
+        let mut battery = Battery::new(3.0, 2.0, 1.5, 0.9, 0.9, 0.0, 0.02);
+        battery.apply_self_discharge(0.25); // 15 minutes at 2% per day
+
+        let expected = 2.0 - 2.0 * 0.02 * 0.25 / 24.0;
+        assert!(
+            (battery.charge - expected).abs() < 1e-12,
+            "Expected charge: {} MWh, Actual charge: {}",
+            expected,
+            battery.charge
+        );
+
+        // Never below the floor
+        let mut battery = Battery::new(3.0, 0.3, 1.5, 0.9, 0.9, 0.3, 0.5);
+        battery.apply_self_discharge(24.0);
+        assert_eq!(battery.charge, 0.3);
+    }
+
+    #[test]
+    fn test_cycle_count_and_state_of_health() {
+        let mut battery = Battery::new(3.0, 0.0, 3.0, 1.0, 1.0, 0.0, 0.0);
+
+        // 100 full charge/discharge cycles
+        for _ in 0..100 {
+            battery.charge_battery(3.0, 1.0).unwrap();
+            battery.discharge_battery(3.0, 1.0).unwrap();
+        }
+
+        // Each cycle adds the current state of health, so C(n) = (1 - 0.9998^n) / 0.0002
+        let expected_cycles = (1.0 - 0.9998_f64.powi(100)) / 0.0002;
+        assert!(
+            (battery.cycles - expected_cycles).abs() < 1e-9,
+            "Expected cycles: {}, Actual cycles: {}",
+            expected_cycles,
+            battery.cycles
         );
+        assert!((battery.state_of_health() - (1.0 - 0.0002 * expected_cycles)).abs() < 1e-9);
+
+        // The degraded capacity limits the next charge
+        let effective_capacity = battery.effective_capacity();
+        let stored = battery.charge_battery(3.0, 1.0).unwrap();
+        assert!((stored.battery_wh - effective_capacity * WH_PER_MWH).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_state_of_health_floor() {
+        let mut battery = Battery::new(3.0, 0.0, 3.0, 1.0, 1.0, 0.0, 0.0);
+        battery.cycles = 5000.0;
+        assert_eq!(battery.state_of_health(), 0.8);
+        assert!((battery.effective_capacity() - 2.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_energy_to_full() {
+        let empty = Battery::new(3.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+        let mid = Battery::new(3.0, 1.2, 1.5, 0.9, 0.9, 0.0, 0.0);
+        let full = Battery::new(3.0, 3.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+
+        assert_eq!(empty.energy_to_full(), 3.0);
+        assert!((mid.energy_to_full() - 1.8).abs() < 1e-12);
+        assert_eq!(full.energy_to_full(), 0.0);
+
+        // A degraded battery is full sooner
+        let mut degraded = Battery::new(3.0, 2.4, 1.5, 0.9, 0.9, 0.0, 0.0);
+        degraded.cycles = 5000.0;
+        assert!(degraded.energy_to_full().abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_energy_available() {
+        let empty = Battery::new(3.0, 0.3, 1.5, 0.9, 0.9, 0.3, 0.0);
+        let mid = Battery::new(3.0, 1.2, 1.5, 0.9, 0.9, 0.3, 0.0);
+        let full = Battery::new(3.0, 3.0, 1.5, 0.9, 0.9, 0.3, 0.0);
+
+        assert_eq!(empty.energy_available(), 0.0);
+        assert!((mid.energy_available() - 0.9).abs() < 1e-12);
+        assert!((full.energy_available() - 2.7).abs() < 1e-12);
+
+        // Below the floor nothing is available rather than a negative amount
+        let below_floor = Battery::new(3.0, 0.1, 1.5, 0.9, 0.9, 0.3, 0.0);
+        assert_eq!(below_floor.energy_available(), 0.0);
     }
 
     #[test]
     fn test_load_forecasts_invalid_file() {
-        let result = load_forecasts("non_existent_file.json");
+        let result = load_forecasts("non_existent_file.json", false);
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(LoadError::Io { ref source, .. }) if source.kind() == std::io::ErrorKind::NotFound
+            ),
             "Expected an error when loading a non-existent file."
         );
     }
@@ -202,7 +528,7 @@ mod tests {
         let temp_file = NamedTempFile::new().unwrap();
         let _ = fs::write(temp_file.path(), "invalid json data");
 
-        let result = load_forecasts(temp_file.path().to_str().unwrap());
+        let result = load_forecasts(temp_file.path().to_str().unwrap(), false);
         assert!(
             result.is_err(),
             "Expected an error when loading invalid JSON data."
@@ -226,7 +552,7 @@ mod tests {
 
         let _ = fs::write(temp_file.path(), valid_json);
 
-        let result = load_forecasts(temp_file.path().to_str().unwrap()).unwrap();
+        let result = load_forecasts(temp_file.path().to_str().unwrap(), false).unwrap();
         assert_eq!(result.forecasts.len(), 1, "Expected to load one forecast.");
         assert_eq!(
             result.forecasts[0].consumption_average_power_interval, 5.0,
@@ -234,11 +560,137 @@ mod tests {
         );
     }
 
+    /// Writes the forecasts to a temporary JSON file and loads them in strict mode.
+    fn load_strict(forecasts: Vec<Forecast>) -> Result<Forecasts, LoadError> {
+        let temp_file = NamedTempFile::new().unwrap();
+        let json = serde_json::to_string(&Forecasts { forecasts }).unwrap();
+        let _ = fs::write(temp_file.path(), json);
+        load_forecasts(temp_file.path().to_str().unwrap(), true)
+    }
+
+    #[test]
+    fn test_load_forecasts_strict_timeline() {
+        // A clean timeline loads unchanged
+        let result = load_strict(make_forecasts(&[1.0, 2.0, 3.0])).unwrap();
+        assert_eq!(result.forecasts.len(), 3);
+
+        // Out-of-order input is sorted into a clean timeline
+        let mut shuffled = make_forecasts(&[1.0, 2.0, 3.0]);
+        shuffled.swap(0, 2);
+        let result = load_strict(shuffled).unwrap();
+        let consumptions: Vec<f64> = result
+            .forecasts
+            .iter()
+            .map(|forecast| forecast.consumption_average_power_interval)
+            .collect();
+        assert_eq!(consumptions, vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_load_forecasts_strict_gap_and_overlap() {
+        // Dropping the middle interval leaves a gap
+        let mut gapped = make_forecasts(&[1.0, 2.0, 3.0]);
+        let missing = gapped.remove(1);
+        let message = load_strict(gapped).err().unwrap().to_string();
+        assert!(
+            message.contains("Gap") && message.contains(&missing.start.to_string()),
+            "Unexpected error message: {}",
+            message
+        );
+
+        // Stretching the first interval makes it overlap the second
+        let mut overlapping = make_forecasts(&[1.0, 2.0, 3.0]);
+        overlapping[0].end += Duration::minutes(5);
+        let message = load_strict(overlapping).err().unwrap().to_string();
+        assert!(
+            message.contains("Overlap"),
+            "Unexpected error message: {}",
+            message
+        );
+
+        // The loose mode still accepts the same input
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut gapped = make_forecasts(&[1.0, 2.0, 3.0]);
+        gapped.remove(1);
+        let json = serde_json::to_string(&Forecasts { forecasts: gapped }).unwrap();
+        let _ = fs::write(temp_file.path(), json);
+        assert!(load_forecasts(temp_file.path().to_str().unwrap(), false).is_ok());
+    }
+
+    /// Writes the given contents to a temporary file with a `.csv` extension.
+    fn write_csv(contents: &str) -> NamedTempFile {
+        let temp_file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+        let _ = fs::write(temp_file.path(), contents);
+        temp_file
+    }
+
+    #[test]
+    fn test_load_forecasts_csv_valid_data() {
+        let temp_file = write_csv(
+            "start,end,consumption_average_power_interval\n\
+             2022-12-12T00:00:00Z,2022-12-12T00:15:00Z,5.0\n\
+             2022-12-12T00:15:00Z,2022-12-12T00:30:00Z,6.5\n",
+        );
+
+        // The extension selects the CSV reader
+        let result = load_forecasts(temp_file.path().to_str().unwrap(), true).unwrap();
+        assert_eq!(result.forecasts.len(), 2, "Expected to load two forecasts.");
+        assert_eq!(result.forecasts[1].consumption_average_power_interval, 6.5);
+        assert_eq!(result.forecasts[1].temperature_c, None);
+    }
+
+    #[test]
+    fn test_load_forecasts_rejects_invalid_p90() {
+        let mut forecasts = make_forecasts(&[5.0]);
+        forecasts[0].consumption_p90 = Some(-1.0);
+        assert!(matches!(
+            load_strict(forecasts),
+            Err(LoadError::Validation(_))
+        ));
+
+        let temp_file = write_csv(
+            "start,end,consumption_average_power_interval,consumption_p90\n\
+             2022-12-12T00:00:00Z,2022-12-12T00:15:00Z,5.0,NaN\n",
+        );
+        assert!(matches!(
+            load_forecasts(temp_file.path().to_str().unwrap(), false),
+            Err(LoadError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_forecasts_csv_malformed_row() {
+        let temp_file = write_csv(
+            "start,end,consumption_average_power_interval\n\
+             2022-12-12T00:00:00Z,2022-12-12T00:15:00Z,5.0\n\
+             2022-12-12T00:15:00Z,not a time,6.5\n",
+        );
+
+        let message = load_forecasts_csv(temp_file.path().to_str().unwrap())
+            .err()
+            .expect("Expected an error for a malformed row.")
+            .to_string();
+        assert!(message.contains("row 3"), "Unexpected error: {}", message);
+    }
+
+    #[test]
+    fn test_load_forecasts_csv_missing_column() {
+        let temp_file = write_csv(
+            "start,end\n\
+             2022-12-12T00:00:00Z,2022-12-12T00:15:00Z\n",
+        );
+
+        assert!(
+            load_forecasts_csv(temp_file.path().to_str().unwrap()).is_err(),
+            "Expected an error when a column is missing."
+        );
+    }
+
     #[test]
     fn test_load_prices_invalid_file() {
         let result = load_day_ahead_prices("non_existent_file.json");
         assert!(
-            result.is_err(),
+            matches!(result, Err(LoadError::Io { .. })),
             "Expected an error when loading a non-existent file."
         );
     }
@@ -251,7 +703,7 @@ mod tests {
 
         let result = load_day_ahead_prices(temp_file.path().to_str().unwrap());
         assert!(
-            result.is_err(),
+            matches!(result, Err(LoadError::Json { .. })),
             "Expected an error when loading invalid JSON data."
         );
     }
@@ -290,4 +742,4071 @@ mod tests {
             assert_eq!(price.market_price_per_kwh, 0.25, "Expected price to match.");
         }
     }
+
+    /// Builds a day-ahead prices JSON document with one hourly price per `(currency, price)`.
+    fn prices_json(entries: &[(&str, f64)]) -> String {
+        let prices: Vec<String> = entries
+            .iter()
+            .enumerate()
+            .map(|(hour, (currency, price))| {
+                let start = series_start() + Duration::hours(hour as i64);
+                format!(
+                    r#"{{"start": "{}", "end": "{}", "market_price_currency": "{}", "market_price_per_kwh": {}}}"#,
+                    start.to_rfc3339(),
+                    (start + Duration::hours(1)).to_rfc3339(),
+                    currency,
+                    price
+                )
+            })
+            .collect();
+        format!(r#"{{"prices": [{}]}}"#, prices.join(", "))
+    }
+
+    #[test]
+    fn test_load_prices_uniform_currency() {
+        let json = prices_json(&[("USD", 0.20), ("USD", 0.40)]);
+
+        let (prices_data, average_price) = load_day_ahead_prices_reader(json.as_bytes()).unwrap();
+
+        assert_eq!(prices_data.prices.len(), 8);
+        assert!(prices_data
+            .prices
+            .iter()
+            .all(|price| price.market_price_currency == "USD"));
+        assert!((average_price - 0.30).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_load_prices_mixed_currencies_is_error() {
+        let json = prices_json(&[("EUR", 0.20), ("USD", 0.40)]);
+
+        let error = load_day_ahead_prices_reader(json.as_bytes())
+            .err()
+            .expect("Expected an error for mixed currencies.");
+        assert!(error.to_string().contains("EUR, USD"));
+
+        // A reporting currency without a rate for USD cannot fix it either
+        assert!(load_day_ahead_prices_reader_with_mapping(
+            json.as_bytes(),
+            &HashMap::new(),
+            Some("EUR"),
+            &HashMap::new(),
+            DEFAULT_INTERVAL_MINUTES,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_load_prices_converts_to_reporting_currency() {
+        let json = prices_json(&[("EUR", 0.20), ("USD", 0.50)]);
+        let rates = HashMap::from([("USD".to_string(), 0.8)]);
+
+        let (prices_data, average_price) = load_day_ahead_prices_reader_with_mapping(
+            json.as_bytes(),
+            &HashMap::new(),
+            Some("EUR"),
+            &rates,
+            DEFAULT_INTERVAL_MINUTES,
+            None,
+        )
+        .unwrap();
+
+        assert!(prices_data
+            .prices
+            .iter()
+            .all(|price| price.market_price_currency == "EUR"));
+        assert_eq!(prices_data.prices[0].market_price_per_kwh, 0.20);
+        assert!((prices_data.prices[4].market_price_per_kwh - 0.40).abs() < 1e-9);
+        assert!((average_price - 0.30).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ev_schedule_fills_cheapest_intervals() {
+        let mut forecasts = make_forecasts(&[7_000_000.0; 4]);
+        let prices = make_prices(&[0.30, 0.10, 0.20, 0.40]);
+        let schedule = EvSchedule {
+            windows: vec![EvWindow {
+                start: series_start(),
+                end: series_start() + Duration::hours(1),
+                energy_required_wh: 375_000.0,
+                max_power_w: 1_000_000.0,
+            }],
+        };
+
+        apply_ev_schedule(&mut forecasts, &prices, &schedule).unwrap();
+
+        // The cheapest interval takes the full charger power, the next cheapest the rest
+        let consumptions: Vec<f64> = forecasts
+            .iter()
+            .map(|f| f.consumption_average_power_interval)
+            .collect();
+        assert_eq!(
+            consumptions,
+            vec![7_000_000.0, 8_000_000.0, 7_500_000.0, 7_000_000.0]
+        );
+
+        // The EV load pushes the cheap interval over the grid limit, so the battery serves it
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &load_settings(),
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert!(plan[1].energy_from_battery_wh > 0.0);
+        assert_eq!(plan[2].energy_from_battery_wh, 0.0);
+        assert!(plan[2].energy_to_battery_wh > 0.0);
+    }
+
+    #[test]
+    fn test_ev_schedule_in_watts_on_a_megawatt_site() {
+        // A 50 kW charger needing 50 kWh within the hour, on a 7 MW site
+        let mut forecasts = make_forecasts(&[7_000_000.0; 4]);
+        let prices = make_prices(&[0.30, 0.10, 0.20, 0.40]);
+        let schedule = EvSchedule {
+            windows: vec![EvWindow {
+                start: series_start(),
+                end: series_start() + Duration::hours(1),
+                energy_required_wh: 50_000.0,
+                max_power_w: 50_000.0,
+            }],
+        };
+
+        apply_ev_schedule(&mut forecasts, &prices, &schedule).unwrap();
+
+        // The charger runs at 50 kW for the whole hour: 0.05 MW on top of every interval
+        for forecast in &forecasts {
+            assert_eq!(forecast.consumption_average_power_interval, 7_050_000.0);
+        }
+        let added_wh: f64 = forecasts
+            .iter()
+            .map(|f| (f.consumption_average_power_interval - 7_000_000.0) * 0.25)
+            .sum();
+        assert_eq!(added_wh, 50_000.0);
+
+        // 7.05 MW stays under the 7.8 MW grid limit, so the battery is not discharged
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &load_settings(),
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert!(plan.iter().all(|p| p.energy_from_battery_wh == 0.0));
+    }
+
+    #[test]
+    fn test_ev_schedule_shortfall() {
+        let mut forecasts = make_forecasts(&[5_000_000.0; 2]);
+        let prices = make_prices(&[0.30, 0.10]);
+        let schedule = EvSchedule {
+            windows: vec![EvWindow {
+                start: series_start(),
+                end: series_start() + Duration::minutes(30),
+                energy_required_wh: 1_000_000.0,
+                max_power_w: 1_000_000.0,
+            }],
+        };
+
+        let result = apply_ev_schedule(&mut forecasts, &prices, &schedule);
+        assert!(
+            result.is_err(),
+            "Expected an error when the EV window cannot deliver the required energy."
+        );
+    }
+
+    #[test]
+    fn test_discharge_blocked_below_min_temperature() {
+        let mut settings = load_settings();
+        settings.min_discharge_temp_c = Some(-10.0);
+
+        let mut forecasts = make_forecasts(&[9_000_000.0, 9_000_000.0]);
+        forecasts[0].temperature_c = Some(-15.0);
+        forecasts[1].temperature_c = Some(5.0);
+        let prices = make_prices(&[0.30, 0.30]);
+
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+
+        // Too cold: no discharge despite consumption above the grid limit
+        assert_eq!(plan[0].energy_from_battery_wh, 0.0);
+        assert_eq!(plan[0].energy_to_battery_wh, 0.0);
+        // Warm enough: discharges as usual
+        assert!(plan[1].energy_from_battery_wh > 0.0);
+    }
+
+    #[test]
+    fn test_battery_utilization() {
+        // Four 15-minute intervals; at 1.5 MW each one can move at most 375000 Wh
+        let plan = make_plan(&[(0.0, 375_000.0), (0.0, 0.0), (187_500.0, 0.0), (0.0, 0.0)]);
+
+        let utilization = battery_utilization(&plan, 3.0, 1.5, 1.5);
+        assert!(
+            (utilization - 0.375).abs() < 1e-9,
+            "Expected utilization: 0.375, Actual utilization: {}",
+            utilization
+        );
+
+        // Derated to 0.75 MW in both directions, each interval moves at most 187500 Wh
+        let utilization = battery_utilization(&plan, 3.0, 0.75, 0.75);
+        assert!((utilization - 0.75).abs() < 1e-9);
+        // The faster direction bounds the throughput, whichever it is
+        let utilization = battery_utilization(&plan, 3.0, 0.75, 1.5);
+        assert!((utilization - 0.375).abs() < 1e-9);
+
+        assert_eq!(battery_utilization(&[], 3.0, 1.5, 1.5), 0.0);
+    }
+
+    #[test]
+    fn test_skip_charge_without_profitable_later_use() {
+        let forecasts = make_forecasts(&[5_000_000.0; 5]);
+        let prices = make_prices(&[0.10, 0.20, 0.30, 0.10, 0.10]);
+
+        // Without the look-ahead every favorable interval charges
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0; 5]),
+            make_prices(&[0.10, 0.20, 0.30, 0.10, 0.10]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.16,
+            None,
+        )
+        .unwrap();
+        assert!(plan[3].energy_to_battery_wh > 0.0);
+        assert!(plan[4].energy_to_battery_wh > 0.0);
+
+        let mut settings = load_settings();
+        settings.skip_unprofitable_charge = true;
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.16,
+            None,
+        )
+        .unwrap();
+
+        // The first interval can sell into the later 0.30 price, the late ones cannot
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+        assert_eq!(plan[3].energy_to_battery_wh, 0.0);
+        assert_eq!(plan[4].energy_to_battery_wh, 0.0);
+
+        // Planned on the P90, a peak in the last interval makes charging before it pay
+        let mut forecasts = make_forecasts(&[5_000_000.0; 5]);
+        forecasts[4].consumption_p90 = Some(9_000_000.0);
+        settings.planning_quantile = PlanningQuantile::P90;
+        let plan = plan_battery_usage(
+            forecasts,
+            make_prices(&[0.10, 0.20, 0.30, 0.10, 0.10]),
+            &mut initialize_battery(),
+            &settings,
+            0.16,
+            None,
+        )
+        .unwrap();
+        assert!(plan[3].energy_to_battery_wh > 0.0);
+    }
+
+    #[test]
+    fn test_charge_power_scales_stored_energy() {
+        let charge = |charge_power_mw: f64| {
+            let mut settings = load_settings();
+            settings.charge_power_mw = charge_power_mw;
+            // Start empty so neither run reaches full capacity
+            let mut battery = Battery::new(3.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+            plan_battery_usage(
+                make_forecasts(&[5_000_000.0; 2]),
+                make_prices(&[0.10, 0.10]),
+                &mut battery,
+                &settings,
+                0.16,
+                None,
+            )
+            .unwrap()
+        };
+
+        let full = charge(1.5);
+        let reduced = charge(0.5);
+        for (full, reduced) in full.iter().zip(reduced.iter()) {
+            assert!(
+                (reduced.energy_to_battery_wh * 3.0 - full.energy_to_battery_wh).abs() < 1e-6,
+                "Expected a third of {} Wh, Actual: {} Wh",
+                full.energy_to_battery_wh,
+                reduced.energy_to_battery_wh
+            );
+        }
+        // 0.5 MW for 15 minutes
+        assert!((reduced[0].energy_to_battery_wh - 125_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_config_rejects_invalid_charge_power() {
+        let temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let base = r#"
+        [settings]
+        capacity = 3.0
+        initial_charge = 1.5
+        max_rate = 1.5
+        efficiency = 0.9
+        grid_limit = 7800000.0
+        "#;
+
+        let _ = fs::write(temp_file.path(), base);
+        let settings = config::load_config(temp_file.path().to_str().unwrap())
+            .unwrap()
+            .settings;
+        assert_eq!(settings.charge_power_mw, 1.5); // Defaults to the previous hardcoded power
+
+        for charge_power_mw in ["0.0", "-1.0", "2.0"] {
+            let config = format!("{}charge_power_mw = {}\n", base, charge_power_mw);
+            let _ = fs::write(temp_file.path(), config);
+            assert!(
+                config::load_config(temp_file.path().to_str().unwrap()).is_err(),
+                "Expected charge power {} MW to be rejected",
+                charge_power_mw
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_with_field_mapping() {
+        let mapping = HashMap::from([
+            (
+                "consumption_kw".to_string(),
+                "consumption_average_power_interval".to_string(),
+            ),
+            ("price".to_string(), "market_price_per_kwh".to_string()),
+        ]);
+
+        let forecasts_file = NamedTempFile::new().unwrap();
+        let forecasts_json = r#"
+        {
+            "forecasts": [
+                {
+                    "start": "2022-12-12T00:00:00Z",
+                    "end": "2022-12-12T00:15:00Z",
+                    "consumption_kw": 5.0
+                }
+            ]
+        }"#;
+        let _ = fs::write(forecasts_file.path(), forecasts_json);
+
+        let forecasts =
+            load_forecasts_with_mapping(forecasts_file.path().to_str().unwrap(), &mapping).unwrap();
+        assert_eq!(
+            forecasts.forecasts[0].consumption_average_power_interval,
+            5.0
+        );
+
+        // Without the mapping the provider's field name is not recognized
+        assert!(load_forecasts(forecasts_file.path().to_str().unwrap(), false).is_err());
+
+        let prices_file = NamedTempFile::new().unwrap();
+        let prices_json = r#"
+        {
+            "prices": [
+                {
+                    "start": "2022-12-12T23:00:00Z",
+                    "end": "2022-12-13T00:00:00Z",
+                    "market_price_currency": "EUR",
+                    "price": 0.25
+                }
+            ]
+        }"#;
+        let _ = fs::write(prices_file.path(), prices_json);
+
+        let (prices_data, average_price) = load_day_ahead_prices_with_mapping(
+            prices_file.path().to_str().unwrap(),
+            &mapping,
+            None,
+            &HashMap::new(),
+            DEFAULT_INTERVAL_MINUTES,
+            None,
+        )
+        .unwrap();
+        assert_eq!(prices_data.prices[0].market_price_per_kwh, 0.25);
+        assert_eq!(average_price, 0.25);
+    }
+
+    #[test]
+    fn test_save_plan_per_day() {
+        // Eight intervals starting at 23:00 span two calendar days
+        let mut consumptions = [5_000_000.0; 8];
+        consumptions[0] = 9_000_000.0;
+        let forecasts = make_forecasts(&consumptions);
+        let prices = make_prices(&[0.30; 8]);
+        let plan = plan_battery_usage(
+            forecasts,
+            prices.clone(),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.25,
+            None,
+        )
+        .unwrap();
+
+        let dir = tempdir().unwrap();
+        let expected = aggregate_by_day(&plan, &prices, &load_settings());
+        let summaries = save_plan_per_day(
+            plan,
+            &prices,
+            &load_settings(),
+            dir.path().to_str().unwrap(),
+            EnergyUnit::Wh,
+        )
+        .unwrap();
+
+        assert_eq!(
+            summaries, expected,
+            "Expected the totals of aggregate_by_day."
+        );
+        assert_eq!(summaries.len(), 2, "Expected one summary per day.");
+        assert_eq!(summaries[0].totals.intervals, 4);
+        assert_eq!(summaries[1].totals.intervals, 4);
+        assert!(summaries[0].totals.energy_discharged_wh > 0.0);
+        assert_eq!(summaries[1].totals.energy_discharged_wh, 0.0);
+
+        for file in [
+            "plan_2022-12-12.json",
+            "plan_2022-12-13.json",
+            "plan_summary.json",
+        ] {
+            assert!(
+                dir.path().join(file).exists(),
+                "Expected {} to exist.",
+                file
+            );
+        }
+
+        let day: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(dir.path().join("plan_2022-12-13.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(day["planning"].as_array().unwrap().len(), 4);
+    }
+
+    /// Builds a plan over consecutive 15-minute intervals from (from, to) energy pairs.
+    fn make_plan(energies: &[(f64, f64)]) -> Vec<Plan> {
+        make_forecasts(&vec![0.0; energies.len()])
+            .iter()
+            .zip(energies)
+            .map(|(forecast, (from, to))| Plan {
+                start: forecast.start,
+                end: forecast.end,
+                energy_from_battery_wh: *from,
+                requested_discharge_wh: None,
+                energy_to_battery_wh: *to,
+                energy_to_grid_wh: 0.0,
+                energy_from_surplus_wh: 0.0,
+                grid_cost_eur: 0.0,
+                battery_revenue_eur: 0.0,
+                energy_curtailed_wh: None,
+                energy_pv_curtailed_wh: None,
+                reason: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_against_inverter_ramp() {
+        let limits = InverterLimits {
+            max_rate_mw: 1.5,
+            max_ramp_mw: 1.0,
+            min_dwell_intervals: 1,
+        };
+
+        // 0 MW -> 0.5 MW -> 1.5 MW charging: the second step ramps by 1.0 MW, within the limit
+        let plan = make_plan(&[(0.0, 0.0), (0.0, 125_000.0), (0.0, 375_000.0)]);
+        assert!(validate_against_inverter(&plan, &limits).is_empty());
+
+        // 1.5 MW charging straight to 1.5 MW discharging ramps by 3.0 MW
+        let plan = make_plan(&[(0.0, 375_000.0), (375_000.0, 0.0)]);
+        let violations = validate_against_inverter(&plan, &limits);
+        assert_eq!(violations.len(), 1, "Expected exactly one violation.");
+        assert_eq!(violations[0].kind, ViolationKind::Ramp);
+        assert_eq!(violations[0].start, plan[1].start);
+    }
+
+    #[test]
+    fn test_validate_against_inverter_rate_and_dwell() {
+        let limits = InverterLimits {
+            max_rate_mw: 1.0,
+            max_ramp_mw: 10.0,
+            min_dwell_intervals: 2,
+        };
+
+        // Off, on for a single 1.5 MW interval, off again
+        let plan = make_plan(&[(0.0, 0.0), (0.0, 375_000.0), (0.0, 0.0)]);
+        let kinds: Vec<ViolationKind> = validate_against_inverter(&plan, &limits)
+            .into_iter()
+            .map(|violation| violation.kind)
+            .collect();
+        assert_eq!(kinds, vec![ViolationKind::MaxRate, ViolationKind::Dwell]);
+    }
+
+    #[test]
+    fn test_co2_avoided_by_discharging() {
+        // Discharge 375000 Wh in a dirty interval, charge in a clean one
+        let plan = make_plan(&[(375_000.0, 0.0), (0.0, 375_000.0)]);
+        let intensities = vec![
+            CarbonIntensity {
+                start: plan[0].start,
+                end: plan[0].end,
+                grams_co2_per_kwh: 500.0,
+            },
+            CarbonIntensity {
+                start: plan[1].start,
+                end: plan[1].end,
+                grams_co2_per_kwh: 50.0,
+            },
+        ];
+
+        // 375 kWh at 500 g/kWh
+        let avoided = co2_avoided_kg(&plan, &intensities);
+        assert!(
+            (avoided - 187.5).abs() < 1e-9,
+            "Expected avoided: 187.5 kg, Actual avoided: {}",
+            avoided
+        );
+
+        // Without carbon data for the discharge interval nothing is avoided
+        assert_eq!(co2_avoided_kg(&plan, &intensities[1..]), 0.0);
+    }
+
+    #[test]
+    fn test_carbon_strategy_dispatch() {
+        let forecasts = make_forecasts(&[5_000_000.0; 4]);
+        let prices = make_prices(&[0.30; 4]);
+
+        // Clean midday, dirty evening
+        let intensities: Vec<CarbonIntensity> = forecasts
+            .iter()
+            .zip([100.0, 100.0, 600.0, 600.0])
+            .map(|(forecast, grams_co2_per_kwh)| CarbonIntensity {
+                start: forecast.start,
+                end: forecast.end,
+                grams_co2_per_kwh,
+            })
+            .collect();
+
+        let mut settings = load_settings();
+        settings.strategy = Strategy::Carbon;
+
+        // Prices are above the average threshold, so only carbon drives the dispatch
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            Some(&intensities),
+        )
+        .unwrap();
+
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+        assert!(plan[1].energy_to_battery_wh > 0.0);
+        assert!(plan[2].energy_from_battery_wh > 0.0);
+        assert!(plan[3].energy_from_battery_wh > 0.0);
+        assert_eq!(plan[0].energy_from_battery_wh, 0.0);
+        assert_eq!(plan[2].energy_to_battery_wh, 0.0);
+
+        // The carbon strategy cannot run without carbon data
+        let result = plan_battery_usage(
+            make_forecasts(&[5_000_000.0]),
+            make_prices(&[0.30]),
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_plan_aligns_prices_by_timestamp() {
+        // Prices start an interval earlier than the forecasts and arrive out of order
+        let mut prices = make_prices(&[0.50, 0.10, 0.50, 0.10]);
+        prices.reverse();
+        let forecasts: Vec<Forecast> = make_forecasts(&[5_000_000.0; 4])
+            .into_iter()
+            .skip(1)
+            .collect();
+
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // Only the intervals priced at 0.10 charge
+        assert_eq!(plan.len(), 3);
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+        assert_eq!(plan[1].energy_to_battery_wh, 0.0);
+        assert!(plan[2].energy_to_battery_wh > 0.0);
+    }
+
+    #[test]
+    fn test_plan_errors_on_missing_price() {
+        let forecasts = make_forecasts(&[5_000_000.0; 4]);
+        let mut prices = make_prices(&[0.10; 4]);
+        let gap_start = prices.remove(2).start;
+
+        let result = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        );
+
+        let message = result.err().expect("Expected an error").to_string();
+        assert!(
+            message.contains("1 forecast interval(s)") && message.contains(&gap_start.to_string()),
+            "Unexpected error message: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_net_consumption() {
+        let mut forecasts = make_forecasts(&[5.0, 5.0]);
+        assert_eq!(forecasts[0].net_consumption(), 5.0); // No production given
+
+        forecasts[1].production_average_power_interval = Some(7.0);
+        assert_eq!(forecasts[1].net_consumption(), -2.0); // Exporting
+    }
+
+    #[test]
+    fn test_production_offsets_discharge() {
+        // Both intervals exceed the grid limit, but PV covers the second one
+        let mut forecasts = make_forecasts(&[9_000_000.0, 9_000_000.0]);
+        forecasts[1].production_average_power_interval = Some(10_000_000.0);
+
+        let plan = plan_battery_usage(
+            forecasts,
+            make_prices(&[0.50, 0.50]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        assert!(plan[0].energy_from_battery_wh > 0.0);
+        assert_eq!(plan[1].energy_from_battery_wh, 0.0);
+    }
+
+    #[test]
+    fn test_resample_hourly_forecast_to_quarter_hours() {
+        let forecast = Forecast {
+            start: series_start(),
+            end: series_start() + Duration::hours(1),
+            consumption_average_power_interval: 4.0,
+            consumption_p90: None,
+            production_average_power_interval: Some(1.0),
+            temperature_c: Some(3.0),
+        };
+
+        let resampled = resample_forecasts(vec![forecast], 15).unwrap();
+        assert_eq!(resampled.len(), 4, "Expected four 15-minute forecasts.");
+        for (i, forecast) in resampled.iter().enumerate() {
+            assert_eq!(
+                forecast.start,
+                series_start() + Duration::minutes(i as i64 * 15)
+            );
+            assert_eq!(forecast.end - forecast.start, Duration::minutes(15));
+            assert_eq!(forecast.consumption_average_power_interval, 4.0);
+            assert_eq!(forecast.production_average_power_interval, Some(1.0));
+            assert_eq!(forecast.temperature_c, Some(3.0));
+        }
+    }
+
+    #[test]
+    fn test_resample_five_minute_forecasts_up() {
+        let forecasts: Vec<Forecast> = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .enumerate()
+            .map(|(i, consumption)| Forecast {
+                start: series_start() + Duration::minutes(i as i64 * 5),
+                end: series_start() + Duration::minutes((i as i64 + 1) * 5),
+                consumption_average_power_interval: *consumption,
+                consumption_p90: None,
+                production_average_power_interval: None,
+                temperature_c: None,
+            })
+            .collect();
+
+        let resampled = resample_forecasts(forecasts, 15).unwrap();
+        assert_eq!(resampled.len(), 2);
+        assert_eq!(resampled[0].start, series_start());
+        assert_eq!(resampled[0].end, series_start() + Duration::minutes(15));
+        assert_eq!(resampled[0].consumption_average_power_interval, 2.0);
+        assert_eq!(resampled[1].consumption_average_power_interval, 5.0);
+        assert_eq!(resampled[1].production_average_power_interval, None);
+    }
+
+    #[test]
+    fn test_resample_indivisible_interval() {
+        // 15 minutes is neither a multiple nor a divisor of 25 minutes
+        assert!(resample_forecasts(make_forecasts(&[1.0]), 25).is_err());
+        // Two 15-minute forecasts cannot fill an hour
+        assert!(resample_forecasts(make_forecasts(&[1.0, 2.0]), 60).is_err());
+    }
+
+    /// Builds a single price of the given length starting at the series start.
+    fn make_price(minutes: i64, value: f64) -> ElectricityPrice {
+        ElectricityPrice {
+            start: series_start(),
+            end: series_start() + Duration::minutes(minutes),
+            market_price_currency: "EUR".to_string(),
+            market_price_per_kwh: value,
+            is_fallback: false,
+            source: PriceSource::DayAhead,
+        }
+    }
+
+    #[test]
+    fn test_convert_hourly_prices_to_quarter_hours() {
+        let converted = convert_to_intervals(vec![make_price(60, 0.25)], 15).unwrap();
+        assert_eq!(converted.len(), 4, "Expected four 15-minute prices.");
+        assert_eq!(converted[3].start, series_start() + Duration::minutes(45));
+        assert_eq!(converted[3].end, series_start() + Duration::hours(1));
+        assert!(converted
+            .iter()
+            .all(|price| price.market_price_per_kwh == 0.25));
+    }
+
+    #[test]
+    fn test_convert_half_hourly_prices_to_quarter_hours() {
+        let converted = convert_to_intervals(vec![make_price(30, 0.25)], 15).unwrap();
+        assert_eq!(converted.len(), 2, "Expected two 15-minute prices.");
+        assert_eq!(converted[1].end, series_start() + Duration::minutes(30));
+
+        // A finer target works the same way
+        let converted = convert_to_intervals(vec![make_price(30, 0.25)], 5).unwrap();
+        assert_eq!(converted.len(), 6);
+    }
+
+    #[test]
+    fn test_convert_prices_indivisible_interval() {
+        let result = convert_to_intervals(vec![make_price(60, 0.25)], 25);
+        assert!(
+            result.is_err(),
+            "Expected an error when 60 minutes cannot be split into 25-minute intervals."
+        );
+    }
+
+    /// Builds a day-ahead prices JSON document with hourly prices from `first`, written
+    /// in local time: `before` until `switch`, `after` from then on.
+    fn local_prices_json(
+        first: DateTime<Utc>,
+        hours: i64,
+        switch: DateTime<Utc>,
+        before: FixedOffset,
+        after: FixedOffset,
+    ) -> String {
+        let local = |time: DateTime<Utc>| {
+            let offset = if time < switch { before } else { after };
+            time.with_timezone(&offset).to_rfc3339()
+        };
+        let prices: Vec<String> = (0..hours)
+            .map(|hour| {
+                let start = first + Duration::hours(hour);
+                format!(
+                    r#"{{"start": "{}", "end": "{}", "market_price_currency": "EUR", "market_price_per_kwh": 0.25}}"#,
+                    local(start),
+                    local(start + Duration::hours(1))
+                )
+            })
+            .collect();
+        format!(r#"{{"prices": [{}]}}"#, prices.join(", "))
+    }
+
+    /// Asserts that the prices are back-to-back intervals of `minutes` from `start` to `end`.
+    fn assert_contiguous(
+        prices: &[ElectricityPrice],
+        minutes: i64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) {
+        assert_eq!(prices.first().unwrap().start, start);
+        assert_eq!(prices.last().unwrap().end, end);
+        assert!(prices
+            .iter()
+            .all(|price| price.end - price.start == Duration::minutes(minutes)));
+        assert!(prices.windows(2).all(|pair| pair[0].end == pair[1].start));
+    }
+
+    #[test]
+    fn test_convert_prices_across_spring_forward() {
+        // Amsterdam skips 02:00-03:00 on 26 March 2023, so the local day has 23 hours
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let cest = FixedOffset::east_opt(2 * 3600).unwrap();
+        let first = "2023-03-25T23:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let switch = "2023-03-26T01:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let json = local_prices_json(first, 23, switch, cet, cest);
+
+        let (prices, _) = load_day_ahead_prices_reader(json.as_bytes()).unwrap();
+        let converted = convert_to_intervals(prices.prices, 15).unwrap();
+        assert_eq!(
+            converted.len(),
+            92,
+            "Expected 23 hours of 15-minute prices."
+        );
+        assert_contiguous(&converted, 15, first, first + Duration::hours(23));
+    }
+
+    #[test]
+    fn test_convert_price_spanning_spring_forward_gap() {
+        // 01:00 to 04:00 on the local clock, but only two hours elapse
+        let json = r#"{"prices": [{"start": "2023-03-26T01:00:00+01:00", "end": "2023-03-26T04:00:00+02:00", "market_price_currency": "EUR", "market_price_per_kwh": 0.25}]}"#;
+
+        let (prices, _) = load_day_ahead_prices_reader(json.as_bytes()).unwrap();
+        let converted = convert_to_intervals(prices.prices, 15).unwrap();
+        assert_eq!(
+            converted.len(),
+            8,
+            "Expected two hours of 15-minute prices."
+        );
+        let start = "2023-03-26T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_contiguous(&converted, 15, start, start + Duration::hours(2));
+        assert_eq!(
+            converted[4].start,
+            "2023-03-26T03:00:00+02:00"
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_convert_prices_across_fall_back() {
+        // Amsterdam repeats 02:00-03:00 on 29 October 2023, so the local day has 25 hours
+        let cest = FixedOffset::east_opt(2 * 3600).unwrap();
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        let first = "2023-10-28T22:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let switch = "2023-10-29T01:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let json = local_prices_json(first, 25, switch, cest, cet);
+
+        let (prices, _) = load_day_ahead_prices_reader(json.as_bytes()).unwrap();
+        let converted = convert_to_intervals(prices.prices, 15).unwrap();
+        assert_eq!(
+            converted.len(),
+            100,
+            "Expected 25 hours of 15-minute prices."
+        );
+        assert_contiguous(&converted, 15, first, first + Duration::hours(25));
+    }
+
+    #[test]
+    fn test_percentile_threshold_on_skewed_prices() {
+        // A single spike drags the mean above nearly every interval
+        let values = [0.10, 0.11, 0.12, 0.13, 0.14, 0.15, 0.16, 0.17, 0.18, 2.00];
+        let prices = make_prices(&values);
+        let average = values.iter().sum::<f64>() / values.len() as f64;
+        let percentile = price_percentile(&prices, 0.3);
+        assert_eq!(percentile, 0.12);
+
+        let charged_intervals = |threshold: f64| -> usize {
+            // A large battery, so capacity never stops the charging
+            let mut battery = Battery::new(100.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0);
+            plan_battery_usage(
+                make_forecasts(&[5_000_000.0; 10]),
+                make_prices(&values),
+                &mut battery,
+                &load_settings(),
+                threshold,
+                None,
+            )
+            .unwrap()
+            .iter()
+            .filter(|interval| interval.energy_to_battery_wh > 0.0)
+            .count()
+        };
+
+        assert_eq!(charged_intervals(average), 9);
+        assert_eq!(charged_intervals(percentile), 3);
+    }
+
+    #[test]
+    fn test_negative_price_always_charges() {
+        let mut settings = load_settings();
+        settings.skip_unprofitable_charge = true;
+
+        // The threshold is below every price, so only the negative interval qualifies
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0; 3]),
+            make_prices(&[0.20, -0.05, 0.20]),
+            &mut initialize_battery(),
+            &settings,
+            -0.10,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(plan[0].energy_to_battery_wh, 0.0);
+        assert!(plan[1].energy_to_battery_wh > 0.0);
+        assert_eq!(plan[2].energy_to_battery_wh, 0.0);
+    }
+
+    #[test]
+    fn test_load_prices_accepts_negative_price() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let json = r#"
+        {
+            "prices": [
+                {
+                    "start": "2022-12-12T00:00:00Z",
+                    "end": "2022-12-12T01:00:00Z",
+                    "market_price_currency": "EUR",
+                    "market_price_per_kwh": -0.05
+                }
+            ]
+        }"#;
+
+        let _ = fs::write(temp_file.path(), json);
+        let (prices, average) = load_day_ahead_prices(temp_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(prices.prices[0].market_price_per_kwh, -0.05);
+        assert_eq!(average, -0.05);
+    }
+
+    #[test]
+    fn test_plan_cost_and_savings() {
+        // Charge in a cheap interval, then shave a peak in an expensive one
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0, 9_300_000.0, 5_000_000.0]),
+            make_prices(&[0.10, 0.50, 0.40]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // 1.5 MW for 15 minutes = 375 kWh, bought at 0.10 EUR/kWh
+        assert!((plan[0].grid_cost_eur - 37.5).abs() < 1e-9);
+        assert_eq!(plan[0].battery_revenue_eur, 0.0);
+        // 375 kWh delivered at the max rate, avoiding imports at 0.50 EUR/kWh
+        assert_eq!(plan[1].grid_cost_eur, 0.0);
+        assert!((plan[1].battery_revenue_eur - 187.5).abs() < 1e-9);
+        // Idle
+        assert_eq!(plan[2].grid_cost_eur, 0.0);
+        assert_eq!(plan[2].battery_revenue_eur, 0.0);
+
+        let totals = plan_totals(&plan);
+        assert!((totals.total_cost_eur - 37.5).abs() < 1e-9);
+        assert!((totals.total_savings_eur - 150.0).abs() < 1e-9);
+
+        let temp_file = NamedTempFile::new().unwrap();
+        save_plan(plan, temp_file.path().to_str().unwrap(), EnergyUnit::Wh).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).unwrap()).unwrap();
+        assert!((saved["total_cost_eur"].as_f64().unwrap() - 37.5).abs() < 1e-9);
+        assert!((saved["total_savings_eur"].as_f64().unwrap() - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_save_plan_csv_round_trip() {
+        let mut plan = make_plan(&[(0.0, 375_000.0), (187_500.5, 0.0)]);
+        plan[1].energy_to_grid_wh = 62_500.0;
+        plan[0].energy_from_surplus_wh = 125_000.0;
+        let temp_file = tempfile::Builder::new().suffix(".csv").tempfile().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        save_plan_csv(&plan, path, EnergyUnit::Wh).unwrap();
+
+        let mut reader = csv::Reader::from_path(path).unwrap();
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "start",
+                "end",
+                "energy_from_battery_wh",
+                "energy_to_battery_wh",
+                "energy_to_grid_wh",
+                "energy_from_surplus_wh"
+            ]
+        );
+
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), plan.len());
+        for (row, interval) in rows.iter().zip(&plan) {
+            assert_eq!(row[0].parse::<DateTime<Utc>>().unwrap(), interval.start);
+            assert_eq!(row[1].parse::<DateTime<Utc>>().unwrap(), interval.end);
+            assert_eq!(
+                row[2].parse::<f64>().unwrap(),
+                interval.energy_from_battery_wh
+            );
+            assert_eq!(
+                row[3].parse::<f64>().unwrap(),
+                interval.energy_to_battery_wh
+            );
+            assert_eq!(row[4].parse::<f64>().unwrap(), interval.energy_to_grid_wh);
+            assert_eq!(
+                row[5].parse::<f64>().unwrap(),
+                interval.energy_from_surplus_wh
+            );
+        }
+
+        // save_plan picks the CSV writer from the extension
+        save_plan(make_plan(&[(1.0, 0.0)]), path, EnergyUnit::Wh).unwrap();
+        assert!(fs::read_to_string(path).unwrap().starts_with(
+            "start,end,energy_from_battery_wh,energy_to_battery_wh,energy_to_grid_wh,energy_from_surplus_wh\n"
+        ));
+    }
+
+    #[test]
+    fn test_energy_unit_conversions() {
+        assert_eq!(convert(0.9, EnergyUnit::Wh), 900_000.0);
+        assert_eq!(convert(0.9, EnergyUnit::Kwh), 900.0);
+        assert_eq!(convert(0.9, EnergyUnit::Mwh), 0.9);
+        assert_eq!(to_mwh(900_000.0, EnergyUnit::Wh), 0.9);
+        assert_eq!(to_mwh(900.0, EnergyUnit::Kwh), 0.9);
+        assert_eq!(to_mwh(0.9, EnergyUnit::Mwh), 0.9);
+        assert_eq!(from_wh(187_500.5, EnergyUnit::Wh), 187_500.5);
+        assert_eq!(from_wh(375_000.0, EnergyUnit::Kwh), 375.0);
+        assert_eq!(from_wh(375_000.0, EnergyUnit::Mwh), 0.375);
+    }
+
+    #[test]
+    fn test_save_plan_in_energy_unit() {
+        let plan = make_plan(&[(0.0, 375_000.0), (187_500.5, 0.0)]);
+        let expected: Vec<(f64, f64)> = plan
+            .iter()
+            .map(|interval| {
+                (
+                    interval.energy_from_battery_wh,
+                    interval.energy_to_battery_wh,
+                )
+            })
+            .collect();
+
+        // Watt-hours reproduce the plan's values exactly
+        let temp_file = NamedTempFile::new().unwrap();
+        save_plan(plan, temp_file.path().to_str().unwrap(), EnergyUnit::Wh).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).unwrap()).unwrap();
+        for (interval, (from, to)) in saved["planning"].as_array().unwrap().iter().zip(&expected) {
+            assert_eq!(interval["energy_from_battery_wh"].as_f64().unwrap(), *from);
+            assert_eq!(interval["energy_to_battery_wh"].as_f64().unwrap(), *to);
+        }
+
+        // Other units rename and scale the energy fields, and leave the rest alone
+        let plan = make_plan(&[(0.0, 375_000.0), (187_500.5, 0.0)]);
+        save_plan(plan, temp_file.path().to_str().unwrap(), EnergyUnit::Kwh).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).unwrap()).unwrap();
+        let first = &saved["planning"][0];
+        assert_eq!(first["energy_to_battery_kwh"].as_f64().unwrap(), 375.0);
+        assert!(first.get("energy_to_battery_wh").is_none());
+        assert_eq!(first["grid_cost_eur"].as_f64().unwrap(), 0.0);
+        assert_eq!(
+            saved["planning"][1]["energy_from_battery_kwh"]
+                .as_f64()
+                .unwrap(),
+            187.5005
+        );
+        assert!(saved.get("total_cost_eur").is_some());
+    }
+
+    #[test]
+    fn test_cli_arguments() {
+        let cli = Cli::try_parse_from([
+            "battery",
+            "--config",
+            "site.toml",
+            "--forecasts",
+            "forecasts.csv",
+            "--prices",
+            "prices.json",
+            "--output",
+            "plan.csv",
+        ])
+        .unwrap();
+        assert_eq!(cli.config, "site.toml");
+        assert_eq!(cli.forecasts, "forecasts.csv");
+        assert_eq!(cli.prices, "prices.json");
+        assert_eq!(cli.output, "plan.csv");
+
+        // Without flags the previous file names are used
+        let cli = Cli::try_parse_from(["battery"]).unwrap();
+        assert_eq!(cli.config, "config.toml");
+        assert_eq!(cli.forecasts, "forecasts.json");
+        assert_eq!(cli.prices, "day-ahead.json");
+        assert_eq!(cli.output, "output_plan.json");
+        assert!(!cli.validate_only);
+        assert!(
+            Cli::try_parse_from(["battery", "--validate-only"])
+                .unwrap()
+                .validate_only
+        );
+
+        assert!(Cli::try_parse_from(["battery", "--unknown"]).is_err());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let valid = r#"
+        [settings]
+        capacity = 3.0
+        initial_charge = 1.5
+        max_rate = 1.5
+        efficiency = 0.9
+        grid_limit = 7800000.0
+        "#;
+
+        let _ = fs::write(temp_file.path(), valid);
+        assert!(config::load_config(temp_file.path().to_str().unwrap()).is_ok());
+
+        let invalid = [
+            ("efficiency = 0.9", "efficiency = 1.5", "efficiency"),
+            ("efficiency = 0.9", "efficiency = 0.0", "efficiency"),
+            ("capacity = 3.0", "capacity = -3.0", "capacity"),
+            ("capacity = 3.0", "capacity = nan", "capacity"),
+            ("capacity = 3.0", "capacity = inf", "capacity"),
+            (
+                "initial_charge = 1.5",
+                "initial_charge = 4.0",
+                "initial_charge",
+            ),
+            (
+                "initial_charge = 1.5",
+                "initial_charge = -0.5",
+                "initial_charge",
+            ),
+            ("max_rate = 1.5", "max_rate = 0.0", "max_rate"),
+            ("max_rate = 1.5", "max_rate = nan", "max_rate"),
+            (
+                "max_rate = 1.5",
+                "max_rate = 1.5\nmax_discharge_rate = -1.0",
+                "max_discharge_rate",
+            ),
+            ("grid_limit = 7800000.0", "grid_limit = -1.0", "grid_limit"),
+            ("grid_limit = 7800000.0", "grid_limit = nan", "grid_limit"),
+            (
+                "grid_limit = 7800000.0",
+                "grid_limit = 7800000.0\nfeed_in_factor = nan",
+                "feed_in_factor",
+            ),
+            (
+                "grid_limit = 7800000.0",
+                "grid_limit = 7800000.0\ninterval_minutes = 7",
+                "interval_minutes",
+            ),
+            (
+                "grid_limit = 7800000.0",
+                "grid_limit = 7800000.0\npeak_shave_target = -1.0",
+                "peak_shave_target",
+            ),
+            (
+                "grid_limit = 7800000.0",
+                "grid_limit = 7800000.0\npeak_shave_target = inf",
+                "peak_shave_target",
+            ),
+        ];
+        for (from, to, field) in invalid {
+            let _ = fs::write(temp_file.path(), valid.replace(from, to));
+            let message = format!(
+                "{:#}",
+                config::load_config(temp_file.path().to_str().unwrap())
+                    .err()
+                    .unwrap_or_else(|| panic!("Expected `{}` to be rejected", to))
+            );
+            assert!(
+                message.contains(&format!("`{}`", field)),
+                "Expected the error to name `{}`: {}",
+                field,
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_yaml_config() {
+        let toml_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let _ = fs::write(
+            toml_file.path(),
+            r#"
+            [settings]
+            capacity = 3.0
+            initial_charge = 1.5
+            max_rate = 1.5
+            charge_efficiency = 0.95
+            discharge_efficiency = 0.9
+            grid_limit = 7800000.0
+            min_charge = 0.3
+            strategy = "carbon"
+            "#,
+        );
+        let yaml_file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        let _ = fs::write(
+            yaml_file.path(),
+            r#"
+settings:
+  capacity: 3.0
+  initial_charge: 1.5
+  max_rate: 1.5
+  charge_efficiency: 0.95
+  discharge_efficiency: 0.9
+  grid_limit: 7800000.0
+  min_charge: 0.3
+  strategy: carbon
+"#,
+        );
+
+        let from_toml = config::load_config(toml_file.path().to_str().unwrap())
+            .unwrap()
+            .settings;
+        let from_yaml = config::load_config(yaml_file.path().to_str().unwrap())
+            .unwrap()
+            .settings;
+        assert_eq!(from_toml, from_yaml);
+        assert_eq!(from_yaml.strategy, Strategy::Carbon);
+
+        let ini_file = tempfile::Builder::new().suffix(".ini").tempfile().unwrap();
+        let message = config::load_config(ini_file.path().to_str().unwrap())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.contains("Unsupported configuration file format"),
+            "Unexpected error: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_optimal_plan_beats_greedy() {
+        // A lossless 1 MWh battery that moves at most 0.25 MWh per 15 minutes
+        let mut settings = load_settings();
+        settings.capacity = 1.0;
+        settings.initial_charge = 0.0;
+        settings.max_rate = 1.0;
+        settings.charge_power_mw = 1.0;
+        settings.efficiency = Some(1.0);
+        let battery = || Battery::new(1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0);
+
+        // A flat 1 MW load; cheap first half, expensive second half
+        let values = [0.10, 0.10, 0.10, 0.10, 0.50, 0.50, 0.50, 0.50];
+        let forecasts = || make_forecasts(&[1_000_000.0; 8]);
+
+        let optimal =
+            plan_battery_usage_optimal(forecasts(), make_prices(&values), battery(), &settings, 4)
+                .unwrap();
+
+        // By hand: fill up during the cheap half, then cover the load in the expensive half
+        for interval in &optimal[..4] {
+            assert!((interval.energy_to_battery_wh - 250_000.0).abs() < 1e-6);
+            assert_eq!(interval.energy_from_battery_wh, 0.0);
+        }
+        for interval in &optimal[4..] {
+            assert_eq!(interval.energy_to_battery_wh, 0.0);
+            assert!((interval.energy_from_battery_wh - 250_000.0).abs() < 1e-6);
+        }
+
+        // 1000 kWh bought at 0.10 EUR/kWh avoids 1000 kWh at 0.50 EUR/kWh
+        let optimal_totals = plan_totals(&optimal);
+        assert!((optimal_totals.total_savings_eur - 400.0).abs() < 1e-6);
+
+        // The greedy planner charges too, but never discharges below the grid limit
+        let greedy = plan_battery_usage(
+            forecasts(),
+            make_prices(&values),
+            &mut battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert!(optimal_totals.total_savings_eur > plan_totals(&greedy).total_savings_eur);
+    }
+
+    #[test]
+    fn test_optimal_plan_falls_back_to_greedy_within_budget() {
+        let mut settings = load_settings();
+        settings.capacity = 1.0;
+        settings.initial_charge = 0.0;
+        settings.max_rate = 1.0;
+        settings.charge_power_mw = 1.0;
+        settings.efficiency = Some(1.0);
+        let battery = || Battery::new(1.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0);
+        let values = [0.10, 0.10, 0.10, 0.10, 0.50, 0.50, 0.50, 0.50];
+        let forecasts = || make_forecasts(&[1_000_000.0; 8]);
+        let energies = |plan: &[Plan]| {
+            plan.iter()
+                .map(|interval| {
+                    (
+                        interval.energy_from_battery_wh,
+                        interval.energy_to_battery_wh,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // No time at all: the greedy plan is returned, flagged
+        let budgeted = plan_battery_usage_within(
+            forecasts(),
+            make_prices(&values),
+            battery(),
+            &settings,
+            4,
+            std::time::Duration::ZERO,
+        )
+        .unwrap();
+        assert!(budgeted.fell_back_to_greedy);
+        let greedy = plan_battery_usage(
+            forecasts(),
+            make_prices(&values),
+            &mut battery(),
+            &settings,
+            charge_threshold(&make_prices(&values), &settings),
+            None,
+        )
+        .unwrap();
+        assert_eq!(energies(&budgeted.plan), energies(&greedy));
+
+        // Plenty of time: the optimal plan
+        let budgeted = plan_battery_usage_within(
+            forecasts(),
+            make_prices(&values),
+            battery(),
+            &settings,
+            4,
+            std::time::Duration::from_secs(60),
+        )
+        .unwrap();
+        assert!(!budgeted.fell_back_to_greedy);
+        let optimal =
+            plan_battery_usage_optimal(forecasts(), make_prices(&values), battery(), &settings, 4)
+                .unwrap();
+        assert_eq!(energies(&budgeted.plan), energies(&optimal));
+    }
+
+    #[test]
+    fn test_export_to_grid_at_high_prices() {
+        let mut settings = load_settings();
+        settings.sell_threshold_per_kwh = Some(0.50);
+        settings.feed_in_factor = 0.8;
+
+        // The charge threshold is below every price, so the battery never charges
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0; 3]),
+            make_prices(&[0.20, 0.60, 0.30]),
+            &mut initialize_battery(),
+            &settings,
+            0.05,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(plan[0].energy_to_grid_wh, 0.0);
+        assert_eq!(plan[2].energy_to_grid_wh, 0.0);
+        // Limited to 1.5 MW for 15 minutes
+        let max_export_wh = settings.max_rate * 0.25 * WH_PER_MWH;
+        assert!((plan[1].energy_to_grid_wh - max_export_wh).abs() < 1e-6);
+        assert_eq!(plan[1].energy_from_battery_wh, 0.0);
+        // 375 kWh at 80% of 0.60 EUR/kWh
+        assert!((plan[1].battery_revenue_eur - 180.0).abs() < 1e-6);
+
+        // Without a sell threshold the battery idles
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0; 3]),
+            make_prices(&[0.20, 0.60, 0.30]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.05,
+            None,
+        )
+        .unwrap();
+        assert!(plan
+            .iter()
+            .all(|interval| interval.energy_to_grid_wh == 0.0));
+    }
+
+    #[test]
+    fn test_peak_shave_target() {
+        // 7 MW stays under the 7.8 MW grid limit, 9 MW exceeds it
+        let forecasts = || make_forecasts(&[7_000_000.0, 9_000_000.0]);
+        let prices = || make_prices(&[0.50, 0.50]);
+
+        // Without a target only the interval above the grid limit discharges
+        let plan = plan_battery_usage(
+            forecasts(),
+            prices(),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan[0].energy_from_battery_wh, 0.0);
+        assert!(plan[1].energy_from_battery_wh > 0.0);
+
+        // With a 6 MW target the first interval is shaved as well
+        let mut settings = load_settings();
+        settings.peak_shave_target = Some(6_000_000.0);
+        let plan = plan_battery_usage(
+            forecasts(),
+            prices(),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert!(plan[0].energy_from_battery_wh > 0.0);
+        // Both intervals are limited by max_rate, 1.5 MW for 15 minutes
+        for interval in &plan {
+            assert!(interval.energy_from_battery_wh <= 375_000.0 + 1e-6);
+        }
+        assert!(plan[1].energy_from_battery_wh > 0.0);
+
+        // A target above the grid limit falls back to the grid limit
+        settings.peak_shave_target = Some(10_000_000.0);
+        assert_eq!(settings.shave_limit(), settings.grid_limit);
+    }
+
+    #[test]
+    fn test_max_daily_cycles() {
+        // The series starts at 23:00 UTC, so the day changes after four intervals
+        let mut settings = load_settings();
+        settings.max_daily_cycles = Some(0.1);
+
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0; 6]),
+            make_prices(&[0.10; 6]),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // Each charge stores 0.3375 MWh, i.e. 0.05625 cycles of a 3 MWh battery,
+        // so the budget is used up after two intervals
+        let charging: Vec<bool> = plan
+            .iter()
+            .map(|interval| interval.energy_to_battery_wh > 0.0)
+            .collect();
+        assert_eq!(charging, vec![true, true, false, false, true, true]);
+    }
+
+    #[test]
+    fn test_summarize_plan() {
+        let forecasts = make_forecasts(&[5_000_000.0, 9_300_000.0, 5_000_000.0]);
+        let prices = make_prices(&[0.10, 0.50, 0.40]);
+        let settings = load_settings();
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+        // Charge 375 kWh at 0.10, discharge 375 kWh at 0.50, idle at 0.40
+        assert!((summary.energy_charged_wh - 375_000.0).abs() < 1e-6);
+        assert!((summary.energy_discharged_wh - 375_000.0).abs() < 1e-6);
+        assert_eq!(summary.active_intervals, 2);
+        // 1250 kWh at 0.10 + 2325 kWh at 0.50 + 1250 kWh at 0.40
+        assert!((summary.cost_without_battery_eur - 1_787.5).abs() < 1e-6);
+        assert!((summary.cost_with_battery_eur - 1_637.5).abs() < 1e-6);
+        assert!((summary.net_savings_eur - 150.0).abs() < 1e-6);
+        // 2325 - 375 kWh in 15 minutes is 7.8 MW, at the 7.8 MW limit
+        assert_eq!(summary.intervals_over_grid_limit, 0);
+    }
+
+    #[test]
+    fn test_effective_price() {
+        let price = make_price(0, 0.10);
+
+        assert!((price.effective_price(0.05, 0.2) - 0.18).abs() < 1e-12);
+        // Without fee and tax the consumer pays the market price
+        assert_eq!(price.effective_price(0.0, 0.0), 0.10);
+        assert_eq!(load_settings().import_price(&price), 0.10);
+    }
+
+    #[test]
+    fn test_summarize_plan_with_grid_fee_and_tax() {
+        let forecasts = make_forecasts(&[5_000_000.0, 9_300_000.0, 5_000_000.0]);
+        let prices = make_prices(&[0.10, 0.50, 0.40]);
+        let mut settings = load_settings();
+        settings.grid_fee_per_kwh = 0.05;
+        settings.tax_rate = 0.2;
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            (0.30 + 0.05) * 1.2,
+            None,
+        )
+        .unwrap();
+
+        // Charging is paid at the import price of 0.18 EUR/kWh
+        assert!((plan[0].grid_cost_eur - 375.0 * 0.18).abs() < 1e-6);
+
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+        // 1250 kWh at 0.18 + 2325 kWh at 0.66 + 1250 kWh at 0.54
+        assert!((summary.cost_without_battery_eur - 2_434.5).abs() < 1e-6);
+        // Plus 375 kWh charged at 0.18, minus 375 kWh discharged at 0.66
+        assert!((summary.cost_with_battery_eur - 2_254.5).abs() < 1e-6);
+        assert!((summary.net_savings_eur - 180.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_summarize_plan_without_activity() {
+        // Prices above the threshold and no peaks, so the battery idles
+        let forecasts = make_forecasts(&[5_000_000.0; 2]);
+        let prices = make_prices(&[0.40, 0.40]);
+        let settings = load_settings();
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+        assert_eq!(summary.energy_charged_wh, 0.0);
+        assert_eq!(summary.energy_discharged_wh, 0.0);
+        assert_eq!(summary.active_intervals, 0);
+        assert!((summary.cost_without_battery_eur - 1_000.0).abs() < 1e-6);
+        assert_eq!(
+            summary.cost_with_battery_eur,
+            summary.cost_without_battery_eur
+        );
+        assert_eq!(summary.net_savings_eur, 0.0);
+        assert_eq!(summary.intervals_over_grid_limit, 0);
+    }
+
+    #[test]
+    fn test_load_from_readers() {
+        let forecasts_json: &[u8] = br#"
+        {
+            "forecasts": [
+                {
+                    "start": "2022-12-12T00:00:00Z",
+                    "end": "2022-12-12T00:15:00Z",
+                    "consumption_average_power_interval": 5.0
+                }
+            ]
+        }"#;
+        let forecasts = load_forecasts_reader(forecasts_json).unwrap();
+        assert_eq!(forecasts.forecasts.len(), 1);
+        assert_eq!(
+            forecasts.forecasts[0].start,
+            "2022-12-12T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            forecasts.forecasts[0].consumption_average_power_interval,
+            5.0
+        );
+
+        let prices_json: &[u8] = br#"
+        {
+            "prices": [
+                {
+                    "start": "2022-12-12T00:00:00Z",
+                    "end": "2022-12-12T01:00:00Z",
+                    "market_price_currency": "EUR",
+                    "market_price_per_kwh": 0.25
+                }
+            ]
+        }"#;
+        let (prices, average) = load_day_ahead_prices_reader(prices_json).unwrap();
+        assert_eq!(prices.prices.len(), 4, "Expected four 15-minute prices.");
+        assert_eq!(prices.prices[0].market_price_per_kwh, 0.25);
+        assert_eq!(average, 0.25);
+
+        assert!(load_forecasts_reader(&b"invalid json data"[..]).is_err());
+    }
+
+    #[test]
+    fn test_simulate_valid_plan() {
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0, 5_000_000.0, 9_300_000.0, 9_300_000.0]),
+            make_prices(&[0.10, 0.10, 0.50, 0.50]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        let mut battery = initialize_battery();
+        let final_state = simulate_plan(&mut battery, &plan).unwrap();
+
+        // Two charges of 0.3375 MWh, two discharges of 0.375 MWh / 0.9
+        let expected = 1.5 + 2.0 * 0.3375 - 2.0 * 0.375 / 0.9;
+        assert!(
+            (final_state.charge - expected).abs() < 1e-9,
+            "Expected final charge: {} MWh, Actual: {}",
+            expected,
+            final_state.charge
+        );
+        assert_eq!(final_state.charge, battery.charge);
+    }
+
+    #[test]
+    fn test_simulate_infeasible_plan() {
+        // 600 kWh in 15 minutes is 2.4 MW, above the 1.5 MW max charge rate
+        let plan = make_plan(&[(0.0, 600_000.0)]);
+        let message = simulate_plan(&mut initialize_battery(), &plan)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.contains("max charge rate"),
+            "Unexpected error: {}",
+            message
+        );
+
+        // Five full-rate discharges need 2.08 MWh, but only 1.5 MWh is stored
+        let plan = make_plan(&[(375_000.0, 0.0); 5]);
+        let message = simulate_plan(&mut initialize_battery(), &plan)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.contains("minimum charge"),
+            "Unexpected error: {}",
+            message
+        );
+
+        // Five full-rate charges store 1.69 MWh, but only 1.5 MWh fits
+        let plan = make_plan(&[(0.0, 375_000.0); 5]);
+        let message = simulate_plan(&mut initialize_battery(), &plan)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.contains("capacity"),
+            "Unexpected error: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_plan_rolling_diverges_from_full_horizon() {
+        let forecasts = make_forecasts(&[5_000_000.0; 8]);
+        let prices = make_prices(&[0.30, 0.20, 0.10, 0.10, 0.40, 0.40, 0.40, 0.40]);
+        let settings = load_settings();
+
+        let full = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            charge_threshold(&prices, &settings),
+            None,
+        )
+        .unwrap();
+        let rolling =
+            plan_rolling(&forecasts, &prices, initialize_battery(), &settings, 0.5).unwrap();
+        assert_eq!(rolling.len(), forecasts.len());
+
+        // Over the full day 0.20 is below the 0.2875 average, but not below the next half hour's 0.15
+        assert!(full[1].energy_to_battery_wh > 0.0);
+        assert_eq!(rolling[1].energy_to_battery_wh, 0.0);
+        // The rolling planner cannot see the cheap hours are over and charges at 0.40
+        assert_eq!(full[4].energy_to_battery_wh, 0.0);
+        assert!(rolling[4].energy_to_battery_wh > 0.0);
+
+        // Each step starts where the previous one ended, so the battery fills up once
+        // and the committed plan replays without breaking the capacity
+        let mut battery = initialize_battery();
+        let final_state = simulate_plan(&mut battery, &rolling).unwrap();
+        let stored_mwh = rolling
+            .iter()
+            .map(|interval| interval.energy_to_battery_wh)
+            .sum::<f64>()
+            * 0.9
+            / WH_PER_MWH;
+        assert!((final_state.charge - (1.5 + stored_mwh)).abs() < 1e-9);
+        // Full, up to the capacity lost to the few cycles made
+        assert!(final_state.charge > 2.999 && final_state.charge <= 3.0);
+        assert!(rolling[7].energy_to_battery_wh < 1e-6);
+    }
+
+    #[test]
+    fn test_plan_serializes_true_watt_hours() {
+        // A lossless battery discharging 3.6 MW for 15 minutes delivers 0.9 MWh
+        let mut battery = Battery::new(3.0, 1.5, 3.6, 1.0, 1.0, 0.0, 0.0);
+        let settings = load_settings();
+        let plan = plan_battery_usage(
+            make_forecasts(&[settings.grid_limit + 5_000_000.0]),
+            make_prices(&[0.30]),
+            &mut battery,
+            &settings,
+            0.0,
+            None,
+        )
+        .unwrap();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        save_plan(plan, temp_file.path().to_str().unwrap(), EnergyUnit::Wh).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).unwrap()).unwrap();
+        assert_eq!(
+            saved["planning"][0]["energy_from_battery_wh"]
+                .as_f64()
+                .unwrap(),
+            900_000.0
+        );
+    }
+
+    #[test]
+    fn test_charge_to_target_exactly() {
+        let mut battery = initialize_battery(); // 1.5 of 3.0 MWh
+
+        // 0.3 MWh are missing for 60%, which needs 1/3 MW for an hour at 90% efficiency
+        let grid_mwh = battery.charge_to_target(0.6, 1.0).unwrap();
+        assert!((battery.charge - 1.8).abs() < 1e-9);
+        assert!((grid_mwh - 0.3 / 0.9).abs() < 1e-9);
+
+        // Already at the target, nothing more is drawn
+        assert!(battery.charge_to_target(0.6, 1.0).unwrap().abs() < 1e-9);
+
+        // Without time to charge in, nothing is drawn either
+        assert_eq!(battery.charge_to_target(1.0, 0.0).unwrap(), 0.0);
+        assert!((battery.charge - 1.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_charge_to_target_rate_limited() {
+        let mut battery = initialize_battery();
+
+        // Filling 1.5 MWh in 15 minutes would need far more than the 1.5 MW max rate
+        let grid_mwh = battery.charge_to_target(1.0, 0.25).unwrap();
+        assert!((grid_mwh - 0.375).abs() < 1e-9);
+        assert!((battery.charge - (1.5 + 0.375 * 0.9)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_charge_to_target_invalid_input() {
+        let mut battery = initialize_battery();
+
+        for target_soc in [-0.1, 1.2, f64::NAN] {
+            assert!(matches!(
+                battery.charge_to_target(target_soc, 1.0),
+                Err(BatteryError::InvalidTargetSoc { .. })
+            ));
+        }
+        assert!(matches!(
+            battery.charge_to_target(0.4, 1.0),
+            Err(BatteryError::TargetBelowCharge { .. })
+        ));
+        assert_eq!(battery.charge, 1.5);
+    }
+
+    /// Builds a bank of an empty 2 MWh / 2 MW pack and an empty 1 MWh / 0.5 MW pack, both lossless.
+    fn make_bank() -> BatteryBank {
+        BatteryBank::new(vec![
+            Battery::new(2.0, 0.0, 2.0, 1.0, 1.0, 0.0, 0.0),
+            Battery::new(1.0, 0.0, 0.5, 1.0, 1.0, 0.0, 0.0),
+        ])
+    }
+
+    #[test]
+    fn test_bank_charge_splits_by_headroom() {
+        let mut bank = make_bank();
+
+        // 0.9 MW splits 2:1 by room to full, within both packs' rates
+        let flow = bank.charge_battery(0.9, 1.0).unwrap();
+        let charges: Vec<f64> = bank.batteries().iter().map(|b| b.charge).collect();
+        assert!((charges[0] - 0.6).abs() < 1e-9);
+        assert!((charges[1] - 0.3).abs() < 1e-9);
+        assert!((flow.grid_wh - 900_000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_bank_charge_respects_member_rates() {
+        let mut bank = make_bank();
+
+        // A 2:1 split of 2 MW would give the small pack 0.67 MW, above its 0.5 MW rate,
+        // so it is capped and the large pack takes the rest
+        let flow = bank.charge_battery(2.0, 0.25).unwrap();
+        let charges: Vec<f64> = bank.batteries().iter().map(|b| b.charge).collect();
+        assert!((charges[0] - 1.5 * 0.25).abs() < 1e-9);
+        assert!((charges[1] - 0.5 * 0.25).abs() < 1e-9);
+        assert!((flow.grid_wh - 500_000.0).abs() < 1e-3);
+
+        // More than the bank's combined rate is limited to it
+        let mut bank = make_bank();
+        bank.charge_battery(10.0, 0.25).unwrap();
+        assert!((bank.charge() - bank.max_rate() * 0.25).abs() < 1e-9);
+
+        assert!(bank.charge_battery(-1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_bank_discharge_splits_by_available_energy() {
+        let mut bank = make_bank();
+        bank.charge_battery(0.9, 1.0).unwrap(); // 0.6 and 0.3 MWh
+
+        let flow = bank.discharge_battery(0.3, 1.0).unwrap();
+        let charges: Vec<f64> = bank.batteries().iter().map(|b| b.charge).collect();
+        assert!((charges[0] - 0.4).abs() < 1e-9);
+        assert!((charges[1] - 0.2).abs() < 1e-9);
+        assert!((flow.grid_wh - 300_000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_plan_with_battery_bank() {
+        let forecasts = make_forecasts(&[5_000_000.0]);
+        let prices = make_prices(&[0.10]);
+        let settings = load_settings();
+
+        let plan =
+            plan_battery_usage(forecasts, prices, &mut make_bank(), &settings, 0.30, None).unwrap();
+
+        // The configured 1.5 MW charge power is split 2:1 across the packs
+        assert!((plan[0].energy_to_battery_wh - 375_000.0).abs() < 1e-3);
+    }
+
+    /// Returns the message of a schema error, failing the test for any other result.
+    fn schema_error<T>(result: Result<T, LoadError>) -> String {
+        match result {
+            Err(LoadError::Schema(message)) => message,
+            Err(error) => panic!("Expected a schema error, got: {}", error),
+            Ok(_) => panic!("Expected a schema error, got a successful load"),
+        }
+    }
+
+    #[test]
+    fn test_schema_missing_top_level_array() {
+        let message = schema_error(load_forecasts_reader(&br#"{"data": []}"#[..]));
+        assert_eq!(message, "missing top-level field `forecasts`");
+
+        let message = schema_error(load_day_ahead_prices_reader(&br#"{"prices": {}}"#[..]));
+        assert_eq!(message, "`prices` must be an array");
+    }
+
+    #[test]
+    fn test_schema_missing_entry_field() {
+        let json = br#"{"forecasts": [
+            {"start": "2022-12-12T23:00:00Z", "end": "2022-12-12T23:15:00Z", "consumption_average_power_interval": 1.0},
+            {"start": "2022-12-12T23:15:00Z", "consumption_average_power_interval": 1.0}
+        ]}"#;
+        let message = schema_error(load_forecasts_reader(&json[..]));
+        assert_eq!(message, "missing field `end` in forecasts[1]");
+
+        // Optional fields may be left out or null
+        let json = br#"{"forecasts": [
+            {"start": "2022-12-12T23:00:00Z", "end": "2022-12-12T23:15:00Z", "consumption_average_power_interval": 1.0, "temperature_c": null}
+        ]}"#;
+        assert!(load_forecasts_reader(&json[..]).is_ok());
+    }
+
+    #[test]
+    fn test_schema_wrong_field_type() {
+        let json = br#"{"prices": [
+            {"start": "2022-12-12T23:00:00Z", "end": "2022-12-13T00:00:00Z", "market_price_currency": "EUR", "market_price_per_kwh": "0.25"}
+        ]}"#;
+        let message = schema_error(load_day_ahead_prices_reader(&json[..]));
+        assert_eq!(
+            message,
+            "field `market_price_per_kwh` in prices[0] must be a number, got \"0.25\""
+        );
+
+        let json = br#"{"forecasts": [
+            {"start": "yesterday", "end": "2022-12-12T23:15:00Z", "consumption_average_power_interval": 1.0}
+        ]}"#;
+        let message = schema_error(load_forecasts_reader(&json[..]));
+        assert!(message.contains("`start` in forecasts[0] must be an RFC 3339 timestamp"));
+    }
+
+    #[test]
+    fn test_battery_snapshot_and_restore() {
+        let mut battery = initialize_battery();
+
+        // One cycle of charging and discharging
+        battery.charge_battery(1.5, 1.0).unwrap();
+        battery.discharge_battery(1.5, 1.0).unwrap();
+        let snapshot = battery.snapshot();
+        assert_eq!(
+            snapshot,
+            BatteryState {
+                charge: battery.charge,
+                cycles: battery.cycles,
+            }
+        );
+
+        // More cycles age the battery further
+        for _ in 0..3 {
+            battery.charge_battery(1.5, 1.0).unwrap();
+            battery.discharge_battery(1.5, 0.5).unwrap();
+        }
+        assert_ne!(battery.snapshot(), snapshot);
+
+        battery.restore(snapshot);
+        assert_eq!(battery.snapshot(), snapshot);
+        assert_eq!(battery.state_of_health(), 1.0 - 0.0002 * snapshot.cycles);
+    }
+
+    #[test]
+    fn test_battery_reset() {
+        let mut battery = initialize_battery();
+        battery.charge_battery(1.5, 1.0).unwrap();
+        assert!(battery.cycles > 0.0);
+
+        battery.reset();
+        assert_eq!(battery.charge, 1.5);
+        assert_eq!(battery.cycles, 0.0);
+        assert_eq!(battery.state_of_health(), 1.0);
+    }
+
+    /// Two valleys followed by two peaks, in 15-minute intervals, for the arbitrage planner.
+    fn two_peak_prices() -> Vec<ElectricityPrice> {
+        let mut values = Vec::new();
+        for (value, intervals) in [
+            (0.05, 12),
+            (0.35, 4),
+            (0.40, 12),
+            (0.35, 4),
+            (0.06, 12),
+            (0.35, 4),
+            (0.42, 12),
+            (0.35, 4),
+        ] {
+            values.extend(std::iter::repeat_n(value, intervals));
+        }
+        make_prices(&values)
+    }
+
+    #[test]
+    fn test_arbitrage_buys_valleys_and_sells_peaks() {
+        let settings = load_settings();
+        let prices = two_peak_prices();
+        let plan = plan_arbitrage(prices.clone(), initialize_battery(), &settings).unwrap();
+        assert_eq!(plan.len(), prices.len());
+
+        let max_energy_wh = 1.5 * 0.25 * WH_PER_MWH;
+        let mut stored_wh = 1.5 * WH_PER_MWH;
+        for (interval, price) in plan.iter().zip(&prices) {
+            let value = price.market_price_per_kwh;
+            assert_eq!(
+                interval.energy_from_battery_wh, 0.0,
+                "Site load is ignored."
+            );
+            assert!(interval.energy_to_battery_wh == 0.0 || interval.energy_to_grid_wh == 0.0);
+            assert!(interval.energy_to_battery_wh == 0.0 || value <= 0.06);
+            assert!(interval.energy_to_grid_wh == 0.0 || value >= 0.40);
+            assert!(interval.energy_to_battery_wh <= max_energy_wh + 1e-6);
+            assert!(interval.energy_to_grid_wh <= max_energy_wh + 1e-6);
+
+            // The stored energy never leaves the battery's capacity
+            stored_wh += interval.energy_to_battery_wh * 0.9 - interval.energy_to_grid_wh / 0.9;
+            assert!((-1e-6..=3.0 * WH_PER_MWH + 1e-6).contains(&stored_wh));
+        }
+        assert!(
+            stored_wh >= 1.5 * WH_PER_MWH - 1e-6,
+            "The initial charge is held."
+        );
+
+        // Both valleys are bought and both peaks sold
+        let bought = |range: std::ops::Range<usize>| -> f64 {
+            plan[range]
+                .iter()
+                .map(|interval| interval.energy_to_battery_wh)
+                .sum()
+        };
+        let sold = |range: std::ops::Range<usize>| -> f64 {
+            plan[range]
+                .iter()
+                .map(|interval| interval.energy_to_grid_wh)
+                .sum()
+        };
+        assert!(bought(0..12) > 0.0 && bought(32..44) > 0.0);
+        assert!(sold(16..28) > 0.0 && sold(48..60) > 0.0);
+        assert!(plan_totals(&plan).total_savings_eur > 0.0);
+    }
+
+    #[test]
+    fn test_arbitrage_respects_daily_cycles() {
+        let mut settings = load_settings();
+        let unlimited = plan_arbitrage(two_peak_prices(), initialize_battery(), &settings).unwrap();
+        settings.max_daily_cycles = Some(1.0);
+        let limited = plan_arbitrage(two_peak_prices(), initialize_battery(), &settings).unwrap();
+
+        let mut cycles: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+        for interval in &limited {
+            let moved_mwh = (interval.energy_to_battery_wh * 0.9
+                + interval.energy_to_grid_wh / 0.9)
+                / WH_PER_MWH;
+            *cycles.entry(interval.start.date_naive()).or_default() += moved_mwh / 3.0 / 2.0;
+        }
+        assert!(cycles.values().all(|day_cycles| *day_cycles <= 1.0 + 1e-9));
+
+        let sold =
+            |plan: &[Plan]| -> f64 { plan.iter().map(|interval| interval.energy_to_grid_wh).sum() };
+        assert!(sold(&limited) > 0.0);
+        assert!(sold(&limited) < sold(&unlimited));
+    }
+
+    #[test]
+    fn test_streaming_planner_matches_eager_planner() {
+        let mut settings = load_settings();
+        settings.grid_limit = 1.0;
+        settings.sell_threshold_per_kwh = Some(0.45);
+        // Peaks, cheap hours and an export opportunity, with prices starting an hour early
+        let consumptions = [0.0, 0.5, 3.0, 2.0, 0.0, 0.0, 1.5, 0.0, 0.0, 0.0];
+        let values = [
+            0.30, 0.30, 0.30, 0.30, 0.10, 0.40, 0.20, 0.05, 0.10, 0.30, 0.50, 0.20, 0.15, 0.30,
+        ];
+        let forecasts: Vec<Forecast> = make_forecasts(&consumptions)
+            .into_iter()
+            .map(|mut forecast| {
+                forecast.start += Duration::hours(1);
+                forecast.end += Duration::hours(1);
+                forecast
+            })
+            .collect();
+        let prices = make_prices(&values);
+        let threshold = charge_threshold(&prices, &settings);
+
+        let eager = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            threshold,
+            None,
+        )
+        .unwrap();
+        let streamed: Vec<Plan> = plan_battery_usage_iter(
+            forecasts.into_iter(),
+            prices.into_iter(),
+            initialize_battery(),
+            &settings,
+            threshold,
+        )
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+
+        assert_eq!(streamed.len(), consumptions.len());
+        assert_eq!(
+            serde_json::to_value(&streamed).unwrap(),
+            serde_json::to_value(&eager).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_streaming_planner_stops_at_missing_price() {
+        let settings = load_settings();
+        let forecasts = make_forecasts(&[0.0, 0.0, 0.0]);
+        // The second interval has no price
+        let prices: Vec<ElectricityPrice> = make_prices(&[0.1, 0.2, 0.3])
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .map(|(_, price)| price)
+            .collect();
+
+        let results: Vec<_> = plan_battery_usage_iter(
+            forecasts.into_iter(),
+            prices.into_iter(),
+            initialize_battery(),
+            &settings,
+            0.2,
+        )
+        .collect();
+        assert_eq!(results.len(), 2, "Expected one plan, then the error.");
+        assert!(results[0].is_ok());
+        let message = results[1].as_ref().err().unwrap().to_string();
+        assert!(
+            message.contains("No price"),
+            "Unexpected error: {}",
+            message
+        );
+    }
+
+    /// Removes the intervals at the given indices from a series.
+    fn without<T>(series: Vec<T>, removed: &[usize]) -> Vec<T> {
+        series
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !removed.contains(index))
+            .map(|(_, item)| item)
+            .collect()
+    }
+
+    #[test]
+    fn test_fill_single_missing_forecast() {
+        let mut forecasts = make_forecasts(&[1.0, 2.0, 4.0, 3.0]);
+        forecasts[1].temperature_c = Some(10.0);
+        forecasts[3].temperature_c = Some(14.0);
+
+        let filled = fill_gaps(without(forecasts, &[2]), 15).unwrap();
+        assert_eq!(filled.len(), 4);
+        assert_eq!(filled[2].start, series_start() + Duration::minutes(30));
+        assert_eq!(filled[2].end, series_start() + Duration::minutes(45));
+        assert!((filled[2].consumption_average_power_interval - 2.5).abs() < 1e-9);
+        assert_eq!(filled[2].temperature_c, Some(12.0));
+        assert_eq!(filled[2].production_average_power_interval, None);
+    }
+
+    #[test]
+    fn test_fill_several_missing_forecasts() {
+        let forecasts = make_forecasts(&[1.0, 2.0, 3.0, 4.0]);
+        let filled = fill_gaps(without(forecasts, &[1, 2]), 15).unwrap();
+        let values: Vec<f64> = filled
+            .iter()
+            .map(|forecast| forecast.consumption_average_power_interval)
+            .collect();
+        assert_eq!(filled.len(), 4);
+        assert!((values[1] - 2.0).abs() < 1e-9 && (values[2] - 3.0).abs() < 1e-9);
+        assert!(filled.windows(2).all(|pair| pair[0].end == pair[1].start));
+    }
+
+    #[test]
+    fn test_fill_gaps_at_edges_and_misaligned() {
+        // Nothing before the first or after the last forecast can be detected
+        let forecasts = make_forecasts(&[1.0, 2.0, 3.0, 4.0]);
+        let filled = fill_gaps(without(forecasts, &[0, 3]), 15).unwrap();
+        assert_eq!(filled.len(), 2);
+        assert_eq!(filled[0].start, series_start() + Duration::minutes(15));
+
+        // A gap of 20 minutes is not a whole number of 15-minute intervals
+        let mut forecasts = make_forecasts(&[1.0, 2.0]);
+        forecasts[1].start += Duration::minutes(20);
+        forecasts[1].end += Duration::minutes(20);
+        assert!(matches!(
+            fill_gaps(forecasts, 15),
+            Err(LoadError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_fill_price_gaps_carries_prices() {
+        let start = series_start();
+        let end = start + Duration::minutes(75);
+
+        // A missing interval in the middle carries the last known price forward
+        let prices = without(make_prices(&[0.1, 0.2, 0.3, 0.4, 0.5]), &[2]);
+        let filled = fill_price_gaps(prices, start, end, 15).unwrap();
+        assert_eq!(filled.len(), 5);
+        assert_eq!(filled[2].start, start + Duration::minutes(30));
+        assert_eq!(filled[2].market_price_per_kwh, 0.2);
+        assert_eq!(filled[2].market_price_currency, "EUR");
+
+        // Missing intervals at the edges take the nearest known price
+        let prices = without(make_prices(&[0.1, 0.2, 0.3, 0.4, 0.5]), &[0, 4]);
+        let filled = fill_price_gaps(prices, start, end, 15).unwrap();
+        let values: Vec<f64> = filled
+            .iter()
+            .map(|price| price.market_price_per_kwh)
+            .collect();
+        assert_eq!(values, vec![0.2, 0.2, 0.3, 0.4, 0.4]);
+        assert_eq!(filled[0].start, start);
+        assert_eq!(filled[4].end, end);
+
+        // Without any price there is nothing to carry
+        assert!(matches!(
+            fill_price_gaps(Vec::new(), start, end, 15),
+            Err(LoadError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_consecutive_days_carry_the_final_charge() {
+        let settings = load_settings();
+        let shift = |forecasts: Vec<Forecast>, prices: Vec<ElectricityPrice>| {
+            let forecasts = forecasts
+                .into_iter()
+                .map(|mut forecast| {
+                    forecast.start += Duration::days(1);
+                    forecast.end += Duration::days(1);
+                    forecast
+                })
+                .collect::<Vec<_>>();
+            let prices = prices
+                .into_iter()
+                .map(|mut price| {
+                    price.start += Duration::days(1);
+                    price.end += Duration::days(1);
+                    price
+                })
+                .collect::<Vec<_>>();
+            (forecasts, prices)
+        };
+
+        // Day one charges for an hour: 1.5 MWh from the grid, 1.35 MWh stored
+        let mut battery = initialize_battery();
+        plan_battery_usage(
+            make_forecasts(&[0.0; 4]),
+            make_prices(&[0.05; 4]),
+            &mut battery,
+            &settings,
+            0.10,
+            None,
+        )
+        .unwrap();
+        assert!((battery.charge - 2.85).abs() < 1e-9);
+
+        // Day two only has room for what day one left, not a fresh 1.5 MWh charge
+        let room = battery.energy_to_full();
+        let (forecasts, prices) = shift(make_forecasts(&[0.0]), make_prices(&[0.05]));
+        let day_two =
+            plan_battery_usage(forecasts, prices, &mut battery, &settings, 0.10, None).unwrap();
+        let expected_wh = room / 0.9 * WH_PER_MWH;
+        assert!(
+            (day_two[0].energy_to_battery_wh - expected_wh).abs() < 1e-3,
+            "Expected {} Wh, got {} Wh",
+            expected_wh,
+            day_two[0].energy_to_battery_wh
+        );
+        assert!(day_two[0].energy_to_battery_wh < 375_000.0);
+        // Full, up to the capacity lost to the cycles of the last charge
+        assert!(battery.charge > 2.999);
+    }
+
+    #[test]
+    fn test_summary_reports_grid_limit_violations() {
+        let mut settings = load_settings();
+        settings.grid_limit = 1_000_000.0;
+        // 0.1 MWh covers only a fraction of the 2 MW excess of the spike
+        let mut battery = Battery::new(0.1, 0.1, 1.5, 1.0, 1.0, 0.0, 0.0);
+        let forecasts = make_forecasts(&[500_000.0, 3_000_000.0, 500_000.0]);
+        let prices = make_prices(&[0.30, 0.30, 0.30]);
+
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut battery,
+            &settings,
+            0.0,
+            None,
+        )
+        .unwrap();
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+
+        // 750 kWh consumed, 100 kWh from the battery: 2.6 MW average import
+        assert_eq!(summary.intervals_over_grid_limit, 1);
+        assert_eq!(summary.grid_limit_violations.len(), 1);
+        let violation = &summary.grid_limit_violations[0];
+        assert_eq!(violation.start, forecasts[1].start);
+        assert_eq!(violation.end, forecasts[1].end);
+        assert!(
+            (violation.shortfall_w - 1_600_000.0).abs() < 1e-6,
+            "Unexpected shortfall: {}",
+            violation.shortfall_w
+        );
+    }
+
+    #[test]
+    fn test_summary_nets_exports_against_imports() {
+        let settings = load_settings();
+        let forecasts = make_forecasts(&[8_000_000.0]);
+        let prices = make_prices(&[0.60]);
+
+        // 0.4 MW exported from the battery while the site draws 8 MW
+        let mut plan = make_plan(&[(0.0, 0.0)]);
+        plan[0].energy_to_grid_wh = 100_000.0;
+        plan[0].battery_revenue_eur = 100.0 * 0.60 * settings.feed_in_factor;
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+
+        // 7.6 MW net import stays under the 7.8 MW limit
+        assert_eq!(summary.intervals_over_grid_limit, 0);
+        assert!(
+            (summary.cost_without_battery_eur
+                - summary.cost_with_battery_eur
+                - plan[0].battery_revenue_eur)
+                .abs()
+                < 1e-9
+        );
+    }
+
+    /// Loads the bundled forecasts and prices at the given resolution and plans them.
+    fn plan_at_resolution(interval_minutes: i64) -> Vec<Plan> {
+        let mut settings = load_settings();
+        settings.interval_minutes = interval_minutes;
+        let forecasts = resample_forecasts(
+            load_forecasts("forecasts.json", false).unwrap().forecasts,
+            interval_minutes,
+        )
+        .unwrap();
+        let (prices, _) = load_day_ahead_prices_with_mapping(
+            "day-ahead.json",
+            &HashMap::new(),
+            Some(&settings.reporting_currency),
+            &settings.exchange_rates,
+            interval_minutes,
+            None,
+        )
+        .unwrap();
+        let threshold = charge_threshold(&prices.prices, &settings);
+        plan_battery_usage(
+            forecasts,
+            prices.prices,
+            &mut initialize_battery(),
+            &settings,
+            threshold,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pipeline_at_thirty_minutes() {
+        let plan = plan_at_resolution(30);
+        assert_eq!(plan.len(), 48, "Expected a day of 30-minute intervals.");
+        assert!(plan
+            .iter()
+            .all(|interval| interval.end - interval.start == Duration::minutes(30)));
+
+        // A 30-minute interval charges up to 1.5 MW for half an hour, not a quarter
+        let largest_charge = plan
+            .iter()
+            .map(|interval| interval.energy_to_battery_wh)
+            .fold(0.0, f64::max);
+        assert!((largest_charge - 750_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pipeline_at_five_minutes() {
+        let plan = plan_at_resolution(5);
+        assert_eq!(plan.len(), 288, "Expected a day of 5-minute intervals.");
+        assert!(plan.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert!(plan
+            .iter()
+            .all(|interval| interval.energy_to_battery_wh <= 125_000.0 + 1e-6));
+    }
+
+    #[test]
+    fn test_validate_bundled_inputs() {
+        let config = config::load_config("config.toml").unwrap();
+        let forecasts = load_forecasts("forecasts.json", false).unwrap();
+        let (prices, _) = load_day_ahead_prices("day-ahead.json").unwrap();
+        validate_inputs(&config, &forecasts, &prices).unwrap();
+    }
+
+    #[test]
+    fn test_validate_inputs_horizon_mismatch() {
+        let config = config::load_config("config.toml").unwrap();
+        let forecasts = load_forecasts("forecasts.json", false).unwrap();
+        let (mut prices, _) = load_day_ahead_prices("day-ahead.json").unwrap();
+        // Prices for the first twelve hours only, and one of them for the wrong interval
+        prices.prices.truncate(48);
+        prices.prices[1].end += Duration::minutes(5);
+
+        let message = validate_inputs(&config, &forecasts, &prices)
+            .expect_err("Expected the horizon mismatch to be reported")
+            .to_string();
+        assert!(
+            message.contains("Prices cover"),
+            "Unexpected error: {}",
+            message
+        );
+        assert!(message.contains("lasts 20 minutes instead of 15"));
+        assert!(message.contains("No price for 49 forecast interval(s)"));
+        // Every problem is listed, not just the first
+        assert!(message.lines().count() > 3);
+    }
+
+    #[test]
+    fn test_surplus_production_charges_at_high_price() {
+        // PV exceeds consumption in the first interval while the price is far above the threshold
+        let mut forecasts = make_forecasts(&[1_000_000.0, 1_000_000.0]);
+        forecasts[0].production_average_power_interval = Some(2_000_000.0);
+        let mut battery = initialize_battery();
+
+        let plan = plan_battery_usage(
+            forecasts,
+            make_prices(&[0.50, 0.50]),
+            &mut battery,
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // The 1 MW surplus is stored for the whole interval, without buying from the grid
+        assert!((plan[0].energy_from_surplus_wh - 250_000.0).abs() < 1e-6);
+        assert_eq!(plan[0].energy_to_battery_wh, 0.0);
+        assert_eq!(plan[0].grid_cost_eur, 0.0);
+        assert_eq!(plan[1].energy_from_surplus_wh, 0.0);
+        assert!(battery.charge > 1.5);
+    }
+
+    #[test]
+    fn test_summarize_plan_values_surplus_at_feed_in_price() {
+        let mut settings = load_settings();
+        settings.grid_fee_per_kwh = 0.05;
+        settings.feed_in_factor = 0.8;
+        let mut forecasts = make_forecasts(&[1_000_000.0]);
+        forecasts[0].production_average_power_interval = Some(2_000_000.0);
+        let prices = make_prices(&[0.50]);
+
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+
+        // Without the battery the 250 kWh surplus is exported at 0.8 * 0.50 EUR/kWh
+        assert!((summary.cost_without_battery_eur + 100.0).abs() < 1e-9);
+        // Storing it forgoes the feed-in credit of what is stored, not the import price
+        let stored_kwh = plan[0].energy_from_surplus_wh / 1_000.0;
+        assert!((summary.cost_with_battery_eur - (-100.0 + stored_kwh * 0.40)).abs() < 1e-9);
+        // Surplus charging counts as charged energy
+        assert_eq!(summary.energy_charged_wh, plan[0].energy_from_surplus_wh);
+    }
+
+    #[test]
+    fn test_battery_builder() {
+        let battery = Battery::builder()
+            .capacity(3.0)
+            .initial_charge(1.5)
+            .max_rate(1.5)
+            .max_discharge_rate(1.0)
+            .efficiency(0.9)
+            .min_charge(0.3)
+            .build()
+            .unwrap();
+
+        assert_eq!(battery.capacity(), 3.0);
+        assert_eq!(battery.charge, 1.5);
+        assert_eq!(battery.max_charge_rate(), 1.5);
+        assert_eq!(battery.max_discharge_rate(), 1.0);
+        assert_eq!(battery.cycles, 0.0);
+    }
+
+    #[test]
+    fn test_battery_builder_rejects_invalid_properties() {
+        let valid = || {
+            Battery::builder()
+                .capacity(3.0)
+                .initial_charge(1.5)
+                .max_rate(1.5)
+                .efficiency(0.9)
+        };
+        let cases = [
+            ("capacity", valid().capacity(0.0)),
+            ("initial_charge", valid().initial_charge(4.0)),
+            ("initial_charge", valid().initial_charge(-0.5)),
+            ("min_charge", valid().min_charge(3.5)),
+            ("max_rate", valid().max_rate(0.0)),
+            ("max_charge_rate", valid().max_charge_rate(-1.0)),
+            ("max_discharge_rate", valid().max_discharge_rate(0.0)),
+            ("charge_efficiency", valid().charge_efficiency(1.2)),
+            ("discharge_efficiency", valid().discharge_efficiency(0.0)),
+            (
+                "self_discharge_rate_per_day",
+                valid().self_discharge_rate_per_day(2.0),
+            ),
+            (
+                "max_rate",
+                Battery::builder()
+                    .capacity(3.0)
+                    .initial_charge(1.5)
+                    .efficiency(0.9),
+            ),
+        ];
+
+        for (expected, builder) in cases {
+            match builder.build() {
+                Err(BatteryError::InvalidProperty { property, .. }) => {
+                    assert_eq!(property, expected)
+                }
+                other => panic!(
+                    "Expected `{}` to be rejected, got {:?}",
+                    expected,
+                    other.is_ok()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_plan_ndjson_one_interval_per_line() {
+        let plan = make_plan(&[(0.0, 375_000.0), (187_500.5, 0.0), (0.0, 0.0)]);
+        let temp_file = tempfile::Builder::new()
+            .suffix(".ndjson")
+            .tempfile()
+            .unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        save_plan_ndjson(&plan, path).unwrap();
+
+        let output = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (line, interval) in lines.iter().zip(&plan) {
+            let parsed: Plan = serde_json::from_str(line).unwrap();
+            assert_eq!(parsed.start, interval.start);
+            assert_eq!(parsed.end, interval.end);
+            assert_eq!(
+                parsed.energy_from_battery_wh,
+                interval.energy_from_battery_wh
+            );
+            assert_eq!(parsed.energy_to_battery_wh, interval.energy_to_battery_wh);
+        }
+
+        // save_plan picks the NDJSON writer from the extension
+        assert_eq!(OutputFormat::from_path(path), OutputFormat::Ndjson);
+        save_plan(make_plan(&[(1.0, 0.0)]), path, EnergyUnit::Wh).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_plan_trims_to_common_horizon() {
+        // Forecasts cover intervals 0-5, prices cover intervals 2-7
+        let forecasts = make_forecasts(&[1.0; 6]);
+        let prices: Vec<ElectricityPrice> = make_prices(&[0.50; 8]).into_iter().skip(2).collect();
+        let window_start = series_start() + Duration::minutes(30);
+        let window_end = series_start() + Duration::minutes(90);
+
+        let (trimmed_forecasts, trimmed_prices, trim) =
+            trim_to_common_horizon(forecasts.clone(), prices.clone()).unwrap();
+        let trim = trim.expect("Expected the unequal horizons to be trimmed");
+        assert_eq!(trim.start, window_start);
+        assert_eq!(trim.end, window_end);
+        assert_eq!(trim.forecasts_dropped, 2);
+        assert_eq!(trim.prices_dropped, 2);
+        assert_eq!(trimmed_forecasts.len(), 4);
+        assert_eq!(trimmed_prices.len(), 4);
+
+        let settings = load_settings();
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan.len(), 4);
+        assert_eq!(plan[0].start, window_start);
+        assert_eq!(plan[3].end, window_end);
+
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+        assert_eq!(summary.covered_start, Some(window_start));
+        assert_eq!(summary.covered_end, Some(window_end));
+
+        // Matching horizons are left alone, and disjoint ones cannot be planned
+        let (_, _, trim) =
+            trim_to_common_horizon(make_forecasts(&[1.0; 4]), make_prices(&[0.5; 4])).unwrap();
+        assert!(trim.is_none());
+        assert!(trim_to_common_horizon(forecasts, make_prices(&[0.50; 8]).split_off(6)).is_err());
+    }
+
+    #[test]
+    fn test_routine_charging_stays_below_max_soc() {
+        let mut settings = load_settings();
+        settings.max_soc = 0.6;
+        let mut battery = initialize_battery();
+
+        let plan = plan_battery_usage(
+            make_forecasts(&[1.0; 8]),
+            make_prices(&[0.05; 8]),
+            &mut battery,
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // Charging stops at 60% of 3 MWh instead of filling the battery
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+        assert_eq!(plan[7].energy_to_battery_wh, 0.0);
+        assert!(
+            (battery.charge - 1.8).abs() < 1e-9,
+            "Charge: {}",
+            battery.charge
+        );
+    }
+
+    #[test]
+    fn test_routine_discharge_stays_above_min_soc() {
+        let mut settings = load_settings();
+        settings.min_soc = 0.4;
+        settings.sell_threshold_per_kwh = Some(0.40);
+        let mut battery = initialize_battery();
+
+        let plan = plan_battery_usage(
+            make_forecasts(&[1.0; 8]),
+            make_prices(&[0.50; 8]),
+            &mut battery,
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        // Exporting stops at 40% of 3 MWh
+        assert!(plan[0].energy_to_grid_wh > 0.0);
+        assert_eq!(plan[7].energy_to_grid_wh, 0.0);
+        assert!(
+            (battery.charge - 1.2).abs() < 1e-9,
+            "Charge: {}",
+            battery.charge
+        );
+
+        // Shaving a peak may still use the energy below the band
+        let plan = plan_battery_usage(
+            make_forecasts(&[9_000_000.0]),
+            make_prices(&[0.50]),
+            &mut battery,
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert!(plan[0].energy_from_battery_wh > 0.0);
+        assert!(battery.charge < 1.2);
+
+        // The band must be ordered and within [0, 1]
+        let mut config = config::load_config("config.toml").unwrap();
+        config.settings.min_soc = 0.9;
+        config.settings.max_soc = 0.5;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_round_trip_efficiency() {
+        assert!((initialize_battery().round_trip_efficiency() - 0.81).abs() < 1e-12);
+
+        // Charge an empty battery in two cheap intervals, then discharge it into peaks
+        let mut battery = Battery::builder()
+            .capacity(3.0)
+            .initial_charge(0.0)
+            .max_rate(1.5)
+            .efficiency(0.9)
+            .build()
+            .unwrap();
+        let forecasts = make_forecasts(&[1.0, 1.0, 9_000_000.0, 9_000_000.0, 9_000_000.0]);
+        let prices = make_prices(&[0.05, 0.05, 0.50, 0.50, 0.50]);
+        let settings = load_settings();
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut battery,
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert!(battery.charge < 1e-9);
+
+        let summary = summarize_plan(&plan, &forecasts, &prices, &settings);
+        let realized = summary.realized_round_trip_efficiency.unwrap();
+        assert!((realized - 0.81).abs() < 1e-9, "Realized: {}", realized);
+
+        // Without charging there is nothing to compare against
+        let idle = make_plan(&[(0.0, 0.0), (1_000.0, 0.0)]);
+        let summary = summarize_plan(&idle, &forecasts, &prices, &settings);
+        assert_eq!(summary.realized_round_trip_efficiency, None);
+    }
+
+    #[test]
+    fn test_charge_price_margin_leaves_idle_band() {
+        let mut settings = load_settings();
+        settings.charge_price_margin = 0.02;
+        settings.sell_threshold_per_kwh = Some(0.25);
+
+        // Prices hovering around the 0.30 threshold, then one on either side of the band
+        let plan = plan_battery_usage(
+            make_forecasts(&[1.0; 6]),
+            make_prices(&[0.29, 0.31, 0.30, 0.285, 0.27, 0.33]),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        for interval in &plan[..4] {
+            assert_eq!(interval.energy_to_battery_wh, 0.0);
+            assert_eq!(interval.energy_to_grid_wh, 0.0);
+        }
+        assert!(plan[4].energy_to_battery_wh > 0.0);
+        assert!(plan[5].energy_to_grid_wh > 0.0);
+
+        // Without a margin the same prices charge and export
+        settings.charge_price_margin = 0.0;
+        let plan = plan_battery_usage(
+            make_forecasts(&[1.0; 2]),
+            make_prices(&[0.29, 0.31]),
+            &mut initialize_battery(),
+            &settings,
+            0.30,
+            None,
+        )
+        .unwrap();
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+        assert!(plan[1].energy_to_grid_wh > 0.0);
+    }
+
+    /// A log target shared with the test that captures the log output.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_log_has_one_record_per_interval() {
+        let buffer = SharedBuffer::default();
+        logger_builder(LogFormat::Json, false)
+            .target(env_logger::Target::Pipe(Box::new(buffer.clone())))
+            .filter_level(log::LevelFilter::Info)
+            .try_init()
+            .expect("No other test installs a logger");
+
+        // Years away from the other tests' intervals, which log to the same logger
+        let shift = |start: DateTime<Utc>| start + Duration::days(3650);
+        let mut forecasts = make_forecasts(&[1.0, 9_000_000.0, 1.0]);
+        let mut prices = make_prices(&[0.10, 0.50, 0.50]);
+        for forecast in &mut forecasts {
+            (forecast.start, forecast.end) = (shift(forecast.start), shift(forecast.end));
+        }
+        for price in &mut prices {
+            (price.start, price.end) = (shift(price.start), shift(price.end));
+        }
+        let starts: Vec<String> = forecasts
+            .iter()
+            .map(|forecast| forecast.start.to_rfc3339())
+            .collect();
+
+        plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &load_settings(),
+            0.30,
+            None,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Every line is a JSON object"))
+            .filter(|record: &serde_json::Value| {
+                record["target"] == "battery::decisions"
+                    && starts
+                        .iter()
+                        .any(|start| record["interval"] == start.as_str())
+            })
+            .collect();
+
+        assert_eq!(records.len(), 3);
+        let actions: Vec<&str> = records
+            .iter()
+            .map(|record| record["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(actions, vec!["charge", "discharge", "idle"]);
+        for record in &records {
+            assert!(record["energy_wh"].is_f64());
+            assert!(record["price_per_kwh"].is_f64());
+        }
+        assert_eq!(records[0]["energy_wh"], 375_000.0);
+        assert_eq!(records[0]["price_per_kwh"], 0.10);
+    }
+
+    #[test]
+    fn test_diff_plans() {
+        let plan = make_plan(&[(0.0, 375_000.0), (100_000.0, 0.0), (0.0, 0.0)]);
+        assert!(diff_plans(
+            &plan,
+            &make_plan(&[(0.0, 375_000.0), (100_000.0, 0.0), (0.0, 0.0)])
+        )
+        .is_empty());
+        assert_eq!(plan_cost_delta_eur(&plan, &plan), 0.0);
+
+        // One interval discharges more, and is cheaper by its revenue
+        let mut other = make_plan(&[(0.0, 375_000.0), (250_000.0, 0.0), (0.0, 0.0)]);
+        other[1].battery_revenue_eur = 12.5;
+        let diffs = diff_plans(&plan, &other);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].start, plan[1].start);
+        assert_eq!(diffs[0].discharge_a_wh, Some(100_000.0));
+        assert_eq!(diffs[0].discharge_b_wh, Some(250_000.0));
+        assert_eq!(diffs[0].discharge_delta_wh, 150_000.0);
+        assert_eq!(diffs[0].charge_delta_wh, 0.0);
+        assert_eq!(diffs[0].cost_delta_eur, -12.5);
+        assert_eq!(plan_cost_delta_eur(&plan, &other), -12.5);
+
+        // Intervals only one plan has are reported with the other side missing
+        let shorter = make_plan(&[(0.0, 375_000.0)]);
+        let diffs = diff_plans(&plan, &shorter);
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].discharge_a_wh, Some(100_000.0));
+        assert_eq!(diffs[0].discharge_b_wh, None);
+        assert_eq!(diffs[0].discharge_delta_wh, -100_000.0);
+        assert_eq!(diffs[1].start, plan[2].start);
+        assert_eq!(diffs[1].charge_b_wh, None);
+    }
+
+    #[test]
+    fn test_available_discharge_power() {
+        let battery = initialize_battery();
+
+        // 1.5 MWh deliver 1.35 MWh, far more than the rate allows in 15 minutes
+        assert_eq!(battery.available_discharge_power(0.25), 1.5);
+
+        // Over two hours the stored energy runs out first
+        assert!((battery.available_discharge_power(2.0) - 0.675).abs() < 1e-12);
+
+        // Energy below the minimum charge is not available
+        let reserved = Battery::new(3.0, 1.5, 1.5, 0.9, 0.9, 1.0, 0.0);
+        assert!((reserved.available_discharge_power(1.0) - 0.45).abs() < 1e-12);
+        assert_eq!(reserved.available_discharge_power(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_equal_prices_rank_earliest_first() {
+        let prices = make_prices(&[0.20, 0.10, 0.10, 0.30, 0.10, 0.20]);
+        assert_eq!(rank_by_price(&prices), vec![1, 2, 4, 0, 5, 3]);
+
+        // The ranking does not depend on the input order
+        let mut reversed = prices.clone();
+        reversed.reverse();
+        let ranked: Vec<_> = rank_by_price(&reversed)
+            .into_iter()
+            .map(|index| reversed[index].start)
+            .collect();
+        let expected: Vec<_> = [1, 2, 4, 0, 5, 3]
+            .iter()
+            .map(|index| prices[*index].start)
+            .collect();
+        assert_eq!(ranked, expected);
+
+        // With a flat tariff, the EV charges in the earliest intervals, on every run
+        for _ in 0..10 {
+            let mut forecasts = make_forecasts(&[1_000_000.0; 4]);
+            let schedule = EvSchedule {
+                windows: vec![EvWindow {
+                    start: series_start(),
+                    end: series_start() + Duration::hours(1),
+                    energy_required_wh: 375_000.0,
+                    max_power_w: 1_000_000.0,
+                }],
+            };
+            apply_ev_schedule(&mut forecasts, &make_prices(&[0.25; 4]), &schedule).unwrap();
+            let consumptions: Vec<f64> = forecasts
+                .iter()
+                .map(|f| f.consumption_average_power_interval)
+                .collect();
+            assert_eq!(
+                consumptions,
+                vec![2_000_000.0, 1_500_000.0, 1_000_000.0, 1_000_000.0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_grid_import_limit_throttles_charging() {
+        let mut settings = load_settings();
+        settings.grid_import_limit = 7_500_000.0;
+
+        // Cheap intervals under the grid limit, one with a high baseline load, in W
+        let forecasts = make_forecasts(&[7_000_000.0, 5_000_000.0, 7_600_000.0]);
+        let prices = make_prices(&[0.10, 0.10, 0.10]);
+
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+
+        // Only 0.5 of the 1.5 MW charge power fits under the 7.5 MW import limit
+        assert!((plan[0].energy_to_battery_wh - 125_000.0).abs() < 1e-6);
+        assert!((plan[1].energy_to_battery_wh - 375_000.0).abs() < 1e-6);
+        // Already above the import limit, so no charging at all
+        assert_eq!(plan[2].energy_to_battery_wh, 0.0);
+        assert_eq!(plan[2].energy_from_battery_wh, 0.0);
+    }
+
+    #[test]
+    fn test_charge_reports_curtailment_when_nearly_full() {
+        let mut battery = initialize_battery();
+        battery.charge = 2.9;
+
+        // 1.5 MW for an hour would store 1.35 MWh, but only 0.1 MWh fits
+        let outcome = battery.charge_battery_reporting(1.5, 1.0).unwrap();
+        assert!((outcome.requested_mwh - 1.35).abs() < 1e-9);
+        assert!((outcome.stored_mwh - 0.1).abs() < 1e-9);
+        assert!(
+            (outcome.curtailed_mwh - (outcome.requested_mwh - outcome.stored_mwh)).abs() < 1e-12
+        );
+        assert!((outcome.flow.battery_wh - 100_000.0).abs() < 1e-6);
+
+        // The plan records the grid energy that could not be drawn
+        let mut settings = load_settings();
+        settings.record_curtailment = true;
+        let mut battery = initialize_battery();
+        battery.charge = 2.9;
+        let plan = plan_battery_usage(
+            make_forecasts(&[5_000_000.0, 5_000_000.0]),
+            make_prices(&[0.10, 0.10]),
+            &mut battery,
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        let drawn_wh = plan[0].energy_to_battery_wh;
+        assert!((drawn_wh - 100_000.0 / 0.9).abs() < 1e-3);
+        assert!((plan[0].energy_curtailed_wh.unwrap() - (375_000.0 - drawn_wh)).abs() < 1e-3);
+        assert_eq!(plan[1].energy_curtailed_wh, Some(375_000.0));
+
+        // Without the setting, nothing is recorded
+        let plan = make_plan(&[(0.0, 0.0)]);
+        assert!(!serde_json::to_string(&plan).unwrap().contains("curtailed"));
+    }
+
+    #[test]
+    fn test_price_profile_fills_uncovered_intervals() {
+        let json = prices_json(&[("EUR", 0.20)]);
+        let (prices_data, _) = load_day_ahead_prices_reader_with_mapping(
+            json.as_bytes(),
+            &HashMap::new(),
+            Some("EUR"),
+            &HashMap::new(),
+            60,
+            None,
+        )
+        .unwrap();
+
+        // Weekday hours cost their hour / 100, weekend hours a flat 0.05
+        let weekday: Vec<String> = (0..24)
+            .map(|hour| (hour as f64 / 100.0).to_string())
+            .collect();
+        let profile_json = |holidays: &str| {
+            format!(
+                r#"{{"currency": "EUR", "weekday": [{}], "weekend": [{}], "holidays": [{}]}}"#,
+                weekday.join(", "),
+                vec!["0.05"; 24].join(", "),
+                holidays
+            )
+        };
+        let profile_file = NamedTempFile::new().unwrap();
+        fs::write(profile_file.path(), profile_json("")).unwrap();
+        let profile = load_price_profile(profile_file.path().to_str().unwrap()).unwrap();
+
+        // The day-ahead prices cover Monday 23:00 only, the profile prices Tuesday 00:00 and 01:00
+        let merged = merge_price_profile(
+            prices_data.prices.clone(),
+            &profile,
+            series_start(),
+            series_start() + Duration::hours(3),
+            60,
+            Some("EUR"),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let values: Vec<(f64, bool)> = merged
+            .iter()
+            .map(|price| (price.market_price_per_kwh, price.is_fallback))
+            .collect();
+        assert_eq!(values, vec![(0.20, false), (0.0, true), (0.01, true)]);
+        assert!(merged.windows(2).all(|pair| pair[0].end == pair[1].start));
+
+        // Weekends and holidays use the weekend curve
+        let saturday: DateTime<Utc> = "2022-12-17T10:00:00Z".parse().unwrap();
+        assert_eq!(profile.price_at(saturday), 0.05);
+        let holiday_file = NamedTempFile::new().unwrap();
+        fs::write(holiday_file.path(), profile_json(r#""2022-12-13""#)).unwrap();
+        let holiday_profile = load_price_profile(holiday_file.path().to_str().unwrap()).unwrap();
+        let merged = merge_price_profile(
+            prices_data.prices,
+            &holiday_profile,
+            series_start(),
+            series_start() + Duration::hours(3),
+            60,
+            Some("EUR"),
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(merged[1].market_price_per_kwh, 0.05);
+        assert_eq!(merged[2].market_price_per_kwh, 0.05);
+
+        // A curve without 24 hours is rejected
+        let short_file = NamedTempFile::new().unwrap();
+        fs::write(
+            short_file.path(),
+            r#"{"currency": "EUR", "weekday": [0.1], "weekend": [0.1]}"#,
+        )
+        .unwrap();
+        assert!(load_price_profile(short_file.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_charge_and_discharge_energy_targets() {
+        // Achievable: the whole target is drawn, and 90 % of it is stored
+        let mut battery = initialize_battery();
+        assert!((battery.charge_energy(0.5, 1.0).unwrap() - 0.5).abs() < 1e-9);
+        assert!((battery.charge - 1.95).abs() < 1e-9);
+        let mut battery = initialize_battery();
+        assert!((battery.discharge_energy(0.45, 1.0).unwrap() - 0.45).abs() < 1e-9);
+        assert!((battery.charge - 1.0).abs() < 1e-9);
+
+        // Rate-limited: at most 1.5 MW over the duration
+        let mut battery = initialize_battery();
+        assert!((battery.charge_energy(1.0, 0.25).unwrap() - 0.375).abs() < 1e-9);
+        let mut battery = initialize_battery();
+        assert!((battery.discharge_energy(1.0, 0.5).unwrap() - 0.75).abs() < 1e-9);
+
+        // Capacity-limited: only 0.1 MWh fits, drawn with losses
+        let mut battery = initialize_battery();
+        battery.charge = 2.9;
+        assert!((battery.charge_energy(1.0, 1.0).unwrap() - 0.1 / 0.9).abs() < 1e-9);
+
+        // Availability-limited: 0.2 MWh stored delivers 0.18 MWh
+        let mut battery = initialize_battery();
+        battery.charge = 0.2;
+        assert!((battery.discharge_energy(1.0, 1.0).unwrap() - 0.18).abs() < 1e-9);
+        assert!(battery.charge.abs() < 1e-9);
+
+        assert!(matches!(
+            initialize_battery().charge_energy(-1.0, 1.0),
+            Err(BatteryError::NegativePower { .. })
+        ));
+        assert_eq!(
+            initialize_battery().discharge_energy(1.0, 0.0).unwrap(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_round_wh_half_to_even() {
+        // 1.23456 MWh is 1 234 560 Wh at any precision, not a tenth of it
+        for decimals in 0..=2 {
+            assert_eq!(round_wh(1.23456, decimals), 1_234_560.0);
+        }
+        assert_eq!(round_wh(1.2345678, 0), 1_234_568.0);
+        assert_eq!(round_wh(1.2345678, 1), 1_234_567.8);
+        assert_eq!(round_wh(1.2345678, 2), 1_234_567.8);
+        assert_eq!(round_wh(1.23456789, 2), 1_234_567.89);
+
+        // Halfway cases go to the even neighbour instead of always up
+        assert_eq!(round_half_even(2.5, 0), 2.0);
+        assert_eq!(round_half_even(3.5, 0), 4.0);
+        assert_eq!(round_half_even(-2.5, 0), -2.0);
+
+        // Only the energies of a plan are rounded
+        let mut plan = make_plan(&[(1234.5678, 0.04)]);
+        plan[0].grid_cost_eur = 0.123456;
+        round_plan(&mut plan, 1);
+        assert_eq!(plan[0].energy_from_battery_wh, 1234.6);
+        assert_eq!(plan[0].energy_to_battery_wh, 0.0);
+        assert_eq!(plan[0].grid_cost_eur, 0.123456);
+    }
+
+    #[test]
+    fn test_intraday_prices_override_day_ahead() {
+        let day_ahead_file = NamedTempFile::new().unwrap();
+        fs::write(
+            day_ahead_file.path(),
+            prices_json(&[("EUR", 0.20), ("EUR", 0.30), ("EUR", 0.40)]),
+        )
+        .unwrap();
+
+        // Intraday prices for the second hour, and for an hour beyond the day-ahead horizon
+        let intraday = |hour: i64, price: f64| {
+            let start = series_start() + Duration::hours(hour);
+            format!(
+                r#"{{"start": "{}", "end": "{}", "market_price_currency": "EUR", "market_price_per_kwh": {}}}"#,
+                start.to_rfc3339(),
+                (start + Duration::hours(1)).to_rfc3339(),
+                price
+            )
+        };
+        let intraday_file = NamedTempFile::new().unwrap();
+        fs::write(
+            intraday_file.path(),
+            format!(
+                r#"{{"prices": [{}, {}]}}"#,
+                intraday(1, 0.50),
+                intraday(5, 0.90)
+            ),
+        )
+        .unwrap();
+
+        let (prices_data, average_price) = load_prices_merged(
+            day_ahead_file.path().to_str().unwrap(),
+            Some(intraday_file.path().to_str().unwrap()),
+        )
+        .unwrap();
+
+        let prices = &prices_data.prices;
+        assert_eq!(prices.len(), 12);
+        for (index, price) in prices.iter().enumerate() {
+            let (expected_price, expected_source) = match index / 4 {
+                0 => (0.20, PriceSource::DayAhead),
+                1 => (0.50, PriceSource::Intraday),
+                _ => (0.40, PriceSource::DayAhead),
+            };
+            assert_eq!(price.market_price_per_kwh, expected_price);
+            assert_eq!(price.source, expected_source);
+        }
+        assert!((average_price - (0.20 + 0.50 + 0.40) / 3.0).abs() < 1e-9);
+
+        // Without intraday prices, the day-ahead prices are used as they are
+        let (prices_data, _) =
+            load_prices_merged(day_ahead_file.path().to_str().unwrap(), None).unwrap();
+        assert!(prices_data
+            .prices
+            .iter()
+            .all(|price| price.source == PriceSource::DayAhead));
+    }
+
+    #[test]
+    fn test_p90_planning_discharges_more() {
+        // The mean stays under the grid limit, the P90 exceeds it in the second interval
+        let mut forecasts = make_forecasts(&[7_000_000.0, 7_500_000.0, 7_000_000.0]);
+        forecasts[1].consumption_p90 = Some(8_300_000.0);
+        let prices = make_prices(&[0.30, 0.30, 0.30]);
+
+        let plan_with = |quantile| {
+            let mut settings = load_settings();
+            settings.planning_quantile = quantile;
+            plan_battery_usage(
+                forecasts.clone(),
+                prices.clone(),
+                &mut initialize_battery(),
+                &settings,
+                0.25,
+                None,
+            )
+            .unwrap()
+        };
+        let discharged = |plan: &[Plan]| -> f64 {
+            plan.iter()
+                .map(|interval| interval.energy_from_battery_wh)
+                .sum()
+        };
+
+        let mean_plan = plan_with(PlanningQuantile::Mean);
+        let p90_plan = plan_with(PlanningQuantile::P90);
+        assert_eq!(discharged(&mean_plan), 0.0);
+        assert!(discharged(&p90_plan) > discharged(&mean_plan));
+        assert!(p90_plan[1].energy_from_battery_wh > 0.0);
+        // Forecasts without a P90 are planned on their mean
+        assert_eq!(p90_plan[0].energy_from_battery_wh, 0.0);
+        assert_eq!(p90_plan[2].energy_from_battery_wh, 0.0);
+    }
+
+    #[test]
+    fn test_battery_from_settings() {
+        let mut settings = load_settings();
+        settings.max_charge_rate = Some(1.0);
+        settings.charge_efficiency = Some(0.95);
+        settings.min_charge = 0.3;
+        settings.self_discharge_rate_per_day = 0.01;
+
+        let battery = Battery::from_settings(&settings).unwrap();
+        assert_eq!(battery.capacity(), settings.capacity);
+        assert_eq!(battery.charge, settings.initial_charge);
+        assert_eq!(battery.max_charge_rate(), 1.0);
+        assert_eq!(battery.max_discharge_rate(), settings.max_rate);
+        assert_eq!(battery.charge_efficiency(), 0.95);
+        assert_eq!(
+            battery.discharge_efficiency(),
+            settings.discharge_efficiency()
+        );
+        assert_eq!(battery.min_charge(), 0.3);
+        assert_eq!(battery.self_discharge_rate_per_day(), 0.01);
+        assert_eq!(battery.cycles, 0.0);
+
+        // The settings are validated like any other battery
+        settings.initial_charge = settings.capacity + 1.0;
+        assert!(matches!(
+            Battery::from_settings(&settings),
+            Err(BatteryError::InvalidProperty {
+                property: "initial_charge",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_explain_records_decision_reasons() {
+        let mut settings = load_settings();
+        settings.explain = true;
+
+        // A cheap interval, a peak above the grid limit and an expensive interval
+        let forecasts = make_forecasts(&[5_000_000.0, 9_300_000.0, 5_000_000.0]);
+        let prices = make_prices(&[0.10, 0.30, 0.30]);
+
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        let reasons: Vec<_> = plan.iter().map(|p| p.reason.as_deref()).collect();
+        assert_eq!(
+            reasons,
+            [
+                Some("price 0.1 <= threshold 0.25, charged 1.5 MW"),
+                Some("consumption 9300000 > grid_limit 7800000, discharged 1.5 MW"),
+                Some("price 0.3 > threshold 0.25, idle"),
+            ]
+        );
+
+        // Without --explain, no reasons are recorded or written
+        settings.explain = false;
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert!(plan.iter().all(|p| p.reason.is_none()));
+        assert!(!serde_json::to_string(&plan).unwrap().contains("reason"));
+    }
+
+    #[test]
+    fn test_empty_prices_are_rejected() {
+        let result = load_day_ahead_prices_reader(r#"{"prices": []}"#.as_bytes());
+        let error = result.err().unwrap();
+        assert!(matches!(error, LoadError::Empty("day-ahead prices")));
+        assert_eq!(error.to_string(), "No day-ahead prices in the input");
+    }
+
+    #[test]
+    fn test_empty_forecasts_are_rejected() {
+        let result = plan_battery_usage(
+            Vec::new(),
+            make_prices(&[0.10, 0.30]),
+            &mut initialize_battery(),
+            &load_settings(),
+            0.25,
+            None,
+        );
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "No intervals to plan: got 0 forecast(s) and 2 price(s)"
+        );
+    }
+
+    /// Builds the metered intervals of a plan, with the energies given as `(from, to)`.
+    fn metered(plan: &[Plan], energies: &[(f64, f64)]) -> Vec<MeteredInterval> {
+        plan.iter()
+            .zip(energies)
+            .map(|(interval, (from, to))| MeteredInterval {
+                start: interval.start,
+                end: interval.end,
+                energy_from_battery_wh: *from,
+                energy_to_battery_wh: *to,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reconcile_perfect_agreement() {
+        let mut settings = load_settings();
+        settings.grid_fee_per_kwh = 0.0;
+        settings.tax_rate = 0.0;
+        let energies = [(0.0, 375_000.0), (100_000.0, 0.0), (0.0, 0.0)];
+        let plan = make_plan(&energies);
+
+        let report = reconcile(
+            &plan,
+            &metered(&plan, &energies),
+            &make_prices(&[0.10, 0.30, 0.30]),
+            &settings,
+        );
+
+        assert_eq!(report.intervals.len(), 3);
+        assert!(report.intervals.iter().all(|interval| {
+            interval.from_battery_difference_wh == 0.0
+                && interval.to_battery_difference_wh == 0.0
+                && interval.cost_impact_eur == 0.0
+        }));
+        assert_eq!(report.total_from_battery_difference_wh, 0.0);
+        assert_eq!(report.total_to_battery_difference_wh, 0.0);
+        assert_eq!(report.total_cost_impact_eur, 0.0);
+        assert!(report.unmetered.is_empty() && report.unplanned.is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_reports_mismatched_interval() {
+        let mut settings = load_settings();
+        settings.grid_fee_per_kwh = 0.0;
+        settings.tax_rate = 0.0;
+        let plan = make_plan(&[(0.0, 375_000.0), (100_000.0, 0.0), (0.0, 0.0)]);
+
+        // The first interval charged 75 kWh less than planned, the last was not metered,
+        // and an interval after the plan was metered
+        let mut actual = metered(&plan, &[(0.0, 300_000.0), (100_000.0, 0.0)]);
+        actual.push(MeteredInterval {
+            start: plan[2].end,
+            end: plan[2].end + Duration::minutes(15),
+            energy_from_battery_wh: 50_000.0,
+            energy_to_battery_wh: 0.0,
+        });
+
+        let report = reconcile(&plan, &actual, &make_prices(&[0.10, 0.30, 0.30]), &settings);
+
+        assert_eq!(report.intervals.len(), 2);
+        let first = &report.intervals[0];
+        assert_eq!(first.planned_to_battery_wh, 375_000.0);
+        assert_eq!(first.actual_to_battery_wh, 300_000.0);
+        assert_eq!(first.to_battery_difference_wh, -75_000.0);
+        // 75 kWh not bought at 0.10 EUR/kWh
+        assert!((first.cost_impact_eur + 7.5).abs() < 1e-9);
+        assert_eq!(report.intervals[1].from_battery_difference_wh, 0.0);
+        assert_eq!(report.total_to_battery_difference_wh, -75_000.0);
+        assert!((report.total_cost_impact_eur + 7.5).abs() < 1e-9);
+        assert_eq!(report.unmetered, vec![plan[2].start]);
+        assert_eq!(report.unplanned, vec![plan[2].end]);
+    }
+
+    #[test]
+    fn test_grid_export_limit_curtails_surplus() {
+        let mut settings = load_settings();
+        settings.grid_export_limit = 1_000_000.0;
+        let mut battery = initialize_battery();
+        battery.charge = 2.9;
+
+        // 3 MW of surplus, then 0.5 MW once the battery is full, in W
+        let mut forecasts = make_forecasts(&[0.0, 0.0]);
+        forecasts[0].production_average_power_interval = Some(3_000_000.0);
+        forecasts[1].production_average_power_interval = Some(500_000.0);
+        let prices = make_prices(&[0.10, 0.10]);
+
+        let plan =
+            plan_battery_usage(forecasts, prices, &mut battery, &settings, 0.25, None).unwrap();
+
+        // Only 0.1 MWh fits in the battery, drawing 0.1 / 0.9 MWh of the surplus
+        let stored_wh = 100_000.0 / 0.9;
+        assert!((plan[0].energy_from_surplus_wh - stored_wh).abs() < 1e-6);
+        let surplus_wh = 3.0 * 0.25 * WH_PER_MWH;
+        let curtailed_wh = plan[0].energy_pv_curtailed_wh.unwrap();
+        assert!((curtailed_wh - (surplus_wh - stored_wh - 250_000.0)).abs() < 1e-6);
+        // What is left is exported, and never more than 1 MW for the quarter hour
+        let exported_wh = surplus_wh - stored_wh - curtailed_wh;
+        assert!(exported_wh <= 250_000.0 + 1e-6);
+
+        // Below the export limit nothing is curtailed
+        assert_eq!(plan[1].energy_pv_curtailed_wh, None);
+    }
+
+    #[test]
+    fn test_optimal_plan_objectives() {
+        // A 1 MWh battery with losses that moves at most 0.25 MWh per 15 minutes
+        let mut settings = load_settings();
+        settings.capacity = 1.0;
+        settings.initial_charge = 0.0;
+        settings.max_rate = 1.0;
+        settings.charge_power_mw = 1.0;
+        settings.efficiency = Some(0.9);
+        let battery = || Battery::new(1.0, 0.0, 1.0, 1.0, 0.9, 0.0, 0.0);
+
+        // A cheap interval, a surplus of 1 MW, then an expensive 1 MW load
+        let mut forecasts = make_forecasts(&[1_000_000.0; 6]);
+        forecasts[1].production_average_power_interval = Some(2_000_000.0);
+        let values = [0.10, 0.30, 0.50, 0.50, 0.50, 0.50];
+
+        // Grid cost, grid import and battery throughput of a plan, all in kWh or EUR
+        let metrics = |plan: &[Plan]| {
+            let (mut cost, mut import, mut throughput) = (0.0, 0.0, 0.0);
+            for ((interval, forecast), price) in plan.iter().zip(&forecasts).zip(values) {
+                let import_wh = forecast.net_consumption() * 0.25 + interval.energy_to_battery_wh
+                    - interval.energy_from_battery_wh;
+                cost += import_wh / 1_000.0 * price;
+                import += import_wh.max(0.0) / 1_000.0;
+                throughput +=
+                    (interval.energy_to_battery_wh + interval.energy_from_battery_wh) / 1_000.0;
+            }
+            (cost, import, throughput)
+        };
+        let mut plan_for = |objective| {
+            settings.objective = objective;
+            let plan = plan_battery_usage_optimal(
+                forecasts.clone(),
+                make_prices(&values),
+                battery(),
+                &settings,
+                20,
+            )
+            .unwrap();
+            metrics(&plan)
+        };
+
+        let min_cost = plan_for(Objective::MinCost);
+        let max_self_sufficiency = plan_for(Objective::MaxSelfSufficiency);
+        let min_cycles = plan_for(Objective::MinCycles);
+
+        // Each objective wins on its own metric
+        assert!(min_cost.0 < max_self_sufficiency.0 && min_cost.0 < min_cycles.0);
+        assert!(max_self_sufficiency.1 < min_cost.1 && max_self_sufficiency.1 < min_cycles.1);
+        assert!(min_cycles.2 < min_cost.2 && min_cycles.2 < max_self_sufficiency.2);
+        // Nothing forces the battery to move without a peak over the grid limit
+        assert_eq!(min_cycles.2, 0.0);
+    }
+
+    #[test]
+    fn test_peek_matches_charge_and_discharge_without_mutating() {
+        // A nearly full battery, so the charge is limited by the room to full
+        let mut battery = initialize_battery();
+        battery.charge = 2.9;
+        let before = battery.snapshot();
+
+        let peeked = battery.peek_charge(1.5, 1.0).unwrap();
+        assert_eq!(battery.snapshot(), before);
+        let flow = battery.charge_battery(1.5, 1.0).unwrap();
+        assert_eq!(peeked.flow, flow);
+        assert_eq!(peeked.charge_mwh, battery.charge);
+        assert!((peeked.moved_mwh - 0.1).abs() < 1e-9);
+
+        // Discharging more than is available above the minimum charge
+        let before = battery.snapshot();
+        let peeked = battery.peek_discharge(1.5, 4.0).unwrap();
+        assert_eq!(battery.snapshot(), before);
+        let flow = battery.discharge_battery(1.5, 4.0).unwrap();
+        assert_eq!(peeked.flow, flow);
+        assert_eq!(peeked.charge_mwh, battery.charge);
+
+        // Negative powers are rejected without touching the battery either
+        assert!(battery.peek_charge(-1.0, 1.0).is_err());
+        assert!(battery.peek_discharge(-1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_parallel_scenarios_match_serial_baseline() {
+        let config = config::load_config("config.toml").unwrap();
+        let forecasts = make_forecasts(&[5_000_000.0, 9_000_000.0, 5_000_000.0, 8_500_000.0]);
+        let prices = make_prices(&[0.10, 0.30, 0.20, 0.60]);
+
+        let variations: Vec<ConfigOverride> = [
+            (None, None, None),
+            (Some(9_500_000.0), None, None),
+            (None, Some(0.5), None),
+            (None, None, Some(0.15)),
+            (Some(8_000_000.0), Some(1.0), Some(0.35)),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(
+            |(i, (grid_limit, charge_power_mw, charge_threshold))| ConfigOverride {
+                name: format!("scenario {}", i),
+                grid_limit,
+                charge_power_mw,
+                charge_threshold,
+                sell_threshold_per_kwh: None,
+            },
+        )
+        .collect();
+
+        let serial: Vec<_> = variations
+            .iter()
+            .map(|variation| evaluate_scenario(&config, variation, &forecasts, &prices).unwrap())
+            .collect();
+        let parallel = evaluate_scenarios(&config, variations, &forecasts, &prices).unwrap();
+
+        assert_eq!(parallel, serial);
+        // The variations lead to different plans, each from a fresh battery
+        assert!(parallel[0].total_savings_eur != parallel[1].total_savings_eur);
+        assert!(parallel.iter().all(|result| result.cycles < 1.0));
+    }
+
+    #[test]
+    fn test_build_timeline_from_aligned_inputs() {
+        let mut forecasts = make_forecasts(&[5_000_000.0, 9_000_000.0, 5_000_000.0]);
+        forecasts[2].production_average_power_interval = Some(1_000_000.0);
+        // Prices in any order, and one beyond the forecasts
+        let mut prices = make_prices(&[0.10, 0.30, 0.20, 0.60]);
+        prices.reverse();
+
+        let timeline = build_timeline(&forecasts, &prices).unwrap();
+        assert_eq!(timeline.len(), 3);
+        for (interval, forecast) in timeline.iter().zip(&forecasts) {
+            assert_eq!(
+                (interval.start, interval.end),
+                (forecast.start, forecast.end)
+            );
+        }
+        assert_eq!(
+            timeline.iter().map(|i| i.price).collect::<Vec<_>>(),
+            [0.10, 0.30, 0.20]
+        );
+        assert_eq!(timeline[1].consumption, 9_000_000.0);
+        assert_eq!(timeline[2].production, Some(1_000_000.0));
+
+        // Planning the timeline plans the same as the separate inputs
+        let settings = load_settings();
+        let from_timeline =
+            plan_from_timeline(&timeline, &mut initialize_battery(), &settings).unwrap();
+        let prices = make_prices(&[0.10, 0.30, 0.20]);
+        let threshold = charge_threshold(&prices, &settings);
+        let from_inputs = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            threshold,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_timeline).unwrap(),
+            serde_json::to_value(&from_inputs).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_timeline_rejects_misaligned_inputs() {
+        let forecasts = make_forecasts(&[5_000_000.0, 5_000_000.0]);
+
+        // A price shifted by five minutes matches no forecast
+        let mut prices = make_prices(&[0.10, 0.30]);
+        prices[1].start += Duration::minutes(5);
+        prices[1].end += Duration::minutes(5);
+        let message = build_timeline(&forecasts, &prices)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            message.contains("No price for 1 forecast interval(s)"),
+            "{}",
+            message
+        );
+
+        // Forecasts out of order
+        let mut shuffled = make_forecasts(&[5_000_000.0, 5_000_000.0]);
+        shuffled.reverse();
+        let message = build_timeline(&shuffled, &make_prices(&[0.10, 0.30]))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(message.contains("overlaps or precedes"), "{}", message);
+    }
+
+    #[test]
+    fn test_snap_to_grid_rounds_to_nearest_boundary() {
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        assert_eq!(
+            snap_to_grid(at("2022-12-13T00:00:03Z"), 15),
+            at("2022-12-13T00:00:00Z")
+        );
+        assert_eq!(
+            snap_to_grid(at("2022-12-13T00:14:58Z"), 15),
+            at("2022-12-13T00:15:00Z")
+        );
+        assert_eq!(
+            snap_to_grid(at("2022-12-12T23:59:59.500Z"), 60),
+            at("2022-12-13T00:00:00Z")
+        );
+        // Exactly halfway rounds up
+        assert_eq!(
+            snap_to_grid(at("2022-12-13T00:07:30Z"), 15),
+            at("2022-12-13T00:15:00Z")
+        );
+        assert_eq!(
+            snap_to_grid(at("2022-12-13T00:15:00Z"), 15),
+            at("2022-12-13T00:15:00Z")
+        );
+    }
+
+    #[test]
+    fn test_snapped_timestamps_align() {
+        // Prices a few seconds off the hour, forecasts a few seconds off the quarter hour
+        let json = r#"{"prices": [
+            {"start": "2022-12-12T23:00:03Z", "end": "2022-12-13T00:00:02Z", "market_price_currency": "EUR", "market_price_per_kwh": 0.10},
+            {"start": "2022-12-13T00:00:02Z", "end": "2022-12-13T00:59:58Z", "market_price_currency": "EUR", "market_price_per_kwh": 0.30}
+        ]}"#;
+        let load = |snap_minutes| {
+            load_day_ahead_prices_reader_with_mapping(
+                json.as_bytes(),
+                &HashMap::new(),
+                Some("EUR"),
+                &HashMap::new(),
+                DEFAULT_INTERVAL_MINUTES,
+                snap_minutes,
+            )
+        };
+        let mut forecasts = make_forecasts(&[5_000_000.0; 8]);
+        for forecast in &mut forecasts {
+            forecast.start += Duration::seconds(2);
+            forecast.end -= Duration::seconds(1);
+        }
+
+        // Without snapping the prices cannot be split into quarter hours
+        assert!(load(None).is_err());
+        let (prices, _) = load(Some(15)).unwrap();
+        assert_eq!(prices.prices.len(), 8);
+        assert!(build_timeline(&forecasts, &prices.prices).is_err());
+
+        snap_forecasts(&mut forecasts, 15);
+        let timeline = build_timeline(&forecasts, &prices.prices).unwrap();
+        assert_eq!(timeline.len(), 8);
+        assert_eq!(timeline[0].start, series_start());
+        assert_eq!(timeline[4].price, 0.30);
+    }
+
+    #[test]
+    fn test_cloned_battery_is_independent() {
+        let original = initialize_battery();
+        let mut clone = original.clone();
+        assert_eq!(clone, original);
+
+        clone.charge_battery(1.5, 1.0).unwrap();
+        assert_ne!(clone, original);
+        assert_eq!(original.charge, 1.5);
+        assert_eq!(original.cycles, 0.0);
+        assert_eq!(original, initialize_battery());
+    }
+
+    #[test]
+    fn test_battery_debug_format() {
+        let battery = Battery::new(3.0, 1.5, 1.5, 0.9, 0.9, 0.0, 0.0);
+        assert_eq!(
+            format!("{:?}", battery),
+            "Battery { capacity: 3.0, initial_charge: 1.5, charge: 1.5, max_charge_rate: 1.5, \
+             max_discharge_rate: 1.5, charge_efficiency: 0.9, discharge_efficiency: 0.9, \
+             min_charge: 0.0, self_discharge_rate_per_day: 0.0, cycles: 0.0 }"
+        );
+    }
+
+    #[test]
+    fn test_start_charge_overrides_initial_charge() {
+        let settings = load_settings();
+        let forecasts = make_forecasts(&[5_000_000.0]);
+        let prices = make_prices(&[0.10]);
+
+        // Starting full, the cheap interval has nothing left to charge
+        let mut battery = initialize_battery();
+        battery.set_start_charge(3.0).unwrap();
+        assert_eq!(battery.charge, 3.0);
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut battery,
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert_eq!(plan[0].energy_to_battery_wh, 0.0);
+
+        // `reset` returns to the start charge rather than the configured one
+        battery.reset();
+        assert_eq!(battery.charge, 3.0);
+
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert!(plan[0].energy_to_battery_wh > 0.0);
+
+        // Out of range values are rejected and leave the battery untouched
+        let mut battery = Battery::builder()
+            .capacity(3.0)
+            .initial_charge(1.5)
+            .max_rate(1.5)
+            .charge_efficiency(0.9)
+            .discharge_efficiency(0.9)
+            .min_charge(0.5)
+            .build()
+            .unwrap();
+        for start_charge in [0.2, 3.5, f64::NAN] {
+            assert!(matches!(
+                battery.set_start_charge(start_charge),
+                Err(BatteryError::InvalidProperty {
+                    property: "start_charge",
+                    ..
+                })
+            ));
+        }
+        assert_eq!(battery.charge, 1.5);
+
+        let cli = Cli::try_parse_from(["battery", "--start-charge", "2.25"]).unwrap();
+        assert_eq!(cli.start_charge, Some(2.25));
+        assert_eq!(Cli::try_parse_from(["battery"]).unwrap().start_charge, None);
+    }
+
+    #[test]
+    fn test_simultaneous_charge_and_discharge_is_rejected() {
+        let valid = make_plan(&[(0.0, 1_000.0), (500.0, 0.0), (0.0, 0.0)]);
+        assert!(check_simultaneous_flows(&valid).is_ok());
+        assert!(simulate_plan(&mut initialize_battery(), &valid).is_ok());
+
+        // Exporting counts as discharging, so it cannot coincide with charging either
+        let mut contradictory = make_plan(&[(0.0, 1_000.0), (500.0, 250.0)]);
+        let message = check_simultaneous_flows(&contradictory)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("2022-12-12 23:15:00 UTC"), "{}", message);
+        contradictory[1].energy_from_battery_wh = 0.0;
+        contradictory[1].energy_to_grid_wh = 500.0;
+        assert!(check_simultaneous_flows(&contradictory).is_err());
+
+        assert!(simulate_plan(&mut initialize_battery(), &contradictory).is_err());
+        let temp_file = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        assert!(save_plan(contradictory, path, EnergyUnit::Wh).is_err());
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+        save_plan(valid, path, EnergyUnit::Wh).unwrap();
+    }
+
+    #[test]
+    fn test_aggregate_plan_by_day_and_month() {
+        // The plan starts at 23:00, so its first day only has one hour
+        let mut energies = vec![(0.0, 1_000_000.0); 4];
+        energies.extend(vec![(50_000.0, 0.0); 96]);
+        let mut plan = make_plan(&energies);
+        for interval in &mut plan {
+            if interval.energy_to_battery_wh > 0.0 {
+                interval.grid_cost_eur = 10.0;
+            } else {
+                interval.battery_revenue_eur = 2.0;
+            }
+        }
+        let mut price_values = vec![0.1; 4];
+        price_values.extend(vec![0.2; 96]);
+        let prices = make_prices(&price_values);
+
+        // Cycles count the energy in the pack: 90% of what is charged from the grid,
+        // and what is discharged divided by the 90% efficiency
+        let settings = load_settings();
+        let days = aggregate_by_day(&plan, &prices, &settings);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, NaiveDate::from_ymd_opt(2022, 12, 12).unwrap());
+        assert_eq!(days[0].totals.intervals, 4);
+        assert_eq!(days[0].totals.hours, 1.0);
+        assert_eq!(days[0].totals.energy_charged_wh, 4_000_000.0);
+        assert_eq!(days[0].totals.energy_discharged_wh, 0.0);
+        assert_eq!(days[0].totals.cost_eur, 40.0);
+        assert_eq!(days[0].totals.savings_eur, -40.0);
+        assert!((days[0].totals.cycles - 0.6).abs() < 1e-9);
+        assert_eq!(days[0].totals.average_price_per_kwh, Some(0.1));
+
+        assert_eq!(days[1].date, NaiveDate::from_ymd_opt(2022, 12, 13).unwrap());
+        assert_eq!(days[1].totals.intervals, 96);
+        assert_eq!(days[1].totals.hours, 24.0);
+        assert_eq!(days[1].totals.energy_discharged_wh, 4_800_000.0);
+        assert_eq!(days[1].totals.cost_eur, 0.0);
+        assert!((days[1].totals.savings_eur - 192.0).abs() < 1e-9);
+        assert!((days[1].totals.cycles - 0.8 / 0.9).abs() < 1e-9);
+
+        // Both days fall in December, so the month is the overall rollup
+        let months = aggregate_by_month(&plan, &prices, &settings);
+        assert_eq!(months.len(), 1);
+        assert_eq!((months[0].year, months[0].month), (2022, 12));
+        let month = &months[0].totals;
+        assert_eq!(month.intervals, 100);
+        assert_eq!(month.hours, 25.0);
+        assert_eq!(
+            month.energy_charged_wh + month.energy_discharged_wh,
+            days.iter()
+                .map(|day| day.totals.energy_charged_wh + day.totals.energy_discharged_wh)
+                .sum::<f64>()
+        );
+        let totals = plan_totals(&plan);
+        assert!((month.cost_eur - totals.total_cost_eur).abs() < 1e-9);
+        assert!((month.savings_eur - totals.total_savings_eur).abs() < 1e-9);
+        assert!((month.cycles - (0.6 + 0.8 / 0.9)).abs() < 1e-9);
+        assert!((month.average_price_per_kwh.unwrap() - 0.196).abs() < 1e-9);
+
+        // Serialized, the totals sit next to the period
+        let json = serde_json::to_value(&days[0]).unwrap();
+        assert_eq!(json["date"], "2022-12-12");
+        assert_eq!(json["intervals"], 4);
+
+        // Intervals without a price leave the average out
+        assert_eq!(
+            aggregate_by_day(&plan, &[], &settings)[0]
+                .totals
+                .average_price_per_kwh,
+            None
+        );
+    }
+
+    #[test]
+    fn test_charge_strategies() {
+        let mut settings = load_settings();
+        let prices = make_prices(&[0.10, 0.20, 0.30, 0.40, 0.50]);
+
+        // Below the average, below a percentile, and the same via charge_percentile
+        assert!((charge_threshold(&prices, &settings) - 0.30).abs() < 1e-9);
+        settings.charge_strategy = ChargeStrategy::BelowPercentile(0.4);
+        let percentile_threshold = charge_threshold(&prices, &settings);
+        assert_eq!(percentile_threshold, price_percentile(&prices, 0.4));
+        settings.charge_strategy = ChargeStrategy::BelowAverage;
+        settings.charge_percentile = Some(0.4);
+        assert_eq!(charge_threshold(&prices, &settings), percentile_threshold);
+        settings.charge_percentile = None;
+
+        // A window across midnight, from 23:30 to 00:15 UTC; the plan starts at 23:00
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        settings.charge_strategy = ChargeStrategy::FixedWindows(vec![(time(23, 30), time(0, 15))]);
+        settings.explain = true;
+        let forecasts = make_forecasts(&[5_000_000.0; 6]);
+        // The cheapest and the negative price fall outside the window
+        let prices = make_prices(&[0.01, -0.05, 0.30, 0.30, 0.30, 0.30]);
+        let threshold = charge_threshold(&prices, &settings);
+        let plan = plan_battery_usage(
+            forecasts,
+            prices,
+            &mut Battery::new(10.0, 0.0, 1.5, 0.9, 0.9, 0.0, 0.0),
+            &settings,
+            threshold,
+            None,
+        )
+        .unwrap();
+        let charging: Vec<bool> = plan.iter().map(|p| p.energy_to_battery_wh > 0.0).collect();
+        assert_eq!(charging, [false, false, true, true, true, false]);
+        assert_eq!(
+            plan[2].reason.as_deref(),
+            Some("time 23:30:00 in a charge window, charged 1.5 MW")
+        );
+        assert_eq!(
+            plan[0].reason.as_deref(),
+            Some("time 23:00:00 outside the charge windows, idle")
+        );
+
+        // The strategies are read from the configuration and validated
+        let temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let base = r#"
+        [settings]
+        capacity = 3.0
+        initial_charge = 1.5
+        max_rate = 1.5
+        efficiency = 0.9
+        grid_limit = 7800000.0
+        "#;
+        let load = |extra: &str| {
+            fs::write(temp_file.path(), format!("{}{}", base, extra)).unwrap();
+            config::load_config(temp_file.path().to_str().unwrap())
+        };
+        assert_eq!(
+            load("").unwrap().settings.charge_strategy,
+            ChargeStrategy::BelowAverage
+        );
+        assert_eq!(
+            load("charge_strategy = { below_percentile = 0.3 }")
+                .unwrap()
+                .settings
+                .charge_strategy,
+            ChargeStrategy::BelowPercentile(0.3)
+        );
+        assert_eq!(
+            load(r#"charge_strategy = { fixed_windows = [["22:00:00", "06:00:00"]] }"#)
+                .unwrap()
+                .settings
+                .charge_strategy,
+            ChargeStrategy::FixedWindows(vec![(time(22, 0), time(6, 0))])
+        );
+        for invalid in [
+            "charge_strategy = { below_percentile = 1.5 }",
+            "charge_strategy = { fixed_windows = [] }",
+            r#"charge_strategy = { fixed_windows = [["22:00:00", "22:00:00"]] }"#,
+            "charge_strategy = { below_percentile = 0.3 }\ncharge_percentile = 0.3",
+        ] {
+            let message = format!("{:#}", load(invalid).unwrap_err());
+            assert!(message.contains("`charge_strategy`"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_efficiency_math_matches_charge_and_discharge() {
+        assert_eq!(grid_energy_for_stored(0.9, 0.9), 1.0);
+        assert_eq!(delivered_for_drawn(1.0, 0.9), 0.9);
+
+        // A charge that fills the battery draws exactly the grid energy for what it stored
+        let mut battery = Battery::new(3.0, 2.5, 1.5, 0.9, 0.8, 0.0, 0.0);
+        let flow = battery.charge_battery(1.5, 1.0).unwrap();
+        let stored = to_mwh(flow.battery_wh, EnergyUnit::Wh);
+        assert!((stored - 0.5).abs() < 1e-12);
+        assert_eq!(
+            flow.grid_wh,
+            convert(grid_energy_for_stored(stored, 0.9), EnergyUnit::Wh)
+        );
+
+        // A discharge that empties the battery delivers exactly what it drew, after losses
+        let mut battery = Battery::new(3.0, 0.5, 1.5, 0.9, 0.8, 0.0, 0.0);
+        let flow = battery.discharge_battery(1.5, 1.0).unwrap();
+        assert_eq!(
+            flow.grid_wh,
+            convert(delivered_for_drawn(0.5, 0.8), EnergyUnit::Wh)
+        );
+        assert_eq!(flow.battery_wh, convert(0.5, EnergyUnit::Wh));
+
+        // Unconstrained, the two directions are the inverse of the efficiency losses
+        let mut battery = Battery::new(3.0, 1.5, 1.5, 0.9, 0.8, 0.0, 0.0);
+        let charged = battery.charge_battery(1.0, 0.5).unwrap();
+        assert!(
+            (charged.grid_wh
+                - grid_energy_for_stored(charged.battery_wh, battery.charge_efficiency()))
+            .abs()
+                < 1e-6
+        );
+        let discharged = battery.discharge_battery(1.0, 0.5).unwrap();
+        assert!(
+            (discharged.grid_wh
+                - delivered_for_drawn(discharged.battery_wh, battery.discharge_efficiency()))
+            .abs()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn test_partial_discharge_records_requested_energy() {
+        let settings = load_settings();
+        // 9 MW against the 7.8 MW grid limit for 15 minutes: 300 kWh over
+        let forecasts = make_forecasts(&[9_000_000.0, 5_000_000.0]);
+        let prices = make_prices(&[0.30, 0.30]);
+
+        // A battery with 0.1 MWh above the floor delivers 90 kWh of it
+        let mut battery = Battery::new(3.0, 0.1, 1.5, 0.9, 0.9, 0.0, 0.0);
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut battery,
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        let requested = plan[0].requested_discharge_wh.unwrap();
+        assert!((requested - 300_000.0).abs() < 1e-6);
+        assert!(requested > plan[0].energy_from_battery_wh);
+        assert!((plan[0].energy_from_battery_wh - 90_000.0).abs() < 1e-6);
+
+        // A charged battery covers the excess exactly, at 1.2 MW
+        let plan = plan_battery_usage(
+            forecasts.clone(),
+            prices.clone(),
+            &mut initialize_battery(),
+            &settings,
+            0.25,
+            None,
+        )
+        .unwrap();
+        assert!((plan[0].energy_from_battery_wh - 300_000.0).abs() < 1e-6);
+        assert_eq!(plan[0].requested_discharge_wh, Some(300_000.0));
+
+        // Intervals below the limit request nothing, and leave the field out of the output
+        assert_eq!(plan[1].requested_discharge_wh, None);
+        let json = serde_json::to_value(&plan[1]).unwrap();
+        assert!(json.get("requested_discharge_wh").is_none());
+    }
+
+    #[test]
+    fn test_chemistry_preset_fills_in_defaults() {
+        let temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        let base = r#"
+        [settings]
+        capacity = 3.0
+        initial_charge = 1.5
+        max_rate = 1.5
+        grid_limit = 7800000.0
+        "#;
+        let load = |extra: &str| {
+            fs::write(temp_file.path(), format!("{}{}", base, extra)).unwrap();
+            config::load_config(temp_file.path().to_str().unwrap())
+        };
+
+        // The preset stands in for the efficiency the configuration would otherwise need
+        let settings = load(r#"chemistry = "lead_acid""#).unwrap().settings;
+        assert_eq!(settings.chemistry, Some(Chemistry::LeadAcid));
+        assert_eq!(settings.efficiency, Some(0.85));
+        assert_eq!(settings.min_soc, 0.5);
+        assert_eq!(settings.max_soc, 1.0);
+        assert_eq!(settings.self_discharge_rate_per_day, 0.0017);
+
+        // Settings given explicitly win over the preset, even when they equal serde's default
+        let settings = load("chemistry = \"lifepo4\"\nefficiency = 0.9\nmin_soc = 0.0")
+            .unwrap()
+            .settings;
+        assert_eq!(settings.efficiency, Some(0.9));
+        assert_eq!(settings.min_soc, 0.0);
+        assert_eq!(settings.max_soc, Chemistry::LiFePo4.preset().max_soc);
+        assert_eq!(settings.self_discharge_rate_per_day, 0.001);
+
+        assert!(load(r#"chemistry = "nickel_iron""#).is_err());
+        // Without a preset, the efficiency is still required
+        assert!(load("").is_err());
+
+        // YAML configurations get the same preset
+        let yaml_file = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+        fs::write(
+            yaml_file.path(),
+            "settings:\n  capacity: 3.0\n  initial_charge: 1.5\n  max_rate: 1.5\n  grid_limit: 7800000.0\n  chemistry: NMC\n  max_soc: 0.8\n",
+        )
+        .unwrap();
+        let settings = config::load_config(yaml_file.path().to_str().unwrap())
+            .unwrap()
+            .settings;
+        assert_eq!(settings.chemistry, Some(Chemistry::Nmc));
+        assert_eq!(settings.efficiency, Some(0.95));
+        assert_eq!(settings.max_soc, 0.8);
+    }
+
+    #[test]
+    fn test_grid_export_limit_throttles_export() {
+        let mut settings = load_settings();
+        settings.sell_threshold_per_kwh = Some(0.50);
+        settings.grid_export_limit = 500_000.0;
+
+        // A 0.3 MW load absorbs part of the export, leaving 0.8 MW under the limit
+        let plan = plan_battery_usage(
+            make_forecasts(&[300_000.0]),
+            make_prices(&[0.60]),
+            &mut initialize_battery(),
+            &settings,
+            0.05,
+            None,
+        )
+        .unwrap();
+
+        assert!((plan[0].energy_to_grid_wh - 200_000.0).abs() < 1e-6);
+    }
 }
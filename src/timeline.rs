@@ -0,0 +1,133 @@
+use crate::battery::EnergyStorage;
+use crate::config::Settings;
+use crate::forecast::Forecast;
+use crate::planning::{align_prices, charge_threshold, plan_battery_usage, Plan};
+use crate::prices::ElectricityPrice;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// One planning interval with its forecast and price joined, so the two can no
+/// longer drift apart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineInterval {
+    /// Start time of the interval.
+    pub start: DateTime<Utc>,
+    /// End time of the interval.
+    pub end: DateTime<Utc>,
+    /// Average power consumption during the interval in MW.
+    pub consumption: f64,
+    /// 90th percentile of the consumption in MW, if the forecast has one.
+    pub consumption_p90: Option<f64>,
+    /// Average on-site production during the interval in MW, if any.
+    pub production: Option<f64>,
+    /// Forecasted ambient temperature in degrees Celsius, if known.
+    pub temperature_c: Option<f64>,
+    /// Market price of electricity per kWh, in the currency of the prices.
+    pub price: f64,
+}
+
+impl TimelineInterval {
+    /// Returns the forecast of the interval.
+    pub fn forecast(&self) -> Forecast {
+        Forecast {
+            start: self.start,
+            end: self.end,
+            consumption_average_power_interval: self.consumption,
+            consumption_p90: self.consumption_p90,
+            production_average_power_interval: self.production,
+            temperature_c: self.temperature_c,
+        }
+    }
+
+    /// Returns the price of the interval, in the given currency.
+    pub fn electricity_price(&self, currency: &str) -> ElectricityPrice {
+        ElectricityPrice {
+            start: self.start,
+            end: self.end,
+            market_price_currency: currency.to_string(),
+            market_price_per_kwh: self.price,
+            is_fallback: false,
+            source: Default::default(),
+        }
+    }
+}
+
+/// Joins forecasts and prices into a single timeline, matching each forecast with
+/// the price of the same interval.
+///
+/// Prices outside the forecasts are left out, as the timeline follows the forecasts.
+///
+/// # Arguments
+///
+/// * `forecasts`: The forecasts, at the planning resolution and in chronological order.
+/// * `prices`: The electricity prices, at the same resolution, in any order.
+///
+/// # Returns
+/// A `Result` containing one `TimelineInterval` per forecast, in forecast order.
+///
+/// # Errors
+/// Returns an error if the forecasts overlap or are out of order, or listing every
+/// forecast interval without a price for the same start and end time.
+pub fn build_timeline(
+    forecasts: &[Forecast],
+    prices: &[ElectricityPrice],
+) -> Result<Vec<TimelineInterval>> {
+    if let Some(pair) = forecasts
+        .windows(2)
+        .find(|pair| pair[1].start < pair[0].end)
+    {
+        return Err(anyhow!(
+            "Forecast interval starting at {} overlaps or precedes the one ending at {}",
+            pair[1].start,
+            pair[0].end
+        ));
+    }
+
+    let aligned = align_prices(forecasts, prices)?;
+    Ok(forecasts
+        .iter()
+        .zip(aligned)
+        .map(|(forecast, price)| TimelineInterval {
+            start: forecast.start,
+            end: forecast.end,
+            consumption: forecast.consumption_average_power_interval,
+            consumption_p90: forecast.consumption_p90,
+            production: forecast.production_average_power_interval,
+            temperature_c: forecast.temperature_c,
+            price: price.market_price_per_kwh,
+        })
+        .collect())
+}
+
+/// Plans the battery usage over a timeline, the same way `plan_battery_usage`
+/// plans the forecasts and prices it was built from.
+///
+/// The charge threshold is taken from the timeline's prices, as with
+/// `charge_threshold`, and the carbon strategy is not supported.
+///
+/// # Arguments
+///
+/// * `timeline`: The joined forecasts and prices, in the reporting currency.
+/// * `battery`: The battery, or a `BatteryBank` of several packs, in its initial state.
+/// * `settings`: The configuration settings.
+///
+/// # Returns
+/// A `Result` containing one `Plan` per timeline interval.
+///
+/// # Errors
+/// Returns an error if the timeline is empty or planning fails.
+pub fn plan_from_timeline<B: EnergyStorage>(
+    timeline: &[TimelineInterval],
+    battery: &mut B,
+    settings: &Settings,
+) -> Result<Vec<Plan>> {
+    let forecasts: Vec<Forecast> = timeline.iter().map(TimelineInterval::forecast).collect();
+    let prices: Vec<ElectricityPrice> = timeline
+        .iter()
+        .map(|interval| interval.electricity_price(&settings.reporting_currency))
+        .collect();
+    let threshold = charge_threshold(&prices, settings);
+
+    plan_battery_usage(forecasts, prices, battery, settings, threshold, None)
+}
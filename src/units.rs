@@ -0,0 +1,123 @@
+use crate::battery::WH_PER_MWH;
+use serde::Deserialize;
+
+/// Number of watts in a megawatt.
+pub const W_PER_MW: f64 = 1_000_000.0;
+
+/// Converts a power from watts, the unit of forecasts and grid limits, to megawatts,
+/// the unit of the battery's rates.
+///
+/// # Arguments
+///
+/// * `power_w`: The power in W.
+///
+/// # Returns
+/// The power in MW.
+pub fn w_to_mw(power_w: f64) -> f64 {
+    power_w / W_PER_MW
+}
+
+/// A unit of energy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnergyUnit {
+    /// Watt-hours, the unit of the plan's energy fields.
+    #[default]
+    Wh,
+    /// Kilowatt-hours, the unit prices are quoted in.
+    Kwh,
+    /// Megawatt-hours, the unit the battery tracks its charge in.
+    Mwh,
+}
+
+impl EnergyUnit {
+    /// Returns the number of units in one megawatt-hour.
+    pub fn per_mwh(self) -> f64 {
+        match self {
+            EnergyUnit::Wh => WH_PER_MWH,
+            EnergyUnit::Kwh => 1_000.0,
+            EnergyUnit::Mwh => 1.0,
+        }
+    }
+
+    /// Returns the suffix used in field names for this unit, e.g. `kwh`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            EnergyUnit::Wh => "wh",
+            EnergyUnit::Kwh => "kwh",
+            EnergyUnit::Mwh => "mwh",
+        }
+    }
+}
+
+/// Converts an energy from megawatt-hours to the given unit.
+///
+/// # Arguments
+///
+/// * `value_mwh`: The energy in MWh.
+/// * `to`: The unit to convert to.
+///
+/// # Returns
+/// The energy in `to`.
+pub fn convert(value_mwh: f64, to: EnergyUnit) -> f64 {
+    value_mwh * to.per_mwh()
+}
+
+/// Converts an energy from the given unit to megawatt-hours.
+///
+/// # Arguments
+///
+/// * `value`: The energy in `from`.
+/// * `from`: The unit of `value`.
+///
+/// # Returns
+/// The energy in MWh.
+pub fn to_mwh(value: f64, from: EnergyUnit) -> f64 {
+    value / from.per_mwh()
+}
+
+/// Rounds a value to a number of decimals, rounding halfway cases to the nearest
+/// even digit so that repeated runs and sums do not drift upwards.
+///
+/// # Arguments
+///
+/// * `value`: The value to round.
+/// * `decimals`: The number of decimals to keep.
+///
+/// # Returns
+/// The rounded value.
+pub fn round_half_even(value: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals as i32);
+    (value * factor).round_ties_even() / factor
+}
+
+/// Converts an energy from megawatt-hours to watt-hours, rounded half to even.
+///
+/// # Arguments
+///
+/// * `mwh`: The energy in MWh.
+/// * `decimals`: The number of decimals of the result, in Wh.
+///
+/// # Returns
+/// The energy in Wh.
+pub fn round_wh(mwh: f64, decimals: u32) -> f64 {
+    round_half_even(convert(mwh, EnergyUnit::Wh), decimals)
+}
+
+/// Converts an energy in watt-hours to the given unit.
+///
+/// Watt-hours are returned unchanged, so converting to `Wh` is exact.
+///
+/// # Arguments
+///
+/// * `value_wh`: The energy in Wh.
+/// * `to`: The unit to convert to.
+///
+/// # Returns
+/// The energy in `to`.
+pub fn from_wh(value_wh: f64, to: EnergyUnit) -> f64 {
+    match to {
+        EnergyUnit::Wh => value_wh,
+        _ => convert(to_mwh(value_wh, EnergyUnit::Wh), to),
+    }
+}
@@ -0,0 +1,178 @@
+use crate::config::Config;
+use crate::forecast::Forecasts;
+use crate::prices::DayAheadPrices;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use log::info;
+use std::collections::HashMap;
+
+/// Checks that the configuration, forecasts and prices can be planned together,
+/// without planning them.
+///
+/// The checks cover:
+/// - the settings, as when the configuration is loaded;
+/// - the horizon: both inputs are non-empty, and the prices cover the forecasts;
+/// - the timestamps: every interval lasts `interval_minutes`, starts on that grid,
+///   follows the previous one without a gap or overlap, and every forecast has a
+///   price for the same interval;
+/// - the values: consumption, its P90 and production are finite and non-negative, and prices
+///   are finite and in the reporting currency.
+///
+/// # Arguments
+///
+/// * `config`: The configuration.
+/// * `forecasts`: The forecasts, at the planning resolution.
+/// * `prices`: The day-ahead prices, converted to the planning resolution.
+///
+/// # Returns
+/// A `Result` indicating whether the inputs are consistent.
+///
+/// # Errors
+/// Returns an error listing every problem found, one per line.
+pub fn validate_inputs(
+    config: &Config,
+    forecasts: &Forecasts,
+    prices: &DayAheadPrices,
+) -> Result<()> {
+    let settings = &config.settings;
+    let interval = Duration::minutes(settings.interval_minutes);
+    let mut problems = Vec::new();
+
+    if let Err(error) = config.validate() {
+        problems.push(format!("Invalid configuration: {}", error));
+    }
+
+    // Horizon
+    let forecasts = &forecasts.forecasts;
+    let prices = &prices.prices;
+    if forecasts.is_empty() {
+        problems.push("No forecasts".to_string());
+    }
+    if prices.is_empty() {
+        problems.push("No prices".to_string());
+    }
+    if let (Some(first_forecast), Some(last_forecast), Some(first_price), Some(last_price)) = (
+        forecasts.first(),
+        forecasts.last(),
+        prices.first(),
+        prices.last(),
+    ) {
+        if first_price.start > first_forecast.start || last_price.end < last_forecast.end {
+            problems.push(format!(
+                "Prices cover {} to {}, but forecasts cover {} to {}",
+                first_price.start, last_price.end, first_forecast.start, last_forecast.end
+            ));
+        }
+    }
+
+    // Timestamps
+    let timeline = |name: &str, intervals: &[(DateTime<Utc>, DateTime<Utc>)]| {
+        let mut problems = Vec::new();
+        for (start, end) in intervals {
+            if *end - *start != interval {
+                problems.push(format!(
+                    "{} interval at {} lasts {} minutes instead of {}",
+                    name,
+                    start,
+                    (*end - *start).num_minutes(),
+                    settings.interval_minutes
+                ));
+            }
+            if start.minute() as i64 % settings.interval_minutes != 0 || start.second() != 0 {
+                problems.push(format!(
+                    "{} interval at {} does not start on a {}-minute boundary",
+                    name, start, settings.interval_minutes
+                ));
+            }
+        }
+        for pair in intervals.windows(2) {
+            if pair[0].1 != pair[1].0 {
+                problems.push(format!(
+                    "{} interval ending at {} is followed by one starting at {}",
+                    name, pair[0].1, pair[1].0
+                ));
+            }
+        }
+        problems
+    };
+    let forecast_intervals: Vec<_> = forecasts
+        .iter()
+        .map(|forecast| (forecast.start, forecast.end))
+        .collect();
+    let price_intervals: Vec<_> = prices
+        .iter()
+        .map(|price| (price.start, price.end))
+        .collect();
+    problems.extend(timeline("Forecast", &forecast_intervals));
+    problems.extend(timeline("Price", &price_intervals));
+
+    let price_ends: HashMap<DateTime<Utc>, DateTime<Utc>> = price_intervals.into_iter().collect();
+    let unpriced: Vec<String> = forecasts
+        .iter()
+        .filter(|forecast| price_ends.get(&forecast.start) != Some(&forecast.end))
+        .map(|forecast| forecast.start.to_string())
+        .collect();
+    if !unpriced.is_empty() {
+        problems.push(format!(
+            "No price for {} forecast interval(s), starting at {}",
+            unpriced.len(),
+            unpriced.join(", ")
+        ));
+    }
+
+    // Values
+    for forecast in forecasts {
+        let consumption = forecast.consumption_average_power_interval;
+        if !consumption.is_finite() || consumption < 0.0 {
+            problems.push(format!(
+                "Forecast at {} has an invalid consumption of {}",
+                forecast.start, consumption
+            ));
+        }
+        if let Some(p90) = forecast.consumption_p90 {
+            if !p90.is_finite() || p90 < 0.0 {
+                problems.push(format!(
+                    "Forecast at {} has an invalid P90 consumption of {}",
+                    forecast.start, p90
+                ));
+            }
+        }
+        if let Some(production) = forecast.production_average_power_interval {
+            if !production.is_finite() || production < 0.0 {
+                problems.push(format!(
+                    "Forecast at {} has an invalid production of {}",
+                    forecast.start, production
+                ));
+            }
+        }
+    }
+    for price in prices {
+        if !price.market_price_per_kwh.is_finite() {
+            problems.push(format!(
+                "Price at {} is not a number: {}",
+                price.start, price.market_price_per_kwh
+            ));
+        }
+        if price.market_price_currency != settings.reporting_currency {
+            problems.push(format!(
+                "Price at {} is in {} instead of {}",
+                price.start, price.market_price_currency, settings.reporting_currency
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "Found {} problem(s) in the inputs:\n{}",
+            problems.len(),
+            problems.join("\n")
+        ));
+    }
+
+    info!(
+        "Validated {} forecasts and {} prices",
+        forecasts.len(),
+        prices.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use battery::battery::Battery;
+use proptest::prelude::*;
+
+/// Slack for floating-point rounding, in MWh.
+const TOLERANCE_MWH: f64 = 1e-9;
+
+/// One step of a random operating sequence.
+#[derive(Debug, Clone)]
+enum Operation {
+    Charge { power_mw: f64, duration_hours: f64 },
+    Discharge { power_mw: f64, duration_hours: f64 },
+}
+
+/// Generates a valid battery: `(capacity, min_charge, initial_charge, max_rate, efficiency)`.
+fn battery_properties() -> impl Strategy<Value = (f64, f64, f64, f64, f64)> {
+    (
+        0.1..10.0f64,
+        0.0..0.5f64,
+        0.0..=1.0f64,
+        0.1..5.0f64,
+        0.5..=1.0f64,
+    )
+        .prop_map(
+            |(capacity, min_fraction, charge_fraction, max_rate, efficiency)| {
+                let min_charge = capacity * min_fraction;
+                let initial_charge = min_charge + (capacity - min_charge) * charge_fraction;
+                (capacity, min_charge, initial_charge, max_rate, efficiency)
+            },
+        )
+}
+
+fn operation() -> impl Strategy<Value = Operation> {
+    prop_oneof![
+        (0.0..10.0f64, 0.01..2.0f64).prop_map(|(power_mw, duration_hours)| Operation::Charge {
+            power_mw,
+            duration_hours
+        }),
+        (0.0..10.0f64, 0.01..2.0f64).prop_map(|(power_mw, duration_hours)| {
+            Operation::Discharge {
+                power_mw,
+                duration_hours,
+            }
+        }),
+    ]
+}
+
+fn build(
+    capacity: f64,
+    min_charge: f64,
+    initial_charge: f64,
+    max_rate: f64,
+    efficiency: f64,
+) -> Battery {
+    Battery::builder()
+        .capacity(capacity)
+        .initial_charge(initial_charge)
+        .max_rate(max_rate)
+        .efficiency(efficiency)
+        .min_charge(min_charge)
+        .build()
+        .unwrap()
+}
+
+proptest! {
+    // Keep the runs short enough for CI
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn charge_stays_within_bounds(
+        (capacity, min_charge, initial_charge, max_rate, efficiency) in battery_properties(),
+        operations in prop::collection::vec(operation(), 1..40),
+    ) {
+        let mut battery = build(capacity, min_charge, initial_charge, max_rate, efficiency);
+
+        for operation in operations {
+            let before = battery.charge;
+            let (flow, stored_sign) = match operation {
+                Operation::Charge { power_mw, duration_hours } => {
+                    (battery.charge_battery(power_mw, duration_hours).unwrap(), 1.0)
+                }
+                Operation::Discharge { power_mw, duration_hours } => {
+                    (battery.discharge_battery(power_mw, duration_hours).unwrap(), -1.0)
+                }
+            };
+
+            prop_assert!(flow.grid_wh >= 0.0, "Negative grid energy: {:?}", operation);
+            prop_assert!(flow.battery_wh >= 0.0, "Negative battery energy: {:?}", operation);
+            prop_assert!(battery.charge <= capacity + TOLERANCE_MWH);
+            prop_assert!(battery.charge >= min_charge - TOLERANCE_MWH);
+
+            // The change in charge is exactly the energy that went into or out of the pack
+            let change_wh = (battery.charge - before) * 1_000_000.0;
+            prop_assert!((change_wh - stored_sign * flow.battery_wh).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn round_trip_never_returns_more_than_put_in(
+        (capacity, min_charge, _, max_rate, efficiency) in battery_properties(),
+        power_mw in 0.0..10.0f64,
+        duration_hours in 0.01..2.0f64,
+    ) {
+        // Start empty, so everything delivered later was charged here
+        let mut battery = build(capacity, min_charge, min_charge, max_rate, efficiency);
+
+        let charged = battery.charge_battery(power_mw, duration_hours).unwrap();
+        let mut delivered_wh = 0.0;
+        while battery.energy_available() > TOLERANCE_MWH {
+            delivered_wh += battery.discharge_battery(max_rate, 1.0).unwrap().grid_wh;
+        }
+
+        let expected_wh = charged.grid_wh * battery.round_trip_efficiency();
+        prop_assert!(delivered_wh <= charged.grid_wh + 1e-3);
+        prop_assert!((delivered_wh - expected_wh).abs() < 1e-3);
+    }
+}
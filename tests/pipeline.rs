@@ -0,0 +1,224 @@
+//! End-to-end test of the CLI: configuration, forecasts and prices in, plan out.
+//!
+//! The plan is compared with the golden file `tests/golden/output_plan.json`. When
+//! the planner's behavior changes on purpose, regenerate the golden file with
+//!
+//! ```bash
+//! UPDATE_GOLDEN=1 cargo test --test pipeline
+//! ```
+//!
+//! and review the diff before committing it.
+
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Relative tolerance when comparing numbers, for floating-point differences between platforms.
+const RELATIVE_TOLERANCE: f64 = 1e-9;
+
+const CONFIG: &str = r#"
+[settings]
+capacity = 3.0
+initial_charge = 1.5
+max_rate = 1.5
+charge_power_mw = 1.5
+efficiency = 0.90
+grid_limit = 7800000.0
+min_charge = 0.0
+"#;
+
+/// Two hours of quarter-hour forecasts, with a peak above the grid limit in the second hour.
+const FORECASTS: &str = r#"{"forecasts": [
+    {"start": "2022-12-12T23:00:00Z", "end": "2022-12-12T23:15:00Z", "consumption_average_power_interval": 5000000.0},
+    {"start": "2022-12-12T23:15:00Z", "end": "2022-12-12T23:30:00Z", "consumption_average_power_interval": 5000000.0},
+    {"start": "2022-12-12T23:30:00Z", "end": "2022-12-12T23:45:00Z", "consumption_average_power_interval": 6000000.0},
+    {"start": "2022-12-12T23:45:00Z", "end": "2022-12-13T00:00:00Z", "consumption_average_power_interval": 6000000.0},
+    {"start": "2022-12-13T00:00:00Z", "end": "2022-12-13T00:15:00Z", "consumption_average_power_interval": 7000000.0},
+    {"start": "2022-12-13T00:15:00Z", "end": "2022-12-13T00:30:00Z", "consumption_average_power_interval": 8800000.0},
+    {"start": "2022-12-13T00:30:00Z", "end": "2022-12-13T00:45:00Z", "consumption_average_power_interval": 9300000.0},
+    {"start": "2022-12-13T00:45:00Z", "end": "2022-12-13T01:00:00Z", "consumption_average_power_interval": 7000000.0}
+]}"#;
+
+const PRICES: &str = r#"{"prices": [
+    {"start": "2022-12-12T23:00:00Z", "end": "2022-12-13T00:00:00Z", "market_price_currency": "EUR", "market_price_per_kwh": 0.10},
+    {"start": "2022-12-13T00:00:00Z", "end": "2022-12-13T01:00:00Z", "market_price_currency": "EUR", "market_price_per_kwh": 0.40}
+]}"#;
+
+/// Asserts that two JSON values are equal, with numbers compared up to `RELATIVE_TOLERANCE`.
+fn assert_json_matches(actual: &Value, expected: &Value, path: &str) {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+            assert!(
+                (a - b).abs() <= RELATIVE_TOLERANCE * a.abs().max(b.abs()).max(1.0),
+                "{}: {} differs from the golden {}",
+                path,
+                a,
+                b
+            );
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            assert_eq!(a.len(), b.len(), "{}: different lengths", path);
+            for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                assert_json_matches(a, b, &format!("{}[{}]", path, index));
+            }
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            let keys = |object: &serde_json::Map<String, Value>| {
+                object.keys().cloned().collect::<Vec<_>>()
+            };
+            assert_eq!(keys(a), keys(b), "{}: different fields", path);
+            for (key, value) in a {
+                assert_json_matches(value, &b[key], &format!("{}.{}", path, key));
+            }
+        }
+        _ => assert_eq!(actual, expected, "{}", path),
+    }
+}
+
+#[test]
+fn test_pipeline_matches_golden_output() {
+    let directory = tempfile::tempdir().unwrap();
+    for (name, contents) in [
+        ("config.toml", CONFIG),
+        ("forecasts.json", FORECASTS),
+        ("day-ahead.json", PRICES),
+    ] {
+        fs::write(directory.path().join(name), contents).unwrap();
+    }
+
+    // The CLI's default file names are relative to its working directory
+    let output = Command::new(env!("CARGO_BIN_EXE_battery"))
+        .current_dir(directory.path())
+        .arg("--quiet")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let plan = fs::read_to_string(directory.path().join("output_plan.json")).unwrap();
+
+    let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/output_plan.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, &plan).unwrap();
+    }
+    let golden = fs::read_to_string(&golden_path).unwrap_or_else(|error| {
+        panic!(
+            "Unable to read {}: {}; run with UPDATE_GOLDEN=1 to create it",
+            golden_path.display(),
+            error
+        )
+    });
+
+    let plan: Value = serde_json::from_str(&plan).unwrap();
+    let golden: Value = serde_json::from_str(&golden).unwrap();
+    assert_json_matches(&plan, &golden, "output_plan");
+}
+
+#[test]
+fn test_result_json_envelope_reports_warnings() {
+    // A battery too slow to shave the 1.5 MW excess of the peak
+    let directory = tempfile::tempdir().unwrap();
+    let config = CONFIG
+        .replace("max_rate = 1.5", "max_rate = 0.5")
+        .replace("charge_power_mw = 1.5", "charge_power_mw = 0.5");
+    for (name, contents) in [
+        ("config.toml", config.as_str()),
+        ("forecasts.json", FORECASTS),
+        ("day-ahead.json", PRICES),
+    ] {
+        fs::write(directory.path().join(name), contents).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_battery"))
+        .current_dir(directory.path())
+        .args(["--quiet", "--result-json", "result.json"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let envelope: Value =
+        serde_json::from_str(&fs::read_to_string(directory.path().join("result.json")).unwrap())
+            .unwrap();
+
+    assert_eq!(envelope["status"], "ok");
+    assert_eq!(envelope["errors"], Value::Array(Vec::new()));
+    let warnings: Vec<&str> = envelope["warnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|warning| warning.as_str().unwrap())
+        .collect();
+    assert!(
+        warnings
+            .iter()
+            .any(|warning| warning.starts_with("Grid limit exceeded at 2022-12-13 00:30:00")),
+        "{:?}",
+        warnings
+    );
+    assert!(warnings
+        .iter()
+        .any(|warning| warning.starts_with("Peak at 2022-12-13 00:30:00")));
+    assert_eq!(envelope["plan"].as_array().unwrap().len(), 8);
+    assert!(envelope["economics"]["total_savings_eur"].is_number());
+    assert!(
+        envelope["summary"]["intervals_over_grid_limit"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+
+    // A failed run still writes the envelope, with the error chain
+    fs::remove_file(directory.path().join("forecasts.json")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_battery"))
+        .current_dir(directory.path())
+        .args(["--quiet", "--result-json", "result.json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let envelope: Value =
+        serde_json::from_str(&fs::read_to_string(directory.path().join("result.json")).unwrap())
+            .unwrap();
+    assert_eq!(envelope["status"], "error");
+    assert_eq!(envelope["errors"][0], "Failed to load forecasts");
+    assert_eq!(envelope["plan"], Value::Null);
+}
+
+#[test]
+fn test_result_json_lists_inverter_violations() {
+    // An inverter far slower than the battery's 1.5 MW
+    let directory = tempfile::tempdir().unwrap();
+    let config = format!(
+        "{}\n[inverter]\nmax_rate_mw = 0.1\nmax_ramp_mw = 10.0\nmin_dwell_intervals = 0\n",
+        CONFIG
+    );
+    for (name, contents) in [
+        ("config.toml", config.as_str()),
+        ("forecasts.json", FORECASTS),
+        ("day-ahead.json", PRICES),
+    ] {
+        fs::write(directory.path().join(name), contents).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_battery"))
+        .current_dir(directory.path())
+        .args(["--quiet", "--result-json", "result.json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let envelope: Value =
+        serde_json::from_str(&fs::read_to_string(directory.path().join("result.json")).unwrap())
+            .unwrap();
+
+    assert_eq!(envelope["status"], "error");
+    let error = envelope["errors"][0].as_str().unwrap();
+    assert!(error.starts_with("Plan violates"), "{}", error);
+    assert!(error.contains("(MaxRate)"), "{}", error);
+    assert!(error.contains("2022-12-12 23:00:00 UTC"), "{}", error);
+}
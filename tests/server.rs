@@ -0,0 +1,143 @@
+#![cfg(feature = "server")]
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+
+/// Two hours of quarter-hour forecasts, with a peak above the grid limit.
+fn forecasts() -> Value {
+    let consumptions = [
+        4_000_000.0,
+        4_000_000.0,
+        4_000_000.0,
+        4_000_000.0,
+        9_000_000.0,
+        9_000_000.0,
+        4_000_000.0,
+        4_000_000.0,
+    ];
+    let first_start: DateTime<Utc> = "2022-12-12T23:00:00Z".parse().unwrap();
+    let forecasts: Vec<Value> = consumptions
+        .iter()
+        .enumerate()
+        .map(|(i, consumption)| {
+            let start = first_start + Duration::minutes(i as i64 * 15);
+            json!({
+                "start": start.to_rfc3339(),
+                "end": (start + Duration::minutes(15)).to_rfc3339(),
+                "consumption_average_power_interval": consumption,
+            })
+        })
+        .collect();
+    json!({ "forecasts": forecasts })
+}
+
+/// Posts `body` to `/plan` and returns the status code and the response body.
+fn post_plan(addr: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST /plan HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[test]
+fn test_post_plan_matches_cli_output() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let config_path = format!("{}/config.toml", manifest_dir);
+    let config: toml::Value = toml::from_str(&fs::read_to_string(&config_path).unwrap()).unwrap();
+    let prices = json!({
+        "prices": [
+            {
+                "start": "2022-12-12T23:00:00Z",
+                "end": "2022-12-13T00:00:00Z",
+                "market_price_currency": "EUR",
+                "market_price_per_kwh": 0.10
+            },
+            {
+                "start": "2022-12-13T00:00:00Z",
+                "end": "2022-12-13T01:00:00Z",
+                "market_price_currency": "EUR",
+                "market_price_per_kwh": 0.40
+            }
+        ]
+    });
+    let forecasts = forecasts();
+
+    // Plan the same inputs with the CLI
+    let directory = tempfile::tempdir().unwrap();
+    let forecasts_path = directory.path().join("forecasts.json");
+    let prices_path = directory.path().join("day-ahead.json");
+    let output_path = directory.path().join("plan.json");
+    fs::write(&forecasts_path, forecasts.to_string()).unwrap();
+    fs::write(&prices_path, prices.to_string()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_battery"))
+        .args(["--config", &config_path, "--forecasts"])
+        .arg(&forecasts_path)
+        .arg("--prices")
+        .arg(&prices_path)
+        .arg("--output")
+        .arg(&output_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let cli_output: Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+
+    // Serve the router on a free port
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    runtime.spawn(async move { axum::serve(listener, battery::server::router()).await });
+
+    let body = json!({ "config": config, "forecasts": forecasts, "prices": prices });
+    let (status, response) = post_plan(&addr, &body.to_string());
+    assert_eq!(status, 200, "Unexpected response: {}", response);
+
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(response["planning"], cli_output["planning"]);
+    assert_eq!(
+        response["total_savings_eur"],
+        cli_output["total_savings_eur"]
+    );
+    assert_eq!(response["planning"].as_array().unwrap().len(), 8);
+    assert!(response["summary"]["active_intervals"].as_u64().unwrap() > 0);
+
+    // Invalid inputs are rejected with the error message
+    let (status, response) = post_plan(
+        &addr,
+        &json!({ "config": body["config"], "forecasts": {}, "prices": body["prices"] }).to_string(),
+    );
+    assert_eq!(status, 422);
+    assert!(response.contains("Failed to load forecasts"));
+
+    // Settings naming files on the server are refused before anything is read
+    let mut config = body["config"].clone();
+    config["settings"]["ev_schedule"] = json!("/etc/passwd");
+    let (status, response) = post_plan(
+        &addr,
+        &json!({ "config": config, "forecasts": body["forecasts"], "prices": body["prices"] })
+            .to_string(),
+    );
+    assert_eq!(status, 422);
+    assert!(response.contains("`ev_schedule`"), "{}", response);
+}